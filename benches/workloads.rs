@@ -0,0 +1,296 @@
+//! Throughput benchmarks of the execution engines
+//!
+//! Every workload is a small LC-3 program that is assembled below and run to completion with a
+//! [`BufferedConsole`], once per engine and several times each. The fastest run is reported as
+//! instructions per second, together with the time spent in trap routines. Run with
+//! `cargo bench` (add `--features jit` to include the JIT), optionally followed by `--` and the
+//! names of the workloads to run.
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+use common::engines;
+use lc3_vm::{BufferedConsole, Engine, StopReason, Vm};
+
+use std::collections::HashMap;
+use std::env;
+use std::time::{Duration, Instant};
+
+/// Number of runs of every workload and engine
+const RUNS: usize = 5;
+
+fn main() {
+    let filters: Vec<String> = env::args().skip(1).filter(|arg| arg != "--bench").collect();
+    println!(
+        "{:<12} {:<12} {:>12} {:>12} {:>10} {:>12}",
+        "Workload", "Engine", "Instructions", "Time", "MIPS", "Trap time"
+    );
+    for (name, image) in workloads() {
+        if !filters.is_empty() && !filters.iter().any(|filter| name.contains(filter.as_str())) {
+            continue;
+        }
+        for engine in engines() {
+            let run = (0..RUNS)
+                .map(|_| run(&image, engine))
+                .min_by_key(|run| run.time)
+                .unwrap();
+            println!(
+                "{:<12} {:<12} {:>12} {:>12.3?} {:>10.2} {:>12.3?}",
+                name,
+                format!("{:?}", engine),
+                run.instructions,
+                run.time,
+                run.instructions as f64 / run.time.as_secs_f64() / 1e6,
+                run.trap_time
+            );
+        }
+    }
+}
+
+/// Measurements of a single run
+struct Run {
+    instructions: u64,
+    time: Duration,
+    trap_time: Duration,
+}
+
+fn run(image: &[u8], engine: Engine) -> Run {
+    let mut vm = Vm::new();
+    vm.set_console(Box::new(BufferedConsole::new()));
+    vm.set_engine(engine);
+    vm.load_program(image).expect("Invalid workload");
+    let started = Instant::now();
+    let reason = vm.resume();
+    let time = started.elapsed();
+    assert_eq!(reason, StopReason::Halted, "Workload did not halt");
+    Run {
+        instructions: vm.instructions(),
+        time,
+        trap_time: vm.trap_time(),
+    }
+}
+
+/// Returns the names and program images of all workloads
+fn workloads() -> Vec<(&'static str, Vec<u8>)> {
+    vec![
+        ("arithmetic", arithmetic()),
+        ("memcpy", memcpy()),
+        ("puts", puts()),
+        ("fibonacci", fibonacci()),
+    ]
+}
+
+/// Nested counting loops that add, mask and complement registers
+fn arithmetic() -> Vec<u8> {
+    let mut asm = Assembler::new(0x3000);
+    asm.ld(1, "OUTER_COUNT");
+    asm.label("OUTER");
+    asm.ld(2, "INNER_COUNT");
+    asm.label("INNER");
+    asm.add(0, 0, 2);
+    asm.and_imm(3, 0, 0xF);
+    asm.not(4, 3);
+    asm.add(5, 4, 0);
+    asm.add_imm(2, 2, -1);
+    asm.br(0b001, "INNER");
+    asm.add_imm(1, 1, -1);
+    asm.br(0b001, "OUTER");
+    asm.halt();
+    asm.label("OUTER_COUNT");
+    asm.fill(2000);
+    asm.label("INNER_COUNT");
+    asm.fill(1000);
+    asm.image()
+}
+
+/// Copies a 4096-word buffer back and forth with `LDR` and `STR`
+fn memcpy() -> Vec<u8> {
+    let mut asm = Assembler::new(0x3000);
+    asm.ld(5, "REPEAT");
+    asm.label("COPY");
+    asm.ld(1, "SRC");
+    asm.ld(2, "DST");
+    asm.ld(3, "LEN");
+    asm.label("WORD");
+    asm.ldr(4, 1, 0);
+    asm.str(4, 2, 0);
+    asm.add_imm(1, 1, 1);
+    asm.add_imm(2, 2, 1);
+    asm.add_imm(3, 3, -1);
+    asm.br(0b001, "WORD");
+    asm.add_imm(5, 5, -1);
+    asm.br(0b001, "COPY");
+    asm.halt();
+    asm.label("REPEAT");
+    asm.fill(200);
+    asm.label("SRC");
+    asm.fill(0x4000);
+    asm.label("DST");
+    asm.fill(0x5000);
+    asm.label("LEN");
+    asm.fill(4096);
+    asm.image()
+}
+
+/// Writes a line of text with `PUTS` over and over
+fn puts() -> Vec<u8> {
+    let mut asm = Assembler::new(0x3000);
+    asm.ld(1, "REPEAT");
+    asm.label("LOOP");
+    asm.lea(0, "TEXT");
+    asm.trap(0x22);
+    asm.add_imm(1, 1, -1);
+    asm.br(0b001, "LOOP");
+    asm.halt();
+    asm.label("REPEAT");
+    asm.fill(5000);
+    asm.label("TEXT");
+    for &chr in b"The quick brown fox jumps over the lazy dog.\n" {
+        asm.fill(chr.into());
+    }
+    asm.fill(0);
+    asm.image()
+}
+
+/// Computes Fibonacci numbers with naively recursive `JSR` calls and a stack in `R6`
+fn fibonacci() -> Vec<u8> {
+    let mut asm = Assembler::new(0x3000);
+    asm.ld(6, "STACK");
+    asm.ld(0, "N");
+    asm.jsr("FIB");
+    asm.halt();
+    asm.label("STACK");
+    asm.fill(0xF000);
+    asm.label("N");
+    asm.fill(23);
+
+    // R0 = fib(R0); R1 is clobbered
+    asm.label("FIB");
+    asm.add_imm(6, 6, -1);
+    asm.str(7, 6, 0);
+    asm.add_imm(1, 0, -2);
+    asm.br(0b100, "BASE");
+    asm.add_imm(6, 6, -1);
+    asm.str(0, 6, 0);
+    asm.add_imm(0, 0, -1);
+    asm.jsr("FIB");
+    asm.ldr(1, 6, 0);
+    asm.str(0, 6, 0);
+    asm.add_imm(0, 1, -2);
+    asm.jsr("FIB");
+    asm.ldr(1, 6, 0);
+    asm.add(0, 0, 1);
+    asm.add_imm(6, 6, 1);
+    asm.label("BASE");
+    asm.ldr(7, 6, 0);
+    asm.add_imm(6, 6, 1);
+    asm.ret();
+    asm.image()
+}
+
+/// Minimal assembler for the workloads, which resolves PC-relative label references
+struct Assembler {
+    origin: u16,
+    words: Vec<u16>,
+    labels: HashMap<&'static str, u16>,
+    /// Index of the word, referenced label and width of its offset field
+    fixups: Vec<(usize, &'static str, u32)>,
+}
+
+impl Assembler {
+    fn new(origin: u16) -> Self {
+        Self {
+            origin,
+            words: Vec::new(),
+            labels: HashMap::new(),
+            fixups: Vec::new(),
+        }
+    }
+
+    fn label(&mut self, name: &'static str) {
+        let address = self.origin + self.words.len() as u16;
+        self.labels.insert(name, address);
+    }
+
+    fn fill(&mut self, word: u16) {
+        self.words.push(word);
+    }
+
+    fn with_label(&mut self, word: u16, label: &'static str, bits: u32) {
+        self.fixups.push((self.words.len(), label, bits));
+        self.words.push(word);
+    }
+
+    fn add(&mut self, dr: u16, sr1: u16, sr2: u16) {
+        self.fill(0x1000 | dr << 9 | sr1 << 6 | sr2);
+    }
+
+    fn add_imm(&mut self, dr: u16, sr1: u16, imm: i16) {
+        self.fill(0x1000 | dr << 9 | sr1 << 6 | 0x20 | (imm as u16 & 0x1F));
+    }
+
+    fn and_imm(&mut self, dr: u16, sr1: u16, imm: i16) {
+        self.fill(0x5000 | dr << 9 | sr1 << 6 | 0x20 | (imm as u16 & 0x1F));
+    }
+
+    fn not(&mut self, dr: u16, sr: u16) {
+        self.fill(0x903F | dr << 9 | sr << 6);
+    }
+
+    fn br(&mut self, nzp: u16, label: &'static str) {
+        self.with_label(nzp << 9, label, 9);
+    }
+
+    fn jsr(&mut self, label: &'static str) {
+        self.with_label(0x4800, label, 11);
+    }
+
+    fn ret(&mut self) {
+        self.fill(0xC1C0);
+    }
+
+    fn ld(&mut self, dr: u16, label: &'static str) {
+        self.with_label(0x2000 | dr << 9, label, 9);
+    }
+
+    fn lea(&mut self, dr: u16, label: &'static str) {
+        self.with_label(0xE000 | dr << 9, label, 9);
+    }
+
+    fn ldr(&mut self, dr: u16, base: u16, offset: i16) {
+        self.fill(0x6000 | dr << 9 | base << 6 | (offset as u16 & 0x3F));
+    }
+
+    fn str(&mut self, sr: u16, base: u16, offset: i16) {
+        self.fill(0x7000 | sr << 9 | base << 6 | (offset as u16 & 0x3F));
+    }
+
+    fn trap(&mut self, trapvector: u16) {
+        self.fill(0xF000 | trapvector);
+    }
+
+    fn halt(&mut self) {
+        self.trap(0x25);
+    }
+
+    /// Resolves the label references; returns the program image with its origin
+    fn image(mut self) -> Vec<u8> {
+        for &(index, label, bits) in &self.fixups {
+            let target = self.labels[label];
+            let pc = self.origin + index as u16 + 1;
+            let offset = target.wrapping_sub(pc) as i16;
+            let limit = 1 << (bits - 1);
+            assert!(
+                (-limit..limit).contains(&i32::from(offset)),
+                "{} is out of range",
+                label
+            );
+            self.words[index] |= offset as u16 & ((1 << bits) - 1);
+        }
+        let mut image = self.origin.to_be_bytes().to_vec();
+        for word in &self.words {
+            image.extend_from_slice(&word.to_be_bytes());
+        }
+        image
+    }
+}
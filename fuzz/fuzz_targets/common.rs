@@ -0,0 +1,66 @@
+//! Parts shared by the fuzz targets
+
+// Not every target uses every helper
+#![allow(dead_code)]
+
+use lc3_vm::{BufferedConsole, Fault, Opcode, StopReason, Vm};
+
+/// Maximum number of instructions executed per fuzz input
+pub const STEP_BUDGET: usize = 20_000;
+/// Maximum number of scripted input bytes
+const MAX_INPUT_LEN: usize = 64;
+
+/// Splits fuzz data into scripted keyboard input and the remaining bytes; the first byte gives
+/// the length of the input
+pub fn split_input(data: &[u8]) -> (&[u8], &[u8]) {
+    match data.split_first() {
+        Some((&len, rest)) => rest.split_at((len as usize % (MAX_INPUT_LEN + 1)).min(rest.len())),
+        None => (&[], &[]),
+    }
+}
+
+/// Creates a vm that reads the scripted `input` and writes its output to memory
+pub fn vm_with_input(input: &[u8]) -> Vm {
+    let mut vm = Vm::new();
+    vm.set_console(Box::new(BufferedConsole::with_input(input)));
+    vm
+}
+
+/// Executes at most [`STEP_BUDGET`] instructions and checks the reported stop reasons
+pub fn run(vm: &mut Vm) {
+    for _ in 0..STEP_BUDGET {
+        match vm.step() {
+            None => continue,
+            Some(reason) => {
+                check_stop(vm, reason);
+                return;
+            }
+        }
+    }
+}
+
+/// Panics if the stop `reason` contradicts the state of the vm
+pub fn check_stop(vm: &Vm, reason: StopReason) {
+    match reason {
+        StopReason::Fault { pc, fault } => {
+            assert_eq!(vm.registers().pc, pc, "PC moved past a fault");
+            if let Fault::IllegalOpcode(instr) = fault {
+                let opcode = Opcode::from_instr(instr);
+                let privileged = opcode == Opcode::Rti && vm.registers().user_mode();
+                assert!(
+                    opcode == Opcode::Res || privileged,
+                    "{:?} reported as illegal",
+                    opcode
+                );
+            }
+        }
+        StopReason::WaitingForInput(pc) => {
+            assert_eq!(
+                vm.registers().pc,
+                pc,
+                "PC moved past a trap waiting for input"
+            );
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,35 @@
+//! Executes a single arbitrary instruction from an arbitrary machine state
+//!
+//! This reaches every opcode, trap vector and register combination directly, including
+//! instructions placed at the end of memory or on the device registers.
+
+#![no_main]
+
+mod common;
+
+use lc3_vm::{InvalidOpcode, Opcode};
+use libfuzzer_sys::fuzz_target;
+
+use std::convert::TryFrom;
+
+fuzz_target!(|state: (u16, u16, u16, [u16; 8], u8)| {
+    let (instr, pc, psr, regs, input) = state;
+
+    match Opcode::try_from(instr) {
+        Ok(opcode) => assert_eq!(opcode as u16, instr),
+        Err(error) => assert_eq!((error, instr >> 4 != 0), (InvalidOpcode(instr), true)),
+    }
+    let opcode = Opcode::from_instr(instr);
+    assert_eq!(Opcode::from_mnemonic(opcode.mnemonic()), Some(opcode));
+
+    let mut vm = common::vm_with_input(&[input]);
+    for (index, &value) in regs.iter().enumerate() {
+        vm.registers_mut().write(index as u16, value);
+    }
+    vm.registers_mut().pc = pc;
+    vm.registers_mut().set_psr(psr);
+    vm.memory_mut().poke(pc, instr);
+    if let Some(reason) = vm.step() {
+        common::check_stop(&vm, reason);
+    }
+});
@@ -0,0 +1,18 @@
+//! Loads arbitrary program images and runs them with scripted input
+//!
+//! The first byte gives the length of the scripted input that follows it (see
+//! [`common::split_input`]); the remaining bytes are the image, starting with its origin.
+
+#![no_main]
+
+mod common;
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let (input, image) = common::split_input(data);
+    let mut vm = common::vm_with_input(input);
+    if vm.load_program(image).is_ok() {
+        common::run(&mut vm);
+    }
+});
@@ -0,0 +1,29 @@
+//! Loads arbitrary snapshots and runs them with scripted input
+//!
+//! Complete snapshots are larger than the inputs the fuzzer usually generates, so each input is
+//! loaded once as is, which exercises the header checks, and once padded with the rest of a
+//! snapshot of a new vm, which lets mutated headers and registers run.
+
+#![no_main]
+
+mod common;
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let (input, snapshot) = common::split_input(data);
+    let mut vm = common::vm_with_input(input);
+    if vm.load_snapshot(snapshot).is_ok() {
+        common::run(&mut vm);
+    }
+
+    let mut vm = common::vm_with_input(input);
+    let mut padded = Vec::new();
+    vm.save_snapshot(&mut padded).unwrap();
+    let len = snapshot.len().min(padded.len());
+    padded[..len].copy_from_slice(&snapshot[..len]);
+    padded.extend_from_slice(&snapshot[len..]);
+    if vm.load_snapshot(&padded[..]).is_ok() {
+        common::run(&mut vm);
+    }
+});
@@ -0,0 +1,210 @@
+//! Code coverage of LC-3 programs
+//!
+//! [`Coverage`] is a [`Tracer`] that counts how often every address is executed and how often
+//! every `BR` instruction branched or fell through. The program's instructions and their source
+//! lines come from the listing of a [`DebugInfo`], so the words emitted by directives are not
+//! reported as uncovered code.
+//!
+//! Coverage is written in the [LCOV](https://github.com/linux-test-project/lcov) tracefile
+//! format, with the labels of instructions as functions and conditional branches as branch
+//! records, or as a text summary per symbol.
+
+use crate::debug_info::DebugInfo;
+use crate::vm::{TraceEvent, Tracer};
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Number of executions of a `BR` instruction that branched and that fell through
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BranchCounts {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+/// Tracer that records executed addresses and branch outcomes
+#[derive(Debug)]
+pub struct Coverage {
+    /// Number of executions of every address
+    counts: Vec<u64>,
+    branches: BTreeMap<u16, BranchCounts>,
+}
+
+impl Coverage {
+    /// Creates a new empty `Coverage`
+    pub fn new() -> Self {
+        Self {
+            counts: vec![0; 1 << 16],
+            branches: BTreeMap::new(),
+        }
+    }
+
+    /// Returns how often the instruction at the given `address` was executed
+    pub fn count(&self, address: u16) -> u64 {
+        self.counts[address as usize]
+    }
+
+    /// Returns the outcomes of the `BR` instruction at the given `address`
+    pub fn branch(&self, address: u16) -> BranchCounts {
+        self.branches.get(&address).copied().unwrap_or_default()
+    }
+
+    /// Writes the coverage as an LCOV tracefile for the given assembly `source`
+    pub fn write_lcov(
+        &self,
+        out: &mut dyn Write,
+        debug: &DebugInfo,
+        source: &Path,
+    ) -> io::Result<()> {
+        writeln!(out, "TN:")?;
+        writeln!(out, "SF:{}", source.display())?;
+
+        let mut functions = 0;
+        let mut functions_hit = 0;
+        for (address, name) in debug.symbols() {
+            if let (Some(_), Some(line)) = (debug.instruction_at(address), debug.line_of(address)) {
+                writeln!(out, "FN:{},{}", line, name)?;
+                writeln!(out, "FNDA:{},{}", self.count(address), name)?;
+                functions += 1;
+                functions_hit += (self.count(address) > 0) as usize;
+            }
+        }
+        writeln!(out, "FNF:{}", functions)?;
+        writeln!(out, "FNH:{}", functions_hit)?;
+
+        let mut branches = 0;
+        let mut branches_hit = 0;
+        for (address, word) in debug.instructions() {
+            let line = match debug.line_of(address) {
+                Some(line) if is_conditional_branch(word) => line,
+                _ => continue,
+            };
+            let counts = self.branch(address);
+            for (index, taken) in [counts.taken, counts.not_taken].iter().enumerate() {
+                if self.count(address) == 0 {
+                    writeln!(out, "BRDA:{},{},{},-", line, address, index)?;
+                } else {
+                    writeln!(out, "BRDA:{},{},{},{}", line, address, index, taken)?;
+                }
+                branches += 1;
+                branches_hit += (*taken > 0) as usize;
+            }
+        }
+        writeln!(out, "BRF:{}", branches)?;
+        writeln!(out, "BRH:{}", branches_hit)?;
+
+        let mut lines = BTreeMap::new();
+        for (address, _) in debug.instructions() {
+            if let Some(line) = debug.line_of(address) {
+                let count = lines.entry(line).or_insert(0);
+                *count = self.count(address).max(*count);
+            }
+        }
+        for (line, count) in &lines {
+            writeln!(out, "DA:{},{}", line, count)?;
+        }
+        writeln!(out, "LF:{}", lines.len())?;
+        writeln!(
+            out,
+            "LH:{}",
+            lines.values().filter(|&&count| count > 0).count()
+        )?;
+        writeln!(out, "end_of_record")
+    }
+
+    /// Writes a summary of the covered instructions and branch outcomes per symbol
+    ///
+    /// Every instruction belongs to the closest symbol at or before its address.
+    pub fn write_summary(&self, out: &mut dyn Write, debug: &DebugInfo) -> io::Result<()> {
+        let mut symbols: BTreeMap<Option<u16>, Summary> = BTreeMap::new();
+        let mut total = Summary::default();
+        for (address, word) in debug.instructions() {
+            let symbol = debug.symbol_containing(address).map(|(_, start)| start);
+            symbols.entry(symbol).or_default().add(self, address, word);
+            total.add(self, address, word);
+        }
+
+        writeln!(
+            out,
+            "{:>16} {:>7} {:>16} {:>7}  Symbol",
+            "Instructions", "%", "Branches", "%"
+        )?;
+        for (address, summary) in &symbols {
+            let name = match address {
+                Some(address) => debug.symbol_at(*address).unwrap_or_default(),
+                None => "(no symbol)",
+            };
+            summary.write(out, name)?;
+        }
+        total.write(out, "Total")
+    }
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Tracer for Coverage {
+    fn trace(&mut self, event: &TraceEvent) {
+        self.counts[event.pc as usize] += 1;
+        if let Some(taken) = event.branch_taken() {
+            let counts = self.branches.entry(event.pc).or_default();
+            if taken {
+                counts.taken += 1;
+            } else {
+                counts.not_taken += 1;
+            }
+        }
+    }
+}
+
+/// Numbers of covered instructions and branch outcomes
+#[derive(Debug, Default)]
+struct Summary {
+    instructions: usize,
+    executed: usize,
+    branches: usize,
+    branches_hit: usize,
+}
+
+impl Summary {
+    fn add(&mut self, coverage: &Coverage, address: u16, word: u16) {
+        self.instructions += 1;
+        self.executed += (coverage.count(address) > 0) as usize;
+        if is_conditional_branch(word) {
+            let counts = coverage.branch(address);
+            self.branches += 2;
+            self.branches_hit += (counts.taken > 0) as usize + (counts.not_taken > 0) as usize;
+        }
+    }
+
+    fn write(&self, out: &mut dyn Write, name: &str) -> io::Result<()> {
+        writeln!(
+            out,
+            "{:>16} {:>6.1}% {:>16} {:>6.1}%  {}",
+            format!("{}/{}", self.executed, self.instructions),
+            percentage(self.executed, self.instructions),
+            format!("{}/{}", self.branches_hit, self.branches),
+            percentage(self.branches_hit, self.branches),
+            name
+        )
+    }
+}
+
+/// Returns whether `word` is a `BR` instruction that can both branch and fall through, so
+/// `BR` with all or none of the `nzp` bits is excluded
+fn is_conditional_branch(word: u16) -> bool {
+    let nzp = (word >> 9) & 0x7;
+    word >> 12 == 0 && nzp != 0 && nzp != 0x7
+}
+
+fn percentage(part: usize, total: usize) -> f64 {
+    if total == 0 {
+        100.0
+    } else {
+        part as f64 * 100.0 / total as f64
+    }
+}
@@ -0,0 +1,708 @@
+//! Debug Adapter Protocol (DAP) server
+//!
+//! The server speaks DAP over stdin and stdout, so editors like VS Code can launch it as a debug
+//! adapter. The `launch` request takes the `program` to debug, which is either an object file
+//! (`.obj`) or an assembly source (`.asm`) that was assembled next to its object file. Symbol
+//! tables (`.sym`) and listings (`.lst`) next to the program provide function names and the
+//! mapping between addresses and source lines (see [`DebugInfo`]).
+//!
+//! The program's console output is sent as `output` events. Text entered in the debug console
+//! (`evaluate` requests in the `repl` context) is sent to the program as keyboard input, where
+//! `\n` stands for the Enter key. In other contexts, expressions are evaluated as register names
+//! (`R0`..`R7`, `PC`, `PSR`), symbols or addresses.
+
+use crate::debug_info::DebugInfo;
+use crate::vm::{parse_word, BufferedConsole, Fault, StopReason, Vm};
+
+use serde_json::{json, Value};
+use std::fs::File;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+#[cfg(test)]
+mod tests;
+
+/// Number of instructions executed between two checks for incoming requests
+const REQUEST_POLL_INTERVAL: usize = 4096;
+
+/// Thread id reported for the vm's only thread
+const THREAD_ID: u64 = 1;
+
+/// Variables reference of the registers scope
+const REGISTERS_REFERENCE: u64 = 1;
+
+/// Number of bytes of the whole memory, which is the most that a `readMemory` request reads
+const MEMORY_BYTES: u64 = 2 << 16;
+
+/// How the vm executes instructions while it is running
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RunMode {
+    /// Run until a breakpoint, fault or halt
+    Continue,
+    /// Run until `PC` reaches the given return address of a subroutine call
+    StepOver(u16),
+    /// Run until the current subroutine returns; counts the nested subroutine calls
+    StepOut(u32),
+    /// Execute a single instruction
+    Step,
+}
+
+/// Debug adapter for a single debugging session
+pub struct DapServer {
+    vm: Vm,
+    debug_info: DebugInfo,
+    console: BufferedConsole,
+    /// Sequence number of the next message sent to the client
+    seq: u64,
+    /// Current run mode, or `None` while the vm is stopped
+    run_mode: Option<RunMode>,
+    /// Whether the running vm waits for keyboard input from the debug console
+    waiting_for_input: bool,
+    /// Address of an instruction whose breakpoint is stepped over when execution resumes
+    skipped_breakpoint: Option<u16>,
+    /// Whether the program was launched
+    launched: bool,
+    /// Whether the client finished the configuration (like setting the initial breakpoints)
+    configured: bool,
+    /// Whether execution should stop at the first instruction after configuration is done
+    stop_on_entry: bool,
+    /// Whether the program halted
+    terminated: bool,
+    line_breakpoints: Vec<u16>,
+    instruction_breakpoints: Vec<u16>,
+    /// Stream of the messages to the client
+    output: Box<dyn Write>,
+}
+
+impl DapServer {
+    /// Creates a new `DapServer`; the program is loaded by the client's `launch` request
+    pub fn new() -> Self {
+        Self::with_output(Box::new(io::stdout()))
+    }
+
+    /// Creates a new `DapServer` that sends its messages to `output` instead of stdout
+    fn with_output(output: Box<dyn Write>) -> Self {
+        let console = BufferedConsole::new();
+        let mut vm = Vm::new();
+        vm.set_console(Box::new(console.clone()));
+        Self {
+            vm,
+            debug_info: DebugInfo::new(),
+            console,
+            seq: 1,
+            run_mode: None,
+            waiting_for_input: false,
+            skipped_breakpoint: None,
+            launched: false,
+            configured: false,
+            stop_on_entry: false,
+            terminated: false,
+            line_breakpoints: Vec::new(),
+            instruction_breakpoints: Vec::new(),
+            output,
+        }
+    }
+
+    /// Serves requests from stdin until the client disconnects
+    pub fn serve(self) -> io::Result<()> {
+        self.serve_requests(spawn_reader())
+    }
+
+    /// Serves the requests from the receiver until the client disconnects or the sender is
+    /// dropped
+    fn serve_requests(mut self, requests: Receiver<Value>) -> io::Result<()> {
+        loop {
+            let request = if self.run_mode.is_some() && !self.waiting_for_input {
+                match requests.try_recv() {
+                    Ok(request) => Some(request),
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => return Ok(()),
+                }
+            } else {
+                match requests.recv() {
+                    Ok(request) => Some(request),
+                    Err(_) => return Ok(()),
+                }
+            };
+
+            if let Some(request) = request {
+                if !self.handle(&request)? {
+                    return Ok(());
+                }
+            }
+            if self.run_mode.is_some() && !self.waiting_for_input {
+                self.run_slice()?;
+            }
+        }
+    }
+
+    /// Handles a single request; returns `false` if the session ended
+    fn handle(&mut self, request: &Value) -> io::Result<bool> {
+        let command = request["command"].as_str().unwrap_or_default();
+        let args = &request["arguments"];
+        let result = match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsReadMemoryRequest": true,
+                "supportsInstructionBreakpoints": true,
+                "supportsSetVariable": true,
+            })),
+            "launch" => {
+                let result = self.launch(args);
+                if result.is_ok() && self.configured {
+                    self.start()?;
+                }
+                result
+            }
+            "configurationDone" => {
+                self.configured = true;
+                if self.launched {
+                    self.start()?;
+                }
+                Ok(Value::Null)
+            }
+            "setBreakpoints" => Ok(self.set_breakpoints(args)),
+            "setInstructionBreakpoints" => Ok(self.set_instruction_breakpoints(args)),
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] })),
+            "stackTrace" => Ok(self.stack_trace()),
+            "scopes" => Ok(json!({
+                "scopes": [{
+                    "name": "Registers",
+                    "presentationHint": "registers",
+                    "variablesReference": REGISTERS_REFERENCE,
+                    "expensive": false,
+                }]
+            })),
+            "variables" => Ok(self.variables(args)),
+            "setVariable" => self.set_variable(args),
+            "readMemory" => self.read_memory(args),
+            "evaluate" => self.evaluate(args),
+            "continue" => self
+                .resume(RunMode::Continue)
+                .map(|_| json!({ "allThreadsContinued": true })),
+            "next" => {
+                let pc = self.vm.registers().pc;
+                let is_subroutine_call = self.vm.memory().peek(pc) >> 12 == 0b0100;
+                if is_subroutine_call {
+                    self.resume(RunMode::StepOver(pc.wrapping_add(1)))
+                } else {
+                    self.resume(RunMode::Step)
+                }
+            }
+            "stepIn" => self.resume(RunMode::Step),
+            "stepOut" => self.resume(RunMode::StepOut(0)),
+            "pause" => {
+                if self.run_mode.is_some() {
+                    self.run_mode = None;
+                    self.waiting_for_input = false;
+                    self.send_stopped("pause", None)?;
+                }
+                Ok(Value::Null)
+            }
+            "terminate" => {
+                self.run_mode = None;
+                self.waiting_for_input = false;
+                self.terminated = true;
+                self.send_event("terminated", Value::Null)?;
+                Ok(Value::Null)
+            }
+            "disconnect" => {
+                self.send_response(request, Ok(Value::Null))?;
+                return Ok(false);
+            }
+            _ => Err(format!("Unsupported request `{}`", command)),
+        };
+        self.send_response(request, result)?;
+        Ok(true)
+    }
+
+    fn launch(&mut self, args: &Value) -> Result<Value, String> {
+        let program = args["program"]
+            .as_str()
+            .ok_or("Missing launch argument `program`")?;
+        let program = Path::new(program);
+        let object_file = program.with_extension("obj");
+        let file = File::open(&object_file).map_err(|e| {
+            format!(
+                "Error while opening {} (assemble the program first): {}",
+                object_file.display(),
+                e
+            )
+        })?;
+        self.vm
+            .load_program(file)
+            .map_err(|e| format!("Error while loading program: {}", e))?;
+        self.debug_info = DebugInfo::load(program)
+            .map_err(|e| format!("Error while loading debug information: {}", e))?;
+        self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+        self.launched = true;
+        Ok(Value::Null)
+    }
+
+    /// Starts executing the launched program or stops at its entry
+    fn start(&mut self) -> io::Result<()> {
+        if self.stop_on_entry {
+            self.send_stopped("entry", None)
+        } else {
+            self.resume(RunMode::Continue).ok();
+            Ok(())
+        }
+    }
+
+    fn set_breakpoints(&mut self, args: &Value) -> Value {
+        let lines: Vec<usize> = args["breakpoints"]
+            .as_array()
+            .map(|breakpoints| {
+                breakpoints
+                    .iter()
+                    .filter_map(|breakpoint| breakpoint["line"].as_u64())
+                    .map(|line| line as usize)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let mut addresses = Vec::new();
+        let breakpoints: Vec<Value> = lines
+            .iter()
+            .map(|&line| match self.debug_info.address_of_line(line) {
+                Some(address) => {
+                    addresses.push(address);
+                    json!({
+                        "verified": true,
+                        "line": self.debug_info.line_of(address),
+                        "instructionReference": memory_reference(address),
+                    })
+                }
+                None => json!({
+                    "verified": false,
+                    "line": line,
+                    "message": "No instruction at or after this line (is the listing missing?)",
+                }),
+            })
+            .collect();
+
+        self.line_breakpoints = addresses;
+        self.update_breakpoints();
+        json!({ "breakpoints": breakpoints })
+    }
+
+    fn set_instruction_breakpoints(&mut self, args: &Value) -> Value {
+        let mut addresses = Vec::new();
+        let breakpoints: Vec<Value> = args["breakpoints"]
+            .as_array()
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+            .iter()
+            .map(|breakpoint| {
+                let reference = breakpoint["instructionReference"]
+                    .as_str()
+                    .unwrap_or_default();
+                let offset = breakpoint["offset"].as_i64().unwrap_or(0);
+                match parse_word(reference) {
+                    Some(address) => {
+                        let address = address.wrapping_add(offset as u16);
+                        addresses.push(address);
+                        json!({ "verified": true, "instructionReference": memory_reference(address) })
+                    }
+                    None => json!({ "verified": false, "message": "Invalid instruction reference" }),
+                }
+            })
+            .collect();
+
+        self.instruction_breakpoints = addresses;
+        self.update_breakpoints();
+        json!({ "breakpoints": breakpoints })
+    }
+
+    /// Replaces the vm's breakpoints with the current line and instruction breakpoints
+    fn update_breakpoints(&mut self) {
+        let previous: Vec<u16> = self.vm.breakpoints().collect();
+        for address in previous {
+            self.vm.remove_breakpoint(address);
+        }
+        for &address in self
+            .line_breakpoints
+            .iter()
+            .chain(&self.instruction_breakpoints)
+        {
+            self.vm.add_breakpoint(address);
+        }
+    }
+
+    fn stack_trace(&self) -> Value {
+        let pc = self.vm.registers().pc;
+        let name = match self.debug_info.symbol_containing(pc) {
+            Some((symbol, address)) if address == pc => symbol.to_string(),
+            Some((symbol, address)) => format!("{}+{}", symbol, pc - address),
+            None => format!("x{:04X}", pc),
+        };
+        let mut frame = json!({
+            "id": 0,
+            "name": name,
+            "line": 0,
+            "column": 0,
+            "instructionPointerReference": memory_reference(pc),
+        });
+        if let (Some(source), Some(line)) = (self.debug_info.source(), self.debug_info.line_of(pc))
+        {
+            frame["source"] = json!({
+                "name": source.file_name().map(|name| name.to_string_lossy()),
+                "path": source.to_string_lossy(),
+            });
+            frame["line"] = json!(line);
+            frame["column"] = json!(1);
+        }
+        json!({ "stackFrames": [frame], "totalFrames": 1 })
+    }
+
+    fn variables(&self, args: &Value) -> Value {
+        if args["variablesReference"].as_u64() != Some(REGISTERS_REFERENCE) {
+            return json!({ "variables": [] });
+        }
+        let regs = self.vm.registers();
+        let mut variables: Vec<Value> = (0..8)
+            .map(|index| register_variable(&format!("R{}", index), regs.read(index)))
+            .collect();
+        variables.push(register_variable("PC", regs.pc));
+        variables.push(json!({
+            "name": "PSR",
+            "value": format!("x{:04X} ({:?})", regs.psr(), regs.cond),
+            "variablesReference": 0,
+        }));
+        json!({ "variables": variables })
+    }
+
+    fn set_variable(&mut self, args: &Value) -> Result<Value, String> {
+        let name = args["name"].as_str().unwrap_or_default();
+        let value = args["value"].as_str().unwrap_or_default();
+        let value = parse_word(value).ok_or_else(|| format!("Invalid value: {}", value))?;
+        let regs = self.vm.registers_mut();
+        match name {
+            "PC" => regs.pc = value,
+            "PSR" => regs.set_psr(value),
+            _ => match register_index(name) {
+                Some(index) => regs.write(index, value),
+                None => return Err(format!("Unknown register: {}", name)),
+            },
+        }
+        Ok(json!({ "value": format!("x{:04X}", value) }))
+    }
+
+    /// Handles `readMemory` requests; offsets and counts are in bytes, addresses in words
+    ///
+    /// Offsets must be even, since data always starts at a word. At most the size of the whole
+    /// memory is read.
+    fn read_memory(&self, args: &Value) -> Result<Value, String> {
+        let reference = args["memoryReference"].as_str().unwrap_or_default();
+        let address = parse_word(reference)
+            .ok_or_else(|| format!("Invalid memory reference: {}", reference))?;
+        let offset = args["offset"].as_i64().unwrap_or(0);
+        if offset % 2 != 0 {
+            return Err(format!("Odd byte offset: {}", offset));
+        }
+        let count = args["count"].as_u64().unwrap_or(0).min(MEMORY_BYTES) as usize;
+
+        let start = (address as i64).saturating_add(offset / 2);
+        if !(0..=u16::MAX as i64).contains(&start) {
+            return Ok(json!({ "address": reference, "unreadableBytes": count }));
+        }
+        let mut bytes = Vec::with_capacity(count + 1);
+        let mut address = start as u16;
+        while bytes.len() < count {
+            bytes.extend_from_slice(&self.vm.memory().peek(address).to_be_bytes());
+            if address == u16::MAX {
+                break;
+            }
+            address += 1;
+        }
+        bytes.truncate(count);
+        let unreadable = count - bytes.len();
+        Ok(json!({
+            "address": memory_reference(start as u16),
+            "data": base64(&bytes),
+            "unreadableBytes": unreadable,
+        }))
+    }
+
+    fn evaluate(&mut self, args: &Value) -> Result<Value, String> {
+        let expression = args["expression"].as_str().unwrap_or_default();
+        if args["context"].as_str() == Some("repl") {
+            let input = expression.replace("\\n", "\n");
+            self.console.push_input(input.as_bytes());
+            self.waiting_for_input = false;
+            return Ok(json!({ "result": "", "variablesReference": 0 }));
+        }
+
+        let trimmed = expression.trim();
+        let regs = self.vm.registers();
+        let value = match trimmed.to_ascii_uppercase().as_str() {
+            "PC" => regs.pc,
+            "PSR" => regs.psr(),
+            name => match register_index(name) {
+                Some(index) => regs.read(index),
+                None => {
+                    let address = self
+                        .debug_info
+                        .address_of(trimmed)
+                        .or_else(|| parse_word(trimmed))
+                        .ok_or_else(|| format!("Unknown expression: {}", trimmed))?;
+                    self.vm.memory().peek(address)
+                }
+            },
+        };
+        Ok(json!({
+            "result": format!("x{:04X} ({})", value, value as i16),
+            "variablesReference": 0,
+        }))
+    }
+
+    fn resume(&mut self, mode: RunMode) -> Result<Value, String> {
+        if !self.launched || self.terminated {
+            return Err("The program is not running".to_string());
+        }
+        self.run_mode = Some(mode);
+        self.skipped_breakpoint = Some(self.vm.registers().pc);
+        self.vm.start_timeout();
+        Ok(Value::Null)
+    }
+
+    /// Executes instructions according to the run mode until the vm stops or the next check for
+    /// incoming requests is due
+    fn run_slice(&mut self) -> io::Result<()> {
+        for _ in 0..REQUEST_POLL_INTERVAL {
+            let mode = match self.run_mode {
+                Some(mode) => mode,
+                None => break,
+            };
+            let pc = self.vm.registers().pc;
+            if self.skipped_breakpoint.take() != Some(pc) && self.vm.has_breakpoint(pc) {
+                self.stop("breakpoint", None)?;
+                break;
+            }
+
+            let instr = self.vm.memory().peek(pc);
+            if let Some(reason) = self.vm.step() {
+                self.handle_stop_reason(reason)?;
+                break;
+            }
+
+            match mode {
+                RunMode::Continue => {}
+                RunMode::Step => self.stop("step", None)?,
+                RunMode::StepOver(return_address) => {
+                    if self.vm.registers().pc == return_address {
+                        self.stop("step", None)?;
+                    }
+                }
+                RunMode::StepOut(depth) => {
+                    let is_subroutine_call = instr >> 12 == 0b0100;
+                    let is_return = instr == 0xC1C0;
+                    if is_subroutine_call {
+                        self.run_mode = Some(RunMode::StepOut(depth + 1));
+                    } else if is_return && depth == 0 {
+                        self.stop("step", None)?;
+                    } else if is_return {
+                        self.run_mode = Some(RunMode::StepOut(depth - 1));
+                    }
+                }
+            }
+        }
+        self.flush_output()
+    }
+
+    fn handle_stop_reason(&mut self, reason: StopReason) -> io::Result<()> {
+        match reason {
+            StopReason::Halted => {
+                self.run_mode = None;
+                self.terminated = true;
+                self.flush_output()?;
+                self.send_event("exited", json!({ "exitCode": 0 }))?;
+                self.send_event("terminated", Value::Null)
+            }
+            StopReason::WaitingForInput(pc) => {
+                self.waiting_for_input = true;
+                self.skipped_breakpoint = Some(pc);
+                self.flush_output()
+            }
+            StopReason::Breakpoint(_) => self.stop("breakpoint", None),
+            StopReason::Watchpoint(_) => self.stop("data breakpoint", None),
+            StopReason::Aborted => self.stop("pause", None),
+            StopReason::Fault { fault, .. } => {
+                let description = match fault {
+                    Fault::IllegalOpcode(_) => "Illegal opcode",
+                    Fault::UnsupportedTrap(_) => "Unsupported trap",
+                };
+                self.stop(
+                    "exception",
+                    Some(json!({ "description": description, "text": fault.to_string() })),
+                )
+            }
+            StopReason::LimitExceeded { limit, recent, .. } => self.stop(
+                "exception",
+                Some(json!({
+                    "description": "Limit exceeded",
+                    "text": format!("Reached the {}; last instructions: {}", limit, recent),
+                })),
+            ),
+        }
+    }
+
+    fn stop(&mut self, reason: &str, details: Option<Value>) -> io::Result<()> {
+        self.run_mode = None;
+        self.flush_output()?;
+        self.send_stopped(reason, details)
+    }
+
+    fn send_stopped(&mut self, reason: &str, details: Option<Value>) -> io::Result<()> {
+        let mut body = json!({
+            "reason": reason,
+            "threadId": THREAD_ID,
+            "allThreadsStopped": true,
+        });
+        if let Some(Value::Object(details)) = details {
+            body.as_object_mut().unwrap().extend(details);
+        }
+        self.send_event("stopped", body)
+    }
+
+    /// Sends the program's pending console output as an `output` event
+    fn flush_output(&mut self) -> io::Result<()> {
+        let output = self.console.take_output();
+        if output.is_empty() {
+            return Ok(());
+        }
+        let output = String::from_utf8_lossy(&output).into_owned();
+        self.send_event("output", json!({ "category": "stdout", "output": output }))
+    }
+
+    fn send_response(&mut self, request: &Value, result: Result<Value, String>) -> io::Result<()> {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(Value::Null) => {}
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        self.send(response)?;
+        if request["command"] == "initialize" {
+            self.send_event("initialized", Value::Null)?;
+        }
+        Ok(())
+    }
+
+    fn send_event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        let mut message = json!({ "type": "event", "event": event });
+        if !body.is_null() {
+            message["body"] = body;
+        }
+        self.send(message)
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        message["seq"] = json!(self.seq);
+        self.seq += 1;
+        let content = message.to_string();
+        write!(
+            self.output,
+            "Content-Length: {}\r\n\r\n{}",
+            content.len(),
+            content
+        )?;
+        self.output.flush()
+    }
+}
+
+impl Default for DapServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Spawns a thread that reads DAP messages from stdin and sends them to the returned receiver
+///
+/// The receiver is disconnected at the end of input or on a malformed message.
+fn spawn_reader() -> Receiver<Value> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        let mut stdin = stdin.lock();
+        while let Some(message) = read_message(&mut stdin) {
+            if sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+/// Reads the next `Content-Length` framed JSON message
+fn read_message<R: BufRead>(reader: &mut R) -> Option<Value> {
+    let mut content_length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).ok()? == 0 {
+            return None;
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                content_length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let mut content = vec![0; content_length?];
+    reader.read_exact(&mut content).ok()?;
+    serde_json::from_slice(&content).ok()
+}
+
+fn register_variable(name: &str, value: u16) -> Value {
+    json!({
+        "name": name,
+        "value": format!("x{:04X} ({})", value, value as i16),
+        "variablesReference": 0,
+        "memoryReference": memory_reference(value),
+    })
+}
+
+fn register_index(name: &str) -> Option<u16> {
+    let index = name.strip_prefix(|c| c == 'R' || c == 'r')?.parse().ok()?;
+    if index < 8 {
+        Some(index)
+    } else {
+        None
+    }
+}
+
+fn memory_reference(address: u16) -> String {
+    format!("0x{:04x}", address)
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let buffer = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let bits = u32::from_be_bytes([0, buffer[0], buffer[1], buffer[2]]);
+        for index in 0..4 {
+            if index <= chunk.len() {
+                encoded.push(ALPHABET[(bits >> (18 - 6 * index) & 0x3F) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
@@ -0,0 +1,263 @@
+//! Request and response tests of the debug adapter with in-memory messages
+
+use super::*;
+
+use std::cell::RefCell;
+use std::fs;
+use std::io::Cursor;
+use std::path::PathBuf;
+use std::process;
+use std::rc::Rc;
+
+/// Program at x3000 that echoes a character and halts
+const ECHO: &[u16] = &[
+    0x3000, // .ORIG x3000
+    0xF020, // GETC
+    0xF021, // OUT
+    0x1220, // ADD R1, R0, #0
+    0xF025, // HALT
+];
+
+/// Output stream whose contents stay readable after the server was dropped
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes the object file of `program` and returns its path
+fn object_file(name: &str, program: &[u16]) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("lc3-dap-{}-{}.obj", name, process::id()));
+    let bytes: Vec<u8> = program.iter().flat_map(|word| word.to_be_bytes()).collect();
+    fs::write(&path, bytes).unwrap();
+    path
+}
+
+/// Serves the requests, given as `(command, arguments)`; returns all messages sent back
+fn session(requests: &[(&str, Value)]) -> Vec<Value> {
+    let output = SharedBuffer::default();
+    let server = DapServer::with_output(Box::new(output.clone()));
+    let (sender, receiver) = mpsc::channel();
+    for (seq, (command, arguments)) in requests.iter().enumerate() {
+        let request = json!({
+            "seq": seq + 1,
+            "type": "request",
+            "command": command,
+            "arguments": arguments,
+        });
+        sender.send(request).unwrap();
+    }
+    drop(sender);
+    server.serve_requests(receiver).unwrap();
+
+    let bytes = output.0.borrow().clone();
+    let mut reader = Cursor::new(bytes);
+    let mut messages = Vec::new();
+    while let Some(message) = read_message(&mut reader) {
+        messages.push(message);
+    }
+    assert_eq!(reader.position() as usize, reader.get_ref().len());
+    messages
+}
+
+/// Returns the response to the request with the sequence number `seq`
+fn response(messages: &[Value], seq: u64) -> &Value {
+    messages
+        .iter()
+        .find(|message| message["type"] == "response" && message["request_seq"] == seq)
+        .unwrap_or_else(|| panic!("No response to request {}", seq))
+}
+
+/// Returns the names of the events and the commands of the responses in the order they were sent
+fn names(messages: &[Value]) -> Vec<String> {
+    messages
+        .iter()
+        .map(|message| match message["type"].as_str() {
+            Some("event") => format!("event {}", message["event"].as_str().unwrap()),
+            _ => format!("response {}", message["command"].as_str().unwrap()),
+        })
+        .collect()
+}
+
+#[test]
+fn reads_framed_messages() {
+    let mut reader = Cursor::new(
+        "content-length: 13\r\nContent-Type: application/json\r\n\r\n{\"seq\": 1234}\
+         Content-Length: 2\r\n\r\n{}Content-Length: 5\r\n\r\n{}",
+    );
+    assert_eq!(read_message(&mut reader), Some(json!({ "seq": 1234 })));
+    assert_eq!(read_message(&mut reader), Some(json!({})));
+    // The last message is truncated
+    assert_eq!(read_message(&mut reader), None);
+    assert_eq!(read_message(&mut Cursor::new("\r\n{}")), None);
+}
+
+#[test]
+fn debugs_a_program() {
+    let path = object_file("echo", ECHO);
+    let messages = session(&[
+        ("initialize", json!({ "adapterID": "lc3" })),
+        (
+            "launch",
+            json!({ "program": path.to_str().unwrap(), "stopOnEntry": true }),
+        ),
+        (
+            "setInstructionBreakpoints",
+            json!({ "breakpoints": [{ "instructionReference": "0x3002" }, { "instructionReference": "R9" }] }),
+        ),
+        ("configurationDone", Value::Null),
+        ("evaluate", json!({ "expression": "pc" })),
+        ("continue", json!({ "threadId": 1 })),
+        ("evaluate", json!({ "expression": "a", "context": "repl" })),
+        ("variables", json!({ "variablesReference": 1 })),
+        ("setVariable", json!({ "name": "R1", "value": "x1234" })),
+        (
+            "readMemory",
+            json!({ "memoryReference": "0x3000", "count": 4 }),
+        ),
+        ("terminate", Value::Null),
+        ("continue", json!({ "threadId": 1 })),
+        ("disconnect", Value::Null),
+    ]);
+    fs::remove_file(path).unwrap();
+
+    assert_eq!(
+        names(&messages),
+        [
+            "response initialize",
+            "event initialized",
+            "response launch",
+            "response setInstructionBreakpoints",
+            "event stopped",
+            "response configurationDone",
+            "response evaluate",
+            "response continue",
+            "response evaluate",
+            "event output",
+            "event stopped",
+            "response variables",
+            "response setVariable",
+            "response readMemory",
+            "event terminated",
+            "response terminate",
+            "response continue",
+            "response disconnect",
+        ]
+    );
+    let seqs: Vec<u64> = messages
+        .iter()
+        .map(|message| message["seq"].as_u64().unwrap())
+        .collect();
+    assert_eq!(seqs, (1..=messages.len() as u64).collect::<Vec<_>>());
+
+    assert_eq!(
+        response(&messages, 1)["body"]["supportsConfigurationDoneRequest"],
+        true
+    );
+    let breakpoints = &response(&messages, 3)["body"]["breakpoints"];
+    assert_eq!(breakpoints[0]["verified"], true);
+    assert_eq!(breakpoints[1]["verified"], false);
+    assert_eq!(messages[4]["body"]["reason"], "entry");
+    assert_eq!(response(&messages, 5)["body"]["result"], "x3000 (12288)");
+
+    // The input from the debug console is echoed before the breakpoint is hit
+    assert_eq!(messages[9]["body"]["output"], "a");
+    assert_eq!(messages[10]["body"]["reason"], "breakpoint");
+    let variables = &response(&messages, 8)["body"]["variables"];
+    assert_eq!(variables[0]["value"], "x0061 (97)");
+    assert_eq!(variables[8]["value"], "x3002 (12290)");
+    assert_eq!(response(&messages, 9)["body"]["value"], "x1234");
+    assert_eq!(response(&messages, 10)["body"]["data"], "8CDwIQ==");
+
+    // A terminated program can't be resumed
+    let resumed = response(&messages, 12);
+    assert_eq!(resumed["success"], false);
+    assert_eq!(resumed["message"], "The program is not running");
+}
+
+#[test]
+fn reports_halts_and_unknown_requests() {
+    let path = object_file("halt", ECHO);
+    let messages = session(&[
+        ("launch", json!({ "program": path.to_str().unwrap() })),
+        ("configurationDone", Value::Null),
+        ("evaluate", json!({ "expression": "q", "context": "repl" })),
+        ("stepBack", Value::Null),
+        ("launch", json!({ "program": "/nonexistent/program.obj" })),
+    ]);
+    fs::remove_file(path).unwrap();
+
+    assert_eq!(
+        names(&messages),
+        [
+            "response launch",
+            "response configurationDone",
+            "response evaluate",
+            "event output",
+            "event exited",
+            "event terminated",
+            "response stepBack",
+            "response launch",
+        ]
+    );
+    assert_eq!(messages[3]["body"]["output"], "qHALT");
+    assert_eq!(messages[4]["body"]["exitCode"], 0);
+    assert_eq!(
+        response(&messages, 4)["message"],
+        "Unsupported request `stepBack`"
+    );
+    let failed = response(&messages, 5);
+    assert_eq!(failed["success"], false);
+    assert!(failed["message"]
+        .as_str()
+        .unwrap()
+        .starts_with("Error while opening /nonexistent/program.obj"));
+}
+
+#[test]
+fn reads_memory_within_bounds() {
+    let path = object_file("memory", ECHO);
+    let messages = session(&[
+        ("launch", json!({ "program": path.to_str().unwrap() })),
+        (
+            "readMemory",
+            json!({ "memoryReference": "xFFFE", "count": u64::MAX }),
+        ),
+        (
+            "readMemory",
+            json!({ "memoryReference": "x3001", "offset": -2, "count": 2 }),
+        ),
+        (
+            "readMemory",
+            json!({ "memoryReference": "x3000", "offset": 1, "count": 2 }),
+        ),
+        (
+            "readMemory",
+            json!({ "memoryReference": "x0000", "offset": -2, "count": 2 }),
+        ),
+    ]);
+    fs::remove_file(path).unwrap();
+
+    // The count is capped at the size of the memory
+    let end = &response(&messages, 2)["body"];
+    assert_eq!(end["data"], "AAAAAA==");
+    assert_eq!(end["unreadableBytes"], 2 * 65536 - 4);
+
+    let before = &response(&messages, 3)["body"];
+    assert_eq!(before["address"], "0x3000");
+    assert_eq!(before["data"], "8CA=");
+
+    let odd = response(&messages, 4);
+    assert_eq!(odd["success"], false);
+    assert_eq!(odd["message"], "Odd byte offset: 1");
+
+    assert_eq!(response(&messages, 5)["body"]["unreadableBytes"], 2);
+}
@@ -0,0 +1,224 @@
+//! Symbol and source line information of assembled programs
+//!
+//! The LC-3 assemblers write a symbol table (`.sym`) and optionally a listing (`.lst`) next to
+//! the object file. [`DebugInfo`] reads both to map between addresses, labels and the lines of
+//! the assembly source.
+//!
+//! Symbol tables are expected in the `lc3as` format, where each symbol is a comment line with
+//! the label followed by its hexadecimal address:
+//!
+//! ```plain
+//! //    Symbol Name       Page Address
+//! //    ----------------  ------------
+//! //    START             3000
+//! ```
+//!
+//! Listings are expected in the format of the textbook's assembler, where each line that emits
+//! a word starts with the address, the word in hexadecimal and binary, and the source line
+//! number:
+//!
+//! ```plain
+//!  (3000) E002  1110000000000010 (   2)                 LEA   R0 HELLO
+//! ```
+//!
+//! Words emitted by lines without a directive (like `.FILL` or `.STRINGZ`) are instructions.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Mapping between addresses, symbols and source lines of a program
+#[derive(Debug, Clone, Default)]
+pub struct DebugInfo {
+    /// Symbols by address; if several labels share an address, the first one is kept
+    symbols: BTreeMap<u16, String>,
+    /// Addresses by symbol
+    addresses: HashMap<String, u16>,
+    /// 1-based source line numbers by address
+    lines: BTreeMap<u16, usize>,
+    /// Instruction words by address, excluding the data emitted by directives
+    instructions: BTreeMap<u16, u16>,
+    /// Path of the assembly source, if it exists
+    source: Option<PathBuf>,
+}
+
+impl DebugInfo {
+    /// Creates an empty `DebugInfo` without any symbols or source lines
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads the symbol table, listing and source path that belong to the given program
+    ///
+    /// The `program` may be the path of the object file or the assembly source; the other files
+    /// are looked up by replacing its extension with `sym`, `lst` and `asm`. Missing files are
+    /// skipped, so the result may be empty.
+    pub fn load(program: &Path) -> io::Result<Self> {
+        let mut info = Self::new();
+        if let Some(text) = read_if_exists(&program.with_extension("sym"))? {
+            info.parse_symbols(&text);
+        }
+        if let Some(text) = read_if_exists(&program.with_extension("lst"))? {
+            info.parse_listing(&text);
+        }
+        let source = program.with_extension("asm");
+        if source.is_file() {
+            info.source = Some(source);
+        }
+        Ok(info)
+    }
+
+    /// Adds the symbols of an `lc3as` symbol table
+    pub fn parse_symbols(&mut self, text: &str) {
+        for line in text.lines() {
+            let mut fields = line.trim_start_matches('/').split_whitespace();
+            if let (Some(name), Some(address), None) = (fields.next(), fields.next(), fields.next())
+            {
+                if let Ok(address) = u16::from_str_radix(address, 16) {
+                    self.add_symbol(name, address);
+                }
+            }
+        }
+    }
+
+    /// Adds the source lines of an assembler listing
+    pub fn parse_listing(&mut self, text: &str) {
+        // Line number of the last directive, whose further words are listed without source text
+        let mut directive_line = None;
+        for line in text.lines() {
+            // The `.ORIG` line "emits" the origin at address x0000
+            if line.to_ascii_uppercase().contains(".ORIG") {
+                continue;
+            }
+            if let Some(listed) = parse_listing_line(line) {
+                self.lines.insert(listed.address, listed.line_number);
+                let is_directive = if listed.source.trim().is_empty() {
+                    directive_line == Some(listed.line_number)
+                } else {
+                    let code = listed.source.split(';').next().unwrap_or_default();
+                    code.split_whitespace().any(|token| token.starts_with('.'))
+                };
+                if is_directive {
+                    directive_line = Some(listed.line_number);
+                } else {
+                    self.instructions.insert(listed.address, listed.word);
+                }
+            }
+        }
+    }
+
+    /// Adds a symbol for the given `address`
+    pub fn add_symbol(&mut self, name: &str, address: u16) {
+        self.symbols
+            .entry(address)
+            .or_insert_with(|| name.to_string());
+        self.addresses.insert(name.to_string(), address);
+    }
+
+    /// Returns the path of the assembly source, if it is known
+    pub fn source(&self) -> Option<&Path> {
+        self.source.as_deref()
+    }
+
+    /// Returns the address of the given symbol
+    pub fn address_of(&self, symbol: &str) -> Option<u16> {
+        self.addresses.get(symbol).copied()
+    }
+
+    /// Returns the symbol at exactly the given `address`
+    pub fn symbol_at(&self, address: u16) -> Option<&str> {
+        self.symbols.get(&address).map(String::as_str)
+    }
+
+    /// Returns the closest symbol at or before the given `address` together with its address
+    ///
+    /// This is the label of the routine or data block that (most likely) contains `address`.
+    pub fn symbol_containing(&self, address: u16) -> Option<(&str, u16)> {
+        self.symbols
+            .range(..=address)
+            .next_back()
+            .map(|(&address, name)| (name.as_str(), address))
+    }
+
+    /// Returns all symbols ordered by address
+    pub fn symbols(&self) -> impl Iterator<Item = (u16, &str)> {
+        self.symbols
+            .iter()
+            .map(|(&address, name)| (address, name.as_str()))
+    }
+
+    /// Returns the source line of the word at the given `address`
+    pub fn line_of(&self, address: u16) -> Option<usize> {
+        self.lines.get(&address).copied()
+    }
+
+    /// Returns the address of the first word emitted by the given source line or, if the line
+    /// does not emit any words, by the closest following line
+    pub fn address_of_line(&self, line: usize) -> Option<u16> {
+        self.lines
+            .iter()
+            .filter(|(_, &line_number)| line_number >= line)
+            .min_by_key(|(&address, &line_number)| (line_number, address))
+            .map(|(&address, _)| address)
+    }
+
+    /// Returns the instruction word at the given `address` if the listing shows an instruction
+    /// (and not data) there
+    pub fn instruction_at(&self, address: u16) -> Option<u16> {
+        self.instructions.get(&address).copied()
+    }
+
+    /// Returns the addresses and words of all instructions in the listing, ordered by address
+    pub fn instructions(&self) -> impl Iterator<Item = (u16, u16)> + '_ {
+        self.instructions
+            .iter()
+            .map(|(&address, &word)| (address, word))
+    }
+
+    /// Returns all addresses with a known source line, ordered by address
+    pub fn lines(&self) -> impl Iterator<Item = (u16, usize)> + '_ {
+        self.lines
+            .iter()
+            .map(|(&address, &line_number)| (address, line_number))
+    }
+}
+
+fn read_if_exists(path: &Path) -> io::Result<Option<String>> {
+    match fs::read_to_string(path) {
+        Ok(text) => Ok(Some(text)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Word of a program as shown by a listing line
+struct ListedWord<'a> {
+    address: u16,
+    word: u16,
+    line_number: usize,
+    /// Source text of the line, which may be empty for further words of a directive
+    source: &'a str,
+}
+
+/// Parses a listing line like ` (3000) E002  1110000000000010 (   2)  LEA R0 HELLO`
+fn parse_listing_line(line: &str) -> Option<ListedWord<'_>> {
+    let rest = line.trim_start().strip_prefix('(')?;
+    let (address, rest) = rest.split_once(')')?;
+    let address = u16::from_str_radix(address.trim(), 16).ok()?;
+    let mut fields = rest.split_whitespace();
+    let word = fields.next().filter(|word| word.len() == 4)?;
+    let word = u16::from_str_radix(word, 16).ok()?;
+    let _binary = fields
+        .next()
+        .filter(|binary| binary.len() == 16 && binary.bytes().all(|b| b == b'0' || b == b'1'))?;
+    let rest = rest.split_once('(')?.1;
+    let (line_number, source) = rest.split_once(')')?;
+    let line_number = line_number.trim().parse().ok()?;
+    Some(ListedWord {
+        address,
+        word,
+        line_number,
+        source,
+    })
+}
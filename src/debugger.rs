@@ -0,0 +1,353 @@
+//! Interactive command line debugger
+//!
+//! The debugger reads commands line by line from stdin and controls a [`Vm`] through its
+//! embedding API. Type `help` at the `(lc3)` prompt for a list of the supported commands.
+//!
+//! Addresses and values can be given in LC-3 hexadecimal (`x3000`), C-style hexadecimal
+//! (`0x3000`) or decimal (`12288`) notation.
+
+use crate::vm::{parse_word, StopReason, Vm, WatchKind, Watchpoint};
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, IsTerminal, Write};
+use std::ops::RangeInclusive;
+
+const HELP: &str = "\
+Commands:
+  s, step [COUNT]             execute COUNT instructions (default 1)
+  c, continue                 run until a breakpoint, watchpoint or HALT
+  rs, reverse-step [COUNT]    undo COUNT instructions (default 1)
+  rc, reverse-continue        undo instructions until a breakpoint or watchpoint
+  b, break ADDR               set a breakpoint
+  d, delete ADDR              remove a breakpoint
+  w, watch KIND ADDR[:END]    watch an address range; KIND is read, write or change
+  unwatch KIND ADDR[:END]     remove a watchpoint
+  i, info                     list breakpoints and watchpoints
+  r, regs                     print the registers
+  x ADDR [COUNT]              print COUNT memory words (default 8)
+  save FILE                   save a snapshot of the machine state
+  load FILE                   restore a snapshot of the machine state
+  h, help                     print this help
+  q, quit                     exit the debugger";
+
+const START_OF_HISTORY: &str =
+    "Reached the beginning of the recorded history (is the history enabled?)";
+
+/// Interactive debugger for a [`Vm`] with a loaded program
+pub struct Debugger {
+    vm: Vm,
+    /// Whether the program halted and can not be continued anymore
+    halted: bool,
+}
+
+impl Debugger {
+    /// Creates a new `Debugger` for the given `vm`
+    pub fn new(vm: Vm) -> Self {
+        Self { vm, halted: false }
+    }
+
+    /// Reads and executes commands from stdin until `quit` or the end of input
+    pub fn run(&mut self) {
+        let stdin = io::stdin();
+        loop {
+            print!("(lc3) ");
+            io::stdout().flush().expect("Error while flushing stdout");
+
+            let mut line = String::new();
+            match stdin.lock().read_line(&mut line) {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(e) => panic!("Error while reading command: {}", e),
+            }
+
+            let args: Vec<&str> = line.split_whitespace().collect();
+            match args.split_first() {
+                None => continue,
+                Some((&"q", _)) | Some((&"quit", _)) => break,
+                Some((command, args)) => {
+                    if let Err(message) = self.execute(command, args) {
+                        println!("{}", message);
+                    }
+                }
+            }
+        }
+    }
+
+    fn execute(&mut self, command: &str, args: &[&str]) -> Result<(), String> {
+        match command {
+            "s" | "step" => {
+                let count = match args.first() {
+                    Some(arg) => parse_number(arg)?,
+                    None => 1,
+                };
+                self.step(count)
+            }
+            "c" | "continue" => self.cont(),
+            "rs" | "reverse-step" => {
+                let count = match args.first() {
+                    Some(arg) => parse_number(arg)?,
+                    None => 1,
+                };
+                self.reverse_step(count)
+            }
+            "rc" | "reverse-continue" => {
+                self.halted = false;
+                match self.vm.reverse_continue() {
+                    Some(reason) => self.report(reason),
+                    None => println!("{}", START_OF_HISTORY),
+                }
+                Ok(())
+            }
+            "b" | "break" => {
+                let address = parse_number(required(args, 0, "address")?)?;
+                if self.vm.add_breakpoint(address) {
+                    println!("Breakpoint set at {}", format_address(address));
+                }
+                Ok(())
+            }
+            "d" | "delete" => {
+                let address = parse_number(required(args, 0, "address")?)?;
+                if !self.vm.remove_breakpoint(address) {
+                    return Err(format!("No breakpoint at {}", format_address(address)));
+                }
+                Ok(())
+            }
+            "w" | "watch" => {
+                let watchpoint = parse_watchpoint(args)?;
+                println!(
+                    "Watching {} of {}",
+                    kind_name(watchpoint.kind),
+                    format_range(&watchpoint.range)
+                );
+                self.vm.add_watchpoint(watchpoint);
+                Ok(())
+            }
+            "unwatch" => {
+                let watchpoint = parse_watchpoint(args)?;
+                if !self.vm.remove_watchpoint(&watchpoint) {
+                    return Err("No such watchpoint".to_string());
+                }
+                Ok(())
+            }
+            "i" | "info" => {
+                for address in self.vm.breakpoints() {
+                    println!("breakpoint  {}", format_address(address));
+                }
+                for watchpoint in self.vm.memory().watchpoints() {
+                    println!(
+                        "watchpoint  {} {}",
+                        kind_name(watchpoint.kind),
+                        format_range(&watchpoint.range)
+                    );
+                }
+                Ok(())
+            }
+            "r" | "regs" => {
+                self.print_registers();
+                Ok(())
+            }
+            "x" => {
+                let address = parse_number(required(args, 0, "address")?)?;
+                let count = match args.get(1) {
+                    Some(arg) => parse_number(arg)?,
+                    None => 8,
+                };
+                self.print_memory(address, count);
+                Ok(())
+            }
+            "save" => {
+                let path = required(args, 0, "file")?;
+                File::create(path)
+                    .and_then(|file| self.vm.save_snapshot(BufWriter::new(file)))
+                    .map_err(|e| format!("Cannot save snapshot: {}", e))?;
+                println!("Snapshot saved to {}", path);
+                Ok(())
+            }
+            "load" => {
+                let path = required(args, 0, "file")?;
+                File::open(path)
+                    .and_then(|file| self.vm.load_snapshot(BufReader::new(file)))
+                    .map_err(|e| format!("Cannot load snapshot: {}", e))?;
+                self.halted = false;
+                println!("{}", format_address(self.vm.registers().pc));
+                Ok(())
+            }
+            "h" | "help" => {
+                println!("{}", HELP);
+                Ok(())
+            }
+            _ => Err(format!(
+                "Unknown command `{}`; type `help` for a list of commands",
+                command
+            )),
+        }
+    }
+
+    fn step(&mut self, count: u16) -> Result<(), String> {
+        self.ensure_not_halted()?;
+        for _ in 0..count {
+            if let Some(reason) = self.vm.step() {
+                self.report(reason);
+                return Ok(());
+            }
+        }
+        println!("{}", format_address(self.vm.registers().pc));
+        Ok(())
+    }
+
+    fn reverse_step(&mut self, count: u16) -> Result<(), String> {
+        for _ in 0..count {
+            if !self.vm.step_back() {
+                println!("{}", START_OF_HISTORY);
+                break;
+            }
+            self.halted = false;
+        }
+        println!("{}", format_address(self.vm.registers().pc));
+        Ok(())
+    }
+
+    fn cont(&mut self) -> Result<(), String> {
+        self.ensure_not_halted()?;
+        // Input buffering can only be disabled if stdin is a terminal
+        let reason = match io::stdin().is_terminal() {
+            true => self.vm.run(),
+            false => self.vm.resume(),
+        };
+        self.report(reason);
+        Ok(())
+    }
+
+    fn ensure_not_halted(&self) -> Result<(), String> {
+        if self.halted {
+            Err("The program has halted".to_string())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn report(&mut self, reason: StopReason) {
+        // Program output does not necessarily end with a newline
+        println!();
+        match reason {
+            StopReason::Halted => {
+                self.halted = true;
+                println!("Program halted");
+            }
+            StopReason::Aborted => println!("Program aborted"),
+            StopReason::Breakpoint(address) => {
+                println!("Breakpoint at {}", format_address(address))
+            }
+            StopReason::WaitingForInput(pc) => println!(
+                "Instruction at {} is waiting for input, but stdin was closed",
+                format_address(pc)
+            ),
+            StopReason::Fault { pc, fault } => {
+                println!("Fault at {}: {}", format_address(pc), fault)
+            }
+            StopReason::LimitExceeded { pc, limit, recent } => {
+                println!("Reached the {} at {}", limit, format_address(pc));
+                println!("Last instructions: {}", recent);
+            }
+            StopReason::Watchpoint(hit) => println!(
+                "Watchpoint ({}) at {} triggered by instruction at {}: {} -> {}",
+                kind_name(hit.kind),
+                format_address(hit.address),
+                format_address(hit.pc),
+                format_address(hit.old_value),
+                format_address(hit.new_value),
+            ),
+        }
+    }
+
+    fn print_registers(&self) {
+        let regs = self.vm.registers();
+        for index in 0..8 {
+            print!("R{} {}  ", index, format_address(regs.read(index)));
+            if index == 3 {
+                println!();
+            }
+        }
+        println!();
+        println!("PC {}  COND {:?}", format_address(regs.pc), regs.cond);
+        println!(
+            "Cycles {} ({:?} simulated)",
+            self.vm.cycles(),
+            self.vm.elapsed()
+        );
+    }
+
+    fn print_memory(&self, address: u16, count: u16) {
+        let mem = self.vm.memory();
+        for offset in 0..count {
+            let address = address.wrapping_add(offset);
+            println!(
+                "{}  {}",
+                format_address(address),
+                format_address(mem.peek(address))
+            );
+        }
+    }
+}
+
+/// Formats a 16-bit value in LC-3 hexadecimal notation (e.g. `x3000`)
+fn format_address(value: u16) -> String {
+    format!("x{:04X}", value)
+}
+
+fn format_range(range: &RangeInclusive<u16>) -> String {
+    if range.start() == range.end() {
+        format_address(*range.start())
+    } else {
+        format!(
+            "{}:{}",
+            format_address(*range.start()),
+            format_address(*range.end())
+        )
+    }
+}
+
+fn kind_name(kind: WatchKind) -> &'static str {
+    match kind {
+        WatchKind::Read => "read",
+        WatchKind::Write => "write",
+        WatchKind::Change => "change",
+    }
+}
+
+fn required<'a>(args: &[&'a str], index: usize, name: &str) -> Result<&'a str, String> {
+    args.get(index)
+        .copied()
+        .ok_or_else(|| format!("Missing argument: {}", name))
+}
+
+/// Parses a number with [`parse_word`]
+fn parse_number(arg: &str) -> Result<u16, String> {
+    parse_word(arg).ok_or_else(|| format!("Invalid number: {}", arg))
+}
+
+fn parse_watchpoint(args: &[&str]) -> Result<Watchpoint, String> {
+    let kind = match required(args, 0, "kind")? {
+        "read" => WatchKind::Read,
+        "write" => WatchKind::Write,
+        "change" => WatchKind::Change,
+        other => {
+            return Err(format!(
+                "Invalid watchpoint kind `{}`; expected read, write or change",
+                other
+            ))
+        }
+    };
+    let range = required(args, 1, "address range")?;
+    let (start, end) = match range.split_once(':') {
+        Some((start, end)) => (parse_number(start)?, parse_number(end)?),
+        None => {
+            let address = parse_number(range)?;
+            (address, address)
+        }
+    };
+    if start > end {
+        return Err(format!("Invalid address range: {}", range));
+    }
+    Ok(Watchpoint::new(start..=end, kind))
+}
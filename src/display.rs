@@ -0,0 +1,323 @@
+//! Memory-mapped framebuffer display
+//!
+//! Like the video device of PennSim, the display has 128×124 pixels that are mapped row by row to
+//! the addresses xC000–xFDFF: the pixel at column `x` and row `y` is the word at
+//! `xC000 + 128 * y + x`. Every pixel is a 15-bit RGB color with red in bits 14–10, green in bits
+//! 9–5 and blue in bits 4–0. The framebuffer is ordinary memory, so programs can read it back and
+//! the display only shows what is stored there.
+//!
+//! [`Display`] is a [`Tracer`] that notices writes to the framebuffer and shows a new frame at
+//! most once per interval of instructions: in the terminal with Unicode half blocks in true
+//! color, or as numbered PPM or PNG images in a directory.
+
+use crate::vm::{Memory, TraceEvent, Tracer};
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+
+/// Width of the display in pixels
+pub const DISPLAY_WIDTH: usize = 128;
+
+/// Height of the display in pixels
+pub const DISPLAY_HEIGHT: usize = 124;
+
+/// Addresses of the framebuffer
+pub const DISPLAY_MEMORY: RangeInclusive<u16> = 0xC000..=0xFDFF;
+
+/// Default number of instructions between two frames
+pub const DEFAULT_FRAME_INTERVAL: u64 = 50_000;
+
+/// Pixels of the display at one point in time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisplayImage {
+    /// 15-bit colors, row by row
+    pixels: Vec<u16>,
+}
+
+impl DisplayImage {
+    /// Copies the framebuffer out of `mem`
+    pub fn capture(mem: &Memory) -> Self {
+        Self {
+            pixels: DISPLAY_MEMORY.map(|address| mem.peek(address)).collect(),
+        }
+    }
+
+    /// Returns the 15-bit color of the pixel at column `x` and row `y`
+    pub fn pixel(&self, x: usize, y: usize) -> u16 {
+        self.pixels[y * DISPLAY_WIDTH + x]
+    }
+
+    /// Returns the color of the pixel at column `x` and row `y` with 8 bits per channel
+    pub fn rgb(&self, x: usize, y: usize) -> [u8; 3] {
+        let pixel = self.pixel(x, y);
+        let channel = |shift: u16| {
+            let value = ((pixel >> shift) & 0x1F) as u8;
+            (value << 3) | (value >> 2)
+        };
+        [channel(10), channel(5), channel(0)]
+    }
+
+    /// Returns the pixels as 8-bit RGB triples, row by row
+    fn rgb_bytes(&self) -> Vec<u8> {
+        (0..DISPLAY_HEIGHT)
+            .flat_map(|y| (0..DISPLAY_WIDTH).flat_map(move |x| self.rgb(x, y)))
+            .collect()
+    }
+
+    /// Writes the image as binary PPM (`P6`)
+    pub fn write_ppm(&self, out: &mut impl Write) -> io::Result<()> {
+        write!(out, "P6\n{} {}\n255\n", DISPLAY_WIDTH, DISPLAY_HEIGHT)?;
+        out.write_all(&self.rgb_bytes())
+    }
+
+    /// Writes the image as PNG
+    ///
+    /// The image data is stored without compression, which keeps the encoder small; a frame
+    /// is about 48 KiB.
+    pub fn write_png(&self, out: &mut impl Write) -> io::Result<()> {
+        let mut header = Vec::new();
+        header.extend_from_slice(&(DISPLAY_WIDTH as u32).to_be_bytes());
+        header.extend_from_slice(&(DISPLAY_HEIGHT as u32).to_be_bytes());
+        // 8 bits per channel, RGB, deflate, adaptive filtering, no interlacing
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+        let rgb = self.rgb_bytes();
+        let mut scanlines = Vec::with_capacity(rgb.len() + DISPLAY_HEIGHT);
+        for row in rgb.chunks(DISPLAY_WIDTH * 3) {
+            // Filter type 0 (none)
+            scanlines.push(0);
+            scanlines.extend_from_slice(row);
+        }
+
+        out.write_all(b"\x89PNG\r\n\x1a\n")?;
+        write_png_chunk(out, b"IHDR", &header)?;
+        write_png_chunk(out, b"IDAT", &zlib_stored(&scanlines))?;
+        write_png_chunk(out, b"IEND", &[])
+    }
+
+    /// Draws the image with `▀` half blocks, whose foreground is the upper and background the
+    /// lower pixel, so each of the 62 lines shows two rows of pixels
+    pub fn write_ansi(&self, out: &mut impl Write) -> io::Result<()> {
+        let mut text = String::new();
+        for y in (0..DISPLAY_HEIGHT).step_by(2) {
+            for x in 0..DISPLAY_WIDTH {
+                let [r, g, b] = self.rgb(x, y);
+                let [br, bg, bb] = self.rgb(x, y + 1);
+                text.push_str(&format!(
+                    "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m▀",
+                    r, g, b, br, bg, bb
+                ));
+            }
+            text.push_str("\x1b[0m\r\n");
+        }
+        out.write_all(text.as_bytes())
+    }
+}
+
+/// File format of the frames written to a directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Ppm,
+    Png,
+}
+
+impl ImageFormat {
+    /// Returns the format with the given name (`ppm` or `png`)
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "ppm" => Some(ImageFormat::Ppm),
+            "png" => Some(ImageFormat::Png),
+            _ => None,
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Ppm => "ppm",
+            ImageFormat::Png => "png",
+        }
+    }
+}
+
+enum Output {
+    /// The terminal; the display occupies the top lines and the console output scrolls below
+    Terminal { out: Box<dyn Write>, started: bool },
+    /// Numbered image files in a directory
+    Frames { dir: PathBuf, format: ImageFormat },
+}
+
+/// Tracer that shows the framebuffer whenever it changed
+///
+/// Since tracers can't return errors to the vm, the first error is kept, no more frames are
+/// shown, and the error is returned by [`Display::finish`].
+pub struct Display {
+    output: Output,
+    /// Minimum number of instructions between two frames
+    interval: u64,
+    /// Whether the framebuffer was written since the last frame
+    dirty: bool,
+    /// Number of executed instructions before which no frame is shown
+    next_frame: u64,
+    frames: u64,
+    error: Option<io::Error>,
+}
+
+impl Display {
+    /// Creates a new `Display` that draws to a terminal
+    pub fn terminal(out: Box<dyn Write>) -> Self {
+        Self::new(Output::Terminal {
+            out,
+            started: false,
+        })
+    }
+
+    /// Creates a new `Display` that writes the frames as `frame-000001.png` etc. to `dir`,
+    /// which is created if needed
+    pub fn frames(dir: PathBuf, format: ImageFormat) -> Self {
+        Self::new(Output::Frames { dir, format })
+    }
+
+    fn new(output: Output) -> Self {
+        Self {
+            output,
+            interval: DEFAULT_FRAME_INTERVAL,
+            dirty: false,
+            next_frame: 0,
+            frames: 0,
+            error: None,
+        }
+    }
+
+    /// Sets the minimum number of instructions between two frames
+    pub fn set_interval(&mut self, instructions: u64) {
+        self.interval = instructions;
+    }
+
+    /// Returns the number of frames shown so far
+    pub fn frames_shown(&self) -> u64 {
+        self.frames
+    }
+
+    /// Shows the last changes of the framebuffer in `mem` and returns the first error that
+    /// occurred while showing frames
+    pub fn finish(&mut self, mem: &Memory) -> io::Result<()> {
+        if self.dirty && self.error.is_none() {
+            self.show(mem)?;
+        }
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        if let Output::Terminal { out, started: true } = &mut self.output {
+            // Reset the scrolling region
+            out.write_all(b"\x1b[r")?;
+            out.flush()?;
+        }
+        Ok(())
+    }
+
+    fn show(&mut self, mem: &Memory) -> io::Result<()> {
+        self.dirty = false;
+        self.frames += 1;
+        let image = DisplayImage::capture(mem);
+        match &mut self.output {
+            Output::Terminal { out, started } => {
+                let lines = DISPLAY_HEIGHT / 2;
+                if !*started {
+                    // Clear the screen and let the console output scroll below the display
+                    write!(out, "\x1b[2J\x1b[{}r\x1b[{};1H", lines + 1, lines + 1)?;
+                    *started = true;
+                }
+                // Save the cursor of the console output and restore it afterwards
+                out.write_all(b"\x1b7\x1b[H")?;
+                image.write_ansi(out)?;
+                out.write_all(b"\x1b8")?;
+                out.flush()
+            }
+            Output::Frames { dir, format } => {
+                fs::create_dir_all(&dir)?;
+                let path = dir.join(format!("frame-{:06}.{}", self.frames, format.extension()));
+                let mut file = BufWriter::new(File::create(path)?);
+                match format {
+                    ImageFormat::Ppm => image.write_ppm(&mut file)?,
+                    ImageFormat::Png => image.write_png(&mut file)?,
+                }
+                file.flush()
+            }
+        }
+    }
+}
+
+impl Tracer for Display {
+    fn trace(&mut self, event: &TraceEvent) {
+        if self.error.is_some() {
+            return;
+        }
+        if event
+            .writes
+            .iter()
+            .any(|(address, _)| DISPLAY_MEMORY.contains(address))
+        {
+            self.dirty = true;
+        }
+        if self.dirty && event.instruction >= self.next_frame {
+            self.next_frame = event.instruction + self.interval;
+            if let Err(e) = self.show(event.mem) {
+                self.error = Some(e);
+            }
+        }
+    }
+}
+
+fn write_png_chunk(out: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+    let crc = crc32(kind.iter().chain(data));
+    out.write_all(&crc.to_be_bytes())
+}
+
+/// Wraps `data` in a zlib stream of uncompressed deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    // Deflate with a 32 KiB window and the fastest compression level
+    let mut stream = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xFFFF).peekable();
+    if blocks.peek().is_none() {
+        stream.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        stream.push(last as u8);
+        let len = block.len() as u16;
+        stream.extend_from_slice(&len.to_le_bytes());
+        stream.extend_from_slice(&(!len).to_le_bytes());
+        stream.extend_from_slice(block);
+    }
+    stream.extend_from_slice(&adler32(data).to_be_bytes());
+    stream
+}
+
+fn crc32<'a>(bytes: impl IntoIterator<Item = &'a u8>) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in bytes {
+        a = (a + u32::from(byte)) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
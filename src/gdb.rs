@@ -0,0 +1,485 @@
+//! GDB Remote Serial Protocol (RSP) stub
+//!
+//! The stub lets GDB or any other RSP frontend control a [`Vm`] over a TCP or Unix socket. It
+//! supports reading and writing registers and memory, software breakpoints, write and read
+//! watchpoints, single-stepping, continuing and interrupting a running program.
+//!
+//! LC-3 memory is word-addressed, so addresses in packets are word addresses and lengths count
+//! 16-bit words (GDB's *addressable memory units*). Registers and memory words are transferred
+//! in big-endian byte order. The registers are numbered `R0`..`R7` (0..7), `PC` (8) and `PSR` (9)
+//! and are described to the frontend through `target.xml`.
+//!
+//! Memory writes (`M`) store the words directly like [`Memory::poke`](crate::Memory::poke): they
+//! don't trigger watchpoints or device registers and are not recorded in the execution history.
+
+use crate::vm::{Fault, StopReason, Vm, WatchKind, Watchpoint};
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::TcpListener;
+use std::os::unix::net::{UnixListener, UnixStream};
+
+/// Number of instructions executed between two checks for an interrupt request from the client
+const INTERRUPT_POLL_INTERVAL: usize = 4096;
+
+/// Number of registers exposed to the client (R0..R7, PC, PSR)
+const REGISTER_COUNT: usize = 10;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.lc3.core">
+    <reg name="r0" bitsize="16" type="int16" regnum="0"/>
+    <reg name="r1" bitsize="16" type="int16"/>
+    <reg name="r2" bitsize="16" type="int16"/>
+    <reg name="r3" bitsize="16" type="int16"/>
+    <reg name="r4" bitsize="16" type="int16"/>
+    <reg name="r5" bitsize="16" type="int16"/>
+    <reg name="r6" bitsize="16" type="data_ptr"/>
+    <reg name="r7" bitsize="16" type="code_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="psr" bitsize="16" type="int16"/>
+  </feature>
+</target>
+"#;
+
+/// GDB signal numbers used in stop replies
+mod signal {
+    pub const SIGINT: u8 = 2;
+    pub const SIGILL: u8 = 4;
+    pub const SIGTRAP: u8 = 5;
+    pub const SIGSYS: u8 = 12;
+    pub const SIGXCPU: u8 = 24;
+}
+
+/// Byte stream to a single RSP client
+trait Connection: Read + Write {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+}
+
+impl Connection for std::net::TcpStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        std::net::TcpStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+impl Connection for UnixStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        UnixStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+/// Outcome of handling a single packet
+enum Action {
+    Reply(String),
+    /// Reply and end the session
+    Close(String),
+}
+
+/// GDB remote stub serving a single client connection
+pub struct GdbStub {
+    vm: Vm,
+    conn: Box<dyn Connection>,
+    /// Bytes received from the client, but not processed yet
+    received: VecDeque<u8>,
+    /// Whether the client enabled `QStartNoAckMode`
+    no_ack: bool,
+    /// Whether the program halted, after which it can not be resumed anymore
+    exited: bool,
+}
+
+impl GdbStub {
+    /// Waits for a single client to connect to the given `address` and returns a stub that
+    /// serves `vm` to it
+    ///
+    /// The `address` is either a TCP socket address like `127.0.0.1:1234` or the path of a Unix
+    /// socket prefixed with `unix:`, like `unix:/tmp/lc3.sock`.
+    pub fn accept(vm: Vm, address: &str) -> io::Result<Self> {
+        let conn: Box<dyn Connection> = match address.strip_prefix("unix:") {
+            Some(path) => Box::new(UnixListener::bind(path)?.accept()?.0),
+            None => Box::new(TcpListener::bind(address)?.accept()?.0),
+        };
+        Ok(Self::with_connection(vm, conn))
+    }
+
+    /// Returns a stub that serves `vm` to the client at the other end of `stream`, e.g. of a
+    /// [`UnixStream::pair`]
+    pub fn from_stream(vm: Vm, stream: UnixStream) -> Self {
+        Self::with_connection(vm, Box::new(stream))
+    }
+
+    fn with_connection(vm: Vm, conn: Box<dyn Connection>) -> Self {
+        Self {
+            vm,
+            conn,
+            received: VecDeque::new(),
+            no_ack: false,
+            exited: false,
+        }
+    }
+
+    /// Handles packets until the client detaches, kills the program or disconnects; returns the
+    /// vm in its final state
+    pub fn serve(mut self) -> io::Result<Vm> {
+        while let Some(packet) = self.read_packet()? {
+            match self.handle(&packet)? {
+                Action::Reply(reply) => self.write_packet(&reply)?,
+                Action::Close(reply) => {
+                    self.write_packet(&reply)?;
+                    break;
+                }
+            }
+        }
+        Ok(self.vm)
+    }
+
+    fn handle(&mut self, packet: &str) -> io::Result<Action> {
+        let command = packet.get(..1).unwrap_or_default();
+        let args = packet.get(1..).unwrap_or_default();
+        let reply = match command {
+            "?" => self.stop_reply(None),
+            "g" => (0..REGISTER_COUNT)
+                .map(|index| hex_word(self.read_register(index)))
+                .collect(),
+            "G" => match parse_words(args) {
+                Some(values) if values.len() == REGISTER_COUNT => {
+                    for (index, value) in values.into_iter().enumerate() {
+                        self.write_register(index, value);
+                    }
+                    "OK".to_string()
+                }
+                _ => "E01".to_string(),
+            },
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(index) if index < REGISTER_COUNT => hex_word(self.read_register(index)),
+                _ => "E01".to_string(),
+            },
+            "P" => match args
+                .split_once('=')
+                .map(|(index, value)| (usize::from_str_radix(index, 16), parse_words(value)))
+            {
+                Some((Ok(index), Some(value))) if index < REGISTER_COUNT && value.len() == 1 => {
+                    self.write_register(index, value[0]);
+                    "OK".to_string()
+                }
+                _ => "E01".to_string(),
+            },
+            "m" => match parse_address_length(args) {
+                Some((address, length)) => (0..length)
+                    .map(|offset| hex_word(self.vm.memory().peek(address.wrapping_add(offset))))
+                    .collect(),
+                None => "E01".to_string(),
+            },
+            "M" => match args
+                .split_once(':')
+                .and_then(|(range, data)| Some((parse_address_length(range)?, parse_words(data)?)))
+            {
+                Some(((address, length), values)) if values.len() == length as usize => {
+                    // Like a debugger's memory writes, this bypasses watchpoints and devices
+                    for (offset, value) in values.into_iter().enumerate() {
+                        let address = address.wrapping_add(offset as u16);
+                        self.vm.memory_mut().poke(address, value);
+                    }
+                    "OK".to_string()
+                }
+                _ => "E01".to_string(),
+            },
+            "Z" | "z" => self.handle_breakpoint(command == "Z", args),
+            "s" | "c" => {
+                if self.exited {
+                    "E01".to_string()
+                } else {
+                    if let Ok(address) = u16::from_str_radix(args, 16) {
+                        self.vm.registers_mut().pc = address;
+                    }
+                    let reason = if command == "s" {
+                        self.vm.step()
+                    } else {
+                        Some(self.cont()?)
+                    };
+                    self.stop_reply(reason)
+                }
+            }
+            "b" => match args {
+                "s" => {
+                    if self.vm.step_back() {
+                        self.exited = false;
+                        self.stop_reply(None)
+                    } else {
+                        format!("T{:02x}replaylog:begin;", signal::SIGTRAP)
+                    }
+                }
+                "c" => {
+                    let history_len = self.vm.history_len();
+                    let reason = self.vm.reverse_continue();
+                    if self.vm.history_len() < history_len {
+                        self.exited = false;
+                    }
+                    match reason {
+                        Some(reason) => self.stop_reply(Some(reason)),
+                        None => format!("T{:02x}replaylog:begin;", signal::SIGTRAP),
+                    }
+                }
+                _ => String::new(),
+            },
+            "k" => return Ok(Action::Close(String::new())),
+            "D" => return Ok(Action::Close("OK".to_string())),
+            "H" => "OK".to_string(),
+            "q" | "Q" => self.handle_query(packet),
+            _ => String::new(),
+        };
+        Ok(Action::Reply(reply))
+    }
+
+    fn handle_query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            "PacketSize=4000;qXfer:features:read+;QStartNoAckMode+;ReverseStep+;ReverseContinue+"
+                .to_string()
+        } else if packet == "QStartNoAckMode" {
+            // The acknowledgement for this packet is still sent
+            self.no_ack = true;
+            "OK".to_string()
+        } else if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            match parse_address_length(range) {
+                Some((offset, length)) => {
+                    let start = (offset as usize).min(TARGET_XML.len());
+                    let end = (start + length as usize).min(TARGET_XML.len());
+                    let prefix = if end == TARGET_XML.len() { 'l' } else { 'm' };
+                    format!("{}{}", prefix, &TARGET_XML[start..end])
+                }
+                None => "E01".to_string(),
+            }
+        } else if packet == "qAttached" {
+            "1".to_string()
+        } else if packet == "qC" {
+            "QC1".to_string()
+        } else if packet == "qfThreadInfo" {
+            "m1".to_string()
+        } else if packet == "qsThreadInfo" {
+            "l".to_string()
+        } else {
+            String::new()
+        }
+    }
+
+    /// Handles `Z`/`z` packets, which insert or remove breakpoints and watchpoints
+    fn handle_breakpoint(&mut self, insert: bool, args: &str) -> String {
+        let mut parts = args.split(',');
+        let (kind, address, length) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(kind), Some(address), Some(length)) => (
+                kind,
+                u16::from_str_radix(address, 16),
+                u16::from_str_radix(length, 16),
+            ),
+            _ => return "E01".to_string(),
+        };
+        let (address, length) = match (address, length) {
+            (Ok(address), Ok(length)) => (address, length.max(1)),
+            _ => return "E01".to_string(),
+        };
+        let watch_kind = match kind {
+            // Software and hardware breakpoints are the same thing for the vm
+            "0" | "1" => {
+                if insert {
+                    self.vm.add_breakpoint(address);
+                } else {
+                    self.vm.remove_breakpoint(address);
+                }
+                return "OK".to_string();
+            }
+            "2" => WatchKind::Write,
+            "3" => WatchKind::Read,
+            // Access watchpoints are not supported
+            _ => return String::new(),
+        };
+        let watchpoint = Watchpoint::new(address..=address.saturating_add(length - 1), watch_kind);
+        if insert {
+            self.vm.add_watchpoint(watchpoint);
+        } else {
+            self.vm.remove_watchpoint(&watchpoint);
+        }
+        "OK".to_string()
+    }
+
+    /// Continues execution until the vm stops or the client sends an interrupt request
+    ///
+    /// An interrupt request is reported as [`StopReason::Aborted`].
+    fn cont(&mut self) -> io::Result<StopReason> {
+        self.vm.start_timeout();
+        // A breakpoint at the current PC is stepped over
+        if let Some(reason) = self.vm.step() {
+            return Ok(reason);
+        }
+        loop {
+            for _ in 0..INTERRUPT_POLL_INTERVAL {
+                let pc = self.vm.registers().pc;
+                if self.vm.has_breakpoint(pc) {
+                    return Ok(StopReason::Breakpoint(pc));
+                }
+                if let Some(reason) = self.vm.step() {
+                    return Ok(reason);
+                }
+            }
+            if self.poll_interrupt()? {
+                return Ok(StopReason::Aborted);
+            }
+        }
+    }
+
+    /// Returns the stop reply packet for the given reason
+    ///
+    /// `None` means that the program was stopped after a single-step or has not run yet.
+    fn stop_reply(&mut self, reason: Option<StopReason>) -> String {
+        match reason {
+            None if self.exited => "W00".to_string(),
+            None => format!("S{:02x}", signal::SIGTRAP),
+            Some(StopReason::Halted) => {
+                self.exited = true;
+                "W00".to_string()
+            }
+            Some(StopReason::Aborted) | Some(StopReason::WaitingForInput(_)) => {
+                format!("S{:02x}", signal::SIGINT)
+            }
+            Some(StopReason::Breakpoint(_)) => format!("T{:02x}swbreak:;", signal::SIGTRAP),
+            Some(StopReason::Watchpoint(hit)) => {
+                let name = match hit.kind {
+                    WatchKind::Read => "rwatch",
+                    WatchKind::Write | WatchKind::Change => "watch",
+                };
+                format!("T{:02x}{}:{:04x};", signal::SIGTRAP, name, hit.address)
+            }
+            Some(StopReason::Fault { fault, .. }) => match fault {
+                Fault::IllegalOpcode(_) => format!("S{:02x}", signal::SIGILL),
+                Fault::UnsupportedTrap(_) => format!("S{:02x}", signal::SIGSYS),
+            },
+            Some(StopReason::LimitExceeded { .. }) => format!("S{:02x}", signal::SIGXCPU),
+        }
+    }
+
+    fn read_register(&self, index: usize) -> u16 {
+        let regs = self.vm.registers();
+        match index {
+            0..=7 => regs.read(index as u16),
+            8 => regs.pc,
+            _ => regs.psr(),
+        }
+    }
+
+    fn write_register(&mut self, index: usize, value: u16) {
+        let regs = self.vm.registers_mut();
+        match index {
+            0..=7 => regs.write(index as u16, value),
+            8 => regs.pc = value,
+            _ => regs.set_psr(value),
+        }
+    }
+
+    /// Checks without blocking whether the client sent an interrupt request (`0x03`)
+    fn poll_interrupt(&mut self) -> io::Result<bool> {
+        if self.received.contains(&0x03) {
+            self.received.retain(|&byte| byte != 0x03);
+            return Ok(true);
+        }
+        self.conn.set_nonblocking(true)?;
+        let mut buffer = [0; 64];
+        let result = self.conn.read(&mut buffer);
+        self.conn.set_nonblocking(false)?;
+        match result {
+            Ok(0) => Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(count) => {
+                let interrupted = buffer[..count].contains(&0x03);
+                self.received
+                    .extend(buffer[..count].iter().filter(|&&byte| byte != 0x03));
+                Ok(interrupted)
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Returns the next byte from the client or `None` if the connection was closed
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        if self.received.is_empty() {
+            let mut buffer = [0; 1024];
+            let count = self.conn.read(&mut buffer)?;
+            if count == 0 {
+                return Ok(None);
+            }
+            self.received.extend(&buffer[..count]);
+        }
+        Ok(self.received.pop_front())
+    }
+
+    /// Reads the next packet with a valid checksum; returns `None` if the connection was closed
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            // Skip acknowledgements and stray interrupt requests until the start of a packet
+            match self.read_byte()? {
+                Some(b'$') => {}
+                Some(_) => continue,
+                None => return Ok(None),
+            }
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    Some(b'#') => break,
+                    Some(byte) => data.push(byte),
+                    None => return Ok(None),
+                }
+            }
+            let mut checksum = [0; 2];
+            for digit in checksum.iter_mut() {
+                *digit = match self.read_byte()? {
+                    Some(byte) => byte,
+                    None => return Ok(None),
+                };
+            }
+
+            let expected = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|checksum| u8::from_str_radix(checksum, 16).ok());
+            if expected == Some(checksum_of(&data)) {
+                if !self.no_ack {
+                    self.conn.write_all(b"+")?;
+                }
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+            } else if !self.no_ack {
+                self.conn.write_all(b"-")?;
+            }
+        }
+    }
+
+    fn write_packet(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+        self.conn.write_all(packet.as_bytes())?;
+        self.conn.flush()
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, &byte| sum.wrapping_add(byte))
+}
+
+fn hex_word(value: u16) -> String {
+    format!("{:04x}", value)
+}
+
+/// Parses a sequence of big-endian 16-bit words encoded as hexadecimal digits
+fn parse_words(hex: &str) -> Option<Vec<u16>> {
+    if !hex.len().is_multiple_of(4) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(4)
+        .map(|index| u16::from_str_radix(&hex[index..index + 4], 16).ok())
+        .collect()
+}
+
+/// Parses an `ADDR,LENGTH` pair of hexadecimal numbers
+fn parse_address_length(args: &str) -> Option<(u16, u16)> {
+    let (address, length) = args.split_once(',')?;
+    Some((
+        u16::from_str_radix(address, 16).ok()?,
+        u16::from_str_radix(length, 16).ok()?,
+    ))
+}
@@ -0,0 +1,703 @@
+//! Autograder that runs a program against declarative test cases
+//!
+//! A test file is TOML, or YAML if its extension is `.yaml` or `.yml`. It names the program
+//! (relative to the test file), optional default limits and a list of cases. Each case runs the
+//! program on a fresh [`Vm`] with the given registers, memory and keyboard input, and checks
+//! how the program stopped, its complete output (including the `HALT` message of the halt
+//! trap), registers and memory ranges:
+//!
+//! ```toml
+//! name = "sum"
+//! program = "sum.obj"
+//! max_instructions = 100000
+//!
+//! [[case]]
+//! name = "small numbers"
+//! registers = { R1 = 3, R2 = "x0004" }
+//! memory = { x4000 = [1, 2, "#-3"] }
+//! input = "y"
+//!
+//! [case.expect]
+//! output = "Sum: 7\nHALT"
+//! registers = { R0 = 7 }
+//! memory = { x4100 = [0, 7] }
+//! ```
+//!
+//! Words are integers from -32768 to 65535 or strings like `x3000`, `0x3000`, `#-5` and `12`.
+//! Registers are `R0` to `R7`, `PC` and `PSR`. A memory entry is a single word or a list of
+//! words stored from its address on.
+//!
+//! The limits `max_instructions`, `max_cycles`, `timeout` (in seconds) and `max_output` can be
+//! given for the whole file and for each case. A case without an instruction, cycle or time
+//! limit stops after [`DEFAULT_MAX_INSTRUCTIONS`] instructions. `expect.stop` is `halted` (the
+//! default), `waiting-for-input`, `fault`, `limit` or `any`.
+
+use crate::vm::{parse_word, BufferedConsole, Engine, Limits, StopReason, Vm};
+
+use serde_json::{json, Map, Value};
+
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Instruction limit of cases without an instruction, cycle or time limit
+pub const DEFAULT_MAX_INSTRUCTIONS: u64 = 10_000_000;
+
+/// Test cases of a program
+#[derive(Debug, Clone)]
+pub struct TestSuite {
+    /// Name of the suite in reports
+    pub name: String,
+    /// Program named by the test file, relative to the working directory once loaded with
+    /// [`TestSuite::load`]
+    pub program: Option<PathBuf>,
+    pub cases: Vec<TestCase>,
+}
+
+/// Single run of the program with its initial state and expected results
+#[derive(Debug, Clone)]
+pub struct TestCase {
+    pub name: String,
+    registers: Vec<(Register, u16)>,
+    memory: Vec<(u16, Vec<u16>)>,
+    input: Vec<u8>,
+    limits: Limits,
+    expect: Expectation,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Expectation {
+    stop: ExpectedStop,
+    output: Option<String>,
+    registers: Vec<(Register, u16)>,
+    memory: Vec<(u16, Vec<u16>)>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum ExpectedStop {
+    #[default]
+    Halted,
+    WaitingForInput,
+    Fault,
+    Limit,
+    Any,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Register {
+    General(u16),
+    Pc,
+    Psr,
+}
+
+/// Result of a single test case
+#[derive(Debug, Clone)]
+pub struct CaseResult {
+    pub name: String,
+    /// Descriptions of the differences from the expected results; empty if the case passed
+    pub failures: Vec<String>,
+    /// How the program stopped, or `None` if it could not be loaded
+    pub stop: Option<StopReason>,
+    pub instructions: u64,
+    /// Host time of the run
+    pub time: Duration,
+    pub output: Vec<u8>,
+}
+
+/// Results of all cases of a [`TestSuite`]
+#[derive(Debug, Clone)]
+pub struct TestReport {
+    pub suite: String,
+    pub results: Vec<CaseResult>,
+}
+
+impl TestSuite {
+    /// Reads the test file at `path`; the program path is resolved relative to the file
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        let is_yaml = path
+            .extension()
+            .is_some_and(|extension| extension == "yaml" || extension == "yml");
+        let mut suite = if is_yaml {
+            Self::from_yaml(&text)?
+        } else {
+            Self::from_toml(&text)?
+        };
+        if suite.name.is_empty() {
+            let stem = path.file_stem().unwrap_or_default();
+            suite.name = stem.to_string_lossy().into_owned();
+        }
+        let directory = path.parent().unwrap_or_else(|| Path::new(""));
+        suite.program = suite.program.map(|program| directory.join(program));
+        Ok(suite)
+    }
+
+    /// Parses a test file in TOML
+    pub fn from_toml(text: &str) -> Result<Self, String> {
+        let value = toml::from_str(text).map_err(|e| format!("Invalid TOML: {}", e))?;
+        Self::from_value(&value)
+    }
+
+    /// Parses a test file in YAML
+    pub fn from_yaml(text: &str) -> Result<Self, String> {
+        let value = serde_yaml::from_str(text).map_err(|e| format!("Invalid YAML: {}", e))?;
+        Self::from_value(&value)
+    }
+
+    fn from_value(value: &Value) -> Result<Self, String> {
+        let table = table(value, "test file")?;
+        check_keys(
+            table,
+            &["name", "program", "case", "cases"],
+            true,
+            "test file",
+        )?;
+        let defaults = limits(table, Limits::default(), "test file")?;
+        let cases = match table.get("case").or_else(|| table.get("cases")) {
+            Some(Value::Array(cases)) => cases,
+            Some(_) => return Err("`case` must be a list of tables".to_string()),
+            None => return Err("The test file has no cases".to_string()),
+        };
+        let cases = cases
+            .iter()
+            .enumerate()
+            .map(|(index, case)| TestCase::from_value(case, index, defaults))
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            name: optional_string(table, "name", "test file")?.unwrap_or_default(),
+            program: optional_string(table, "program", "test file")?.map(PathBuf::from),
+            cases,
+        })
+    }
+
+    /// Runs every case with the program image `program` (as in an `.obj` file) on the given
+    /// `engine`
+    pub fn run(&self, program: &[u8], engine: Engine) -> TestReport {
+        TestReport {
+            suite: self.name.clone(),
+            results: self
+                .cases
+                .iter()
+                .map(|case| case.run(program, engine))
+                .collect(),
+        }
+    }
+}
+
+impl TestCase {
+    fn from_value(value: &Value, index: usize, defaults: Limits) -> Result<Self, String> {
+        let context = format!("case {}", index + 1);
+        let table = table(value, &context)?;
+        check_keys(
+            table,
+            &["name", "registers", "memory", "input", "expect"],
+            true,
+            &context,
+        )?;
+        let name = optional_string(table, "name", &context)?.unwrap_or(context);
+        let context = format!("case `{}`", name);
+
+        let mut limits = limits(table, defaults, &context)?;
+        if limits.max_instructions.is_none()
+            && limits.max_cycles.is_none()
+            && limits.timeout.is_none()
+        {
+            limits.max_instructions = Some(DEFAULT_MAX_INSTRUCTIONS);
+        }
+
+        let expect = match table.get("expect") {
+            Some(value) => {
+                let context = format!("{} expect", context);
+                let table = self::table(value, &context)?;
+                check_keys(
+                    table,
+                    &["stop", "output", "registers", "memory"],
+                    false,
+                    &context,
+                )?;
+                Expectation {
+                    stop: match optional_string(table, "stop", &context)?.as_deref() {
+                        None | Some("halted") => ExpectedStop::Halted,
+                        Some("waiting-for-input") => ExpectedStop::WaitingForInput,
+                        Some("fault") => ExpectedStop::Fault,
+                        Some("limit") => ExpectedStop::Limit,
+                        Some("any") => ExpectedStop::Any,
+                        Some(other) => {
+                            return Err(format!(
+                                "{}: invalid stop `{}`; expected halted, waiting-for-input, \
+                                 fault, limit or any",
+                                context, other
+                            ))
+                        }
+                    },
+                    output: optional_string(table, "output", &context)?,
+                    registers: registers(table.get("registers"), &context)?,
+                    memory: memory(table.get("memory"), &context)?,
+                }
+            }
+            None => Expectation::default(),
+        };
+
+        Ok(Self {
+            registers: registers(table.get("registers"), &context)?,
+            memory: memory(table.get("memory"), &context)?,
+            input: optional_string(table, "input", &context)?
+                .unwrap_or_default()
+                .into_bytes(),
+            limits,
+            expect,
+            name,
+        })
+    }
+
+    /// Runs the case on a fresh vm and compares the results
+    pub fn run(&self, program: &[u8], engine: Engine) -> CaseResult {
+        let mut result = CaseResult {
+            name: self.name.clone(),
+            failures: Vec::new(),
+            stop: None,
+            instructions: 0,
+            time: Duration::ZERO,
+            output: Vec::new(),
+        };
+        let console = BufferedConsole::with_input(&self.input);
+        let mut vm = Vm::new();
+        vm.set_console(Box::new(console.clone()));
+        vm.set_engine(engine);
+        if let Err(e) = vm.load_program(program) {
+            result
+                .failures
+                .push(format!("Could not load the program: {}", e));
+            return result;
+        }
+        for &(register, value) in &self.registers {
+            register.write(&mut vm, value);
+        }
+        for (address, words) in &self.memory {
+            for (offset, &word) in words.iter().enumerate() {
+                vm.memory_mut()
+                    .poke(address.wrapping_add(offset as u16), word);
+            }
+        }
+        vm.set_limits(self.limits);
+
+        let started = Instant::now();
+        let stop = vm.resume();
+        result.time = started.elapsed();
+        result.stop = Some(stop);
+        result.instructions = vm.instructions();
+        result.output = console.output();
+
+        let expected_stop = matches!(
+            (self.expect.stop, stop),
+            (ExpectedStop::Any, _)
+                | (ExpectedStop::Halted, StopReason::Halted)
+                | (
+                    ExpectedStop::WaitingForInput,
+                    StopReason::WaitingForInput(_)
+                )
+                | (ExpectedStop::Fault, StopReason::Fault { .. })
+                | (ExpectedStop::Limit, StopReason::LimitExceeded { .. })
+        );
+        if !expected_stop {
+            result.failures.push(format!(
+                "Expected the program to stop with {}, but it {}",
+                self.expect.stop,
+                describe_stop(stop)
+            ));
+        }
+        if let Some(expected) = &self.expect.output {
+            if let Some(diff) = diff_output(expected.as_bytes(), &result.output) {
+                result.failures.push(diff);
+            }
+        }
+        for &(register, expected) in &self.expect.registers {
+            let actual = register.read(&vm);
+            if actual != expected {
+                result.failures.push(format!(
+                    "{}: expected {}, got {}",
+                    register,
+                    format_word(expected),
+                    format_word(actual)
+                ));
+            }
+        }
+        for (address, words) in &self.expect.memory {
+            for (offset, &expected) in words.iter().enumerate() {
+                let address = address.wrapping_add(offset as u16);
+                let actual = vm.memory().peek(address);
+                if actual != expected {
+                    result.failures.push(format!(
+                        "Memory x{:04X}: expected {}, got {}",
+                        address,
+                        format_word(expected),
+                        format_word(actual)
+                    ));
+                }
+            }
+        }
+        result
+    }
+}
+
+impl CaseResult {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+impl TestReport {
+    /// Returns the number of passed cases
+    pub fn passed(&self) -> usize {
+        self.results.iter().filter(|result| result.passed()).count()
+    }
+
+    /// Returns the number of failed cases
+    pub fn failed(&self) -> usize {
+        self.results.len() - self.passed()
+    }
+
+    /// Writes a line per case, the differences of failed cases and a summary
+    pub fn write_text<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        for result in &self.results {
+            let status = if result.passed() { "PASS" } else { "FAIL" };
+            writeln!(
+                writer,
+                "{} {} ({} instructions, {:.3} s)",
+                status,
+                result.name,
+                result.instructions,
+                result.time.as_secs_f64()
+            )?;
+            for failure in &result.failures {
+                for line in failure.lines() {
+                    writeln!(writer, "    {}", line)?;
+                }
+            }
+        }
+        writeln!(
+            writer,
+            "\n{}: {} passed, {} failed",
+            self.suite,
+            self.passed(),
+            self.failed()
+        )
+    }
+
+    /// Writes the results as a JSON object
+    pub fn write_json<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let cases: Vec<Value> = self
+            .results
+            .iter()
+            .map(|result| {
+                json!({
+                    "name": result.name,
+                    "passed": result.passed(),
+                    "failures": result.failures,
+                    "stop": result.stop.map(stop_name),
+                    "instructions": result.instructions,
+                    "time": result.time.as_secs_f64(),
+                    "output": String::from_utf8_lossy(&result.output),
+                })
+            })
+            .collect();
+        let summary = json!({
+            "suite": self.suite,
+            "passed": self.passed(),
+            "failed": self.failed(),
+            "cases": cases,
+        });
+        serde_json::to_writer_pretty(&mut *writer, &summary)?;
+        writeln!(writer)
+    }
+
+    /// Writes the results as a JUnit XML report
+    pub fn write_junit<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let time: Duration = self.results.iter().map(|result| result.time).sum();
+        writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(
+            writer,
+            r#"<testsuite name="{}" tests="{}" failures="{}" time="{:.3}">"#,
+            xml_escape(&self.suite),
+            self.results.len(),
+            self.failed(),
+            time.as_secs_f64()
+        )?;
+        for result in &self.results {
+            writeln!(
+                writer,
+                r#"  <testcase name="{}" classname="{}" time="{:.3}">"#,
+                xml_escape(&result.name),
+                xml_escape(&self.suite),
+                result.time.as_secs_f64()
+            )?;
+            if let Some(first) = result.failures.first() {
+                writeln!(
+                    writer,
+                    r#"    <failure message="{}">{}</failure>"#,
+                    xml_escape(first.lines().next().unwrap_or_default()),
+                    xml_escape(&result.failures.join("\n"))
+                )?;
+            }
+            writeln!(
+                writer,
+                "    <system-out>{}</system-out>",
+                xml_escape(&String::from_utf8_lossy(&result.output))
+            )?;
+            writeln!(writer, "  </testcase>")?;
+        }
+        writeln!(writer, "</testsuite>")
+    }
+}
+
+impl Register {
+    fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_uppercase().as_str() {
+            "PC" => Some(Register::Pc),
+            "PSR" => Some(Register::Psr),
+            name => match name.strip_prefix('R')?.parse() {
+                Ok(index) if index < 8 => Some(Register::General(index)),
+                _ => None,
+            },
+        }
+    }
+
+    fn read(self, vm: &Vm) -> u16 {
+        let regs = vm.registers();
+        match self {
+            Register::General(index) => regs.read(index),
+            Register::Pc => regs.pc,
+            Register::Psr => regs.psr(),
+        }
+    }
+
+    fn write(self, vm: &mut Vm, value: u16) {
+        let regs = vm.registers_mut();
+        match self {
+            Register::General(index) => regs.write(index, value),
+            Register::Pc => regs.pc = value,
+            Register::Psr => regs.set_psr(value),
+        }
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Register::General(index) => write!(f, "R{}", index),
+            Register::Pc => f.write_str("PC"),
+            Register::Psr => f.write_str("PSR"),
+        }
+    }
+}
+
+impl fmt::Display for ExpectedStop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match self {
+            ExpectedStop::Halted => "HALT",
+            ExpectedStop::WaitingForInput => "a read without input",
+            ExpectedStop::Fault => "a fault",
+            ExpectedStop::Limit => "an exceeded limit",
+            ExpectedStop::Any => "anything",
+        };
+        f.write_str(description)
+    }
+}
+
+/// Returns the name of `expect.stop` that matches `reason`
+fn stop_name(reason: StopReason) -> &'static str {
+    match reason {
+        StopReason::Halted => "halted",
+        StopReason::WaitingForInput(_) => "waiting-for-input",
+        StopReason::Fault { .. } => "fault",
+        StopReason::LimitExceeded { .. } => "limit",
+        StopReason::Aborted | StopReason::Breakpoint(_) | StopReason::Watchpoint(_) => "other",
+    }
+}
+
+/// Describes how the program stopped, to complete "it ..."
+fn describe_stop(reason: StopReason) -> String {
+    match reason {
+        StopReason::Halted => "halted".to_string(),
+        StopReason::WaitingForInput(pc) => {
+            format!("waited for more input at x{:04X}", pc)
+        }
+        StopReason::Fault { pc, fault } => format!("faulted at x{:04X}: {}", pc, fault),
+        StopReason::LimitExceeded { pc, limit, recent } => format!(
+            "reached the {} at x{:04X}\nLast instructions: {}",
+            limit, pc, recent
+        ),
+        other => format!("stopped with {:?}", other),
+    }
+}
+
+/// Describes the first difference between the expected and actual output, if any
+fn diff_output(expected: &[u8], actual: &[u8]) -> Option<String> {
+    if expected == actual {
+        return None;
+    }
+    let position = expected
+        .iter()
+        .zip(actual)
+        .position(|(expected, actual)| expected != actual)
+        .unwrap_or_else(|| expected.len().min(actual.len()));
+    let line_start = expected[..position]
+        .iter()
+        .rposition(|&byte| byte == b'\n')
+        .map_or(0, |index| index + 1);
+    let line = expected[..position]
+        .iter()
+        .filter(|&&byte| byte == b'\n')
+        .count();
+    let line_of = |output: &[u8]| {
+        let rest = output.get(line_start..).unwrap_or_default();
+        let end = rest
+            .iter()
+            .position(|&byte| byte == b'\n')
+            .unwrap_or(rest.len());
+        format!("{:?}", String::from_utf8_lossy(&rest[..end]))
+    };
+    Some(format!(
+        "Output differs at line {}, column {} (expected {} bytes, got {}):\n  expected: {}\n  actual:   {}",
+        line + 1,
+        position - line_start + 1,
+        expected.len(),
+        actual.len(),
+        line_of(expected),
+        line_of(actual)
+    ))
+}
+
+fn format_word(value: u16) -> String {
+    format!("x{:04X} ({})", value, value as i16)
+}
+
+/// Escapes text for XML attributes and elements; control characters, which XML 1.0 can't
+/// represent, are written as `\xNN`
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for chr in text.chars() {
+        match chr {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\n' | '\t' | '\r' => escaped.push(chr),
+            chr if chr.is_control() => escaped.push_str(&format!("\\x{:02X}", chr as u32)),
+            chr => escaped.push(chr),
+        }
+    }
+    escaped
+}
+
+fn table<'a>(value: &'a Value, context: &str) -> Result<&'a Map<String, Value>, String> {
+    value
+        .as_object()
+        .ok_or_else(|| format!("{}: expected a table", context))
+}
+
+/// Fails for keys that are not in `allowed` or, if `with_limits`, a limit
+fn check_keys(
+    table: &Map<String, Value>,
+    allowed: &[&str],
+    with_limits: bool,
+    context: &str,
+) -> Result<(), String> {
+    const LIMITS: [&str; 4] = ["max_instructions", "max_cycles", "timeout", "max_output"];
+    let is_allowed = |key: &str| allowed.contains(&key) || with_limits && LIMITS.contains(&key);
+    match table.keys().find(|key| !is_allowed(key)) {
+        Some(key) => Err(format!("{}: unknown key `{}`", context, key)),
+        None => Ok(()),
+    }
+}
+
+fn optional_string(
+    table: &Map<String, Value>,
+    key: &str,
+    context: &str,
+) -> Result<Option<String>, String> {
+    match table.get(key) {
+        None => Ok(None),
+        Some(Value::String(text)) => Ok(Some(text.clone())),
+        Some(_) => Err(format!("{}: `{}` must be a string", context, key)),
+    }
+}
+
+/// Reads the limits of `table`, starting from `defaults`
+fn limits(table: &Map<String, Value>, defaults: Limits, context: &str) -> Result<Limits, String> {
+    let count = |key: &str| match table.get(key) {
+        None => Ok(None),
+        Some(value) => value
+            .as_u64()
+            .map(Some)
+            .ok_or_else(|| format!("{}: `{}` must be a non-negative integer", context, key)),
+    };
+    let timeout = match table.get("timeout") {
+        None => None,
+        Some(value) => Some(
+            value
+                .as_f64()
+                .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+                .ok_or_else(|| format!("{}: `timeout` must be a number of seconds", context))?,
+        ),
+    };
+    Ok(Limits {
+        max_instructions: count("max_instructions")?.or(defaults.max_instructions),
+        max_cycles: count("max_cycles")?.or(defaults.max_cycles),
+        timeout: timeout.or(defaults.timeout),
+        max_output: count("max_output")?.or(defaults.max_output),
+    })
+}
+
+fn registers(value: Option<&Value>, context: &str) -> Result<Vec<(Register, u16)>, String> {
+    let table = match value {
+        Some(value) => table(value, &format!("{} registers", context))?,
+        None => return Ok(Vec::new()),
+    };
+    table
+        .iter()
+        .map(|(name, value)| {
+            let register = Register::parse(name)
+                .ok_or_else(|| format!("{}: unknown register `{}`", context, name))?;
+            Ok((register, word(value, &format!("{} {}", context, name))?))
+        })
+        .collect()
+}
+
+fn memory(value: Option<&Value>, context: &str) -> Result<Vec<(u16, Vec<u16>)>, String> {
+    let table = match value {
+        Some(value) => table(value, &format!("{} memory", context))?,
+        None => return Ok(Vec::new()),
+    };
+    table
+        .iter()
+        .map(|(address, value)| {
+            let context = format!("{} memory {}", context, address);
+            let address =
+                parse_word(address).ok_or_else(|| format!("{}: invalid address", context))?;
+            let words = match value {
+                Value::Array(values) => values
+                    .iter()
+                    .map(|value| word(value, &context))
+                    .collect::<Result<_, _>>()?,
+                value => vec![word(value, &context)?],
+            };
+            Ok((address, words))
+        })
+        .collect()
+}
+
+fn word(value: &Value, context: &str) -> Result<u16, String> {
+    let word = match value {
+        Value::Number(number) => number
+            .as_i64()
+            .filter(|number| (-0x8000..=0xFFFF).contains(number))
+            .map(|number| number as u16),
+        Value::String(text) => parse_word(text),
+        _ => None,
+    };
+    word.ok_or_else(|| format!("{}: invalid word {}", context, value))
+}
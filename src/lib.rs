@@ -1,3 +1,32 @@
+mod coverage;
+mod dap;
+mod debug_info;
+mod debugger;
+mod display;
+mod gdb;
+mod grader;
+mod profile;
+mod trace;
+mod tui;
 mod vm;
 
-pub use vm::Vm;
+pub use coverage::{BranchCounts, Coverage};
+pub use dap::DapServer;
+pub use debug_info::DebugInfo;
+pub use debugger::Debugger;
+pub use display::{
+    Display, DisplayImage, ImageFormat, DEFAULT_FRAME_INTERVAL, DISPLAY_HEIGHT, DISPLAY_MEMORY,
+    DISPLAY_WIDTH,
+};
+pub use gdb::GdbStub;
+pub use grader::{CaseResult, TestCase, TestReport, TestSuite, DEFAULT_MAX_INSTRUCTIONS};
+pub use profile::Profiler;
+pub use trace::{TraceFilter, TraceFormat, TraceRecorder};
+pub use tui::{parse_keys, Frame, Key, Tui};
+pub use vm::{
+    disassemble, parse_word, read_input_log, AccessCounts, BufferedConsole, CallError, CallResult,
+    CallingConvention, CondFlag, Console, Device, DeviceContext, Disk, Engine, Fault, InputEvent,
+    InputRecorder, Interrupt, InvalidOpcode, Limit, Limits, Memory, MemoryChange, Opcode,
+    RecentTrace, Registers, Routine, StopReason, TerminalConsole, TimingModel, TraceEvent, Tracer,
+    Violation, Vm, WatchKind, Watchpoint, WatchpointHit, DISK_ADDRESSES, MAX_CYCLES, SECTOR_WORDS,
+};
@@ -1,17 +1,496 @@
-use lc3_vm::Vm;
+use lc3_vm::{
+    parse_word, read_input_log, Coverage, DapServer, DebugInfo, Debugger, Disk, Display, Engine,
+    GdbStub, ImageFormat, InputRecorder, Limits, Opcode, Profiler, StopReason, TestSuite,
+    TimingModel, TraceFilter, TraceFormat, TraceRecorder, Tui, Vm, DISK_ADDRESSES,
+};
 
+use std::cell::RefCell;
 use std::env;
-use std::fs::File;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+use std::process;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// Default memory budget (in bytes) of the execution history used for reverse debugging
+const DEFAULT_HISTORY_BUDGET: usize = 16 << 20;
 
 fn main() {
-    let path_arg = env::args().nth(1).expect("No file path given");
+    let mut debug = false;
+    let mut tui = false;
+    let mut gdb_address = None;
+    let mut history_budget = DEFAULT_HISTORY_BUDGET;
+    let mut trace_path = None;
+    let mut trace_format = TraceFormat::Text;
+    let mut trace_filter = TraceFilter::new();
+    let mut profile_path = None;
+    let mut folded_path = None;
+    let mut coverage_path = None;
+    let mut timing_path = None;
+    let mut print_cycles = false;
+    let mut print_stats = false;
+    let mut engine = None;
+    let mut limits = Limits::default();
+    let mut resume_path = None;
+    let mut snapshot_path = None;
+    let mut record_input_path = None;
+    let mut replay_input_path = None;
+    let mut coverage_summary_path = None;
+    let mut display_terminal = false;
+    let mut display_frames_path = None;
+    let mut display_format = ImageFormat::Png;
+    let mut display_interval = None;
+    let mut disk_path = None;
+    let mut path_arg = None;
+    let mut args = env::args().skip(1).peekable();
+    if args.next_if_eq("test").is_some() {
+        process::exit(run_tests(args));
+    }
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--debug" => debug = true,
+            "--tui" => tui = true,
+            "--dap" => {
+                DapServer::new()
+                    .serve()
+                    .expect("Error while serving debug adapter");
+                return;
+            }
+            "--gdb" => gdb_address = Some(args.next().expect("No address given for --gdb")),
+            "--history-budget" => {
+                history_budget = args
+                    .next()
+                    .and_then(|budget| budget.parse().ok())
+                    .expect("No valid number of bytes given for --history-budget")
+            }
+            "--trace" => trace_path = Some(args.next().expect("No file path given for --trace")),
+            "--trace-format" => {
+                trace_format = args
+                    .next()
+                    .and_then(|name| TraceFormat::from_name(&name))
+                    .expect("No valid format (text, json or binary) given for --trace-format")
+            }
+            "--trace-range" => trace_filter.add_range(
+                args.next()
+                    .and_then(|range| parse_range(&range))
+                    .expect("No valid address range (START:END) given for --trace-range"),
+            ),
+            "--trace-opcode" => trace_filter.add_opcode(
+                args.next()
+                    .and_then(|name| Opcode::from_mnemonic(&name))
+                    .expect("No valid opcode given for --trace-opcode"),
+            ),
+            "--profile" => {
+                profile_path = Some(args.next().expect("No file path given for --profile"))
+            }
+            "--profile-folded" => {
+                folded_path = Some(
+                    args.next()
+                        .expect("No file path given for --profile-folded"),
+                )
+            }
+            "--coverage" => {
+                coverage_path = Some(args.next().expect("No file path given for --coverage"))
+            }
+            "--coverage-summary" => {
+                coverage_summary_path = Some(
+                    args.next()
+                        .expect("No file path given for --coverage-summary"),
+                )
+            }
+            "--timing" => timing_path = Some(args.next().expect("No file path given for --timing")),
+            "--cycles" => print_cycles = true,
+            "--stats" => print_stats = true,
+            "--engine" => engine = Some(
+                args.next()
+                    .and_then(|name| Engine::from_name(&name))
+                    .expect(
+                    "No valid engine (interpreter, blocks, jit or jit-check) given for --engine",
+                ),
+            ),
+            "--max-instructions" => {
+                limits.max_instructions = Some(
+                    args.next()
+                        .and_then(|count| count.parse().ok())
+                        .expect("No valid number given for --max-instructions"),
+                )
+            }
+            "--max-cycles" => {
+                limits.max_cycles = Some(
+                    args.next()
+                        .and_then(|count| count.parse().ok())
+                        .expect("No valid number given for --max-cycles"),
+                )
+            }
+            "--timeout" => {
+                limits.timeout = Some(
+                    args.next()
+                        .and_then(|seconds| Duration::try_from_secs_f64(seconds.parse().ok()?).ok())
+                        .expect("No valid number of seconds given for --timeout"),
+                )
+            }
+            "--max-output" => {
+                limits.max_output = Some(
+                    args.next()
+                        .and_then(|bytes| bytes.parse().ok())
+                        .expect("No valid number of bytes given for --max-output"),
+                )
+            }
+            "--display" => display_terminal = true,
+            "--display-frames" => {
+                display_frames_path = Some(
+                    args.next()
+                        .expect("No directory given for --display-frames"),
+                )
+            }
+            "--display-format" => {
+                display_format = args
+                    .next()
+                    .and_then(|name| ImageFormat::from_name(&name))
+                    .expect("No valid format (ppm or png) given for --display-format")
+            }
+            "--display-interval" => {
+                display_interval = Some(
+                    args.next()
+                        .and_then(|count| count.parse().ok())
+                        .expect("No valid number of instructions given for --display-interval"),
+                )
+            }
+            "--disk" => disk_path = Some(args.next().expect("No file path given for --disk")),
+            "--resume" => resume_path = Some(args.next().expect("No file path given for --resume")),
+            "--snapshot" => {
+                snapshot_path = Some(args.next().expect("No file path given for --snapshot"))
+            }
+            "--record-input" => {
+                record_input_path =
+                    Some(args.next().expect("No file path given for --record-input"))
+            }
+            "--replay-input" => {
+                replay_input_path =
+                    Some(args.next().expect("No file path given for --replay-input"))
+            }
+            _ => path_arg = Some(arg),
+        }
+    }
+    if path_arg.is_none() && resume_path.is_none() {
+        panic!("No file path given");
+    }
 
     let mut vm = Vm::new();
 
-    let image_file = File::open(path_arg).expect("Error while opening file");
+    if let Some(path) = &path_arg {
+        let image_file = File::open(path).expect("Error while opening file");
+
+        vm.load_program(image_file)
+            .expect("Error while loading program");
+    }
+
+    if let Some(path) = resume_path {
+        let snapshot_file = File::open(path).expect("Error while opening snapshot");
+        vm.load_snapshot(BufReader::new(snapshot_file))
+            .expect("Error while loading snapshot");
+    }
+
+    if let Some(path) = timing_path {
+        let text = fs::read_to_string(path).expect("Error while reading timing model");
+        match TimingModel::parse(&text) {
+            Ok(timing) => vm.set_timing(timing),
+            Err(e) => {
+                eprintln!("Invalid timing model: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+
+    if let Some(engine) = engine {
+        vm.set_engine(engine);
+    }
+
+    if let Some(path) = disk_path {
+        let disk = Disk::open(path).expect("Error while opening disk image");
+        vm.memory_mut().add_device(DISK_ADDRESSES, Box::new(disk));
+    }
+
+    vm.set_limits(limits);
+
+    if debug || gdb_address.is_some() {
+        vm.enable_history(history_budget);
+    }
+
+    if let Some(path) = replay_input_path {
+        let log_file = File::open(path).expect("Error while opening input log");
+        let events =
+            read_input_log(BufReader::new(log_file)).expect("Error while reading input log");
+        vm.replay_input(events);
+    }
+
+    let input_recorder = record_input_path.map(|path| {
+        let log_file = File::create(path).expect("Error while creating input log");
+        let recorder = Rc::new(RefCell::new(InputRecorder::new(Box::new(log_file))));
+        vm.add_tracer(Box::new(Rc::clone(&recorder)));
+        recorder
+    });
+
+    let recorder = trace_path.map(|path| {
+        let file = File::create(path).expect("Error while creating trace file");
+        let mut recorder = TraceRecorder::new(Box::new(file), trace_format);
+        recorder.set_filter(trace_filter);
+        let recorder = Rc::new(RefCell::new(recorder));
+        vm.add_tracer(Box::new(Rc::clone(&recorder)));
+        recorder
+    });
+
+    let profiler = if profile_path.is_some() || folded_path.is_some() {
+        let profiler = Rc::new(RefCell::new(Profiler::new()));
+        vm.add_tracer(Box::new(Rc::clone(&profiler)));
+        Some(profiler)
+    } else {
+        None
+    };
 
-    vm.load_program(image_file)
-        .expect("Error while loading program");
+    let coverage = if coverage_path.is_some() || coverage_summary_path.is_some() {
+        let coverage = Rc::new(RefCell::new(Coverage::new()));
+        vm.add_tracer(Box::new(Rc::clone(&coverage)));
+        Some(coverage)
+    } else {
+        None
+    };
+
+    let display = if display_terminal || display_frames_path.is_some() {
+        let mut display = match display_frames_path {
+            Some(path) => Display::frames(path.into(), display_format),
+            None => Display::terminal(Box::new(std::io::stdout())),
+        };
+        if let Some(interval) = display_interval {
+            display.set_interval(interval);
+        }
+        let display = Rc::new(RefCell::new(display));
+        vm.add_tracer(Box::new(Rc::clone(&display)));
+        Some(display)
+    } else {
+        None
+    };
+
+    let stop_reason = if let Some(address) = gdb_address {
+        GdbStub::accept(vm, &address)
+            .and_then(GdbStub::serve)
+            .expect("Error while serving GDB connection");
+        None
+    } else if debug {
+        Debugger::new(vm).run();
+        None
+    } else if tui {
+        let program = Path::new(path_arg.as_deref().unwrap_or_default());
+        let debug_info = DebugInfo::load(program).expect("Error while loading symbols");
+        Tui::new(vm, debug_info)
+            .run()
+            .expect("Error while running the terminal UI");
+        None
+    } else {
+        let instructions_before = vm.instructions();
+        let trap_time_before = vm.trap_time();
+        let started = Instant::now();
+        let stop_reason = vm.run();
+        let host_time = started.elapsed();
+        if let Some(display) = &display {
+            display
+                .borrow_mut()
+                .finish(vm.memory())
+                .expect("Error while showing the display");
+        }
+        match &snapshot_path {
+            Some(path) if stop_reason != StopReason::Halted => {
+                let snapshot_file = File::create(path).expect("Error while creating snapshot");
+                vm.save_snapshot(BufWriter::new(snapshot_file))
+                    .expect("Error while saving snapshot");
+            }
+            _ => {}
+        }
+        if print_cycles {
+            eprintln!(
+                "\n{} cycles ({:?} at {} Hz)",
+                vm.cycles(),
+                vm.elapsed(),
+                vm.timing().clock_hz()
+            );
+        }
+        if print_stats {
+            let instructions = vm.instructions() - instructions_before;
+            let trap_time = vm.trap_time() - trap_time_before;
+            eprintln!();
+            eprintln!("Instructions  {}", instructions);
+            eprintln!("Host time     {:?}", host_time);
+            eprintln!(
+                "MIPS          {:.2}",
+                instructions as f64 / host_time.as_secs_f64() / 1e6
+            );
+            eprintln!(
+                "Trap time     {:?} ({:.1}%)",
+                trap_time,
+                trap_time.as_secs_f64() * 100.0 / host_time.as_secs_f64()
+            );
+        }
+        Some(stop_reason)
+    };
+
+    if let Some(recorder) = &input_recorder {
+        recorder
+            .borrow_mut()
+            .flush()
+            .expect("Error while writing input log");
+    }
+    if let Some(recorder) = &recorder {
+        recorder
+            .borrow_mut()
+            .flush()
+            .expect("Error while writing trace");
+    }
+    let program = Path::new(path_arg.as_deref().unwrap_or_default());
+    let debug_info = if profiler.is_some() || coverage.is_some() {
+        DebugInfo::load(program).expect("Error while loading symbols")
+    } else {
+        DebugInfo::new()
+    };
+
+    if let Some(profiler) = &profiler {
+        let profiler = profiler.borrow();
+        if let Some(path) = profile_path {
+            let mut file = File::create(path).expect("Error while creating profile");
+            profiler
+                .write_report(&mut file, &debug_info)
+                .expect("Error while writing profile");
+        }
+        if let Some(path) = folded_path {
+            let mut file = File::create(path).expect("Error while creating folded stacks");
+            profiler
+                .write_folded(&mut file, &debug_info)
+                .expect("Error while writing folded stacks");
+        }
+    }
+
+    if let Some(coverage) = &coverage {
+        if debug_info.instructions().next().is_none() {
+            eprintln!("No listing (.lst) found next to the program, so no coverage is reported");
+        }
+        let coverage = coverage.borrow();
+        if let Some(path) = coverage_path {
+            let source = program.with_extension("asm");
+            let mut file = File::create(path).expect("Error while creating coverage file");
+            coverage
+                .write_lcov(
+                    &mut file,
+                    &debug_info,
+                    debug_info.source().unwrap_or(&source),
+                )
+                .expect("Error while writing coverage");
+        }
+        if let Some(path) = coverage_summary_path {
+            let mut file = File::create(path).expect("Error while creating coverage summary");
+            coverage
+                .write_summary(&mut file, &debug_info)
+                .expect("Error while writing coverage summary");
+        }
+    }
+
+    match stop_reason {
+        Some(StopReason::Fault { pc, fault }) => {
+            eprintln!("\nFault at x{:04X}: {}", pc, fault);
+            process::exit(1);
+        }
+        Some(StopReason::WaitingForInput(_)) => {
+            eprintln!("\nThe program is waiting for input, but stdin was closed");
+            process::exit(1);
+        }
+        Some(StopReason::LimitExceeded { pc, limit, recent }) => {
+            eprintln!("\nReached the {} at x{:04X}", limit, pc);
+            eprintln!("Last instructions: {}", recent);
+            process::exit(1);
+        }
+        _ => {}
+    }
+}
+
+/// Runs the `test` subcommand with the arguments after `test`; returns the exit code
+///
+/// The arguments are the test file and `--program FILE`, `--engine NAME`, `--json FILE` and
+/// `--junit FILE`. The exit code is 0 if all cases passed.
+fn run_tests(mut args: impl Iterator<Item = String>) -> i32 {
+    let mut suite_path = None;
+    let mut program_path = None;
+    let mut engine = Engine::BasicBlocks;
+    let mut json_path = None;
+    let mut junit_path = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--program" => {
+                program_path = Some(args.next().expect("No file path given for --program"))
+            }
+            "--engine" => {
+                engine = args
+                    .next()
+                    .and_then(|name| Engine::from_name(&name))
+                    .expect("No valid engine given for --engine")
+            }
+            "--json" => json_path = Some(args.next().expect("No file path given for --json")),
+            "--junit" => junit_path = Some(args.next().expect("No file path given for --junit")),
+            _ => suite_path = Some(arg),
+        }
+    }
+    let suite_path = suite_path.expect("No test file given");
+
+    let suite = match TestSuite::load(Path::new(&suite_path)) {
+        Ok(suite) => suite,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+    let program_path = match program_path
+        .map(Into::into)
+        .or_else(|| suite.program.clone())
+    {
+        Some(path) => path,
+        None => {
+            eprintln!("The test file names no program, so --program is needed");
+            return 2;
+        }
+    };
+    let program = match fs::read(&program_path) {
+        Ok(program) => program,
+        Err(e) => {
+            eprintln!("Could not read {}: {}", program_path.display(), e);
+            return 2;
+        }
+    };
+
+    let report = suite.run(&program, engine);
+    report
+        .write_text(&mut std::io::stdout())
+        .expect("Error while writing results");
+    if let Some(path) = json_path {
+        let mut file = File::create(path).expect("Error while creating JSON summary");
+        report
+            .write_json(&mut file)
+            .expect("Error while writing JSON summary");
+    }
+    if let Some(path) = junit_path {
+        let mut file = File::create(path).expect("Error while creating JUnit report");
+        report
+            .write_junit(&mut file)
+            .expect("Error while writing JUnit report");
+    }
+    if report.failed() == 0 {
+        0
+    } else {
+        1
+    }
+}
 
-    vm.run();
+/// Parses an address range like `x3000:x30FF` (or a single address); the end must not be
+/// below the start
+fn parse_range(range: &str) -> Option<std::ops::RangeInclusive<u16>> {
+    let (start, end) = range.split_once(':').unwrap_or((range, range));
+    let (start, end) = (parse_word(start)?, parse_word(end)?);
+    (start <= end).then_some(start..=end)
 }
@@ -0,0 +1,284 @@
+//! Instruction-level profiler
+//!
+//! [`Profiler`] is a [`Tracer`] that counts how often every address is executed and maintains a
+//! call tree: `JSR` and `JSRR` enter a subroutine at their target address and `RET` (`JMP R7`)
+//! returns to the caller. Each executed instruction is attributed to the subroutine that is
+//! active when it executes, with the first executed address as the root of the tree.
+//!
+//! The profile is written as a text report of the hot functions, addresses and calls, or as
+//! folded stacks (`main;FOO;BAR 42`) that flamegraph tools like `inferno` or `flamegraph.pl`
+//! turn into a flame graph. Addresses are named after the symbols of a [`DebugInfo`].
+
+use crate::debug_info::DebugInfo;
+use crate::vm::{Opcode, TraceEvent, Tracer};
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+
+/// Maximum depth of the call tree; deeper calls are attributed to the deepest subroutine, so
+/// programs that call without ever returning don't grow the tree without bounds
+const MAX_CALL_DEPTH: usize = 256;
+
+/// Number of addresses listed in the hot-spot section of the report
+const HOT_SPOT_LIMIT: usize = 20;
+
+/// Subroutine invocation in the call tree, identified by its path from the root
+#[derive(Debug)]
+struct CallNode {
+    /// Entry address of the subroutine
+    address: u16,
+    parent: Option<usize>,
+    depth: usize,
+    /// Child nodes by entry address
+    children: HashMap<u16, usize>,
+    /// Number of instructions executed directly in this subroutine
+    count: u64,
+}
+
+impl CallNode {
+    fn new(address: u16, parent: Option<usize>, depth: usize) -> Self {
+        Self {
+            address,
+            parent,
+            depth,
+            children: HashMap::new(),
+            count: 0,
+        }
+    }
+}
+
+/// Tracer that counts executed instructions per address and per call stack
+#[derive(Debug)]
+pub struct Profiler {
+    /// Number of executions of every address
+    counts: Vec<u64>,
+    /// Call tree; the root is at index 0
+    nodes: Vec<CallNode>,
+    /// Index of the node of the active subroutine
+    current: usize,
+    /// Number of active calls beyond [`MAX_CALL_DEPTH`], whose returns don't leave a node
+    unrecorded_calls: usize,
+    /// Number of calls by caller and callee entry address
+    calls: BTreeMap<(u16, u16), u64>,
+    total: u64,
+}
+
+impl Profiler {
+    /// Creates a new empty `Profiler`
+    pub fn new() -> Self {
+        Self {
+            counts: vec![0; 1 << 16],
+            nodes: vec![CallNode::new(0, None, 0)],
+            current: 0,
+            unrecorded_calls: 0,
+            calls: BTreeMap::new(),
+            total: 0,
+        }
+    }
+
+    /// Returns the total number of executed instructions
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Returns how often the instruction at the given `address` was executed
+    pub fn count(&self, address: u16) -> u64 {
+        self.counts[address as usize]
+    }
+
+    /// Writes a text report of the hot functions, hot addresses and the call graph
+    pub fn write_report(&self, out: &mut dyn Write, debug: &DebugInfo) -> io::Result<()> {
+        writeln!(out, "Executed instructions: {}", self.total)?;
+
+        writeln!(out)?;
+        writeln!(out, "Functions:")?;
+        writeln!(
+            out,
+            "{:>12} {:>7} {:>12} {:>7}  Function",
+            "Self", "%", "Inclusive", "%"
+        )?;
+        for (address, self_count, inclusive) in self.functions() {
+            writeln!(
+                out,
+                "{:>12} {:>6.2}% {:>12} {:>6.2}%  {}",
+                self_count,
+                self.percentage(self_count),
+                inclusive,
+                self.percentage(inclusive),
+                function_name(debug, address)
+            )?;
+        }
+
+        writeln!(out)?;
+        writeln!(out, "Hot spots:")?;
+        writeln!(out, "{:>12} {:>7}  Address  Location", "Count", "%")?;
+        let mut hot_spots: Vec<_> = (0..=u16::MAX)
+            .filter(|&address| self.count(address) > 0)
+            .collect();
+        hot_spots.sort_by_key(|&address| (u64::MAX - self.count(address), address));
+        for address in hot_spots.into_iter().take(HOT_SPOT_LIMIT) {
+            let count = self.count(address);
+            write!(
+                out,
+                "{:>12} {:>6.2}%  x{:04X}    {}",
+                count,
+                self.percentage(count),
+                address,
+                location(debug, address)
+            )?;
+            match debug.line_of(address) {
+                Some(line) => writeln!(out, " (line {})", line)?,
+                None => writeln!(out)?,
+            }
+        }
+
+        writeln!(out)?;
+        writeln!(out, "Call graph:")?;
+        writeln!(out, "{:>12}  Caller -> Callee", "Calls")?;
+        let mut calls: Vec<_> = self.calls.iter().collect();
+        calls.sort_by_key(|&(&edge, &count)| (u64::MAX - count, edge));
+        for (&(caller, callee), count) in calls {
+            writeln!(
+                out,
+                "{:>12}  {} -> {}",
+                count,
+                function_name(debug, caller),
+                function_name(debug, callee)
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Writes the profile as folded stacks, one line per call stack with the number of
+    /// instructions executed directly in its innermost subroutine
+    pub fn write_folded(&self, out: &mut dyn Write, debug: &DebugInfo) -> io::Result<()> {
+        for (index, node) in self.nodes.iter().enumerate() {
+            if node.count == 0 {
+                continue;
+            }
+            let mut stack: Vec<_> = self
+                .path(index)
+                .map(|address| function_name(debug, address))
+                .collect();
+            stack.reverse();
+            writeln!(out, "{} {}", stack.join(";"), node.count)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the entry address, self count and inclusive count of every function, ordered by
+    /// descending inclusive count
+    fn functions(&self) -> Vec<(u16, u64, u64)> {
+        let mut functions: HashMap<u16, (u64, u64)> = HashMap::new();
+        for (index, node) in self.nodes.iter().enumerate() {
+            functions.entry(node.address).or_default().0 += node.count;
+            // Recursive functions appear several times on the path, but are counted only once
+            let mut seen = Vec::new();
+            for address in self.path(index) {
+                if !seen.contains(&address) {
+                    seen.push(address);
+                    functions.entry(address).or_default().1 += node.count;
+                }
+            }
+        }
+        let mut functions: Vec<_> = functions
+            .into_iter()
+            .filter(|&(_, (_, inclusive))| inclusive > 0)
+            .map(|(address, (self_count, inclusive))| (address, self_count, inclusive))
+            .collect();
+        functions.sort_by_key(|&(address, self_count, inclusive)| {
+            (u64::MAX - inclusive, u64::MAX - self_count, address)
+        });
+        functions
+    }
+
+    /// Returns the entry addresses of the call stack of the given node, innermost first
+    fn path(&self, mut index: usize) -> impl Iterator<Item = u16> + '_ {
+        let mut done = false;
+        std::iter::from_fn(move || {
+            if done {
+                return None;
+            }
+            let node = &self.nodes[index];
+            match node.parent {
+                Some(parent) => index = parent,
+                None => done = true,
+            }
+            Some(node.address)
+        })
+    }
+
+    fn percentage(&self, count: u64) -> f64 {
+        if self.total == 0 {
+            0.0
+        } else {
+            count as f64 * 100.0 / self.total as f64
+        }
+    }
+
+    fn enter(&mut self, address: u16) {
+        let caller = &self.nodes[self.current];
+        *self.calls.entry((caller.address, address)).or_default() += 1;
+        if caller.depth >= MAX_CALL_DEPTH {
+            self.unrecorded_calls += 1;
+            return;
+        }
+        let depth = caller.depth + 1;
+        self.current = match caller.children.get(&address).copied() {
+            Some(child) => child,
+            None => {
+                let child = self.nodes.len();
+                self.nodes
+                    .push(CallNode::new(address, Some(self.current), depth));
+                self.nodes[self.current].children.insert(address, child);
+                child
+            }
+        };
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Tracer for Profiler {
+    fn trace(&mut self, event: &TraceEvent) {
+        if self.total == 0 {
+            self.nodes[0].address = event.pc;
+        }
+        self.total += 1;
+        self.counts[event.pc as usize] += 1;
+        self.nodes[self.current].count += 1;
+
+        match event.opcode {
+            Opcode::Jsr => self.enter(event.regs.pc),
+            Opcode::Jmp if (event.instr >> 6) & 0x7 == 7 => {
+                if self.unrecorded_calls > 0 {
+                    self.unrecorded_calls -= 1;
+                } else if let Some(parent) = self.nodes[self.current].parent {
+                    self.current = parent;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Returns the symbol at the entry address of a function or the address itself
+fn function_name(debug: &DebugInfo, address: u16) -> String {
+    match debug.symbol_at(address) {
+        Some(symbol) => symbol.to_string(),
+        None => format!("x{:04X}", address),
+    }
+}
+
+/// Returns the location of an address relative to the closest preceding symbol, like `LOOP+2`
+fn location(debug: &DebugInfo, address: u16) -> String {
+    match debug.symbol_containing(address) {
+        Some((symbol, start)) if start == address => symbol.to_string(),
+        Some((symbol, start)) => format!("{}+{}", symbol, address - start),
+        None => String::new(),
+    }
+}
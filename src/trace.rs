@@ -0,0 +1,243 @@
+//! Execution trace recorder
+//!
+//! [`TraceRecorder`] is a [`Tracer`] that writes a record for every executed instruction that
+//! passes its [`TraceFilter`]. A record contains the address and raw word of the instruction,
+//! its opcode, the written registers, the read and written memory addresses and the resulting
+//! condition flags. Three formats are supported:
+//!
+//! - [`TraceFormat::Text`]: one human-readable line per instruction:
+//!
+//!   ```plain
+//!   x3003  7200  STR   [x3011]=x0005 nzp=P
+//!   ```
+//!
+//! - [`TraceFormat::Json`]: one JSON object per line (JSON lines):
+//!
+//!   ```plain
+//!   {"instr":29184,"nzp":"P","opcode":"STR","pc":12291,"reads":[],"regs":{},"writes":[[12305,5]]}
+//!   ```
+//!
+//! - [`TraceFormat::Binary`]: the magic `LC3T` and a version byte (`2`), followed by one record
+//!   per instruction. All words are big-endian:
+//!
+//!   | Field     | Size                 | Content                                        |
+//!   |-----------|----------------------|------------------------------------------------|
+//!   | pc        | 2                    | address of the instruction                     |
+//!   | instr     | 2                    | raw instruction word                           |
+//!   | nzp       | 1                    | condition flags (`N`=4, `Z`=2, `P`=1)          |
+//!   | regmask   | 1                    | written registers (bit `n` for `Rn`)           |
+//!   | regs      | 2 per bit in regmask | new values of the written registers, R0 first  |
+//!   | reads     | 4 + 2 per read       | number of reads, then the read addresses       |
+//!   | writes    | 4 + 4 per write      | number of writes, then address and new value   |
+//!
+//!   The counts take four bytes, since a trap like `PUTS` may read all 65536 words.
+
+use crate::vm::{CondFlag, Opcode, TraceEvent, Tracer};
+
+use byteorder::{BigEndian, WriteBytesExt};
+use serde_json::{json, Map, Value};
+
+use std::fmt::Write as _;
+use std::io::{self, BufWriter, Write};
+use std::ops::RangeInclusive;
+
+/// Magic bytes at the start of a binary trace
+pub const BINARY_MAGIC: &[u8; 4] = b"LC3T";
+/// Version of the binary trace format
+pub const BINARY_VERSION: u8 = 2;
+
+/// Output format of a [`TraceRecorder`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    Text,
+    Json,
+    Binary,
+}
+
+impl TraceFormat {
+    /// Returns the format with the given name (`text`, `json` or `binary`)
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "text" => Some(TraceFormat::Text),
+            "json" => Some(TraceFormat::Json),
+            "binary" => Some(TraceFormat::Binary),
+            _ => None,
+        }
+    }
+}
+
+/// Selection of the instructions that are recorded
+///
+/// An instruction is recorded if its address is in any of the address ranges and its opcode is
+/// any of the opcodes. An empty list of ranges or opcodes matches everything.
+#[derive(Debug, Clone, Default)]
+pub struct TraceFilter {
+    ranges: Vec<RangeInclusive<u16>>,
+    opcodes: Vec<Opcode>,
+}
+
+impl TraceFilter {
+    /// Creates a new `TraceFilter` that matches every instruction
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an address range of recorded instructions
+    pub fn add_range(&mut self, range: RangeInclusive<u16>) {
+        self.ranges.push(range);
+    }
+
+    /// Adds an opcode of recorded instructions
+    pub fn add_opcode(&mut self, opcode: Opcode) {
+        self.opcodes.push(opcode);
+    }
+
+    /// Returns whether the instruction at `pc` with the given `opcode` is recorded
+    pub fn matches(&self, pc: u16, opcode: Opcode) -> bool {
+        (self.ranges.is_empty() || self.ranges.iter().any(|range| range.contains(&pc)))
+            && (self.opcodes.is_empty() || self.opcodes.contains(&opcode))
+    }
+}
+
+/// Tracer that writes the executed instructions to a writer
+///
+/// Since tracers can't return errors to the vm, the first write error is kept, recording stops,
+/// and the error is returned by [`TraceRecorder::flush`].
+pub struct TraceRecorder {
+    out: BufWriter<Box<dyn Write>>,
+    format: TraceFormat,
+    filter: TraceFilter,
+    /// Whether the header of the binary format was written
+    started: bool,
+    error: Option<io::Error>,
+}
+
+impl TraceRecorder {
+    /// Creates a new `TraceRecorder` that writes all instructions in the given `format`
+    pub fn new(out: Box<dyn Write>, format: TraceFormat) -> Self {
+        Self {
+            out: BufWriter::new(out),
+            format,
+            filter: TraceFilter::new(),
+            started: false,
+            error: None,
+        }
+    }
+
+    /// Sets the filter that selects the recorded instructions
+    pub fn set_filter(&mut self, filter: TraceFilter) {
+        self.filter = filter;
+    }
+
+    /// Writes all buffered records and returns the first error that occurred while recording
+    pub fn flush(&mut self) -> io::Result<()> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        if self.format == TraceFormat::Binary && !self.started {
+            self.write_header()?;
+        }
+        self.out.flush()
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        self.started = true;
+        self.out.write_all(BINARY_MAGIC)?;
+        self.out.write_u8(BINARY_VERSION)
+    }
+
+    fn record(&mut self, event: &TraceEvent) -> io::Result<()> {
+        match self.format {
+            TraceFormat::Text => writeln!(self.out, "{}", format_text(event)),
+            TraceFormat::Json => writeln!(self.out, "{}", format_json(event)),
+            TraceFormat::Binary => {
+                if !self.started {
+                    self.write_header()?;
+                }
+                self.write_binary(event)
+            }
+        }
+    }
+
+    fn write_binary(&mut self, event: &TraceEvent) -> io::Result<()> {
+        let out = &mut self.out;
+        out.write_u16::<BigEndian>(event.pc)?;
+        out.write_u16::<BigEndian>(event.instr)?;
+        out.write_u8(event.cond() as u8)?;
+        out.write_u8(event.written_registers)?;
+        for (_, value) in event.register_writes() {
+            out.write_u16::<BigEndian>(value)?;
+        }
+        out.write_u32::<BigEndian>(event.reads.len() as u32)?;
+        for &address in event.reads {
+            out.write_u16::<BigEndian>(address)?;
+        }
+        out.write_u32::<BigEndian>(event.writes.len() as u32)?;
+        for (address, value) in event.memory_writes() {
+            out.write_u16::<BigEndian>(address)?;
+            out.write_u16::<BigEndian>(value)?;
+        }
+        Ok(())
+    }
+}
+
+impl Tracer for TraceRecorder {
+    fn trace(&mut self, event: &TraceEvent) {
+        if self.error.is_some() || !self.filter.matches(event.pc, event.opcode) {
+            return;
+        }
+        if let Err(e) = self.record(event) {
+            self.error = Some(e);
+        }
+    }
+}
+
+fn nzp_name(cond: CondFlag) -> &'static str {
+    match cond {
+        CondFlag::Neg => "N",
+        CondFlag::Zero => "Z",
+        CondFlag::Pos => "P",
+    }
+}
+
+/// Formats an event like `x3000  E004  LEA   R0=x3005 nzp=P`, where reads are written as
+/// `@x3011` and writes as `[x3011]=x0005`
+fn format_text(event: &TraceEvent) -> String {
+    let mut line = format!(
+        "x{:04X}  {:04X}  {:<5}",
+        event.pc,
+        event.instr,
+        event.opcode.mnemonic()
+    );
+    for (index, value) in event.register_writes() {
+        let _ = write!(line, " R{}=x{:04X}", index, value);
+    }
+    for address in event.reads {
+        let _ = write!(line, " @x{:04X}", address);
+    }
+    for (address, value) in event.memory_writes() {
+        let _ = write!(line, " [x{:04X}]=x{:04X}", address, value);
+    }
+    let _ = write!(line, " nzp={}", nzp_name(event.cond()));
+    line
+}
+
+fn format_json(event: &TraceEvent) -> Value {
+    let regs: Map<String, Value> = event
+        .register_writes()
+        .map(|(index, value)| (format!("R{}", index), json!(value)))
+        .collect();
+    let writes: Vec<[u16; 2]> = event
+        .memory_writes()
+        .map(|(address, value)| [address, value])
+        .collect();
+    json!({
+        "pc": event.pc,
+        "instr": event.instr,
+        "opcode": event.opcode.mnemonic(),
+        "regs": regs,
+        "reads": event.reads,
+        "writes": writes,
+        "nzp": nzp_name(event.cond()),
+    })
+}
@@ -0,0 +1,702 @@
+//! Full-screen terminal UI
+//!
+//! The TUI shows the registers, a disassembly around `PC`, a memory hex view and the console
+//! output of the program in separate panes. While the program is paused, keys control the vm:
+//!
+//! | Key              | Action                                            |
+//! |------------------|---------------------------------------------------|
+//! | `s`              | execute a single instruction                      |
+//! | `c`              | continue until a breakpoint, watchpoint or `HALT` |
+//! | `b`              | toggle a breakpoint at the selected instruction   |
+//! | `↑`/`↓`, `k`/`j` | select the previous or next instruction           |
+//! | `.`              | select the instruction at `PC`                    |
+//! | `PgUp`/`PgDn`    | scroll the memory view                            |
+//! | `m`              | show the memory at the selected address           |
+//! | `q`, `Ctrl-C`    | quit                                              |
+//!
+//! While the program is running, all keys are passed to the program as input, except `Ctrl-C`,
+//! which pauses it. The rendering is independent of the terminal: [`Tui::render`] draws into a
+//! [`Frame`], which [`Tui::run`] writes to the terminal with ANSI escape sequences.
+
+use crate::debug_info::DebugInfo;
+use crate::vm::{disassemble, BufferedConsole, StopReason, Vm};
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+use termios::{tcsetattr, Termios, ECHO, ICANON, ISIG, TCSANOW};
+
+const PAUSED_HELP: &str = "s step  c continue  b breakpoint  ↑↓ select  PgUp/PgDn memory  q quit";
+const RUNNING_HELP: &str = "Keys go to the program  Ctrl-C pause";
+
+/// Number of instructions executed between two checks for key presses
+const STEPS_PER_UPDATE: usize = 20_000;
+
+/// Number of lines of program output that are kept
+const MAX_OUTPUT_LINES: usize = 1000;
+
+/// Number of words the memory view scrolls per page
+const MEMORY_PAGE: u16 = 0x40;
+
+/// Minimum time between two redraws while the program is running
+const FRAME_INTERVAL: Duration = Duration::from_millis(33);
+
+/// Time between two checks of the terminal size
+const RESIZE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Width of the register pane
+const REGISTERS_WIDTH: usize = 24;
+
+/// Key pressed in the TUI
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    /// Printable character or control character other than `Ctrl-C`
+    Char(char),
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    /// `Ctrl-C`
+    Interrupt,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Paused,
+    Running,
+    Halted,
+}
+
+/// Full-screen terminal UI for a [`Vm`] with a loaded program
+pub struct Tui {
+    vm: Vm,
+    debug_info: DebugInfo,
+    /// Console of the vm; keys are pushed as input and the output is moved to `output`
+    console: BufferedConsole,
+    output: Output,
+    state: State,
+    /// Whether the running program waits for a key
+    waiting_for_input: bool,
+    /// Whether the breakpoint at `PC` is stepped over when the program continues
+    skip_breakpoint: bool,
+    /// Address of the selected instruction in the disassembly
+    cursor: u16,
+    /// First address of the memory view
+    memory_address: u16,
+    message: String,
+    quit: bool,
+}
+
+impl Tui {
+    /// Creates a new `Tui` for the given `vm`, which shows the labels of `debug_info`
+    ///
+    /// The console of the vm is replaced, so the program reads the keys typed into the TUI and
+    /// writes to the output pane.
+    pub fn new(mut vm: Vm, debug_info: DebugInfo) -> Self {
+        let console = BufferedConsole::new();
+        vm.set_console(Box::new(console.clone()));
+        let pc = vm.registers().pc;
+        Self {
+            vm,
+            debug_info,
+            console,
+            output: Output::new(),
+            state: State::Paused,
+            waiting_for_input: false,
+            skip_breakpoint: false,
+            cursor: pc,
+            memory_address: pc & !0x7,
+            message: format!("Paused at x{:04X}", pc),
+            quit: false,
+        }
+    }
+
+    /// Returns the vm
+    pub fn vm(&self) -> &Vm {
+        &self.vm
+    }
+
+    /// Returns whether the program is running
+    pub fn is_running(&self) -> bool {
+        self.state == State::Running
+    }
+
+    /// Returns whether the user asked to quit
+    pub fn has_quit(&self) -> bool {
+        self.quit
+    }
+
+    /// Shows the TUI in the current terminal until the user quits
+    ///
+    /// The terminal is switched to the alternate screen and raw input while the TUI is shown.
+    pub fn run(&mut self) -> io::Result<()> {
+        let original_termios = Termios::from_fd(0)?;
+        let mut raw_termios = original_termios;
+        raw_termios.c_lflag &= !(ICANON | ECHO | ISIG);
+        tcsetattr(0, TCSANOW, &raw_termios)?;
+        let mut stdout = io::stdout();
+        write!(stdout, "\x1b[?1049h\x1b[?25l\x1b[2J")?;
+
+        let result = self.event_loop(&mut stdout);
+
+        write!(stdout, "\x1b[?25h\x1b[?1049l")?;
+        stdout.flush()?;
+        tcsetattr(0, TCSANOW, &original_termios)?;
+        result
+    }
+
+    fn event_loop(&mut self, out: &mut impl Write) -> io::Result<()> {
+        let keys = spawn_key_reader();
+        let mut size = terminal_size();
+        let mut size_checked = Instant::now();
+        let mut drawn: Option<(Frame, Instant)> = None;
+        while !self.quit {
+            if size_checked.elapsed() >= RESIZE_INTERVAL {
+                size = terminal_size();
+                size_checked = Instant::now();
+            }
+            let due = match &drawn {
+                Some((_, time)) => !self.is_running() || time.elapsed() >= FRAME_INTERVAL,
+                None => true,
+            };
+            if due {
+                let frame = self.render(size.0, size.1);
+                if drawn.as_ref().is_none_or(|(last, _)| *last != frame) {
+                    frame.write_ansi(out)?;
+                }
+                drawn = Some((frame, Instant::now()));
+            }
+
+            if self.is_running() && !self.waiting_for_input {
+                loop {
+                    match keys.try_recv() {
+                        Ok(key) => self.handle_key(key),
+                        Err(TryRecvError::Empty) => break,
+                        Err(TryRecvError::Disconnected) => return Ok(()),
+                    }
+                }
+                self.update();
+            } else {
+                match keys.recv_timeout(RESIZE_INTERVAL) {
+                    Ok(key) => {
+                        self.handle_key(key);
+                        self.update();
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => return Ok(()),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Handles a key press
+    pub fn handle_key(&mut self, key: Key) {
+        if self.state == State::Running {
+            match key {
+                Key::Interrupt => self.pause(format!("Paused at x{:04X}", self.vm.registers().pc)),
+                Key::Char(chr) if chr.is_ascii() => {
+                    self.console.push_input(&[chr as u8]);
+                    self.waiting_for_input = false;
+                }
+                _ => {}
+            }
+            return;
+        }
+
+        match key {
+            Key::Char('q') | Key::Interrupt => self.quit = true,
+            Key::Char('s') if self.state == State::Halted => {
+                self.message = "The program halted".to_string()
+            }
+            Key::Char('c') if self.state == State::Halted => {
+                self.message = "The program halted".to_string()
+            }
+            Key::Char('s') => {
+                let reason = self.vm.step();
+                self.collect_output();
+                self.cursor = self.vm.registers().pc;
+                match reason {
+                    Some(StopReason::WaitingForInput(pc)) => {
+                        self.message = format!(
+                            "The instruction at x{:04X} needs input; continue and type it",
+                            pc
+                        )
+                    }
+                    Some(reason) => self.stop(reason),
+                    None => self.message = format!("Stepped to x{:04X}", self.cursor),
+                }
+            }
+            Key::Char('c') => {
+                self.state = State::Running;
+                self.skip_breakpoint = true;
+                self.message = "Running".to_string();
+            }
+            Key::Char('b') => {
+                if self.vm.remove_breakpoint(self.cursor) {
+                    self.message = format!("Removed the breakpoint at x{:04X}", self.cursor);
+                } else {
+                    self.vm.add_breakpoint(self.cursor);
+                    self.message = format!("Breakpoint set at x{:04X}", self.cursor);
+                }
+            }
+            Key::Up | Key::Char('k') => self.cursor = self.cursor.wrapping_sub(1),
+            Key::Down | Key::Char('j') => self.cursor = self.cursor.wrapping_add(1),
+            Key::Char('.') => self.cursor = self.vm.registers().pc,
+            Key::PageUp => self.memory_address = self.memory_address.wrapping_sub(MEMORY_PAGE),
+            Key::PageDown => self.memory_address = self.memory_address.wrapping_add(MEMORY_PAGE),
+            Key::Char('m') => self.memory_address = self.cursor & !0x7,
+            _ => {}
+        }
+    }
+
+    /// Executes instructions for a while if the program is running
+    ///
+    /// Returns early once the program stops or needs input.
+    pub fn update(&mut self) {
+        if self.state != State::Running {
+            return;
+        }
+        for _ in 0..STEPS_PER_UPDATE {
+            let pc = self.vm.registers().pc;
+            if !std::mem::take(&mut self.skip_breakpoint) && self.vm.has_breakpoint(pc) {
+                self.stop(StopReason::Breakpoint(pc));
+                break;
+            }
+            match self.vm.step() {
+                None => {}
+                Some(StopReason::WaitingForInput(_)) => {
+                    // The instruction is re-executed, so its breakpoint was already handled
+                    self.skip_breakpoint = true;
+                    self.waiting_for_input = true;
+                    self.message = "Waiting for input".to_string();
+                    break;
+                }
+                Some(reason) => {
+                    self.stop(reason);
+                    break;
+                }
+            }
+        }
+        self.collect_output();
+        self.cursor = self.vm.registers().pc;
+    }
+
+    fn stop(&mut self, reason: StopReason) {
+        let message = match reason {
+            StopReason::Halted => {
+                self.state = State::Halted;
+                self.waiting_for_input = false;
+                self.message = "The program halted".to_string();
+                return;
+            }
+            StopReason::Breakpoint(pc) => format!("Breakpoint at x{:04X}", pc),
+            StopReason::Watchpoint(hit) => format!(
+                "Watchpoint at x{:04X} triggered by x{:04X}: x{:04X} -> x{:04X}",
+                hit.address, hit.pc, hit.old_value, hit.new_value
+            ),
+            StopReason::Fault { pc, fault } => format!("Fault at x{:04X}: {}", pc, fault),
+            StopReason::LimitExceeded { pc, limit, .. } => {
+                format!("Reached the {} at x{:04X}", limit, pc)
+            }
+            StopReason::WaitingForInput(_) | StopReason::Aborted => {
+                format!("Paused at x{:04X}", self.vm.registers().pc)
+            }
+        };
+        self.pause(message);
+    }
+
+    fn pause(&mut self, message: String) {
+        self.state = State::Paused;
+        self.waiting_for_input = false;
+        self.cursor = self.vm.registers().pc;
+        self.message = message;
+    }
+
+    fn collect_output(&mut self) {
+        self.output.push(&self.console.take_output());
+    }
+
+    /// Draws the panes into a frame of the given size (in characters)
+    pub fn render(&self, width: usize, height: usize) -> Frame {
+        let mut frame = Frame::new(width, height);
+        if width < REGISTERS_WIDTH + 36 || height < 16 {
+            frame.put(0, 0, "The terminal is too small", false);
+            return frame;
+        }
+
+        let body = height - 1;
+        let top = (body / 2).max(13);
+        let words_per_row = if width >= 100 { 8 } else { 4 };
+        let memory_width = 9 + 5 * words_per_row;
+
+        self.render_registers(&mut frame, 0, 0, REGISTERS_WIDTH, top);
+        self.render_disassembly(&mut frame, REGISTERS_WIDTH, 0, width - REGISTERS_WIDTH, top);
+        self.render_memory(&mut frame, 0, top, memory_width, body - top, words_per_row);
+        self.render_output(
+            &mut frame,
+            memory_width,
+            top,
+            width - memory_width,
+            body - top,
+        );
+
+        let help = match self.state {
+            State::Running => RUNNING_HELP,
+            State::Paused | State::Halted => PAUSED_HELP,
+        };
+        let mut status = format!(" {}", self.message);
+        let padding = width.saturating_sub(status.chars().count() + help.chars().count() + 1);
+        if padding > 0 {
+            status.push_str(&" ".repeat(padding));
+            status.push_str(help);
+        }
+        frame.put(0, body, &format!("{:<1$}", status, width), true);
+        frame
+    }
+
+    fn render_registers(&self, frame: &mut Frame, x: usize, y: usize, width: usize, height: usize) {
+        frame.draw_box(x, y, width, height, "Registers");
+        let regs = self.vm.registers();
+        for index in 0..8 {
+            let value = regs.read(index);
+            let text = format!("R{}  x{:04X} {:>7}", index, value, value as i16);
+            frame.put(x + 2, y + 1 + index as usize, &text, false);
+        }
+        let psr = regs.psr();
+        let flags: String = [(0b100, 'N'), (0b010, 'Z'), (0b001, 'P')]
+            .iter()
+            .map(|&(flag, name)| if psr & flag != 0 { name } else { '-' })
+            .collect();
+        frame.put(x + 2, y + 9, &format!("PC  x{:04X}", regs.pc), false);
+        frame.put(
+            x + 2,
+            y + 10,
+            &format!("PSR x{:04X}     {}", psr, flags),
+            false,
+        );
+        let instructions = format!("Executed {}", self.vm.instructions());
+        frame.put(x + 2, y + 11, &instructions, false);
+    }
+
+    fn render_disassembly(
+        &self,
+        frame: &mut Frame,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) {
+        frame.draw_box(x, y, width, height, "Disassembly");
+        let rows = height - 2;
+        let pc = self.vm.registers().pc;
+        let start = self.cursor.wrapping_sub((rows / 3) as u16);
+        for row in 0..rows {
+            let address = start.wrapping_add(row as u16);
+            let instr = self.vm.memory().peek(address);
+            let breakpoint = if self.vm.has_breakpoint(address) {
+                '●'
+            } else {
+                ' '
+            };
+            let current = if address == pc { '▶' } else { ' ' };
+            let label = self.debug_info.symbol_at(address).unwrap_or("");
+            let text = format!(
+                "{}{} x{:04X}  {:04X}  {:<12} {}",
+                breakpoint,
+                current,
+                address,
+                instr,
+                label,
+                disassemble(address, instr)
+            );
+            let text = format!("{:<1$}", text, width - 2);
+            frame.put(x + 1, y + 1 + row, &text, address == self.cursor);
+        }
+    }
+
+    fn render_memory(
+        &self,
+        frame: &mut Frame,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        words_per_row: usize,
+    ) {
+        frame.draw_box(x, y, width, height, "Memory");
+        for row in 0..height - 2 {
+            let address = self
+                .memory_address
+                .wrapping_add((row * words_per_row) as u16);
+            frame.put(x + 2, y + 1 + row, &format!("x{:04X}", address), false);
+            for column in 0..words_per_row {
+                let address = address.wrapping_add(column as u16);
+                let word = format!("{:04X}", self.vm.memory().peek(address));
+                frame.put(
+                    x + 9 + 5 * column,
+                    y + 1 + row,
+                    &word,
+                    address == self.cursor,
+                );
+            }
+        }
+    }
+
+    fn render_output(&self, frame: &mut Frame, x: usize, y: usize, width: usize, height: usize) {
+        frame.draw_box(x, y, width, height, "Output");
+        let (columns, rows) = (width - 2, height - 2);
+        let mut wrapped = Vec::new();
+        for line in self.output.lines.iter().rev() {
+            let chars: Vec<char> = line.chars().collect();
+            let mut chunks: Vec<String> = chars
+                .chunks(columns)
+                .map(|chunk| chunk.iter().collect())
+                .collect();
+            if chunks.is_empty() {
+                chunks.push(String::new());
+            }
+            wrapped.extend(chunks.into_iter().rev());
+            if wrapped.len() >= rows {
+                break;
+            }
+        }
+        wrapped.truncate(rows);
+        for (row, line) in wrapped.iter().rev().enumerate() {
+            frame.put(x + 1, y + 1 + row, line, false);
+        }
+    }
+}
+
+/// Output of the program, split into lines
+///
+/// ANSI escape sequences are dropped, since the output is shown in a pane and not on the
+/// whole screen.
+struct Output {
+    lines: VecDeque<String>,
+    escape: Escape,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Escape {
+    None,
+    /// After `ESC`
+    Start,
+    /// In a control sequence (`ESC [`), which ends with a byte in `@`..`~`
+    Sequence,
+}
+
+impl Output {
+    fn new() -> Self {
+        Self {
+            lines: VecDeque::from(vec![String::new()]),
+            escape: Escape::None,
+        }
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.escape = match (self.escape, byte) {
+                (Escape::None, 0x1B) => Escape::Start,
+                (Escape::Start, b'[') => Escape::Sequence,
+                (Escape::Start, _) => Escape::None,
+                (Escape::Sequence, b'@'..=b'~') => Escape::None,
+                (Escape::Sequence, _) => Escape::Sequence,
+                (Escape::None, _) => {
+                    self.push_byte(byte);
+                    Escape::None
+                }
+            };
+        }
+    }
+
+    fn push_byte(&mut self, byte: u8) {
+        match byte {
+            b'\n' => {
+                self.lines.push_back(String::new());
+                if self.lines.len() > MAX_OUTPUT_LINES {
+                    self.lines.pop_front();
+                }
+            }
+            0x08 | 0x7F => {
+                self.current_line().pop();
+            }
+            b'\t' => self.current_line().push_str("    "),
+            _ if byte.is_ascii_control() => {}
+            _ => self.current_line().push(byte as char),
+        }
+    }
+
+    fn current_line(&mut self) -> &mut String {
+        self.lines.back_mut().unwrap()
+    }
+}
+
+/// Characters of the screen; cells can be shown in inverse video
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    width: usize,
+    height: usize,
+    /// Character and inverse flag of every cell, row by row
+    cells: Vec<(char, bool)>,
+}
+
+impl Frame {
+    /// Creates a blank frame of the given size
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            cells: vec![(' ', false); width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the text of row `y`
+    pub fn line(&self, y: usize) -> String {
+        self.row(y).iter().map(|&(chr, _)| chr).collect()
+    }
+
+    /// Returns the text of all rows
+    pub fn lines(&self) -> impl Iterator<Item = String> + '_ {
+        (0..self.height).map(move |y| self.line(y))
+    }
+
+    /// Returns whether the cell at `x`, `y` is shown in inverse video
+    pub fn is_inverse(&self, x: usize, y: usize) -> bool {
+        self.row(y)[x].1
+    }
+
+    fn row(&self, y: usize) -> &[(char, bool)] {
+        &self.cells[y * self.width..(y + 1) * self.width]
+    }
+
+    /// Writes `text` starting at `x`, `y`; characters beyond the right edge are dropped
+    fn put(&mut self, x: usize, y: usize, text: &str, inverse: bool) {
+        if y >= self.height {
+            return;
+        }
+        for (offset, chr) in text.chars().enumerate() {
+            if x + offset >= self.width {
+                break;
+            }
+            self.cells[y * self.width + x + offset] = (chr, inverse);
+        }
+    }
+
+    /// Draws a box with the given outer size and a title in its top border
+    fn draw_box(&mut self, x: usize, y: usize, width: usize, height: usize, title: &str) {
+        let horizontal = "─".repeat(width - 2);
+        self.put(x, y, &format!("┌{}┐", horizontal), false);
+        for row in y + 1..y + height - 1 {
+            self.put(x, row, "│", false);
+            self.put(x + width - 1, row, "│", false);
+        }
+        self.put(x, y + height - 1, &format!("└{}┘", horizontal), false);
+        self.put(x + 2, y, &format!(" {} ", title), false);
+    }
+
+    /// Draws the frame over the whole terminal
+    fn write_ansi(&self, out: &mut impl Write) -> io::Result<()> {
+        let mut text = String::from("\x1b[H");
+        for y in 0..self.height {
+            if y > 0 {
+                text.push_str("\r\n");
+            }
+            let mut inverse = false;
+            for &(chr, cell_inverse) in self.row(y) {
+                if cell_inverse != inverse {
+                    text.push_str(if cell_inverse { "\x1b[7m" } else { "\x1b[27m" });
+                    inverse = cell_inverse;
+                }
+                text.push(chr);
+            }
+            if inverse {
+                text.push_str("\x1b[27m");
+            }
+        }
+        out.write_all(text.as_bytes())?;
+        out.flush()
+    }
+}
+
+/// Starts a thread that reads keys from stdin; the channel is closed at the end of input
+fn spawn_key_reader() -> Receiver<Key> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buffer = [0; 64];
+        loop {
+            let len = match io::stdin().read(&mut buffer) {
+                Ok(0) | Err(_) => break,
+                Ok(len) => len,
+            };
+            for key in parse_keys(&buffer[..len]) {
+                if sender.send(key).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    receiver
+}
+
+/// Parses the bytes of key presses read at once from a terminal
+pub fn parse_keys(bytes: &[u8]) -> Vec<Key> {
+    let mut keys = Vec::new();
+    let mut index = 0;
+    while index < bytes.len() {
+        let rest = &bytes[index..];
+        let (key, len) = if rest.starts_with(b"\x1b[A") {
+            (Some(Key::Up), 3)
+        } else if rest.starts_with(b"\x1b[B") {
+            (Some(Key::Down), 3)
+        } else if rest.starts_with(b"\x1b[5~") {
+            (Some(Key::PageUp), 4)
+        } else if rest.starts_with(b"\x1b[6~") {
+            (Some(Key::PageDown), 4)
+        } else if rest.starts_with(b"\x1b[") {
+            // Skip other control sequences up to their final byte
+            let end = rest[2..]
+                .iter()
+                .position(|byte| (b'@'..=b'~').contains(byte));
+            (None, end.map_or(rest.len(), |end| end + 3))
+        } else if rest[0] == 0x03 {
+            (Some(Key::Interrupt), 1)
+        } else {
+            (Some(Key::Char(rest[0] as char)), 1)
+        };
+        keys.extend(key);
+        index += len;
+    }
+    keys
+}
+
+/// Returns the number of columns and rows of the terminal, or 80×24 if unknown
+fn terminal_size() -> (usize, usize) {
+    let output = Command::new("stty")
+        .arg("size")
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::null())
+        .output();
+    let size = output.ok().and_then(|output| {
+        let text = String::from_utf8(output.stdout).ok()?;
+        let mut fields = text.split_whitespace().map(|field| field.parse().ok());
+        let rows = fields.next()??;
+        let columns = fields.next()??;
+        Some((columns, rows))
+    });
+    match size {
+        Some((columns, rows)) if columns > 0 && rows > 0 => (columns, rows),
+        _ => (80, 24),
+    }
+}
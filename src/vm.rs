@@ -1,21 +1,174 @@
+mod block;
+mod call;
+mod console;
+mod device;
+mod disassemble;
+mod history;
+mod input_log;
 mod instructions;
+mod limits;
 mod memory;
 mod opcode;
 mod registers;
+mod snapshot;
+mod timing;
+mod tracer;
 mod utils;
+mod watchpoint;
 
-use memory::Memory;
-use opcode::Opcode;
-use registers::Registers;
+pub use call::{CallError, CallResult, CallingConvention, MemoryChange, Routine, Violation};
+pub use console::{BufferedConsole, Console, TerminalConsole};
+pub use device::{Device, DeviceContext, Disk, Interrupt, DISK_ADDRESSES, SECTOR_WORDS};
+pub use disassemble::disassemble;
+pub use input_log::{read_input_log, InputEvent, InputRecorder};
+pub use limits::{Limit, Limits, RecentTrace};
+pub use memory::{AccessCounts, Memory};
+pub use opcode::{InvalidOpcode, Opcode};
+pub use registers::{CondFlag, Registers};
+pub use timing::{TimingModel, MAX_CYCLES};
+pub use tracer::{TraceEvent, Tracer};
+pub use utils::number::parse_word;
+pub use watchpoint::{WatchKind, Watchpoint, WatchpointHit};
+
+use block::{BlockCache, MAX_BLOCK_LEN};
+use history::{History, UndoRecord};
+use input_log::Replay;
+use instructions::{Flow, Instruction};
 
 use byteorder::{BigEndian, ReadBytesExt};
-use std::convert::TryFrom;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
 use std::io::{self, Read};
+use std::time::{Duration, Instant};
 
 pub struct Vm {
     regs: Registers,
     mem: Memory,
     running: bool,
+    breakpoints: BTreeSet<u16>,
+    /// Address of the breakpoint the vm last stopped at, which is skipped once when resuming
+    stopped_at_breakpoint: Option<u16>,
+    /// Undo records of the executed instructions, if reverse execution is enabled
+    history: Option<History>,
+    tracers: Vec<Box<dyn Tracer>>,
+    timing: TimingModel,
+    /// Number of cycles of all executed instructions
+    cycles: u64,
+    /// Number of executed instructions
+    instructions: u64,
+    /// Host time spent in trap routines
+    trap_time: Duration,
+    engine: Engine,
+    /// Translated basic blocks of the engines other than [`Engine::Interpreter`]
+    blocks: BlockCache,
+    limits: Limits,
+    /// Time at which the current run exceeds [`Limits::timeout`]
+    deadline: Option<Instant>,
+    /// Recently executed instructions, which are only tracked while limits are set
+    recent: Option<RecentTrace>,
+    /// Addresses of the labels that [`Vm::call`] accepts
+    symbols: BTreeMap<String, u16>,
+    calling_convention: CallingConvention,
+}
+
+/// Number of loop iterations or instructions between two checks of [`Limits::timeout`]
+const TIMEOUT_CHECK_INTERVAL: u32 = 1024;
+/// Maximum number of instructions of a single block execution while a timeout is set, so
+/// native loops return often enough to check the time
+const TIMEOUT_BLOCK_BUDGET: u64 = 1 << 14;
+
+/// Address of the interrupt vector table
+const INTERRUPT_VECTOR_TABLE: u16 = 0x0100;
+
+/// Way the vm executes instructions while it runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Engine {
+    /// Fetch and execute one instruction at a time
+    Interpreter,
+    /// Translate straight-line basic blocks and execute each of them as a unit
+    ///
+    /// Blocks are only used while no breakpoints, watchpoints, tracers, execution history or
+    /// input replay need to observe single instructions; otherwise the vm interprets.
+    BasicBlocks,
+    /// Like [`BasicBlocks`](Self::BasicBlocks), but compile frequently executed blocks to
+    /// native x86-64 code
+    ///
+    /// Native code leaves device accesses and the control-flow instruction at the end of a
+    /// block to the interpreter.
+    #[cfg(feature = "jit")]
+    Jit,
+    /// Like [`Jit`](Self::Jit), but check every execution of native code against the
+    /// interpreter
+    ///
+    /// The registers, written memory and cycles of the native code are compared with the
+    /// results of interpreting the same instructions from the same state; a difference panics.
+    #[cfg(feature = "jit")]
+    JitChecked,
+}
+
+impl Engine {
+    /// Returns the engine with the given name (`interpreter` or `blocks`, and `jit` or
+    /// `jit-check` with the `jit` feature)
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "interpreter" => Some(Engine::Interpreter),
+            "blocks" => Some(Engine::BasicBlocks),
+            #[cfg(feature = "jit")]
+            "jit" => Some(Engine::Jit),
+            #[cfg(feature = "jit")]
+            "jit-check" => Some(Engine::JitChecked),
+            _ => None,
+        }
+    }
+}
+
+/// Reason why the vm stopped executing instructions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// The program executed the `HALT` trap
+    Halted,
+    /// Execution was stopped through [`Vm::abort`]
+    Aborted,
+    /// The instruction at the given address has a breakpoint and was not executed yet
+    Breakpoint(u16),
+    /// An instruction triggered a watchpoint; the instruction was executed completely
+    Watchpoint(WatchpointHit),
+    /// The instruction at `pc` could not be executed; `PC` still points to it
+    Fault { pc: u16, fault: Fault },
+    /// The trap instruction at the given address needs input, but the [`Console`] has none;
+    /// `PC` still points to it, so it is re-executed when the vm is resumed
+    WaitingForInput(u16),
+    /// A limit of the run was reached before the instruction at `pc` was executed; `recent`
+    /// contains the addresses of the last executed instructions
+    LimitExceeded {
+        pc: u16,
+        limit: Limit,
+        recent: RecentTrace,
+    },
+}
+
+/// Error that prevents an instruction from being executed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// The instruction (given as raw value) has the reserved opcode (`RES`), or is an `RTI`
+    /// executed in user mode
+    IllegalOpcode(u16),
+    /// A `TRAP` instruction used the given unsupported trap vector
+    UnsupportedTrap(u8),
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Fault::IllegalOpcode(instr) => {
+                let opcode = Opcode::from_instr(*instr);
+                write!(f, "Illegal opcode: {:#06b} ({:?})", instr >> 12, opcode)
+            }
+            Fault::UnsupportedTrap(trapvector) => {
+                write!(f, "Unsupported trap code: {:#04x}", trapvector)
+            }
+        }
+    }
 }
 
 impl Vm {
@@ -24,12 +177,27 @@ impl Vm {
             regs: Registers::new(),
             mem: Memory::new(),
             running: false,
+            breakpoints: BTreeSet::new(),
+            stopped_at_breakpoint: None,
+            history: None,
+            tracers: Vec::new(),
+            timing: TimingModel::new(),
+            cycles: 0,
+            instructions: 0,
+            trap_time: Duration::ZERO,
+            engine: Engine::Interpreter,
+            blocks: BlockCache::new(Engine::Interpreter),
+            limits: Limits::default(),
+            deadline: None,
+            recent: None,
+            symbols: BTreeMap::new(),
+            calling_convention: CallingConvention::default(),
         }
     }
 
     pub fn load_program<R: Read>(&mut self, mut reader: R) -> io::Result<()> {
         let origin = reader.read_u16::<BigEndian>()?;
-        for address in origin..(memory::MEMORY_SIZE as u16) {
+        for address in origin..=u16::MAX {
             match reader.read_u16::<BigEndian>() {
                 Ok(instr) => self.mem.write(address, instr),
                 Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
@@ -39,46 +207,566 @@ impl Vm {
         Ok(())
     }
 
-    pub fn run(&mut self) {
+    /// Returns the vm's registers
+    pub fn registers(&self) -> &Registers {
+        &self.regs
+    }
+
+    /// Returns the vm's registers for modification
+    pub fn registers_mut(&mut self) -> &mut Registers {
+        &mut self.regs
+    }
+
+    /// Returns the vm's memory
+    pub fn memory(&self) -> &Memory {
+        &self.mem
+    }
+
+    /// Returns the vm's memory for modification
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        &mut self.mem
+    }
+
+    /// Replaces the console used for keyboard input and display output; returns the previous one
+    pub fn set_console(&mut self, console: Box<dyn Console>) -> Box<dyn Console> {
+        self.mem.set_console(console)
+    }
+
+    /// Returns the addresses of all breakpoints
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    /// Sets a breakpoint at the given `address`; returns whether it was newly added
+    pub fn add_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.insert(address)
+    }
+
+    /// Returns whether a breakpoint is set at the given `address`
+    pub fn has_breakpoint(&self, address: u16) -> bool {
+        self.breakpoints.contains(&address)
+    }
+
+    /// Removes the breakpoint at the given `address`; returns whether it was set
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    /// Adds a watchpoint; see [`Memory::add_watchpoint`]
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.mem.add_watchpoint(watchpoint);
+    }
+
+    /// Removes a watchpoint; see [`Memory::remove_watchpoint`]
+    pub fn remove_watchpoint(&mut self, watchpoint: &Watchpoint) -> bool {
+        self.mem.remove_watchpoint(watchpoint)
+    }
+
+    /// Runs the loaded program in the current terminal until it stops
+    ///
+    /// Input buffering of the terminal is disabled while the program is running.
+    pub fn run(&mut self) -> StopReason {
         let original_termios = utils::io::disable_input_buffering();
-        self.running = true;
-        self.main_loop();
+        let reason = self.resume();
         utils::io::restore_input_buffering(original_termios);
+        reason
+    }
+
+    /// Continues executing instructions until the vm stops
+    ///
+    /// Unlike [`run`](Self::run), this does not change the terminal settings. If the vm
+    /// previously stopped at a breakpoint, that breakpoint is stepped over.
+    pub fn resume(&mut self) -> StopReason {
+        self.running = true;
+        self.start_timeout();
+        self.main_loop()
+    }
+
+    /// Starts measuring [`Limits::timeout`] from now
+    ///
+    /// [`resume`](Self::resume) does this; frontends that continue the program by calling
+    /// [`step`](Self::step) repeatedly call it before they continue.
+    pub fn start_timeout(&mut self) {
+        self.deadline = self
+            .limits
+            .timeout
+            .and_then(|timeout| Instant::now().checked_add(timeout));
     }
 
     pub fn abort(&mut self) {
         self.running = false;
     }
 
-    fn main_loop(&mut self) {
+    /// Adds a tracer that is called after every executed instruction
+    pub fn add_tracer(&mut self, tracer: Box<dyn Tracer>) {
+        self.tracers.push(tracer);
+    }
+
+    /// Removes all tracers
+    pub fn clear_tracers(&mut self) {
+        self.tracers.clear();
+    }
+
+    /// Returns the timing model that determines the cycles of the executed instructions
+    pub fn timing(&self) -> &TimingModel {
+        &self.timing
+    }
+
+    /// Sets the timing model for the following instructions
+    pub fn set_timing(&mut self, timing: TimingModel) {
+        self.timing = timing;
+        self.blocks.clear(&mut self.mem);
+    }
+
+    /// Returns the limits of the following runs
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// Sets the limits of the following runs
+    ///
+    /// The instruction, cycle and output limits apply to the totals since the vm was created,
+    /// the timeout to each call to [`run`](Self::run) or [`resume`](Self::resume).
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+        self.recent = limits.is_limited().then(RecentTrace::new);
+    }
+
+    /// Returns the engine that executes instructions while the vm runs
+    pub fn engine(&self) -> Engine {
+        self.engine
+    }
+
+    /// Sets the engine that executes instructions while the vm runs
+    pub fn set_engine(&mut self, engine: Engine) {
+        self.engine = engine;
+        self.blocks.set_engine(engine, &mut self.mem);
+    }
+
+    /// Returns the number of cycles of all executed instructions
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Returns the number of executed instructions
+    ///
+    /// Instructions that faulted or are waiting for input are not counted.
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    /// Returns the host time spent in trap routines, including the time they waited for input
+    pub fn trap_time(&self) -> Duration {
+        self.trap_time
+    }
+
+    /// Feeds the given recorded input to the program instead of the console input
+    ///
+    /// Each byte is only available to the instruction that executes after exactly
+    /// [`InputEvent::instruction`] instructions, so a run that was recorded with an
+    /// [`InputRecorder`] is reproduced exactly. Once all bytes were delivered, the console is
+    /// used again.
+    pub fn replay_input(&mut self, events: Vec<InputEvent>) {
+        self.mem.start_replay(Replay::new(events));
+    }
+
+    /// Returns the simulated time that the executed instructions took
+    pub fn elapsed(&self) -> Duration {
+        self.timing.duration(self.cycles)
+    }
+
+    /// Starts recording the execution history, so instructions can be undone with
+    /// [`step_back`](Self::step_back) and [`reverse_continue`](Self::reverse_continue)
+    ///
+    /// The history uses at most about `budget` bytes; the oldest instructions are forgotten
+    /// when it is exceeded. Enabling the history again clears it.
+    pub fn enable_history(&mut self, budget: usize) {
+        self.history = Some(History::new(budget));
+    }
+
+    /// Stops recording the execution history and clears it
+    pub fn disable_history(&mut self) {
+        self.history = None;
+    }
+
+    /// Returns the number of instructions that can be undone
+    pub fn history_len(&self) -> usize {
+        self.history.as_ref().map_or(0, History::len)
+    }
+
+    /// Undoes the most recently executed instruction; returns `false` if the history is empty
+    ///
+    /// Console output of the instruction is not undone, but its consumed input is delivered
+    /// again when execution continues.
+    pub fn step_back(&mut self) -> bool {
+        self.undo().is_some()
+    }
+
+    /// Undoes instructions until a breakpoint or watchpoint is reached
+    ///
+    /// Stops with [`StopReason::Breakpoint`] if `PC` reaches a breakpoint and with
+    /// [`StopReason::Watchpoint`] right before an instruction whose memory accesses trigger a
+    /// watchpoint. Returns `None` if the beginning of the recorded history was reached.
+    pub fn reverse_continue(&mut self) -> Option<StopReason> {
+        loop {
+            if let Some(hit) = self.undo()? {
+                return Some(StopReason::Watchpoint(hit));
+            }
+            let pc = self.regs.pc;
+            if self.breakpoints.contains(&pc) {
+                self.stopped_at_breakpoint = Some(pc);
+                return Some(StopReason::Breakpoint(pc));
+            }
+        }
+    }
+
+    /// Undoes the most recently executed instruction; returns `None` if the history is empty
+    /// and otherwise the watchpoint triggered by the instruction's memory accesses, if any
+    fn undo(&mut self) -> Option<Option<WatchpointHit>> {
+        let UndoRecord {
+            regs,
+            cycles,
+            instructions,
+            journal,
+        } = self.history.as_mut()?.pop()?;
+        self.stopped_at_breakpoint = None;
+
+        let pc = regs.pc;
+        let watchpoints = self.mem.watchpoints();
+        let write_hit = journal.writes.iter().find_map(|&(address, old_value)| {
+            let new_value = self.mem.peek(address);
+            watchpoints
+                .iter()
+                .find(|w| w.triggers(address, WatchKind::Write, old_value, new_value))
+                .map(|w| (address, w.kind, old_value, new_value))
+        });
+        let read_hit = journal.reads.iter().find_map(|&address| {
+            let value = self.mem.peek(address);
+            watchpoints
+                .iter()
+                .find(|w| w.triggers(address, WatchKind::Read, value, value))
+                .map(|w| (address, w.kind, value, value))
+        });
+        let hit = write_hit
+            .or(read_hit)
+            .map(|(address, kind, old_value, new_value)| WatchpointHit {
+                pc,
+                address,
+                kind,
+                old_value,
+                new_value,
+            });
+
+        for &(address, old_value) in journal.writes.iter().rev() {
+            self.mem.poke(address, old_value);
+        }
+        self.mem.unread_input(&journal.input);
+        self.regs = regs;
+        self.cycles = cycles;
+        self.instructions = instructions;
+        Some(hit)
+    }
+
+    /// Executes the instruction at `PC`; returns the reason if the vm stopped because of it
+    ///
+    /// Breakpoints are not checked, so this usually executes exactly one instruction. If an
+    /// interrupt is pending, its service routine is entered first and the instruction is the
+    /// first one of the routine; if a breakpoint is set there, the vm stops at it instead. If a
+    /// limit was reached, nothing is executed and [`StopReason::LimitExceeded`] is returned.
+    pub fn step(&mut self) -> Option<StopReason> {
+        self.stopped_at_breakpoint = None;
+        if self.limits.is_limited() {
+            let check_time = self
+                .instructions
+                .is_multiple_of(u64::from(TIMEOUT_CHECK_INTERVAL));
+            if let Some(reason) = self.check_limits(check_time) {
+                return Some(reason);
+            }
+        }
+        let observed = self.history.is_some() || !self.tracers.is_empty();
+        let regs_before = if observed {
+            self.mem.begin_journal();
+            self.regs.take_written();
+            Some(self.regs.clone())
+        } else {
+            None
+        };
+
+        let interrupted = self.take_interrupts();
+        let pc = self.regs.pc;
+        let cycles_before = self.cycles;
+        let instruction = self.instructions;
+        if interrupted && self.breakpoints.contains(&pc) {
+            if let Some(regs) = regs_before {
+                let journal = self.mem.end_journal();
+                if let Some(history) = &mut self.history {
+                    history.push(UndoRecord {
+                        regs,
+                        cycles: cycles_before,
+                        instructions: instruction,
+                        journal,
+                    });
+                }
+            }
+            self.stopped_at_breakpoint = Some(pc);
+            return Some(StopReason::Breakpoint(pc));
+        }
+        self.mem.take_access_counts();
+        self.mem.set_replay_instruction(instruction);
+        let (instr, decoded) = self.mem.fetch_decoded(pc);
+        self.regs.pc = pc.wrapping_add(1);
+        let started = matches!(decoded, Instruction::Trap(_)).then(Instant::now);
+        let result = self.execute(instr, decoded);
+        if let Some(started) = started {
+            self.trap_time += started.elapsed();
+        }
+        let watchpoint_hit = self.mem.take_watchpoint_hit(pc);
+
+        let mut cycles = 0;
+        if let Ok(Flow::Continue | Flow::Halt) = result {
+            cycles = self
+                .timing
+                .instruction_cycles(instr, self.mem.take_access_counts());
+            self.cycles = self.cycles.saturating_add(cycles);
+            self.instructions += 1;
+            self.mem.tick_devices(1, cycles);
+            if let Some(recent) = &mut self.recent {
+                recent.push(pc);
+            }
+        }
+
+        if let Some(regs) = regs_before {
+            let journal = self.mem.end_journal();
+            if let Ok(Flow::Continue | Flow::Halt) = result {
+                if !self.tracers.is_empty() {
+                    let event = TraceEvent {
+                        pc,
+                        instr,
+                        opcode: Opcode::from_instr(instr),
+                        written_registers: self.regs.take_written(),
+                        reads: &journal.reads,
+                        writes: &journal.writes,
+                        cycles,
+                        instruction,
+                        input: &journal.input,
+                        regs: &self.regs,
+                        mem: &self.mem,
+                    };
+                    for tracer in &mut self.tracers {
+                        tracer.trace(&event);
+                    }
+                }
+                if let Some(history) = &mut self.history {
+                    history.push(UndoRecord {
+                        regs,
+                        cycles: cycles_before,
+                        instructions: instruction,
+                        journal,
+                    });
+                }
+            }
+        }
+
+        self.stop_reason(pc, result, watchpoint_hit)
+    }
+
+    /// Executes basic blocks until the vm stops
+    ///
+    /// Falls back to [`step`](Self::step) for code in the device registers.
+    fn run_blocks(&mut self) -> StopReason {
+        self.stopped_at_breakpoint = None;
+        let limited = self.limits.is_limited();
+        let mut iteration = 0u32;
         while self.running {
-            let instr = self.mem.read(self.regs.pc);
-            self.regs.pc = self.regs.pc.wrapping_add(1);
-            let (regs, mem) = (&mut self.regs, &mut self.mem);
-            let opcode = Opcode::try_from(instr >> 12).unwrap();
-            match opcode {
-                Opcode::Br => instructions::br(instr, regs),
-                Opcode::Add => instructions::add(instr, regs),
-                Opcode::Ld => instructions::ld(instr, regs, mem),
-                Opcode::St => instructions::st(instr, regs, mem),
-                Opcode::Jsr => instructions::jsr(instr, regs),
-                Opcode::And => instructions::and(instr, regs),
-                Opcode::Ldr => instructions::ldr(instr, regs, mem),
-                Opcode::Str => instructions::str(instr, regs, mem),
-                Opcode::Rti => panic!("Illegal opcode: 0b1000 (RTI)"),
-                Opcode::Not => instructions::not(instr, regs),
-                Opcode::Ldi => instructions::ldi(instr, regs, mem),
-                Opcode::Sti => instructions::sti(instr, regs, mem),
-                Opcode::Jmp => instructions::jmp(instr, regs),
-                Opcode::Res => panic!("Illegal opcode: 0b1101 (RES)"),
-                Opcode::Lea => instructions::lea(instr, regs),
-                Opcode::Trap => {
-                    let should_halt = instructions::trap(instr, regs, mem);
-                    if should_halt {
-                        self.running = false;
+            if limited {
+                if let Some(reason) =
+                    self.check_limits(iteration.is_multiple_of(TIMEOUT_CHECK_INTERVAL))
+                {
+                    return reason;
+                }
+                iteration = iteration.wrapping_add(1);
+            }
+            let budget = self.block_budget();
+            let exit = match budget >= u64::from(MAX_BLOCK_LEN) {
+                true => {
+                    self.take_interrupts();
+                    self.blocks
+                        .execute(budget, &mut self.regs, &mut self.mem, &self.timing)
+                }
+                false => None,
+            };
+            let reason = match exit {
+                Some(exit) => {
+                    self.cycles = self.cycles.saturating_add(exit.cycles);
+                    self.instructions += exit.instructions;
+                    self.trap_time += exit.trap_time;
+                    self.mem.tick_devices(exit.instructions, exit.cycles);
+                    if let Some(recent) = &mut self.recent {
+                        recent.push_block(exit.start, exit.len, exit.instructions);
+                    }
+                    match exit.terminator {
+                        Some((pc, result)) => self.stop_reason(pc, result, None),
+                        None => None,
                     }
-                },
+                }
+                None => self.step(),
             };
+            if let Some(reason) = reason {
+                return reason;
+            }
+        }
+        StopReason::Aborted
+    }
+
+    /// Returns the number of instructions that a block may execute without passing a limit or
+    /// a device requesting an interrupt
+    ///
+    /// All instructions but the last one must start below the cycle limit, and only the last
+    /// instruction of a block execution can be a `TRAP`, whose cycles are unbounded.
+    fn block_budget(&mut self) -> u64 {
+        let mut budget = match self.deadline {
+            Some(_) => TIMEOUT_BLOCK_BUDGET,
+            None => u64::MAX,
+        };
+        budget = budget.min(self.mem.device_budget(self.timing.max_instruction_cycles()));
+        if let Some(max) = self.limits.max_instructions {
+            budget = budget.min(max.saturating_sub(self.instructions));
+        }
+        if let Some(max) = self.limits.max_cycles {
+            let remaining = max.saturating_sub(self.cycles);
+            budget =
+                budget.min(remaining.saturating_sub(1) / self.timing.max_instruction_cycles() + 1);
+        }
+        budget
+    }
+
+    /// Returns [`StopReason::LimitExceeded`] if a limit is reached; the timeout is only
+    /// checked if `check_time`
+    fn check_limits(&mut self, check_time: bool) -> Option<StopReason> {
+        let limits = &self.limits;
+        let limit = if limits
+            .max_instructions
+            .is_some_and(|max| self.instructions >= max)
+        {
+            Limit::Instructions
+        } else if limits.max_cycles.is_some_and(|max| self.cycles >= max) {
+            Limit::Cycles
+        } else if limits
+            .max_output
+            .is_some_and(|max| self.mem.output_bytes() > max)
+        {
+            Limit::Output
+        } else if check_time
+            && self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            Limit::Timeout
+        } else {
+            return None;
+        };
+        self.running = false;
+        Some(StopReason::LimitExceeded {
+            pc: self.regs.pc,
+            limit,
+            recent: self.recent.unwrap_or_default(),
+        })
+    }
+
+    /// Returns whether single instructions can be skipped by executing basic blocks
+    fn can_use_blocks(&self) -> bool {
+        self.engine != Engine::Interpreter
+            && self.breakpoints.is_empty()
+            && self.history.is_none()
+            && self.tracers.is_empty()
+            && self.mem.watchpoints().is_empty()
+            && !self.mem.is_replaying()
+    }
+
+    /// Enters the service routines of pending interrupts as long as their priority is higher
+    /// than the priority of the running program; returns whether an interrupt was taken
+    ///
+    /// Like an interrupt on the LC-3, this switches to the supervisor stack if the program runs
+    /// in user mode, saving `R6` as the user stack pointer. It then pushes `PSR` and `PC` onto
+    /// the supervisor stack, enters supervisor mode at the priority of the interrupt and jumps
+    /// to the address in the interrupt vector table at `x0100`. `RTI` returns to the
+    /// interrupted program.
+    fn take_interrupts(&mut self) -> bool {
+        let mut taken = false;
+        while let Some(Interrupt { vector, priority }) = self
+            .mem
+            .pending_interrupt()
+            .filter(|interrupt| interrupt.priority > self.regs.priority())
+        {
+            let psr = self.regs.psr();
+            if self.regs.user_mode() {
+                self.regs.saved_usp = self.regs.read(6);
+                self.regs.write(6, self.regs.saved_ssp);
+            }
+            let sp = self.regs.read(6).wrapping_sub(2);
+            self.mem.write(sp.wrapping_add(1), psr);
+            self.mem.write(sp, self.regs.pc);
+            self.regs.write(6, sp);
+            self.regs.set_psr(priority << 8 | psr & 0x7);
+            self.regs.pc = self.mem.read(INTERRUPT_VECTOR_TABLE + u16::from(vector));
+            taken = true;
         }
+        taken
+    }
+
+    /// Returns the reason why the vm stopped after executing the instruction at `pc`, if any
+    #[inline]
+    fn stop_reason(
+        &mut self,
+        pc: u16,
+        result: Result<Flow, Fault>,
+        watchpoint_hit: Option<WatchpointHit>,
+    ) -> Option<StopReason> {
+        match result {
+            Ok(Flow::Continue) => watchpoint_hit.map(StopReason::Watchpoint),
+            Ok(Flow::Halt) => {
+                self.running = false;
+                Some(StopReason::Halted)
+            }
+            Ok(Flow::WaitForInput) => {
+                self.running = false;
+                self.regs.pc = pc;
+                Some(StopReason::WaitingForInput(pc))
+            }
+            Err(fault) => {
+                self.running = false;
+                self.regs.pc = pc;
+                Some(StopReason::Fault { pc, fault })
+            }
+        }
+    }
+
+    fn main_loop(&mut self) -> StopReason {
+        let mut skipped_breakpoint = self.stopped_at_breakpoint.take();
+        while self.running {
+            let pc = self.regs.pc;
+            if skipped_breakpoint.take() != Some(pc) && self.breakpoints.contains(&pc) {
+                self.stopped_at_breakpoint = Some(pc);
+                return StopReason::Breakpoint(pc);
+            }
+            if self.can_use_blocks() {
+                return self.run_blocks();
+            }
+            if let Some(reason) = self.step() {
+                return reason;
+            }
+        }
+        StopReason::Aborted
+    }
+
+    /// Executes a single (already fetched) instruction
+    fn execute(&mut self, instr: u16, instruction: Instruction) -> Result<Flow, Fault> {
+        instructions::execute(instr, instruction, &mut self.regs, &mut self.mem)
+    }
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
     }
 }
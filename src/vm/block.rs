@@ -0,0 +1,389 @@
+//! Basic-block execution engine
+//!
+//! A basic block is a straight-line run of instructions that starts at an address the vm jumped
+//! or fell through to and ends with the first control-flow instruction (`BR`, `JMP`, `JSR`,
+//! `JSRR`, `RTI` or `TRAP`, and the illegal opcode), so all instructions before the last one
+//! execute unconditionally. Blocks are translated once and then executed as a unit: the cycles
+//! of their instructions are summed up in advance, and the condition flags are only computed
+//! for instructions whose flags can be observed, because no later instruction of the block
+//! overwrites them first.
+//!
+//! Blocks never include device registers (xFE00 and above), since fetching them has
+//! side-effects, and contain at most [`MAX_BLOCK_LEN`] instructions. The [`Memory`] tracks
+//! which addresses belong to translated blocks, so a block is dropped as soon as any of its
+//! words is written; a block that modifies its own remaining instructions stops right after
+//! the store.
+
+use super::instructions::{self, Flow, Instruction, Operand};
+use super::memory::mem_mapped_reg_addr::DEVICE_PAGE;
+use super::memory::MEMORY_SIZE;
+use super::{Engine, Fault, Memory, Registers, TimingModel};
+
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "jit")]
+mod jit;
+#[cfg(test)]
+mod tests;
+
+/// Maximum number of instructions of a block, including the one that ends it
+pub const MAX_BLOCK_LEN: u16 = 64;
+
+/// Instruction of a block that is not its last one
+#[derive(Debug, Clone, Copy)]
+struct BlockOp {
+    instruction: Instruction,
+    /// Whether the condition flags set by the instruction must be computed
+    sets_cond: bool,
+}
+
+/// Translated basic block
+#[derive(Debug)]
+pub struct Block {
+    start: u16,
+    ops: Vec<BlockOp>,
+    /// Cycles of the first `n` ops at index `n`, excluding their data accesses
+    prefix_cycles: Vec<u64>,
+    /// Control-flow instruction that ends the block, as raw and decoded value; `None` if the
+    /// block ends because of its length or the device registers
+    terminator: Option<(u16, Instruction)>,
+    /// Number of executions so far, which decides when the block is compiled
+    #[cfg(feature = "jit")]
+    executions: u32,
+    /// Native code of the block, once it was compiled
+    #[cfg(feature = "jit")]
+    native: Option<jit::NativeBlock>,
+}
+
+/// Result of executing a block
+#[derive(Debug)]
+pub struct BlockExit {
+    /// Address of the block
+    pub start: u16,
+    /// Number of instructions of the block, which the instructions of a block that branches
+    /// to its own start repeat
+    pub len: u16,
+    /// Number of completely executed instructions
+    pub instructions: u64,
+    /// Cycles of the completely executed instructions
+    pub cycles: u64,
+    /// Host time spent in the trap routine of a `TRAP` at the end of the block
+    pub trap_time: Duration,
+    /// Address and result of the control-flow instruction that ended the block, if it was
+    /// reached
+    pub terminator: Option<(u16, Result<Flow, Fault>)>,
+}
+
+impl Block {
+    /// Translates the block that starts at `start`; returns `None` for device registers
+    fn translate(start: u16, mem: &mut Memory, timing: &TimingModel) -> Option<Self> {
+        let mut ops = Vec::new();
+        let mut prefix_cycles = vec![0u64];
+        let mut terminator = None;
+        let mut address = start;
+        while address < DEVICE_PAGE && address - start < MAX_BLOCK_LEN {
+            let (instr, instruction) = mem.fetch_decoded(address);
+            address += 1;
+            if ends_block(instruction) {
+                terminator = Some((instr, instruction));
+                break;
+            }
+            let cycles = timing.instruction_cycles(instr, Default::default());
+            prefix_cycles.push(prefix_cycles[ops.len()].saturating_add(cycles));
+            ops.push(BlockOp {
+                instruction,
+                sets_cond: true,
+            });
+        }
+        if address == start {
+            return None;
+        }
+
+        // The flags are observed by the instruction that ends the block, after the block and
+        // after a store, where execution stops if the store modified code or restarted the timer
+        let mut cond_observed = true;
+        for op in ops.iter_mut().rev() {
+            if is_store(op.instruction) {
+                cond_observed = true;
+            } else {
+                op.sets_cond = cond_observed;
+                cond_observed = false;
+            }
+        }
+
+        Some(Self {
+            start,
+            ops,
+            prefix_cycles,
+            terminator,
+            #[cfg(feature = "jit")]
+            executions: 0,
+            #[cfg(feature = "jit")]
+            native: None,
+        })
+    }
+
+    /// Returns the number of addresses that the block covers
+    fn len(&self) -> u16 {
+        self.ops.len() as u16 + self.terminator.is_some() as u16
+    }
+
+    /// Executes the block, which must start at `PC`
+    ///
+    /// If the control-flow instruction at the end needs input or faults, `PC` points to it and
+    /// it is not counted as executed.
+    pub fn execute(
+        &self,
+        regs: &mut Registers,
+        mem: &mut Memory,
+        timing: &TimingModel,
+    ) -> BlockExit {
+        self.execute_from(0, regs, mem, timing)
+    }
+
+    /// Executes the block from its op with index `first`, whose address must be in `PC`
+    fn execute_from(
+        &self,
+        first: usize,
+        regs: &mut Registers,
+        mem: &mut Memory,
+        timing: &TimingModel,
+    ) -> BlockExit {
+        mem.take_access_counts();
+        let (executed, modified_code) = self.execute_ops(first..self.ops.len(), regs, mem);
+        let mut exit = BlockExit {
+            start: self.start,
+            len: self.len(),
+            instructions: (executed - first) as u64,
+            cycles: (self.prefix_cycles[executed] - self.prefix_cycles[first])
+                .saturating_add(timing.access_cycles(mem.take_access_counts())),
+            trap_time: Duration::ZERO,
+            terminator: None,
+        };
+        if let (Some((instr, instruction)), false) = (self.terminator, modified_code) {
+            let pc = regs.pc;
+            regs.pc = pc.wrapping_add(1);
+            let started = matches!(instruction, Instruction::Trap(_)).then(Instant::now);
+            let result = instructions::execute(instr, instruction, regs, mem);
+            if let Some(started) = started {
+                exit.trap_time = started.elapsed();
+            }
+            if let Ok(Flow::Continue | Flow::Halt) = result {
+                exit.instructions += 1;
+                let cycles = timing.instruction_cycles(instr, mem.take_access_counts());
+                exit.cycles = exit.cycles.saturating_add(cycles);
+            }
+            exit.terminator = Some((pc, result));
+        }
+        exit
+    }
+
+    /// Executes the ops with the given indices and sets `PC` to the next instruction; returns
+    /// the index after the last executed op and whether execution stopped early, because a
+    /// store modified code or restarted the timer
+    fn execute_ops(
+        &self,
+        ops: Range<usize>,
+        regs: &mut Registers,
+        mem: &mut Memory,
+    ) -> (usize, bool) {
+        let mut executed = ops.end;
+        let mut modified_code = false;
+        for (index, op) in self.ops[ops.clone()].iter().enumerate() {
+            let index = ops.start + index;
+            let pc = self.start.wrapping_add(index as u16 + 1);
+            let value = match op.instruction {
+                Instruction::Add { dr, sr1, src2 } => {
+                    let value = regs.read(sr1).wrapping_add(operand(src2, regs));
+                    regs.write(dr, value);
+                    value
+                }
+                Instruction::And { dr, sr1, src2 } => {
+                    let value = regs.read(sr1) & operand(src2, regs);
+                    regs.write(dr, value);
+                    value
+                }
+                Instruction::Not { dr, sr } => {
+                    let value = !regs.read(sr);
+                    regs.write(dr, value);
+                    value
+                }
+                Instruction::Lea { dr, pc_offset } => {
+                    let value = pc.wrapping_add(pc_offset);
+                    regs.write(dr, value);
+                    value
+                }
+                Instruction::Ld { dr, pc_offset } => {
+                    let value = mem.read(pc.wrapping_add(pc_offset));
+                    regs.write(dr, value);
+                    value
+                }
+                Instruction::Ldr { dr, base, offset } => {
+                    let value = mem.read(regs.read(base).wrapping_add(offset));
+                    regs.write(dr, value);
+                    value
+                }
+                Instruction::Ldi { dr, pc_offset } => {
+                    let address = mem.read(pc.wrapping_add(pc_offset));
+                    let value = mem.read(address);
+                    regs.write(dr, value);
+                    value
+                }
+                Instruction::St { sr, pc_offset } => {
+                    mem.write(pc.wrapping_add(pc_offset), regs.read(sr));
+                    if mem.store_ends_block() {
+                        executed = index + 1;
+                        modified_code = true;
+                        break;
+                    }
+                    continue;
+                }
+                Instruction::Str { sr, base, offset } => {
+                    mem.write(regs.read(base).wrapping_add(offset), regs.read(sr));
+                    if mem.store_ends_block() {
+                        executed = index + 1;
+                        modified_code = true;
+                        break;
+                    }
+                    continue;
+                }
+                Instruction::Sti { sr, pc_offset } => {
+                    let address = mem.read(pc.wrapping_add(pc_offset));
+                    mem.write(address, regs.read(sr));
+                    if mem.store_ends_block() {
+                        executed = index + 1;
+                        modified_code = true;
+                        break;
+                    }
+                    continue;
+                }
+                _ => unreachable!("Control-flow instructions only end blocks"),
+            };
+            if op.sets_cond {
+                regs.update_cond_flags(value);
+            }
+        }
+
+        regs.pc = self.start.wrapping_add(executed as u16);
+        (executed, modified_code)
+    }
+}
+
+/// Translated blocks by start address
+#[derive(Debug)]
+pub struct BlockCache {
+    blocks: Box<[Option<Box<Block>>]>,
+    engine: Engine,
+}
+
+impl BlockCache {
+    /// Creates an empty `BlockCache` whose blocks are executed by the given `engine`
+    pub fn new(engine: Engine) -> Self {
+        Self {
+            blocks: (0..MEMORY_SIZE).map(|_| None).collect(),
+            engine,
+        }
+    }
+
+    /// Drops all blocks and executes the following ones by the given `engine`
+    pub fn set_engine(&mut self, engine: Engine, mem: &mut Memory) {
+        self.clear(mem);
+        self.engine = engine;
+    }
+
+    /// Executes the block at `PC`; returns `None` for device registers, which can't be part of a
+    /// block
+    ///
+    /// At most `budget` instructions are executed, which must be at least [`MAX_BLOCK_LEN`].
+    /// Only native code that loops inside a block can exceed a single pass of it.
+    pub fn execute(
+        &mut self,
+        budget: u64,
+        regs: &mut Registers,
+        mem: &mut Memory,
+        timing: &TimingModel,
+    ) -> Option<BlockExit> {
+        #[cfg(feature = "jit")]
+        let engine = self.engine;
+        let block = self.get(regs.pc, mem, timing)?;
+        #[cfg(not(feature = "jit"))]
+        let _ = budget;
+        #[cfg(feature = "jit")]
+        {
+            if let Engine::Jit | Engine::JitChecked = engine {
+                let checked = engine == Engine::JitChecked;
+                return Some(jit::execute(block, checked, budget, regs, mem, timing));
+            }
+        }
+        Some(block.execute(regs, mem, timing))
+    }
+
+    /// Returns the block that starts at `pc`, translating it if necessary; returns `None` for
+    /// device registers
+    ///
+    /// Blocks whose code was written since the last call are dropped first.
+    fn get(&mut self, pc: u16, mem: &mut Memory, timing: &TimingModel) -> Option<&mut Block> {
+        if mem.code_modified() {
+            for address in mem.take_modified_code() {
+                self.invalidate(address, mem);
+            }
+        }
+        let entry = &mut self.blocks[pc as usize];
+        if entry.is_none() {
+            let block = Block::translate(pc, mem, timing)?;
+            mem.add_block(block.start, block.len());
+            *entry = Some(Box::new(block));
+        }
+        entry.as_deref_mut()
+    }
+
+    /// Drops all blocks that contain the given `address`
+    fn invalidate(&mut self, address: u16, mem: &mut Memory) {
+        for start in address.saturating_sub(MAX_BLOCK_LEN - 1)..=address {
+            let entry = &mut self.blocks[start as usize];
+            if entry
+                .as_ref()
+                .is_some_and(|block| block.len() > address - start)
+            {
+                let block = entry.take().unwrap();
+                mem.remove_block(block.start, block.len());
+            }
+        }
+    }
+
+    /// Drops all blocks
+    pub fn clear(&mut self, mem: &mut Memory) {
+        self.blocks.iter_mut().for_each(|entry| *entry = None);
+        mem.clear_blocks();
+    }
+}
+
+/// Returns whether the instruction ends a block
+fn ends_block(instruction: Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::Br { .. }
+            | Instruction::Jmp { .. }
+            | Instruction::Jsr { .. }
+            | Instruction::Jsrr { .. }
+            | Instruction::Rti
+            | Instruction::Trap(_)
+            | Instruction::UnsupportedTrap(_)
+            | Instruction::Illegal
+    )
+}
+
+fn is_store(instruction: Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::St { .. } | Instruction::Str { .. } | Instruction::Sti { .. }
+    )
+}
+
+fn operand(src2: Operand, regs: &Registers) -> u16 {
+    match src2 {
+        Operand::Immediate(imm) => imm,
+        Operand::Register(src_reg2) => regs.read(src_reg2),
+    }
+}
@@ -0,0 +1,723 @@
+//! Native x86-64 code for frequently executed basic blocks
+//!
+//! A block is compiled once it was executed [`HOT_THRESHOLD`] times. The native code keeps the
+//! LC-3 registers in a [`JitState`] that is copied from and to the [`Registers`] around each
+//! call. It reads ordinary memory directly, while stores go through [`Memory::write`], so
+//! decoded instructions and blocks are invalidated as usual. `BR`, `JMP`, `JSR` and `JSRR` at
+//! the end of a block are compiled as well, and a `BR` back to the start of its own block loops
+//! inside the native code, up to a number of iterations that keeps the instruction limits of
+//! the vm. The native code returns to the interpreter:
+//!
+//! - before a load or store whose address is a device register, which the interpreter then
+//!   executes with all its side-effects,
+//! - after a store that modified code, like an interpreted block,
+//! - at the end of the block, where the interpreter executes a `TRAP` or illegal instruction.
+//!
+//! Instructions whose address is a device register no matter what the registers contain end the
+//! native code early. The cycles of native instructions are computed when compiling, since all
+//! of their data accesses hit ordinary memory.
+//!
+//! The generated code follows the System V calling convention: it receives the `JitState` in
+//! `rdi`, keeps it in `rbx` and returns the number of executed instructions of the last
+//! iteration in `eax`, with [`CODE_MODIFIED`] set if it stopped after a store that modified
+//! code and [`JUMPED`] set if it executed the instruction at the end of the block.
+
+#[cfg(not(all(target_arch = "x86_64", target_os = "linux")))]
+compile_error!("The `jit` feature is only supported on x86-64 Linux");
+
+use super::{Block, BlockExit};
+use crate::vm::instructions::{self, Instruction, Operand};
+use crate::vm::memory::mem_mapped_reg_addr::DEVICE_PAGE;
+use crate::vm::{Memory, Registers, TimingModel};
+
+use std::collections::BTreeMap;
+use std::io;
+use std::ptr;
+use std::time::Duration;
+
+/// Number of executions after which a block is compiled
+const HOT_THRESHOLD: u32 = 16;
+
+/// Flag in the result of native code that tells that it stopped after a store modified code
+const CODE_MODIFIED: u32 = 1 << 16;
+/// Flag in the result of native code that tells that it executed the jump at the end of the
+/// block and set `JitState::pc`
+const JUMPED: u32 = 1 << 17;
+
+/// Offset of the code after the prologue, where iterations of a block start
+const BODY_OFFSET: usize = 4;
+
+/// Offset of `JitState::cond`
+const COND_OFFSET: u8 = 16;
+/// Offset of `JitState::pc`
+const PC_OFFSET: u8 = 18;
+/// Offset of `JitState::loops`
+const LOOPS_OFFSET: u8 = 24;
+/// Offset of `JitState::words`
+const WORDS_OFFSET: u8 = 32;
+/// Offset of `JitState::memory`
+const MEMORY_OFFSET: u8 = 40;
+/// Offset of `JitState::max_loops`
+const MAX_LOOPS_OFFSET: u8 = 48;
+
+/// State that native code works on
+#[repr(C)]
+struct JitState {
+    regs: [u16; 8],
+    /// Condition flags as in bits 2..0 of the `PSR`
+    cond: u16,
+    /// Target of the jump at the end of the block
+    pc: u16,
+    /// Number of completed iterations of a block that branches to its own start
+    loops: u64,
+    /// First memory word, see [`Memory::words_ptr`]
+    words: *mut u16,
+    memory: *mut Memory,
+    /// Number of iterations after which a block that branches to its own start returns
+    max_loops: u64,
+}
+
+type NativeFn = unsafe extern "C" fn(*mut JitState) -> u32;
+
+/// Compiled instructions of a block
+#[derive(Debug)]
+pub struct NativeBlock {
+    code: ExecutableCode,
+    /// Cycles of the first `n` compiled ops at index `n`, including data accesses
+    cycles: Vec<u64>,
+    /// Cycles of the compiled jump at the end of the block, if any
+    jump_cycles: Option<u64>,
+}
+
+/// Result of running native code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct NativeExit {
+    /// Number of complete iterations of a block that branches to its own start
+    loops: u64,
+    /// Number of executed ops in the last iteration
+    executed: usize,
+    /// Whether the jump at the end of the block was executed
+    jumped: bool,
+    /// Whether execution stopped after a store that modified code
+    modified_code: bool,
+}
+
+impl NativeBlock {
+    /// Compiles `block`; returns `None` if its first instruction can't be compiled or no
+    /// executable memory is available
+    fn compile(block: &Block, timing: &TimingModel) -> Option<Self> {
+        let mut asm = Assembler::new();
+        let mut cycles = vec![0u64];
+        for (index, op) in block.ops.iter().enumerate() {
+            let pc = block.start.wrapping_add(index as u16 + 1);
+            let accesses = match asm.op(op.instruction, op.sets_cond, pc, index as u32) {
+                Some(accesses) => accesses,
+                None => break,
+            };
+            let op_cycles = block.prefix_cycles[index + 1] - block.prefix_cycles[index];
+            let access_cycles = accesses.saturating_mul(timing.memory_cycles());
+            cycles.push(cycles[index].saturating_add(op_cycles.saturating_add(access_cycles)));
+        }
+
+        let compiled = cycles.len() - 1;
+        let jump = match block.terminator {
+            Some((instr, instruction)) if compiled == block.ops.len() => {
+                let pc = block.start.wrapping_add(compiled as u16 + 1);
+                if asm.jump(instruction, pc, block.start, compiled as u32) {
+                    Some(instr)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        };
+        if jump.is_none() {
+            if compiled == 0 {
+                return None;
+            }
+            asm.emit_return(compiled as u32);
+        }
+        let jump_cycles = jump.map(|instr| timing.instruction_cycles(instr, Default::default()));
+        ExecutableCode::new(&asm.finish()).ok().map(|code| Self {
+            code,
+            cycles,
+            jump_cycles,
+        })
+    }
+
+    /// Returns the number of compiled ops
+    fn len(&self) -> usize {
+        self.cycles.len() - 1
+    }
+
+    /// Runs the native code of `block`, which must start at `PC`, and sets `PC` to the next
+    /// instruction; a block that branches to its own start returns after `max_loops` complete
+    /// iterations
+    fn run(
+        &self,
+        block: &Block,
+        max_loops: u64,
+        regs: &mut Registers,
+        mem: &mut Memory,
+    ) -> NativeExit {
+        let memory: *mut Memory = mem;
+        let mut state = JitState {
+            regs: [0; 8],
+            cond: regs.cond as u16,
+            pc: 0,
+            loops: 0,
+            words: Memory::words_ptr(memory),
+            memory,
+            max_loops,
+        };
+        for (index, reg) in state.regs.iter_mut().enumerate() {
+            *reg = regs.read(index as u16);
+        }
+        // SAFETY: the code was generated for the `JitState` layout, only accesses the words
+        // through `words` below DEVICE_PAGE and calls `write_word` with `memory`, which both
+        // stay valid during the call
+        let result = unsafe { self.code.entry()(&mut state) };
+        for (index, &reg) in state.regs.iter().enumerate() {
+            regs.write(index as u16, reg);
+        }
+        // Native code only writes the condition flags, so the priority level is kept
+        regs.set_psr(regs.psr() & !0x7 | state.cond);
+
+        let exit = NativeExit {
+            loops: state.loops,
+            executed: (result & 0xFFFF) as usize,
+            jumped: result & JUMPED != 0,
+            modified_code: result & CODE_MODIFIED != 0,
+        };
+        regs.pc = if exit.jumped {
+            state.pc
+        } else {
+            block.start.wrapping_add(exit.executed as u16)
+        };
+        exit
+    }
+
+    /// Returns the number of instructions and cycles of the native execution `exit`
+    fn count(&self, exit: NativeExit) -> (u64, u64) {
+        let jump_cycles = self.jump_cycles.unwrap_or_default();
+        let instructions =
+            exit.loops * (self.len() as u64 + 1) + exit.executed as u64 + exit.jumped as u64;
+        let cycles = exit
+            .loops
+            .saturating_mul(self.cycles[self.len()].saturating_add(jump_cycles))
+            .saturating_add(self.cycles[exit.executed])
+            .saturating_add(if exit.jumped { jump_cycles } else { 0 });
+        (instructions, cycles)
+    }
+}
+
+/// Executes the block, compiling it once it is hot; if `checked`, native code is compared with
+/// the interpreter, see [`Engine::JitChecked`](crate::vm::Engine::JitChecked)
+///
+/// At most `budget` instructions are executed, which must be at least the length of the block.
+pub fn execute(
+    block: &mut Block,
+    checked: bool,
+    budget: u64,
+    regs: &mut Registers,
+    mem: &mut Memory,
+    timing: &TimingModel,
+) -> BlockExit {
+    if block.executions < HOT_THRESHOLD {
+        block.executions += 1;
+        if block.executions == HOT_THRESHOLD {
+            block.native = NativeBlock::compile(block, timing);
+        }
+    }
+    let native = match &block.native {
+        Some(native) => native,
+        None => return block.execute(regs, mem, timing),
+    };
+
+    // Every iteration and the last, possibly incomplete one execute at most `len + 1`
+    let max_loops = (budget / (native.len() as u64 + 1)).saturating_sub(1);
+    let exit = if checked {
+        run_checked(block, native, max_loops, regs, mem, timing)
+    } else {
+        native.run(block, max_loops, regs, mem)
+    };
+    let (instructions, cycles) = native.count(exit);
+    if exit.jumped || exit.modified_code {
+        return BlockExit {
+            start: block.start,
+            len: block.len(),
+            instructions,
+            cycles,
+            trap_time: Duration::ZERO,
+            terminator: None,
+        };
+    }
+    let mut block_exit = block.execute_from(exit.executed, regs, mem, timing);
+    block_exit.instructions += instructions;
+    block_exit.cycles = block_exit.cycles.saturating_add(cycles);
+    block_exit
+}
+
+/// Runs the native code like [`NativeBlock::run`] and interprets the same instructions from
+/// the same state, which determines the resulting state
+///
+/// # Panics
+///
+/// Panics if the native code and the interpreter disagree.
+fn run_checked(
+    block: &Block,
+    native: &NativeBlock,
+    max_loops: u64,
+    regs: &mut Registers,
+    mem: &mut Memory,
+    timing: &TimingModel,
+) -> NativeExit {
+    let regs_before = regs.clone();
+    mem.begin_journal();
+    let exit = native.run(block, max_loops, regs, mem);
+    let native_writes = mem.end_journal().writes;
+    let native_regs = regs.clone();
+    let mut native_memory = BTreeMap::new();
+    for &(address, _) in &native_writes {
+        native_memory.insert(address, mem.peek(address));
+    }
+    for &(address, old_value) in native_writes.iter().rev() {
+        mem.poke(address, old_value);
+    }
+    // The interpreter reports the modified code again
+    mem.take_modified_code();
+
+    *regs = regs_before;
+    mem.take_access_counts();
+    mem.begin_journal();
+    let context = format!("JIT mismatch in the block at x{:04X}", block.start);
+    let mut cycles = 0u64;
+    for iteration in 0..=exit.loops {
+        let ops = match iteration == exit.loops {
+            true => 0..exit.executed,
+            false => 0..block.ops.len(),
+        };
+        let expected = (ops.end, iteration == exit.loops && exit.modified_code);
+        let result = block.execute_ops(ops, regs, mem);
+        assert_eq!(
+            result, expected,
+            "{}: stop in iteration {}",
+            context, iteration
+        );
+        cycles = cycles.saturating_add(block.prefix_cycles[result.0]);
+        if let (Some((instr, instruction)), true) =
+            (block.terminator, iteration < exit.loops || exit.jumped)
+        {
+            regs.pc = regs.pc.wrapping_add(1);
+            let result = instructions::execute(instr, instruction, regs, mem);
+            assert_eq!(
+                result,
+                Ok(instructions::Flow::Continue),
+                "{}: jump",
+                context
+            );
+            cycles = cycles.saturating_add(timing.instruction_cycles(instr, Default::default()));
+        }
+    }
+    let writes = mem.end_journal().writes;
+    cycles = cycles.saturating_add(timing.access_cycles(mem.take_access_counts()));
+    for &(address, old_value) in &writes {
+        native_memory.entry(address).or_insert(old_value);
+    }
+
+    for index in 0..8 {
+        let (native_value, value) = (native_regs.read(index), regs.read(index));
+        assert_eq!(native_value, value, "{}: R{}", context, index);
+    }
+    assert_eq!(native_regs.pc, regs.pc, "{}: PC", context);
+    assert_eq!(native_regs.psr(), regs.psr(), "{}: PSR", context);
+    for (&address, &native_value) in &native_memory {
+        let value = mem.peek(address);
+        assert_eq!(native_value, value, "{}: [x{:04X}]", context, address);
+    }
+    assert_eq!(native.count(exit).1, cycles, "{}: cycles", context);
+    exit
+}
+
+/// Writes a word for native code; returns whether the store ends the block like it would in an
+/// interpreted block
+unsafe extern "C" fn write_word(memory: *mut Memory, address: u32, value: u32) -> u32 {
+    let mem = &mut *memory;
+    mem.write(address as u16, value as u16);
+    mem.store_ends_block() as u32
+}
+
+/// Register of the general-purpose x86-64 registers used by the generated code
+#[derive(Debug, Clone, Copy)]
+enum Reg {
+    Eax = 0,
+    Ecx = 1,
+    Edx = 2,
+    Esi = 6,
+}
+
+/// Generator of the machine code of a block
+struct Assembler {
+    code: Vec<u8>,
+    /// Positions of `rel32` jump targets that exit the native code, with the value returned
+    exits: Vec<(usize, u32)>,
+}
+
+impl Assembler {
+    fn new() -> Self {
+        let mut asm = Self {
+            code: Vec::new(),
+            exits: Vec::new(),
+        };
+        // push rbx; mov rbx, rdi
+        asm.emit(&[0x53, 0x48, 0x89, 0xFB]);
+        asm
+    }
+
+    /// Emits the instruction `instruction` at `pc - 1`, the op at `index` of its block;
+    /// returns its number of data accesses or `None` if it can't be compiled
+    fn op(
+        &mut self,
+        instruction: Instruction,
+        sets_cond: bool,
+        pc: u16,
+        index: u32,
+    ) -> Option<u64> {
+        let accesses = match instruction {
+            Instruction::Add { dr, sr1, src2 } => {
+                self.load_reg(Reg::Eax, sr1);
+                match src2 {
+                    Operand::Immediate(imm) => self.emit_imm(&[0x05], imm.into()),
+                    Operand::Register(sr2) => {
+                        self.load_reg(Reg::Ecx, sr2);
+                        self.emit(&[0x01, 0xC8]);
+                    }
+                }
+                self.set_reg(dr, sets_cond);
+                0
+            }
+            Instruction::And { dr, sr1, src2 } => {
+                self.load_reg(Reg::Eax, sr1);
+                match src2 {
+                    Operand::Immediate(imm) => self.emit_imm(&[0x25], imm.into()),
+                    Operand::Register(sr2) => {
+                        self.load_reg(Reg::Ecx, sr2);
+                        self.emit(&[0x21, 0xC8]);
+                    }
+                }
+                self.set_reg(dr, sets_cond);
+                0
+            }
+            Instruction::Not { dr, sr } => {
+                self.load_reg(Reg::Eax, sr);
+                self.emit(&[0xF7, 0xD0]);
+                self.set_reg(dr, sets_cond);
+                0
+            }
+            Instruction::Lea { dr, pc_offset } => {
+                self.emit_imm(&[0xB8], pc.wrapping_add(pc_offset).into());
+                self.set_reg(dr, sets_cond);
+                0
+            }
+            Instruction::Ld { dr, pc_offset } => {
+                let address = memory_address(pc.wrapping_add(pc_offset))?;
+                self.load_words_ptr();
+                // movzx eax, word [rdx + disp32]
+                self.emit_imm(&[0x0F, 0xB7, 0x82], address * 2);
+                self.set_reg(dr, sets_cond);
+                1
+            }
+            Instruction::Ldr { dr, base, offset } => {
+                self.load_reg(Reg::Eax, base);
+                self.emit_imm(&[0x05], offset.into());
+                // movzx eax, ax
+                self.emit(&[0x0F, 0xB7, 0xC0]);
+                self.exit_if_device(Reg::Eax, index);
+                self.load_words_ptr();
+                // movzx eax, word [rdx + rax * 2]
+                self.emit(&[0x0F, 0xB7, 0x04, 0x42]);
+                self.set_reg(dr, sets_cond);
+                1
+            }
+            Instruction::Ldi { dr, pc_offset } => {
+                let pointer = memory_address(pc.wrapping_add(pc_offset))?;
+                self.load_words_ptr();
+                self.emit_imm(&[0x0F, 0xB7, 0x82], pointer * 2);
+                self.exit_if_device(Reg::Eax, index);
+                self.emit(&[0x0F, 0xB7, 0x04, 0x42]);
+                self.set_reg(dr, sets_cond);
+                2
+            }
+            Instruction::St { sr, pc_offset } => {
+                let address = memory_address(pc.wrapping_add(pc_offset))?;
+                self.emit_imm(&[0xBE], address);
+                self.write_word(sr, index);
+                1
+            }
+            Instruction::Str { sr, base, offset } => {
+                self.load_reg(Reg::Eax, base);
+                self.emit_imm(&[0x05], offset.into());
+                // movzx esi, ax
+                self.emit(&[0x0F, 0xB7, 0xF0]);
+                self.exit_if_device(Reg::Esi, index);
+                self.write_word(sr, index);
+                1
+            }
+            Instruction::Sti { sr, pc_offset } => {
+                let pointer = memory_address(pc.wrapping_add(pc_offset))?;
+                self.load_words_ptr();
+                // movzx esi, word [rdx + disp32]
+                self.emit_imm(&[0x0F, 0xB7, 0xB2], pointer * 2);
+                self.exit_if_device(Reg::Esi, index);
+                self.write_word(sr, index);
+                2
+            }
+            _ => return None,
+        };
+        Some(accesses)
+    }
+
+    /// Emits the jump `instruction` at `pc - 1` that ends the block at `start` after
+    /// `executed` ops; returns `false` if it can't be compiled
+    fn jump(&mut self, instruction: Instruction, pc: u16, start: u16, executed: u32) -> bool {
+        let result = executed | JUMPED;
+        match instruction {
+            Instruction::Br { nzp, pc_offset } => {
+                let target = pc.wrapping_add(pc_offset);
+                if nzp != 0 {
+                    let not_taken = match nzp {
+                        0x7 => None,
+                        _ => {
+                            // test word [rbx + COND_OFFSET], nzp; jz not_taken
+                            self.emit(&[0x66, 0xF7, 0x43, COND_OFFSET]);
+                            self.emit(&nzp.to_le_bytes());
+                            self.emit(&[0x0F, 0x84]);
+                            Some(self.emit_rel32())
+                        }
+                    };
+                    if target == start {
+                        // mov rax, [rbx + LOOPS_OFFSET]; cmp rax, [rbx + MAX_LOOPS_OFFSET];
+                        // jae exit
+                        self.set_pc(target);
+                        self.emit(&[0x48, 0x8B, 0x43, LOOPS_OFFSET]);
+                        self.emit(&[0x48, 0x3B, 0x43, MAX_LOOPS_OFFSET]);
+                        self.emit_exit(&[0x0F, 0x83], result);
+                        // inc qword [rbx + LOOPS_OFFSET]; jmp body
+                        self.emit(&[0x48, 0xFF, 0x43, LOOPS_OFFSET, 0xE9]);
+                        let position = self.emit_rel32();
+                        self.patch_rel32(position, BODY_OFFSET);
+                    } else {
+                        self.set_pc(target);
+                        self.emit_return(result);
+                    }
+                    match not_taken {
+                        Some(position) => self.patch_rel32(position, self.code.len()),
+                        None => return true,
+                    }
+                }
+                self.set_pc(pc);
+            }
+            Instruction::Jmp { base } => {
+                self.load_reg(Reg::Eax, base);
+                // mov [rbx + PC_OFFSET], ax
+                self.emit(&[0x66, 0x89, 0x43, PC_OFFSET]);
+            }
+            Instruction::Jsr { pc_offset } => {
+                self.set_r7(pc);
+                self.set_pc(pc.wrapping_add(pc_offset));
+            }
+            Instruction::Jsrr { base } => {
+                self.load_reg(Reg::Eax, base);
+                self.set_r7(pc);
+                self.emit(&[0x66, 0x89, 0x43, PC_OFFSET]);
+            }
+            _ => return false,
+        }
+        self.emit_return(result);
+        true
+    }
+
+    /// Emits the exits; returns the machine code
+    fn finish(mut self) -> Vec<u8> {
+        for (position, result) in std::mem::take(&mut self.exits) {
+            self.patch_rel32(position, self.code.len());
+            self.emit_return(result);
+        }
+        self.code
+    }
+
+    /// Emits a placeholder for a `rel32` jump target; returns its position
+    fn emit_rel32(&mut self) -> usize {
+        self.emit(&[0; 4]);
+        self.code.len() - 4
+    }
+
+    /// Sets the `rel32` jump target at `position` to the code at `target`
+    fn patch_rel32(&mut self, position: usize, target: usize) {
+        let offset = target as i64 - (position + 4) as i64;
+        self.code[position..position + 4].copy_from_slice(&(offset as i32).to_le_bytes());
+    }
+
+    /// Emits `mov word [rbx + PC_OFFSET], pc`
+    fn set_pc(&mut self, pc: u16) {
+        self.emit(&[0x66, 0xC7, 0x43, PC_OFFSET]);
+        self.emit(&pc.to_le_bytes());
+    }
+
+    /// Emits `mov word [rbx + 2 * 7], pc`, which sets `R7`
+    fn set_r7(&mut self, pc: u16) {
+        self.emit(&[0x66, 0xC7, 0x43, 14]);
+        self.emit(&pc.to_le_bytes());
+    }
+
+    fn emit(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    fn emit_imm(&mut self, bytes: &[u8], imm: u32) {
+        self.emit(bytes);
+        self.emit(&imm.to_le_bytes());
+    }
+
+    /// Emits `mov eax, result; pop rbx; ret`
+    fn emit_return(&mut self, result: u32) {
+        self.emit_imm(&[0xB8], result);
+        self.emit(&[0x5B, 0xC3]);
+    }
+
+    /// Emits a `jcc rel32` with the given opcode bytes that exits with `result`
+    fn emit_exit(&mut self, jcc: &[u8], result: u32) {
+        self.emit(jcc);
+        let position = self.emit_rel32();
+        self.exits.push((position, result));
+    }
+
+    /// Emits `movzx reg, word [rbx + 2 * lc3_reg]`
+    fn load_reg(&mut self, reg: Reg, lc3_reg: u16) {
+        self.emit(&[0x0F, 0xB7, 0x43 | (reg as u8) << 3, lc3_reg as u8 * 2]);
+    }
+
+    /// Emits `mov rdx, [rbx + WORDS_OFFSET]`
+    fn load_words_ptr(&mut self) {
+        self.emit(&[0x48, 0x8B, 0x53, WORDS_OFFSET]);
+    }
+
+    /// Stores `ax` in the LC-3 register `dr` and sets the condition flags if `sets_cond`
+    fn set_reg(&mut self, dr: u16, sets_cond: bool) {
+        // mov [rbx + 2 * dr], ax
+        self.emit(&[0x66, 0x89, 0x43, dr as u8 * 2]);
+        if sets_cond {
+            self.emit(&[
+                0x66,
+                0x85,
+                0xC0, // test ax, ax
+                0xB9,
+                0x02,
+                0x00,
+                0x00,
+                0x00, // mov ecx, Z
+                0x74,
+                0x0C, // jz store
+                0xB9,
+                0x01,
+                0x00,
+                0x00,
+                0x00, // mov ecx, P
+                0x79,
+                0x05, // jns store
+                0xB9,
+                0x04,
+                0x00,
+                0x00,
+                0x00, // mov ecx, N
+                0x66,
+                0x89,
+                0x4B,
+                COND_OFFSET, // store: mov [rbx + COND_OFFSET], cx
+            ]);
+        }
+    }
+
+    /// Exits with `index` if `reg` (`eax` or `esi`) is a device register address
+    fn exit_if_device(&mut self, reg: Reg, index: u32) {
+        match reg {
+            Reg::Eax => self.emit_imm(&[0x3D], DEVICE_PAGE.into()),
+            _ => self.emit_imm(&[0x81, 0xF8 | reg as u8], DEVICE_PAGE.into()),
+        }
+        // jae exit
+        self.emit_exit(&[0x0F, 0x83], index);
+    }
+
+    /// Writes the LC-3 register `sr` to the address in `esi` through [`write_word`], exiting
+    /// after the op at `index` if that modified code
+    fn write_word(&mut self, sr: u16, index: u32) {
+        self.load_reg(Reg::Edx, sr);
+        // mov rdi, [rbx + MEMORY_OFFSET]
+        self.emit(&[0x48, 0x8B, 0x7B, MEMORY_OFFSET]);
+        // mov rax, write_word; call rax
+        self.emit(&[0x48, 0xB8]);
+        self.emit(&(write_word as *const () as u64).to_le_bytes());
+        self.emit(&[0xFF, 0xD0]);
+        // test eax, eax; jnz exit
+        self.emit(&[0x85, 0xC0]);
+        self.emit_exit(&[0x0F, 0x85], (index + 1) | CODE_MODIFIED);
+    }
+}
+
+/// Returns the `address` if it is ordinary memory
+fn memory_address(address: u16) -> Option<u32> {
+    if address < DEVICE_PAGE {
+        Some(address.into())
+    } else {
+        None
+    }
+}
+
+/// Executable memory pages that hold generated code
+#[derive(Debug)]
+struct ExecutableCode {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+impl ExecutableCode {
+    /// Copies the `code` to new pages that are executable, but not writable
+    fn new(code: &[u8]) -> io::Result<Self> {
+        let len = code.len();
+        // SAFETY: maps new anonymous pages, which are only accessed within their length
+        unsafe {
+            let ptr = libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            if ptr == libc::MAP_FAILED {
+                return Err(io::Error::last_os_error());
+            }
+            let pages = Self { ptr, len };
+            ptr::copy_nonoverlapping(code.as_ptr(), ptr.cast(), len);
+            if libc::mprotect(ptr, len, libc::PROT_READ | libc::PROT_EXEC) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(pages)
+        }
+    }
+
+    /// Returns the generated function
+    fn entry(&self) -> NativeFn {
+        // SAFETY: the pages hold a function generated by `Assembler`
+        unsafe { std::mem::transmute::<*mut libc::c_void, NativeFn>(self.ptr) }
+    }
+}
+
+impl Drop for ExecutableCode {
+    fn drop(&mut self) {
+        // SAFETY: the pages were mapped in `new` and are no longer used
+        unsafe {
+            libc::munmap(self.ptr, self.len);
+        }
+    }
+}
@@ -0,0 +1,80 @@
+//! Translation of basic blocks and their invalidation by stores
+
+use super::*;
+use crate::vm::registers::CondFlag;
+use crate::vm::BufferedConsole;
+
+/// Address of the first instruction of the programs
+const START: u16 = 0x3000;
+
+fn memory(program: &[u16]) -> Memory {
+    let mut mem = Memory::new();
+    mem.set_console(Box::new(BufferedConsole::new()));
+    for (offset, &word) in program.iter().enumerate() {
+        mem.poke(START + offset as u16, word);
+    }
+    mem
+}
+
+#[test]
+fn computes_only_observed_flags() {
+    let mut mem = memory(&[
+        0x1021, // ADD R0, R0, #1
+        0x127F, // ADD R1, R1, #-1
+        0x3210, // ST R1, x3013
+        0x54A0, // AND R2, R2, #0
+        0x96FF, // NOT R3, R3
+        0x0402, // BRz #2
+    ]);
+    let block = Block::translate(START, &mut mem, &TimingModel::default()).unwrap();
+    let sets_cond: Vec<bool> = block
+        .ops
+        .iter()
+        .filter(|op| !is_store(op.instruction))
+        .map(|op| op.sets_cond)
+        .collect();
+    // The flags of the first `ADD` and the `AND` are overwritten before anything observes them
+    assert_eq!(sets_cond, [false, true, false, true]);
+    assert_eq!(block.len(), 6);
+
+    let mut regs = Registers::new();
+    regs.write(3, 0xFFFF);
+    let exit = block.execute(&mut regs, &mut mem, &TimingModel::default());
+    assert_eq!(exit.instructions, 6);
+    assert_eq!(regs.cond, CondFlag::Zero);
+    assert_eq!(regs.pc, 0x3008);
+    assert_eq!(mem.peek(0x3013), 0xFFFF);
+}
+
+#[test]
+fn drops_blocks_that_modify_themselves() {
+    let mut mem = memory(&[
+        0x2004, // LD R0, PATCH
+        0x3001, // ST R0, x3003
+        0x1261, // ADD R1, R1, #1
+        0x1261, // ADD R1, R1, #1
+        0xF025, // HALT
+        0x1265, // PATCH ADD R1, R1, #5
+    ]);
+    let timing = TimingModel::default();
+    let mut cache = BlockCache::new(Engine::BasicBlocks);
+    let mut regs = Registers::new();
+
+    // The block stops right after the store to its own code
+    let exit = cache
+        .execute(u64::MAX, &mut regs, &mut mem, &timing)
+        .unwrap();
+    assert_eq!(exit.instructions, 2);
+    assert!(exit.terminator.is_none());
+    assert_eq!(regs.pc, 0x3002);
+    assert!(mem.code_modified());
+
+    // The next block is translated from the patched code, after the old block was dropped
+    let exit = cache
+        .execute(u64::MAX, &mut regs, &mut mem, &timing)
+        .unwrap();
+    assert!(cache.blocks[START as usize].is_none());
+    assert_eq!(exit.start, 0x3002);
+    assert!(matches!(exit.terminator, Some((0x3004, Ok(Flow::Halt)))));
+    assert_eq!(regs.read(1), 6);
+}
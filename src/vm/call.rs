@@ -0,0 +1,307 @@
+//! Calls of single LC-3 subroutines from Rust
+//!
+//! [`Vm::call`] passes arguments in `R0` and up, sets `R7` to the sentinel
+//! [`CallingConvention::return_address`] and runs until the routine returns there. Afterwards
+//! it reports the registers, the changed memory and the violations of the configured
+//! [`CallingConvention`]: callee-saved registers with a different value and a stack pointer
+//! that does not match its value at the call.
+
+use super::memory::mem_mapped_reg_addr::DEVICE_PAGE;
+use super::{StopReason, Vm};
+
+use std::fmt;
+
+/// Register usage of subroutines, which [`Vm::call`] checks
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallingConvention {
+    /// Registers that must have the same value after the call as at the call
+    pub callee_saved: Vec<u16>,
+    /// Register that holds the stack pointer, which must be the same after the call
+    pub stack_pointer: Option<u16>,
+    /// Initial stack pointer, unless an argument sets the stack pointer register
+    pub stack_top: u16,
+    /// Sentinel address in `R7` at the call; the routine has returned once `PC` reaches it
+    pub return_address: u16,
+}
+
+impl Default for CallingConvention {
+    /// The textbook convention: `R0` returns a value, `R1` to `R5` are restored by the callee
+    /// and `R6` is a stack pointer that starts below the device registers. The sentinel
+    /// return address is xFFFF, which no program executes.
+    fn default() -> Self {
+        Self {
+            callee_saved: vec![1, 2, 3, 4, 5],
+            stack_pointer: Some(6),
+            stack_top: DEVICE_PAGE,
+            return_address: 0xFFFF,
+        }
+    }
+}
+
+/// Routine for [`Vm::call`], given by address or label
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Routine<'a> {
+    Address(u16),
+    /// Label resolved with the symbols of [`Vm::set_symbols`]
+    Symbol(&'a str),
+}
+
+impl From<u16> for Routine<'_> {
+    fn from(address: u16) -> Self {
+        Routine::Address(address)
+    }
+}
+
+impl<'a> From<&'a str> for Routine<'a> {
+    fn from(symbol: &'a str) -> Self {
+        Routine::Symbol(symbol)
+    }
+}
+
+/// Error that prevents a call
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CallError {
+    /// The label is not in the symbols of the vm
+    UnknownSymbol(String),
+    /// More arguments than the seven registers `R0` to `R6` were given
+    TooManyArguments(usize),
+    /// The calling convention names a register other than `R0` to `R7`
+    InvalidRegister(u16),
+}
+
+impl fmt::Display for CallError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CallError::UnknownSymbol(symbol) => write!(f, "Unknown symbol: {}", symbol),
+            CallError::TooManyArguments(count) => {
+                write!(f, "Too many arguments: {} (at most 7)", count)
+            }
+            CallError::InvalidRegister(register) => {
+                write!(
+                    f,
+                    "Invalid register in the calling convention: R{}",
+                    register
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for CallError {}
+
+/// Word of memory that a call changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryChange {
+    pub address: u16,
+    pub old_value: u16,
+    pub new_value: u16,
+}
+
+/// Violation of the [`CallingConvention`] by a routine that returned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Violation {
+    /// A callee-saved register has a different value after the call
+    Clobbered {
+        register: u16,
+        before: u16,
+        after: u16,
+    },
+    /// The stack pointer has a different value after the call
+    StackImbalance { before: u16, after: u16 },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Violation::Clobbered {
+                register,
+                before,
+                after,
+            } => write!(
+                f,
+                "R{} was clobbered: x{:04X} before the call, x{:04X} after it",
+                register, before, after
+            ),
+            Violation::StackImbalance { before, after } => {
+                let words = before.wrapping_sub(after) as i16;
+                let imbalance = match words > 0 {
+                    true => format!("{} more words pushed than popped", words),
+                    false => format!("{} more words popped than pushed", -words),
+                };
+                write!(
+                    f,
+                    "Stack imbalance: the stack pointer was x{:04X} before the call and x{:04X} \
+                     after it ({})",
+                    before, after, imbalance
+                )
+            }
+        }
+    }
+}
+
+/// Effects of a call
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallResult {
+    /// Reason why the vm stopped before the routine returned, or `None` if it returned
+    pub stop: Option<StopReason>,
+    /// `R0` to `R7` after the call
+    pub registers: [u16; 8],
+    /// Number of instructions executed by the call
+    pub instructions: u64,
+    /// Words below the device registers whose value changed, ordered by address
+    pub memory_changes: Vec<MemoryChange>,
+    /// Violations of the calling convention; only checked if the routine returned
+    pub violations: Vec<Violation>,
+}
+
+impl CallResult {
+    /// Returns whether the routine returned to the sentinel return address
+    pub fn returned(&self) -> bool {
+        self.stop.is_none()
+    }
+
+    /// Returns whether the routine returned without violating the calling convention
+    pub fn is_ok(&self) -> bool {
+        self.returned() && self.violations.is_empty()
+    }
+}
+
+impl Vm {
+    /// Replaces the labels that [`call`](Self::call) resolves, e.g. with
+    /// [`DebugInfo::symbols`](crate::DebugInfo::symbols)
+    pub fn set_symbols<'a>(&mut self, symbols: impl IntoIterator<Item = (u16, &'a str)>) {
+        self.symbols = symbols
+            .into_iter()
+            .map(|(address, name)| (name.to_string(), address))
+            .collect();
+    }
+
+    /// Returns the calling convention of [`call`](Self::call)
+    pub fn calling_convention(&self) -> &CallingConvention {
+        &self.calling_convention
+    }
+
+    /// Sets the calling convention of [`call`](Self::call)
+    pub fn set_calling_convention(&mut self, convention: CallingConvention) {
+        self.calling_convention = convention;
+    }
+
+    /// Calls the subroutine `routine` with the arguments `args` in `R0` and up, and runs until
+    /// it returns or the vm stops otherwise
+    ///
+    /// `R7` is set to the sentinel return address of the calling convention, and the stack
+    /// pointer to its top unless an argument sets it. The other registers, the memory and the
+    /// console keep their state, and [limits](Self::set_limits) apply as usual. While the
+    /// call runs, the vm has a breakpoint at the return address, so it always interprets.
+    pub fn call<'a>(
+        &mut self,
+        routine: impl Into<Routine<'a>>,
+        args: &[u16],
+    ) -> Result<CallResult, CallError> {
+        let address = match routine.into() {
+            Routine::Address(address) => address,
+            Routine::Symbol(symbol) => *self
+                .symbols
+                .get(symbol)
+                .ok_or_else(|| CallError::UnknownSymbol(symbol.to_string()))?,
+        };
+        if args.len() > 7 {
+            return Err(CallError::TooManyArguments(args.len()));
+        }
+        let convention = self.calling_convention.clone();
+        if let Some(&register) = convention
+            .callee_saved
+            .iter()
+            .chain(&convention.stack_pointer)
+            .find(|&&register| register > 7)
+        {
+            return Err(CallError::InvalidRegister(register));
+        }
+
+        for (index, &arg) in args.iter().enumerate() {
+            self.regs.write(index as u16, arg);
+        }
+        if let Some(stack_pointer) = convention.stack_pointer {
+            if usize::from(stack_pointer) >= args.len() {
+                self.regs.write(stack_pointer, convention.stack_top);
+            }
+        }
+        self.regs.write(7, convention.return_address);
+        self.regs.pc = address;
+        let registers_before = self.register_values();
+        let memory_before: Vec<u16> = (0..DEVICE_PAGE)
+            .map(|address| self.mem.peek(address))
+            .collect();
+        let instructions_before = self.instructions;
+
+        let added_breakpoint = self.add_breakpoint(convention.return_address);
+        self.stopped_at_breakpoint = None;
+        let stop = match self.resume() {
+            StopReason::Breakpoint(pc) if pc == convention.return_address => None,
+            reason => Some(reason),
+        };
+        if added_breakpoint {
+            self.remove_breakpoint(convention.return_address);
+        }
+        if stop.is_none() {
+            // The return address is not a real breakpoint to step over when resuming
+            self.stopped_at_breakpoint = None;
+        }
+
+        let registers = self.register_values();
+        let mut violations = Vec::new();
+        if stop.is_none() {
+            for &register in &convention.callee_saved {
+                let (before, after) = (
+                    registers_before[register as usize],
+                    registers[register as usize],
+                );
+                if before != after {
+                    violations.push(Violation::Clobbered {
+                        register,
+                        before,
+                        after,
+                    });
+                }
+            }
+            if let Some(stack_pointer) = convention.stack_pointer {
+                let (before, after) = (
+                    registers_before[stack_pointer as usize],
+                    registers[stack_pointer as usize],
+                );
+                if before != after {
+                    violations.push(Violation::StackImbalance { before, after });
+                }
+            }
+        }
+        let memory_changes = memory_before
+            .iter()
+            .enumerate()
+            .filter_map(|(address, &old_value)| {
+                let address = address as u16;
+                let new_value = self.mem.peek(address);
+                (old_value != new_value).then_some(MemoryChange {
+                    address,
+                    old_value,
+                    new_value,
+                })
+            })
+            .collect();
+
+        Ok(CallResult {
+            stop,
+            registers,
+            instructions: self.instructions - instructions_before,
+            memory_changes,
+            violations,
+        })
+    }
+
+    fn register_values(&self) -> [u16; 8] {
+        let mut values = [0; 8];
+        for (index, value) in values.iter_mut().enumerate() {
+            *value = self.regs.read(index as u16);
+        }
+        values
+    }
+}
@@ -0,0 +1,119 @@
+//! Keyboard input and display output of the vm
+//!
+//! All character I/O of a program — the `GETC`, `OUT`, `PUTS`, `IN`, `PUTSP` and `HALT` traps as
+//! well as the keyboard's Memory Mapped Registers — goes through a [`Console`]. By default, the
+//! vm uses a [`TerminalConsole`]; embedders can route I/O elsewhere with a [`BufferedConsole`] or
+//! their own implementation.
+
+use super::utils;
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+/// Source of keyboard input and sink of display output for the vm
+pub trait Console {
+    /// Returns the next input byte or `None` if no input is available
+    ///
+    /// Implementations may block until input becomes available.
+    fn read_byte(&mut self) -> Option<u8>;
+
+    /// Returns whether [`read_byte`](Self::read_byte) would return a byte
+    ///
+    /// This is used to avoid side-effects (like printing a prompt) of instructions that can not
+    /// complete because of missing input. Blocking implementations should return `true`.
+    fn has_input(&mut self) -> bool;
+
+    /// Writes the given bytes to the display
+    fn write(&mut self, bytes: &[u8]);
+}
+
+/// Console that reads from stdin and writes to stdout
+///
+/// Reading blocks until a byte is available, so the vm never runs out of input before the end
+/// of stdin.
+#[derive(Debug, Default)]
+pub struct TerminalConsole;
+
+impl Console for TerminalConsole {
+    fn read_byte(&mut self) -> Option<u8> {
+        utils::io::read_next_byte()
+    }
+
+    fn has_input(&mut self) -> bool {
+        true
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        let mut stdout = io::stdout();
+        stdout
+            .write_all(bytes)
+            .expect("Error while writing to stdout");
+        stdout.flush().expect("Error while flushing stdout");
+    }
+}
+
+/// Console with in-memory input and output buffers
+///
+/// Clones share the same buffers, so a clone can be kept to provide input and to collect the
+/// output while the original is owned by the vm.
+#[derive(Debug, Clone, Default)]
+pub struct BufferedConsole {
+    buffers: Rc<RefCell<Buffers>>,
+}
+
+#[derive(Debug, Default)]
+struct Buffers {
+    input: VecDeque<u8>,
+    output: Vec<u8>,
+}
+
+impl BufferedConsole {
+    /// Creates a new `BufferedConsole` with empty buffers
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a new `BufferedConsole` with the given pending `input`
+    pub fn with_input(input: &[u8]) -> Self {
+        let console = Self::new();
+        console.push_input(input);
+        console
+    }
+
+    /// Appends bytes to the pending input
+    pub fn push_input(&self, input: &[u8]) {
+        self.buffers.borrow_mut().input.extend(input);
+    }
+
+    /// Returns the number of input bytes that were not consumed yet
+    pub fn pending_input(&self) -> usize {
+        self.buffers.borrow().input.len()
+    }
+
+    /// Returns a copy of all output written since the last call to
+    /// [`take_output`](Self::take_output)
+    pub fn output(&self) -> Vec<u8> {
+        self.buffers.borrow().output.clone()
+    }
+
+    /// Returns and clears all output written since the last call
+    pub fn take_output(&self) -> Vec<u8> {
+        std::mem::take(&mut self.buffers.borrow_mut().output)
+    }
+}
+
+impl Console for BufferedConsole {
+    fn read_byte(&mut self) -> Option<u8> {
+        self.buffers.borrow_mut().input.pop_front()
+    }
+
+    fn has_input(&mut self) -> bool {
+        !self.buffers.borrow().input.is_empty()
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.buffers.borrow_mut().output.extend_from_slice(bytes);
+    }
+}
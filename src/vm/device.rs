@@ -0,0 +1,291 @@
+//! Memory-mapped devices
+//!
+//! Reads and writes of the device register page (xFE00–xFFFF) go through an I/O bus to the
+//! [`Device`] that was added for the address. Addresses without a device behave like ordinary
+//! memory. The vm comes with the keyboard (`KBSR` and `KBDR` at xFE00–xFE03) and the
+//! programmable timer (xFE08–xFE0F); more devices, like the [`Disk`], are added with
+//! [`Memory::add_device`].
+//!
+//! The registers of all devices are stored in the memory words of their addresses, so they are
+//! part of snapshots and their changes are recorded in the execution history. State that a
+//! device keeps in its own fields is saved in snapshots through [`Device::save_state`], but it
+//! is not undone.
+
+use super::memory::mem_mapped_reg_addr::DEVICE_PAGE;
+use super::Memory;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+use std::ops::RangeInclusive;
+
+mod disk;
+mod keyboard;
+mod timer;
+
+pub use disk::{Disk, DISK_ADDRESSES, SECTOR_WORDS};
+
+/// Interrupt requested by a device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interrupt {
+    /// Index in the interrupt vector table at x0100
+    pub vector: u8,
+    /// Priority level (0..8); the interrupt is only taken while the program runs at a lower one
+    pub priority: u16,
+}
+
+/// Device whose registers are mapped to addresses of the device register page
+///
+/// All methods receive a [`DeviceContext`] that gives access to the register words and the
+/// console. The default implementations make the registers behave like ordinary memory and
+/// never request an interrupt.
+pub trait Device {
+    /// Returns the value of the register at `address` for a read by the program
+    ///
+    /// Instruction fetches from the address read it as well.
+    fn read(&mut self, address: u16, io: &mut DeviceContext) -> u16 {
+        io.register(address)
+    }
+
+    /// Handles a write of `value` to the register at `address` by the program
+    ///
+    /// Basic blocks end after a store to a device register, so changes of the device are
+    /// noticed before the next instruction.
+    fn write(&mut self, address: u16, value: u16, io: &mut DeviceContext) {
+        io.set_register(address, value);
+    }
+
+    /// Advances the device by the given numbers of executed instructions and cycles
+    ///
+    /// This is called after every instruction or basic block, so the numbers may be larger
+    /// than one, and the program may access the registers before the preceding instructions of
+    /// its block were counted. A device that needs to be advanced exactly returns a budget
+    /// below the length of a block, which makes the vm execute single instructions; see
+    /// [`budget`](Self::budget).
+    fn tick(&mut self, _instructions: u64, _cycles: u64, _io: &mut DeviceContext) {}
+
+    /// Returns the interrupt that the device requests, if any
+    ///
+    /// The interrupt is requested until the device stops returning it, usually after the
+    /// service routine acknowledged it through a register.
+    fn interrupt(&self, _io: &DeviceContext) -> Option<Interrupt> {
+        None
+    }
+
+    /// Returns the number of instructions that can execute before the device may request an
+    /// interrupt, given the maximum number of cycles of an instruction
+    ///
+    /// Basic blocks don't execute more instructions, so the interrupt is taken at the same
+    /// instruction as with the interpreter.
+    fn budget(&self, _max_instruction_cycles: u64, _io: &DeviceContext) -> u64 {
+        u64::MAX
+    }
+
+    /// Writes the state that the device keeps besides its registers to a snapshot
+    fn save_state(&self, _writer: &mut dyn Write) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Restores the state written by [`save_state`](Self::save_state) when a snapshot is
+    /// loaded
+    fn load_state(&mut self, _reader: &mut dyn Read) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Access of a [`Device`] to its registers, the memory and the console
+pub struct DeviceContext<'a> {
+    mem: &'a mut Memory,
+}
+
+impl DeviceContext<'_> {
+    /// Returns the value stored in the register at `address` without any side-effects
+    pub fn register(&self, address: u16) -> u16 {
+        self.mem.peek(address)
+    }
+
+    /// Stores the `value` in the register at `address`, which is recorded in the execution
+    /// history
+    pub fn set_register(&mut self, address: u16, value: u16) {
+        self.mem.write_device_register(address, value);
+    }
+
+    /// Returns the word at `address` for a transfer to the device, without any side-effects
+    pub fn read_memory(&self, address: u16) -> u16 {
+        self.mem.peek(address)
+    }
+
+    /// Stores the `value` at `address` for a transfer from the device, which is recorded in
+    /// the execution history but doesn't trigger watchpoints
+    pub fn write_memory(&mut self, address: u16, value: u16) {
+        self.mem.transfer_word(address, value);
+    }
+
+    /// Returns the next input byte from the console or `None` if no input is available
+    pub fn read_input(&mut self) -> Option<u8> {
+        self.mem.next_input()
+    }
+
+    /// Returns whether [`read_input`](Self::read_input) would return a byte
+    pub fn has_input(&mut self) -> bool {
+        self.mem.has_input()
+    }
+}
+
+/// Device with the addresses it was added for
+struct Mapping {
+    addresses: RangeInclusive<u16>,
+    device: Box<dyn Device>,
+}
+
+/// Devices of the device register page by address range
+#[derive(Default)]
+pub(crate) struct IoBus {
+    mappings: Vec<Mapping>,
+}
+
+impl IoBus {
+    /// Creates an `IoBus` with the keyboard and the timer
+    pub fn new() -> Self {
+        let mut bus = Self::default();
+        bus.add(keyboard::ADDRESSES, Box::new(keyboard::Keyboard));
+        bus.add(timer::ADDRESSES, Box::new(timer::Timer::default()));
+        bus
+    }
+
+    /// Adds the `device` for the `addresses`
+    ///
+    /// # Panics
+    ///
+    /// Panics if the addresses are outside of the device register page or overlap the
+    /// addresses of another device.
+    pub fn add(&mut self, addresses: RangeInclusive<u16>, device: Box<dyn Device>) {
+        assert!(
+            *addresses.start() >= DEVICE_PAGE && addresses.start() <= addresses.end(),
+            "Devices must be mapped to xFE00–xFFFF"
+        );
+        if let Some(mapping) = self.mappings.iter().find(|mapping| {
+            mapping.addresses.start() <= addresses.end()
+                && addresses.start() <= mapping.addresses.end()
+        }) {
+            panic!(
+                "x{:04X}–x{:04X} overlaps the device at x{:04X}–x{:04X}",
+                addresses.start(),
+                addresses.end(),
+                mapping.addresses.start(),
+                mapping.addresses.end()
+            );
+        }
+        self.mappings.push(Mapping { addresses, device });
+    }
+
+    fn device_at(&mut self, address: u16) -> Option<&mut (dyn Device + 'static)> {
+        self.mappings
+            .iter_mut()
+            .find(|mapping| mapping.addresses.contains(&address))
+            .map(|mapping| mapping.device.as_mut())
+    }
+
+    /// Reads the register at `address` of the device that it belongs to
+    pub fn read(&mut self, address: u16, mem: &mut Memory) -> u16 {
+        let mut io = DeviceContext { mem };
+        match self.device_at(address) {
+            Some(device) => device.read(address, &mut io),
+            None => io.register(address),
+        }
+    }
+
+    /// Writes the register at `address` of the device that it belongs to
+    pub fn write(&mut self, address: u16, value: u16, mem: &mut Memory) {
+        let mut io = DeviceContext { mem };
+        match self.device_at(address) {
+            Some(device) => device.write(address, value, &mut io),
+            None => io.set_register(address, value),
+        }
+    }
+
+    /// Advances all devices
+    pub fn tick(&mut self, instructions: u64, cycles: u64, mem: &mut Memory) {
+        let mut io = DeviceContext { mem };
+        for mapping in &mut self.mappings {
+            mapping.device.tick(instructions, cycles, &mut io);
+        }
+    }
+
+    /// Returns the requested interrupt with the highest priority
+    pub fn interrupt(&self, mem: &mut Memory) -> Option<Interrupt> {
+        let io = DeviceContext { mem };
+        self.mappings
+            .iter()
+            .filter_map(|mapping| mapping.device.interrupt(&io))
+            .max_by_key(|interrupt| interrupt.priority)
+    }
+
+    /// Writes the addresses and the state of all devices
+    pub fn save_state(&self, writer: &mut dyn Write) -> io::Result<()> {
+        writer.write_u16::<BigEndian>(self.mappings.len() as u16)?;
+        for mapping in &self.mappings {
+            let mut state = Vec::new();
+            mapping.device.save_state(&mut state)?;
+            let state_len = u32::try_from(state.len()).map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidInput, "Too much device state")
+            })?;
+            writer.write_u16::<BigEndian>(*mapping.addresses.start())?;
+            writer.write_u16::<BigEndian>(*mapping.addresses.end())?;
+            writer.write_u32::<BigEndian>(state_len)?;
+            writer.write_all(&state)?;
+        }
+        Ok(())
+    }
+
+    /// Reads the states written by [`save_state`](Self::save_state) in the order of the
+    /// devices, without restoring them
+    ///
+    /// Returns an error of kind [`InvalidData`](io::ErrorKind::InvalidData) unless the devices
+    /// were added for the same addresses as in the snapshot.
+    pub fn read_state(&self, reader: &mut dyn Read) -> io::Result<Vec<Vec<u8>>> {
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "The snapshot was taken with other devices",
+            )
+        };
+        if usize::from(reader.read_u16::<BigEndian>()?) != self.mappings.len() {
+            return Err(invalid());
+        }
+        let mut states = Vec::with_capacity(self.mappings.len());
+        for mapping in &self.mappings {
+            let start = reader.read_u16::<BigEndian>()?;
+            let end = reader.read_u16::<BigEndian>()?;
+            if (start..=end) != mapping.addresses {
+                return Err(invalid());
+            }
+            let state_len = reader.read_u32::<BigEndian>()?;
+            let mut state = Vec::new();
+            reader.take(u64::from(state_len)).read_to_end(&mut state)?;
+            if state.len() != state_len as usize {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            states.push(state);
+        }
+        Ok(states)
+    }
+
+    /// Restores the states returned by [`read_state`](Self::read_state)
+    pub fn load_state(&mut self, states: Vec<Vec<u8>>) -> io::Result<()> {
+        for (mapping, state) in self.mappings.iter_mut().zip(states) {
+            mapping.device.load_state(&mut &state[..])?;
+        }
+        Ok(())
+    }
+
+    /// Returns the smallest budget of all devices
+    pub fn budget(&self, max_instruction_cycles: u64, mem: &mut Memory) -> u64 {
+        let io = DeviceContext { mem };
+        self.mappings
+            .iter()
+            .map(|mapping| mapping.device.budget(max_instruction_cycles, &io))
+            .min()
+            .unwrap_or(u64::MAX)
+    }
+}
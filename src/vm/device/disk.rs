@@ -0,0 +1,129 @@
+//! Block storage device backed by a host file
+//!
+//! The disk transfers sectors of 256 words between the vm's memory and an image file, in which
+//! every sector takes 512 bytes with big-endian words, like in object files. It is controlled
+//! through four device registers:
+//!
+//! | Register | Address | Contents |
+//! |----------|---------|----------|
+//! | `DSR`    | `xFE10` | Status: bit 15 is set when a transfer completed, bit 0 when it failed; writing clears both |
+//! | `DCR`    | `xFE12` | Control: writing starts a transfer, with the command in bits 1–0 (`1` reads a sector into memory, `2` writes memory to a sector), bit 14 enabling the interrupt and bits 10–8 being its priority |
+//! | `DSEC`   | `xFE14` | Sector number |
+//! | `DBUF`   | `xFE16` | Address of the buffer in memory |
+//!
+//! Transfers complete immediately, during the instruction that writes `DCR`. Sectors past the
+//! end of the image read as zeros, and writing them extends the image. A transfer fails if the
+//! buffer would reach into the device registers (xFE00 and above). While the completed flag
+//! and the interrupt enable bit are set, the disk requests an interrupt through vector `x83`.
+//!
+//! The transferred words are recorded in the execution history, but the changes of the image
+//! file are not undone.
+
+use super::{Device, DeviceContext, Interrupt};
+use crate::vm::memory::mem_mapped_reg_addr::{DBUF, DCR, DEVICE_PAGE, DSEC, DSR};
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+/// Addresses of the disk registers
+pub const DISK_ADDRESSES: RangeInclusive<u16> = DSR..=DBUF + 1;
+
+/// Number of words of a sector
+pub const SECTOR_WORDS: usize = 256;
+
+/// Interrupt vector of the disk
+const VECTOR: u8 = 0x83;
+
+/// Command in `DCR` that reads a sector into memory
+const READ: u16 = 1;
+/// Command in `DCR` that writes memory to a sector
+const WRITE: u16 = 2;
+/// Bits of `DCR` that hold the command
+const COMMAND: u16 = 0x3;
+/// Bit of `DCR` that enables the interrupt
+const INTERRUPT_ENABLE: u16 = 1 << 14;
+/// Bit of `DSR` that is set when a transfer completed
+const DONE: u16 = 1 << 15;
+/// Bit of `DSR` that is set when a transfer failed
+const ERROR: u16 = 1;
+
+/// Block storage device whose sectors are stored in an image file
+pub struct Disk {
+    image: File,
+}
+
+impl Disk {
+    /// Creates a new `Disk` that stores its sectors in the image file at `path`, which is
+    /// created if it doesn't exist
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let image = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        Ok(Self { image })
+    }
+
+    /// Reads the sector into the buffer in memory, which must end before the device registers
+    fn read_sector(&mut self, sector: u16, buffer: u16, io: &mut DeviceContext) -> io::Result<()> {
+        self.image.seek(SeekFrom::Start(sector_offset(sector)))?;
+        let mut bytes = Vec::with_capacity(SECTOR_WORDS * 2);
+        (&mut self.image)
+            .take(SECTOR_WORDS as u64 * 2)
+            .read_to_end(&mut bytes)?;
+        bytes.resize(SECTOR_WORDS * 2, 0);
+        for (offset, word) in bytes.chunks(2).enumerate() {
+            io.write_memory(
+                buffer + offset as u16,
+                u16::from_be_bytes([word[0], word[1]]),
+            );
+        }
+        Ok(())
+    }
+
+    /// Writes the buffer in memory, which must end before the device registers, to the sector
+    fn write_sector(&mut self, sector: u16, buffer: u16, io: &DeviceContext) -> io::Result<()> {
+        let bytes: Vec<u8> = (0..SECTOR_WORDS as u16)
+            .flat_map(|offset| io.read_memory(buffer + offset).to_be_bytes())
+            .collect();
+        self.image.seek(SeekFrom::Start(sector_offset(sector)))?;
+        self.image.write_all(&bytes)
+    }
+}
+
+impl Device for Disk {
+    fn write(&mut self, address: u16, value: u16, io: &mut DeviceContext) {
+        match address {
+            DSR => io.set_register(DSR, 0),
+            DCR => {
+                let (sector, buffer) = (io.register(DSEC), io.register(DBUF));
+                let in_memory = usize::from(buffer) + SECTOR_WORDS <= usize::from(DEVICE_PAGE);
+                let result = match value & COMMAND {
+                    _ if !in_memory => Err(io::ErrorKind::InvalidInput.into()),
+                    READ => self.read_sector(sector, buffer, io),
+                    WRITE => self.write_sector(sector, buffer, io),
+                    _ => Err(io::ErrorKind::InvalidInput.into()),
+                };
+                io.set_register(DCR, value & !COMMAND);
+                io.set_register(DSR, if result.is_ok() { DONE } else { DONE | ERROR });
+            }
+            _ => io.set_register(address, value),
+        }
+    }
+
+    fn interrupt(&self, io: &DeviceContext) -> Option<Interrupt> {
+        let dcr = io.register(DCR);
+        (dcr & INTERRUPT_ENABLE != 0 && io.register(DSR) & DONE != 0).then_some(Interrupt {
+            vector: VECTOR,
+            priority: (dcr >> 8) & 0x7,
+        })
+    }
+}
+
+/// Returns the position of the sector in the image file
+fn sector_offset(sector: u16) -> u64 {
+    u64::from(sector) * SECTOR_WORDS as u64 * 2
+}
@@ -0,0 +1,31 @@
+//! Keyboard
+//!
+//! Reading the keyboard status register (`KBSR`) polls the console: if a key is available, bit
+//! 15 of `KBSR` is set and the key is stored in the keyboard data register (`KBDR`), otherwise
+//! `KBSR` is cleared.
+
+use super::{Device, DeviceContext};
+use crate::vm::memory::mem_mapped_reg_addr::{KBDR, KBSR};
+
+use std::ops::RangeInclusive;
+
+/// Addresses of the keyboard registers
+pub const ADDRESSES: RangeInclusive<u16> = KBSR..=KBDR + 1;
+
+/// Keyboard that reads the console input
+pub struct Keyboard;
+
+impl Device for Keyboard {
+    fn read(&mut self, address: u16, io: &mut DeviceContext) -> u16 {
+        if address == KBSR {
+            let chr = io.read_input().unwrap_or(0);
+            if chr != 0 {
+                io.set_register(KBSR, 1 << 15);
+                io.set_register(KBDR, chr as u16);
+            } else {
+                io.set_register(KBSR, 0);
+            }
+        }
+        io.register(address)
+    }
+}
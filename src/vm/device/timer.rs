@@ -0,0 +1,124 @@
+//! Programmable interval timer
+//!
+//! The timer counts executed instructions or simulated cycles and sets its expired flag
+//! whenever the count runs out. It is controlled through four device registers:
+//!
+//! | Register | Address | Contents |
+//! |----------|---------|----------|
+//! | `TCR`    | `xFE08` | Control: bit 15 enables the timer, bit 14 enables its interrupt, bit 13 counts cycles instead of instructions, bits 10–8 are the interrupt priority |
+//! | `TIR`    | `xFE0A` | Interval: the number of instructions or cycles between two expirations |
+//! | `TSR`    | `xFE0C` | Status: bit 15 is set when the timer expired; writing clears it |
+//! | `TCNT`   | `xFE0E` | Count: the instructions or cycles left until the timer expires |
+//!
+//! Writing `TCR` or `TIR` restarts the count at the interval. After a write to `TCR`, `TIR` or
+//! `TCNT`, counting starts with the next instruction. The timer restarts by itself after it
+//! expired, so it is periodic. While the expired flag and the interrupt enable bit are set, the
+//! timer requests an interrupt through vector `x81`.
+//!
+//! Since the registers are stored in memory words, the timer is part of snapshots and the
+//! execution history.
+
+use super::{Device, DeviceContext, Interrupt};
+use crate::vm::memory::mem_mapped_reg_addr::{TCNT, TCR, TIR, TSR};
+
+use byteorder::{ReadBytesExt, WriteBytesExt};
+use std::io::{self, Read, Write};
+use std::ops::RangeInclusive;
+
+/// Addresses of the timer registers
+pub const ADDRESSES: RangeInclusive<u16> = TCR..=TCNT + 1;
+
+/// Interrupt vector of the timer
+const VECTOR: u8 = 0x81;
+
+/// Bit of `TCR` that enables the timer
+const ENABLE: u16 = 1 << 15;
+/// Bit of `TCR` that enables the interrupt
+const INTERRUPT_ENABLE: u16 = 1 << 14;
+/// Bit of `TCR` that makes the timer count cycles
+const COUNT_CYCLES: u16 = 1 << 13;
+/// Bit of `TSR` that is set when the timer expired
+const EXPIRED: u16 = 1 << 15;
+
+/// Programmable interval timer
+#[derive(Default)]
+pub struct Timer {
+    /// Whether the current instruction restarted the timer, so it doesn't count towards the
+    /// interval
+    restarted: bool,
+}
+
+impl Timer {
+    /// Returns whether the timer counts cycles, or `None` if it doesn't run
+    fn counts_cycles(io: &DeviceContext) -> Option<bool> {
+        let tcr = io.register(TCR);
+        (tcr & ENABLE != 0 && io.register(TIR) != 0).then_some(tcr & COUNT_CYCLES != 0)
+    }
+}
+
+impl Device for Timer {
+    fn write(&mut self, address: u16, value: u16, io: &mut DeviceContext) {
+        match address {
+            TCR | TIR => {
+                io.set_register(address, value);
+                io.set_register(TCNT, io.register(TIR));
+                self.restarted = true;
+            }
+            TCNT => {
+                io.set_register(address, value);
+                self.restarted = true;
+            }
+            TSR => io.set_register(TSR, 0),
+            _ => io.set_register(address, value),
+        }
+    }
+
+    /// Nothing is counted if the instructions restarted the timer. The instructions before that
+    /// can't have passed the previous count, since basic blocks end after a store to a device
+    /// register.
+    fn tick(&mut self, instructions: u64, cycles: u64, io: &mut DeviceContext) {
+        if std::mem::take(&mut self.restarted) {
+            return;
+        }
+        let elapsed = match Self::counts_cycles(io) {
+            Some(true) => cycles,
+            Some(false) => instructions,
+            None => return,
+        };
+        let count = u64::from(io.register(TCNT));
+        if elapsed < count {
+            io.set_register(TCNT, (count - elapsed) as u16);
+            return;
+        }
+        let interval = u64::from(io.register(TIR));
+        let overshoot = (elapsed - count) % interval;
+        io.set_register(TCNT, (interval - overshoot) as u16);
+        io.set_register(TSR, io.register(TSR) | EXPIRED);
+    }
+
+    fn interrupt(&self, io: &DeviceContext) -> Option<Interrupt> {
+        let tcr = io.register(TCR);
+        (tcr & INTERRUPT_ENABLE != 0 && io.register(TSR) & EXPIRED != 0).then_some(Interrupt {
+            vector: VECTOR,
+            priority: (tcr >> 8) & 0x7,
+        })
+    }
+
+    fn budget(&self, max_instruction_cycles: u64, io: &DeviceContext) -> u64 {
+        let count = u64::from(io.register(TCNT));
+        match Self::counts_cycles(io) {
+            Some(true) => count.saturating_sub(1) / max_instruction_cycles + 1,
+            Some(false) => count,
+            None => u64::MAX,
+        }
+    }
+
+    fn save_state(&self, writer: &mut dyn Write) -> io::Result<()> {
+        writer.write_u8(self.restarted.into())
+    }
+
+    fn load_state(&mut self, reader: &mut dyn Read) -> io::Result<()> {
+        self.restarted = reader.read_u8()? != 0;
+        Ok(())
+    }
+}
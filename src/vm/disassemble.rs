@@ -0,0 +1,61 @@
+//! Disassembly of single instructions
+
+use super::instructions::{Instruction, Operand};
+
+/// Returns the assembly text of the instruction `instr` at `address`, e.g. `ADD R0, R1, #-1`
+///
+/// PC-relative operands are shown as absolute addresses (`BRz x3005`). Words that are not
+/// valid instructions are shown as `.FILL` directives.
+pub fn disassemble(address: u16, instr: u16) -> String {
+    let target =
+        |pc_offset: u16| format!("x{:04X}", address.wrapping_add(1).wrapping_add(pc_offset));
+    let operand = |src2| match src2 {
+        Operand::Register(sr2) => format!("R{}", sr2),
+        Operand::Immediate(imm5) => format!("#{}", imm5 as i16),
+    };
+
+    match Instruction::decode(instr) {
+        Instruction::Br { nzp: 0, .. } => "NOP".to_string(),
+        Instruction::Br { nzp, pc_offset } => {
+            let flags = match nzp {
+                0b111 => String::new(),
+                _ => [(0b100, 'n'), (0b010, 'z'), (0b001, 'p')]
+                    .iter()
+                    .filter(|&&(flag, _)| nzp & flag != 0)
+                    .map(|&(_, name)| name)
+                    .collect(),
+            };
+            format!("BR{} {}", flags, target(pc_offset))
+        }
+        Instruction::Add { dr, sr1, src2 } => format!("ADD R{}, R{}, {}", dr, sr1, operand(src2)),
+        Instruction::And { dr, sr1, src2 } => format!("AND R{}, R{}, {}", dr, sr1, operand(src2)),
+        Instruction::Ld { dr, pc_offset } => format!("LD R{}, {}", dr, target(pc_offset)),
+        Instruction::Ldi { dr, pc_offset } => format!("LDI R{}, {}", dr, target(pc_offset)),
+        Instruction::Lea { dr, pc_offset } => format!("LEA R{}, {}", dr, target(pc_offset)),
+        Instruction::St { sr, pc_offset } => format!("ST R{}, {}", sr, target(pc_offset)),
+        Instruction::Sti { sr, pc_offset } => format!("STI R{}, {}", sr, target(pc_offset)),
+        Instruction::Ldr { dr, base, offset } => {
+            format!("LDR R{}, R{}, #{}", dr, base, offset as i16)
+        }
+        Instruction::Str { sr, base, offset } => {
+            format!("STR R{}, R{}, #{}", sr, base, offset as i16)
+        }
+        Instruction::Not { dr, sr } => format!("NOT R{}, R{}", dr, sr),
+        Instruction::Jsr { pc_offset } => format!("JSR {}", target(pc_offset)),
+        Instruction::Jsrr { base } => format!("JSRR R{}", base),
+        Instruction::Jmp { base: 7 } => "RET".to_string(),
+        Instruction::Jmp { base } => format!("JMP R{}", base),
+        Instruction::Rti => "RTI".to_string(),
+        Instruction::Trap(_) => match instr & 0xFF {
+            0x20 => "GETC",
+            0x21 => "OUT",
+            0x22 => "PUTS",
+            0x23 => "IN",
+            0x24 => "PUTSP",
+            _ => "HALT",
+        }
+        .to_string(),
+        Instruction::UnsupportedTrap(trapvector) => format!("TRAP x{:02X}", trapvector),
+        Instruction::Illegal => format!(".FILL x{:04X}", instr),
+    }
+}
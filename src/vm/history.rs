@@ -0,0 +1,105 @@
+//! Execution history for reverse execution
+//!
+//! While the history is enabled, the vm records an [`UndoRecord`] for every executed
+//! instruction: the registers before the instruction, the previous values of all memory words
+//! it wrote (including device registers), the addresses it read and the input bytes it consumed.
+//! Undoing an instruction restores the registers and memory and queues the consumed input again,
+//! so executing forward afterwards reproduces the same run. Console output is not undone.
+//!
+//! The history is bounded by a memory budget; the oldest records are dropped when it is exceeded.
+
+use super::registers::Registers;
+
+use std::collections::VecDeque;
+use std::mem;
+
+#[cfg(test)]
+mod tests;
+
+/// Memory accesses and input of the instruction that is currently executed
+#[derive(Debug, Default)]
+pub struct Journal {
+    /// Written addresses with the values they had before the write, in order of the writes
+    pub writes: Vec<(u16, u16)>,
+    /// Addresses of data reads, in order of the reads
+    pub reads: Vec<u16>,
+    /// Consumed input bytes, in order of consumption
+    pub input: Vec<u8>,
+}
+
+/// Information needed to undo a single executed instruction
+#[derive(Debug)]
+pub struct UndoRecord {
+    /// Registers before the instruction was executed
+    pub regs: Registers,
+    /// Cycle counter before the instruction was executed
+    pub cycles: u64,
+    /// Number of instructions executed before the instruction
+    pub instructions: u64,
+    pub journal: Journal,
+}
+
+impl UndoRecord {
+    /// Returns the approximate number of bytes used by this record
+    fn size(&self) -> usize {
+        mem::size_of::<Self>()
+            + self.journal.writes.capacity() * mem::size_of::<(u16, u16)>()
+            + self.journal.reads.capacity() * mem::size_of::<u16>()
+            + self.journal.input.capacity()
+    }
+}
+
+/// Bounded list of undo records, from the oldest to the most recently executed instruction
+#[derive(Debug)]
+pub struct History {
+    records: VecDeque<UndoRecord>,
+    /// Maximum number of bytes used by all records
+    budget: usize,
+    /// Number of bytes used by all records
+    size: usize,
+}
+
+impl History {
+    /// Creates a new empty `History` that uses at most `budget` bytes
+    pub fn new(budget: usize) -> Self {
+        Self {
+            records: VecDeque::new(),
+            budget,
+            size: 0,
+        }
+    }
+
+    /// Returns the number of recorded instructions
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    /// Adds the record of the most recently executed instruction, dropping the oldest records
+    /// if the budget is exceeded
+    pub fn push(&mut self, mut record: UndoRecord) {
+        record.journal.writes.shrink_to_fit();
+        record.journal.reads.shrink_to_fit();
+        record.journal.input.shrink_to_fit();
+        self.size += record.size();
+        self.records.push_back(record);
+        while self.size > self.budget {
+            match self.records.pop_front() {
+                Some(oldest) => self.size -= oldest.size(),
+                None => break,
+            }
+        }
+    }
+
+    /// Removes all records
+    pub fn clear(&mut self) {
+        self.records.clear();
+        self.size = 0;
+    }
+
+    /// Removes and returns the record of the most recently executed instruction
+    pub fn pop(&mut self) -> Option<UndoRecord> {
+        let record = self.records.pop_back()?;
+        self.size -= record.size();
+        Some(record)
+    }
+}
@@ -0,0 +1,56 @@
+//! Eviction of undo records when the budget is exceeded
+
+use super::*;
+
+/// Returns a record whose instruction wrote `writes` words; `PC` identifies the record
+fn record(pc: u16, writes: usize) -> UndoRecord {
+    let mut regs = Registers::default();
+    regs.pc = pc;
+    UndoRecord {
+        regs,
+        cycles: 0,
+        instructions: 0,
+        journal: Journal {
+            writes: vec![(0x4000, 0); writes],
+            ..Journal::default()
+        },
+    }
+}
+
+#[test]
+fn drops_the_oldest_records() {
+    let size = record(0, 0).size();
+    let mut history = History::new(3 * size);
+    for pc in 0..5 {
+        history.push(record(pc, 0));
+    }
+    assert_eq!(history.len(), 3);
+    assert_eq!(history.size, 3 * size);
+    assert_eq!(history.pop().unwrap().regs.pc, 4);
+    assert_eq!(history.pop().unwrap().regs.pc, 3);
+    assert_eq!(history.pop().unwrap().regs.pc, 2);
+    assert!(history.pop().is_none());
+    assert_eq!(history.size, 0);
+}
+
+#[test]
+fn counts_the_journal() {
+    let size = record(0, 0).size();
+    let mut history = History::new(3 * size);
+    history.push(record(0, 0));
+    history.push(record(1, 0));
+    // The writes of the last record take the space of the two others
+    history.push(record(2, 2 * size / mem::size_of::<(u16, u16)>()));
+    assert_eq!(history.len(), 1);
+    assert_eq!(history.pop().unwrap().regs.pc, 2);
+
+    // A record that exceeds the budget by itself isn't kept
+    history.push(record(3, 4 * size));
+    assert_eq!(history.len(), 0);
+    assert_eq!(history.size, 0);
+
+    history.push(record(4, 0));
+    history.clear();
+    assert_eq!(history.len(), 0);
+    assert_eq!(history.size, 0);
+}
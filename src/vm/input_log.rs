@@ -0,0 +1,164 @@
+//! Deterministic record and replay of keyboard input
+//!
+//! Programs only see input at well-defined points: a `GETC` or `IN` trap, or a read of the
+//! keyboard status register (KBSR) that finds a key. Recording the consumed bytes together with
+//! the number of instructions executed before each of them is enough to reproduce a run: when
+//! replaying, a byte is only available to the instruction with the recorded number, so every
+//! `GETC`, `IN` and KBSR poll sees exactly the same input as in the recorded run.
+//!
+//! Input logs are text files with one `INSTRUCTION BYTE` pair of decimal numbers per line, after
+//! a header line that identifies the format:
+//!
+//! ```plain
+//! # lc3-vm input log 1
+//! 1520 119
+//! 98231 113
+//! ```
+
+use super::tracer::{TraceEvent, Tracer};
+
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Write};
+
+/// Header line of input logs
+const HEADER: &str = "# lc3-vm input log 1";
+
+/// Input byte consumed by a program
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputEvent {
+    /// Number of instructions executed before the instruction that consumed the byte
+    pub instruction: u64,
+    pub byte: u8,
+}
+
+/// Reads an input log written by an [`InputRecorder`]
+pub fn read_input_log<R: BufRead>(reader: R) -> io::Result<Vec<InputEvent>> {
+    let mut lines = reader.lines();
+    let header = lines.next().transpose()?;
+    if header.as_deref().map(str::trim_end) != Some(HEADER) {
+        return Err(invalid_data("Not an input log"));
+    }
+    let mut events = Vec::new();
+    for (index, line) in lines.enumerate() {
+        let line = line?;
+        let mut fields = line.split_whitespace();
+        let event = match (fields.next(), fields.next(), fields.next()) {
+            (None, _, _) => continue,
+            (Some(instruction), Some(byte), None) => instruction
+                .parse()
+                .ok()
+                .zip(byte.parse().ok())
+                .map(|(instruction, byte)| InputEvent { instruction, byte }),
+            _ => None,
+        };
+        match event {
+            Some(event) => events.push(event),
+            None => {
+                return Err(invalid_data(&format!(
+                    "Invalid input log entry in line {}",
+                    index + 2
+                )))
+            }
+        }
+    }
+    Ok(events)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Tracer that writes the input consumed by the program to an input log
+///
+/// Every entry is written and flushed immediately, so the log is complete even if the process is
+/// killed. Input that is delivered again after instructions were undone is only logged once.
+/// Like [`TraceRecorder`](crate::TraceRecorder), the first write error stops recording and is
+/// returned by [`InputRecorder::flush`].
+pub struct InputRecorder {
+    out: Box<dyn Write>,
+    /// Number of instructions before the most recently logged byte
+    last_instruction: Option<u64>,
+    error: Option<io::Error>,
+}
+
+impl InputRecorder {
+    /// Creates a new `InputRecorder` that writes the input log to `out`
+    pub fn new(mut out: Box<dyn Write>) -> Self {
+        let error = writeln!(out, "{}", HEADER).and_then(|_| out.flush()).err();
+        Self {
+            out,
+            last_instruction: None,
+            error,
+        }
+    }
+
+    /// Returns the first error that occurred while recording
+    pub fn flush(&mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => self.out.flush(),
+        }
+    }
+}
+
+impl Tracer for InputRecorder {
+    fn trace(&mut self, event: &TraceEvent) {
+        if event.input.is_empty()
+            || self.error.is_some()
+            || self.last_instruction >= Some(event.instruction)
+        {
+            return;
+        }
+        self.last_instruction = Some(event.instruction);
+        for &byte in event.input {
+            let result = writeln!(self.out, "{} {}", event.instruction, byte);
+            if let Err(e) = result.and_then(|_| self.out.flush()) {
+                self.error = Some(e);
+                return;
+            }
+        }
+    }
+}
+
+/// Input log that is fed to the program instead of the console
+#[derive(Debug)]
+pub struct Replay {
+    events: VecDeque<InputEvent>,
+    /// Number of instructions executed before the current instruction
+    instruction: u64,
+}
+
+impl Replay {
+    pub fn new(events: Vec<InputEvent>) -> Self {
+        Self {
+            events: events.into(),
+            instruction: 0,
+        }
+    }
+
+    /// Sets the number of instructions executed before the current instruction
+    pub fn set_instruction(&mut self, instruction: u64) {
+        self.instruction = instruction;
+    }
+
+    /// Returns whether all bytes were delivered
+    pub fn is_done(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Returns whether a byte is recorded for the current instruction
+    pub fn has_input(&self) -> bool {
+        self.events
+            .front()
+            .is_some_and(|event| event.instruction == self.instruction)
+    }
+
+    /// Returns the byte recorded for the current instruction
+    pub fn read_byte(&mut self) -> Option<u8> {
+        if self.has_input() {
+            self.events.pop_front().map(|event| event.byte)
+        } else {
+            None
+        }
+    }
+}
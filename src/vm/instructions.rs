@@ -1,16 +1,62 @@
 //! All instructions that are supported and have an implementation
 //!
 //! Instructions are 16-bit values and have a specific binary encoding. The first four bits of
-//! each instruction express the [`Opcode`](super::Opcode).
+//! each instruction express the [`Opcode`](super::Opcode). The functions below perform
+//! instructions whose operands were already extracted by [`Instruction::decode`].
 
+mod decode;
 mod trap;
 
-use super::{utils::bit_ops::sign_extend, Memory, Registers};
+pub use decode::{Instruction, Operand};
+
+use super::{CondFlag, Fault, Memory, Registers};
 use trap::TrapCode;
 
-use std::convert::TryFrom;
+/// Effect of an instruction on the execution of the vm
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    /// Continue with the next instruction
+    Continue,
+    /// Stop executing instructions, because the program halted
+    Halt,
+    /// Re-execute the instruction once input is available, because it needs input from the
+    /// [`Console`](super::Console)
+    WaitForInput,
+}
 
-/// Parses and performs the `BR` (*branch*) instruction
+/// Executes the decoded `instruction`, whose raw value is `instr`; returns how the vm should
+/// continue or why it can't be executed
+pub fn execute(
+    instr: u16,
+    instruction: Instruction,
+    regs: &mut Registers,
+    mem: &mut Memory,
+) -> Result<Flow, Fault> {
+    match instruction {
+        Instruction::Br { nzp, pc_offset } => br(nzp, pc_offset, regs),
+        Instruction::Add { dr, sr1, src2 } => add(dr, sr1, src2, regs),
+        Instruction::Ld { dr, pc_offset } => ld(dr, pc_offset, regs, mem),
+        Instruction::St { sr, pc_offset } => st(sr, pc_offset, regs, mem),
+        Instruction::Jsr { pc_offset } => jsr(pc_offset, regs),
+        Instruction::Jsrr { base } => jsrr(base, regs),
+        Instruction::And { dr, sr1, src2 } => and(dr, sr1, src2, regs),
+        Instruction::Ldr { dr, base, offset } => ldr(dr, base, offset, regs, mem),
+        Instruction::Str { sr, base, offset } => str(sr, base, offset, regs, mem),
+        Instruction::Not { dr, sr } => not(dr, sr, regs),
+        Instruction::Ldi { dr, pc_offset } => ldi(dr, pc_offset, regs, mem),
+        Instruction::Sti { sr, pc_offset } => sti(sr, pc_offset, regs, mem),
+        Instruction::Jmp { base } => jmp(base, regs),
+        Instruction::Lea { dr, pc_offset } => lea(dr, pc_offset, regs),
+        Instruction::Rti if regs.user_mode() => return Err(Fault::IllegalOpcode(instr)),
+        Instruction::Rti => rti(regs, mem),
+        Instruction::Trap(trap_code) => return Ok(trap(trap_code, regs, mem)),
+        Instruction::UnsupportedTrap(trapvector) => return Err(Fault::UnsupportedTrap(trapvector)),
+        Instruction::Illegal => return Err(Fault::IllegalOpcode(instr)),
+    };
+    Ok(Flow::Continue)
+}
+
+/// Performs the `BR` (*branch*) instruction
 ///
 /// # Binary encoding
 ///
@@ -32,17 +78,19 @@ use std::convert::TryFrom;
 /// BRnz  LABEL
 /// BRnzp LABEL
 /// ```
-pub fn br(instr: u16, regs: &mut Registers) {
-    // Condition flags (Negative, Zero, Positive)
-    // Not masked because the bitwise AND with `regs.cond` acts like a mask.
-    let nzp = instr >> 9;
-    if (nzp & (regs.cond as u16)) > 0 {
-        let pc_offset = sign_extend(instr & 0x1FF, 9);
+pub fn br(nzp: u16, pc_offset: u16, regs: &mut Registers) {
+    if br_taken(nzp, regs.cond) {
         regs.pc = regs.pc.wrapping_add(pc_offset);
     }
 }
 
-/// Parses and performs the `ADD` (*addition*) instruction
+/// Returns whether a `BR` instruction with the given `nzp` bits branches with the given
+/// condition flags
+pub fn br_taken(nzp: u16, cond: CondFlag) -> bool {
+    (nzp & (cond as u16)) > 0
+}
+
+/// Performs the `ADD` (*addition*) instruction
 ///
 /// **Note**: this instruction updates the `COND` register (NZP flags) based on the value written
 /// to `DR`.
@@ -65,30 +113,17 @@ pub fn br(instr: u16, regs: &mut Registers) {
 /// ADD  DR, SR1, SR2
 /// ADD  DR, SR1, imm5
 /// ```
-pub fn add(instr: u16, regs: &mut Registers) {
-    let dest_reg = (instr >> 9) & 0x7;
-    let src_reg1 = (instr >> 6) & 0x7;
-    let mode = (instr >> 5) & 0x1;
-    let value;
-    match mode {
-        // Immediate mode
-        0x1 => {
-            let imm = sign_extend(instr & 0x1F, 5);
-            value = regs.read(src_reg1).wrapping_add(imm);
-        }
-        // Register mode
-        0x0 => {
-            let src_reg2 = instr & 0x7;
-            value = regs.read(src_reg1).wrapping_add(regs.read(src_reg2));
-        }
-        _ => unreachable!(),
-    }
+pub fn add(dest_reg: u16, src_reg1: u16, src2: Operand, regs: &mut Registers) {
+    let value = match src2 {
+        Operand::Immediate(imm) => regs.read(src_reg1).wrapping_add(imm),
+        Operand::Register(src_reg2) => regs.read(src_reg1).wrapping_add(regs.read(src_reg2)),
+    };
 
-    regs.write(dest_reg, value as u16);
+    regs.write(dest_reg, value);
     regs.update_cond_flags(value);
 }
 
-/// Parses and performs the `LD` (*load*) instruction
+/// Performs the `LD` (*load*) instruction
 ///
 /// **Note**: this instruction updates the `COND` register (NZP flags) based on the value written
 /// to `DR`.
@@ -106,15 +141,13 @@ pub fn add(instr: u16, regs: &mut Registers) {
 /// ```asm
 /// LD   DR, LABEL
 /// ```
-pub fn ld(instr: u16, regs: &mut Registers, mem: &mut Memory) {
-    let dest_reg = (instr >> 9) & 0x7;
-    let pc_offset = sign_extend(instr & 0x1FF, 9);
+pub fn ld(dest_reg: u16, pc_offset: u16, regs: &mut Registers, mem: &mut Memory) {
     let value = mem.read(regs.pc.wrapping_add(pc_offset));
     regs.write(dest_reg, value);
     regs.update_cond_flags(value);
 }
 
-/// Parses and performs the `ST` (*store*) instruction
+/// Performs the `ST` (*store*) instruction
 ///
 /// # Binary encoding
 ///
@@ -129,14 +162,12 @@ pub fn ld(instr: u16, regs: &mut Registers, mem: &mut Memory) {
 /// ```asm
 /// ST   SR, LABEL
 /// ```
-pub fn st(instr: u16, regs: &Registers, mem: &mut Memory) {
-    let src_reg = (instr >> 9) & 0x7;
-    let pc_offset = sign_extend(instr & 0x1FF, 9);
+pub fn st(src_reg: u16, pc_offset: u16, regs: &Registers, mem: &mut Memory) {
     let value = regs.read(src_reg);
     mem.write(regs.pc.wrapping_add(pc_offset), value);
 }
 
-/// Parses and performs the `JSR` (*jump to subroutine*) instruction
+/// Performs the `JSR` (*jump to subroutine*) instruction
 ///
 /// # Binary encodings
 ///
@@ -156,25 +187,20 @@ pub fn st(instr: u16, regs: &Registers, mem: &mut Memory) {
 /// JSR  LABEL
 /// JSRR BaseR
 /// ```
-pub fn jsr(instr: u16, regs: &mut Registers) {
+pub fn jsr(pc_offset: u16, regs: &mut Registers) {
     regs.write(7, regs.pc);
-    let flag = (instr >> 11) & 0x1;
-    match flag {
-        // JSR
-        0x1 => {
-            let pc_offset = sign_extend(instr & 0x7FF, 11);
-            regs.pc = regs.pc.wrapping_add(pc_offset);
-        }
-        // JSRR
-        0x0 => {
-            let base_reg = (instr >> 6) & 0x7;
-            regs.pc = regs.read(base_reg);
-        }
-        _ => unreachable!(),
-    }
+    regs.pc = regs.pc.wrapping_add(pc_offset);
+}
+
+/// Performs the `JSRR` form of the [`jsr`] instruction, which jumps to the address in `BaseR`
+pub fn jsrr(base_reg: u16, regs: &mut Registers) {
+    // Read the base register first, since it may be `R7`
+    let target = regs.read(base_reg);
+    regs.write(7, regs.pc);
+    regs.pc = target;
 }
 
-/// Parses and performs the `AND` (*bitwise AND*) instruction
+/// Performs the `AND` (*bitwise AND*) instruction
 ///
 /// **Note**: this instruction updates the `COND` register (NZP flags) based on the value written
 /// to `DR`.
@@ -197,30 +223,17 @@ pub fn jsr(instr: u16, regs: &mut Registers) {
 /// AND  DR, SR1, SR2
 /// AND  DR, SR1, imm5
 /// ```
-pub fn and(instr: u16, regs: &mut Registers) {
-    let dest_reg = (instr >> 9) & 0x7;
-    let src_reg1 = (instr >> 6) & 0x7;
-    let mode = (instr >> 5) & 0x1;
-    let value;
-    match mode {
-        // Immediate mode
-        0x1 => {
-            let imm = sign_extend(instr & 0x1F, 5);
-            value = regs.read(src_reg1) & imm;
-        }
-        // Register mode
-        0x0 => {
-            let src_reg2 = instr & 0x7;
-            value = regs.read(src_reg1) & regs.read(src_reg2);
-        }
-        _ => unreachable!(),
-    }
+pub fn and(dest_reg: u16, src_reg1: u16, src2: Operand, regs: &mut Registers) {
+    let value = match src2 {
+        Operand::Immediate(imm) => regs.read(src_reg1) & imm,
+        Operand::Register(src_reg2) => regs.read(src_reg1) & regs.read(src_reg2),
+    };
 
     regs.write(dest_reg, value);
     regs.update_cond_flags(value);
 }
 
-/// Parses and performs the `LDR` (*load base + offset*) instruction
+/// Performs the `LDR` (*load base + offset*) instruction
 ///
 /// **Note**: this instruction updates the `COND` register (NZP flags) based on the value written
 /// to `DR`.
@@ -238,16 +251,13 @@ pub fn and(instr: u16, regs: &mut Registers) {
 /// ```asm
 /// LDR  DR, BaseR, offset6
 /// ```
-pub fn ldr(instr: u16, regs: &mut Registers, mem: &mut Memory) {
-    let dest_reg = (instr >> 9) & 0x7;
-    let base_reg = (instr >> 6) & 0x7;
-    let offset = sign_extend(instr & 0x3F, 6);
+pub fn ldr(dest_reg: u16, base_reg: u16, offset: u16, regs: &mut Registers, mem: &mut Memory) {
     let value = mem.read(regs.read(base_reg).wrapping_add(offset));
     regs.write(dest_reg, value);
     regs.update_cond_flags(value);
 }
 
-/// Parses and performs the `STR` (*store base + offset*) instruction
+/// Performs the `STR` (*store base + offset*) instruction
 ///
 /// # Binary encoding
 ///
@@ -262,15 +272,12 @@ pub fn ldr(instr: u16, regs: &mut Registers, mem: &mut Memory) {
 /// ```asm
 /// STR  SR, BaseR, offset6
 /// ```
-pub fn str(instr: u16, regs: &Registers, mem: &mut Memory) {
-    let src_reg = (instr >> 9) & 0x7;
-    let base_reg = (instr >> 6) & 0x7;
-    let offset = sign_extend(instr & 0x3F, 6);
+pub fn str(src_reg: u16, base_reg: u16, offset: u16, regs: &Registers, mem: &mut Memory) {
     let value = regs.read(src_reg);
     mem.write(regs.read(base_reg).wrapping_add(offset), value);
 }
 
-/// Parses and performs the `NOT` (*bitwise complement*) instruction
+/// Performs the `NOT` (*bitwise complement*) instruction
 ///
 /// **Note**: this instruction updates the `COND` register (NZP flags) based on the value written
 /// to `DR`.
@@ -288,15 +295,13 @@ pub fn str(instr: u16, regs: &Registers, mem: &mut Memory) {
 /// ```asm
 /// NOT  DR, SR
 /// ```
-pub fn not(instr: u16, regs: &mut Registers) {
-    let dest_reg = (instr >> 9) & 0x7;
-    let src_reg = (instr >> 6) & 0x7;
+pub fn not(dest_reg: u16, src_reg: u16, regs: &mut Registers) {
     let value = !regs.read(src_reg);
     regs.write(dest_reg, value);
     regs.update_cond_flags(value);
 }
 
-/// Parses and performs the `LDI` (*load indirect*) instruction
+/// Performs the `LDI` (*load indirect*) instruction
 ///
 /// **Note**: this instruction updates the `COND` register (NZP flags) based on the value written
 /// to `DR`.
@@ -314,16 +319,14 @@ pub fn not(instr: u16, regs: &mut Registers) {
 /// ```asm
 /// LDI  DR, LABEL
 /// ```
-pub fn ldi(instr: u16, regs: &mut Registers, mem: &mut Memory) {
-    let dest_reg = (instr >> 9) & 0x7;
-    let pc_offset = sign_extend(instr & 0x1FF, 9);
+pub fn ldi(dest_reg: u16, pc_offset: u16, regs: &mut Registers, mem: &mut Memory) {
     let mem_addr = mem.read(regs.pc.wrapping_add(pc_offset));
     let value = mem.read(mem_addr);
     regs.write(dest_reg, value);
     regs.update_cond_flags(value);
 }
 
-/// Parses and performs the `STI` (*store indirect*) instruction
+/// Performs the `STI` (*store indirect*) instruction
 ///
 /// # Binary encoding
 ///
@@ -338,14 +341,12 @@ pub fn ldi(instr: u16, regs: &mut Registers, mem: &mut Memory) {
 /// ```asm
 /// STI  SR, LABEL
 /// ```
-pub fn sti(instr: u16, regs: &Registers, mem: &mut Memory) {
-    let src_reg = (instr >> 9) & 0x7;
-    let pc_offset = sign_extend(instr & 0x1FF, 9);
+pub fn sti(src_reg: u16, pc_offset: u16, regs: &Registers, mem: &mut Memory) {
     let mem_addr = mem.read(regs.pc.wrapping_add(pc_offset));
     mem.write(mem_addr, regs.read(src_reg));
 }
 
-/// Parses and performs the `JMP` (*jump*) instruction
+/// Performs the `JMP` (*jump*) instruction
 ///
 /// Note that if the instruction's BaseR is R7, this instruction is equivalent to the `RET`
 /// (*return from subroutine*) instruction.
@@ -368,12 +369,11 @@ pub fn sti(instr: u16, regs: &Registers, mem: &mut Memory) {
 /// JMP  BaseR
 /// RET
 /// ```
-pub fn jmp(instr: u16, regs: &mut Registers) {
-    let base_reg = (instr >> 6) & 0x7;
+pub fn jmp(base_reg: u16, regs: &mut Registers) {
     regs.pc = regs.read(base_reg);
 }
 
-/// Parses and performs the `LEA` (*load effective address*) instruction
+/// Performs the `LEA` (*load effective address*) instruction
 ///
 /// **Note**: this instruction updates the `COND` register (NZP flags) based on the value written
 /// to `DR`.
@@ -391,15 +391,48 @@ pub fn jmp(instr: u16, regs: &mut Registers) {
 /// ```asm
 /// LDI  DR, LABEL
 /// ```
-pub fn lea(instr: u16, regs: &mut Registers) {
-    let dest_reg = (instr >> 9) & 0x7;
-    let pc_offset = sign_extend(instr & 0x1FF, 9);
+pub fn lea(dest_reg: u16, pc_offset: u16, regs: &mut Registers) {
     let value = regs.pc.wrapping_add(pc_offset);
     regs.write(dest_reg, value);
     regs.update_cond_flags(value);
 }
 
-/// Parses and performs the `TRAP` (*system call*) instruction; returns whether the vm should halt
+/// Performs the `RTI` (*return from interrupt*) instruction
+///
+/// Pops `PC` and `PSR` from the stack in `R6`, which an interrupt pushed them to. If the popped
+/// `PSR` returns to user mode, the supervisor stack pointer is saved and `R6` is restored from
+/// the saved user stack pointer. `RTI` is only legal in supervisor mode; in user mode it faults
+/// like an illegal opcode.
+///
+/// # Binary encoding
+///
+/// ```plain
+/// ┌───┬───┬───┬───┬───┬───┬───┬───┬───┬───┬───┬───┬───┬───┬───┬───┐
+/// │ 1   0   0   0 │ 0   0   0   0   0   0   0   0   0   0   0   0 │
+/// └───┴───┴───┴───┴───┴───┴───┴───┴───┴───┴───┴───┴───┴───┴───┴───┘
+/// ```
+///
+/// # Assembly format
+///
+/// ```asm
+/// RTI
+/// ```
+pub fn rti(regs: &mut Registers, mem: &mut Memory) {
+    let stack_pointer = regs.read(6);
+    regs.pc = mem.read(stack_pointer);
+    let psr = mem.read(stack_pointer.wrapping_add(1));
+    regs.write(6, stack_pointer.wrapping_add(2));
+    regs.set_psr(psr);
+    if regs.user_mode() {
+        regs.saved_ssp = regs.read(6);
+        regs.write(6, regs.saved_usp);
+    }
+}
+
+/// Performs the `TRAP` (*system call*) instruction; returns how the vm should continue
+///
+/// Trap vectors that don't belong to one of the [`TrapCode`]s are rejected when the instruction
+/// is decoded.
 ///
 /// # Binary encoding
 ///
@@ -414,19 +447,33 @@ pub fn lea(instr: u16, regs: &mut Registers) {
 /// ```asm
 /// TRAP trapvector8
 /// ```
-pub fn trap(instr: u16, regs: &mut Registers, mem: &mut Memory) -> bool {
-    let trapvector = instr & 0xFF;
-    let trap_code = TrapCode::try_from(trapvector).unwrap_or_else(|_| panic!("Unsupported trap code: {:#010b}", trapvector));
-    match trap_code {
-        TrapCode::Getc => trap::getc(regs),
-        TrapCode::Out => trap::out(regs),
-        TrapCode::Puts => trap::puts(regs, mem),
-        TrapCode::In => trap::r#in(regs),
-        TrapCode::Putsp => trap::putsp(regs, mem),
+pub fn trap(trap_code: TrapCode, regs: &mut Registers, mem: &mut Memory) -> Flow {
+    let has_input = match trap_code {
+        TrapCode::Getc => trap::getc(regs, mem),
+        TrapCode::Out => {
+            trap::out(regs, mem);
+            true
+        }
+        TrapCode::Puts => {
+            trap::puts(regs, mem);
+            true
+        }
+        TrapCode::In => trap::r#in(regs, mem),
+        TrapCode::Putsp => {
+            trap::putsp(regs, mem);
+            true
+        }
         TrapCode::Halt => {
-            trap::halt();
-            return true;
+            trap::halt(mem);
+            return Flow::Halt;
         }
+    };
+    if has_input {
+        Flow::Continue
+    } else {
+        Flow::WaitForInput
     }
-    false
 }
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,159 @@
+//! Predecoded instructions
+//!
+//! Decoding extracts the register indices and sign-extends the offsets and immediates of an
+//! instruction once, so executing it again only has to dispatch on an [`Instruction`]. The
+//! [`Memory`](crate::vm::Memory) caches the decoded instruction of every address it fetched
+//! code from and invalidates the entry when the address is written, so self-modifying code
+//! keeps working.
+
+use super::trap::TrapCode;
+use crate::vm::utils::bit_ops::sign_extend;
+
+use std::convert::TryFrom;
+
+/// Second source operand of `ADD` and `AND`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    /// Index of the `SR2` register
+    Register(u16),
+    /// Sign-extended `imm5` value
+    Immediate(u16),
+}
+
+/// Instruction with its operands extracted; register operands are indices and offsets are
+/// sign-extended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    Br {
+        nzp: u16,
+        pc_offset: u16,
+    },
+    Add {
+        dr: u16,
+        sr1: u16,
+        src2: Operand,
+    },
+    Ld {
+        dr: u16,
+        pc_offset: u16,
+    },
+    St {
+        sr: u16,
+        pc_offset: u16,
+    },
+    Jsr {
+        pc_offset: u16,
+    },
+    Jsrr {
+        base: u16,
+    },
+    And {
+        dr: u16,
+        sr1: u16,
+        src2: Operand,
+    },
+    Ldr {
+        dr: u16,
+        base: u16,
+        offset: u16,
+    },
+    Str {
+        sr: u16,
+        base: u16,
+        offset: u16,
+    },
+    Not {
+        dr: u16,
+        sr: u16,
+    },
+    Ldi {
+        dr: u16,
+        pc_offset: u16,
+    },
+    Sti {
+        sr: u16,
+        pc_offset: u16,
+    },
+    Jmp {
+        base: u16,
+    },
+    Lea {
+        dr: u16,
+        pc_offset: u16,
+    },
+    Rti,
+    Trap(TrapCode),
+    /// `TRAP` with a trap vector that is not supported
+    UnsupportedTrap(u8),
+    /// The reserved opcode
+    Illegal,
+}
+
+impl Instruction {
+    /// Decodes the raw instruction `instr`
+    pub fn decode(instr: u16) -> Self {
+        let dr = (instr >> 9) & 0x7;
+        let sr1 = (instr >> 6) & 0x7;
+        let pc_offset9 = sign_extend(instr & 0x1FF, 9);
+        let offset6 = sign_extend(instr & 0x3F, 6);
+        let src2 = if (instr >> 5) & 0x1 == 0x1 {
+            Operand::Immediate(sign_extend(instr & 0x1F, 5))
+        } else {
+            Operand::Register(instr & 0x7)
+        };
+
+        match instr >> 12 {
+            0b0000 => Instruction::Br {
+                nzp: dr,
+                pc_offset: pc_offset9,
+            },
+            0b0001 => Instruction::Add { dr, sr1, src2 },
+            0b0010 => Instruction::Ld {
+                dr,
+                pc_offset: pc_offset9,
+            },
+            0b0011 => Instruction::St {
+                sr: dr,
+                pc_offset: pc_offset9,
+            },
+            0b0100 if (instr >> 11) & 0x1 == 0x1 => Instruction::Jsr {
+                pc_offset: sign_extend(instr & 0x7FF, 11),
+            },
+            0b0100 => Instruction::Jsrr { base: sr1 },
+            0b0101 => Instruction::And { dr, sr1, src2 },
+            0b0110 => Instruction::Ldr {
+                dr,
+                base: sr1,
+                offset: offset6,
+            },
+            0b0111 => Instruction::Str {
+                sr: dr,
+                base: sr1,
+                offset: offset6,
+            },
+            0b1000 => Instruction::Rti,
+            0b1001 => Instruction::Not { dr, sr: sr1 },
+            0b1010 => Instruction::Ldi {
+                dr,
+                pc_offset: pc_offset9,
+            },
+            0b1011 => Instruction::Sti {
+                sr: dr,
+                pc_offset: pc_offset9,
+            },
+            0b1100 => Instruction::Jmp { base: sr1 },
+            0b1110 => Instruction::Lea {
+                dr,
+                pc_offset: pc_offset9,
+            },
+            0b1111 => {
+                let trapvector = instr & 0xFF;
+                match TrapCode::try_from(trapvector) {
+                    Ok(trap_code) => Instruction::Trap(trap_code),
+                    Err(()) => Instruction::UnsupportedTrap(trapvector as u8),
+                }
+            }
+            _ => Instruction::Illegal,
+        }
+    }
+}
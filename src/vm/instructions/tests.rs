@@ -0,0 +1,462 @@
+//! Conformance tests of the instruction handlers
+//!
+//! Every test decodes a raw instruction and executes it like the vm does: `PC` already points
+//! to the next instruction when the handler runs.
+
+use super::*;
+use crate::vm::BufferedConsole;
+
+/// Address of the executed instruction
+const PC: u16 = 0x3000;
+
+struct Machine {
+    regs: Registers,
+    mem: Memory,
+    console: BufferedConsole,
+}
+
+impl Machine {
+    fn new() -> Self {
+        let console = BufferedConsole::new();
+        let mut mem = Memory::new();
+        mem.set_console(Box::new(console.clone()));
+        Self {
+            regs: Registers::new(),
+            mem,
+            console,
+        }
+    }
+
+    /// Executes `instr` as if it were stored at `pc`
+    fn execute_at(&mut self, pc: u16, instr: u16) -> Result<Flow, Fault> {
+        self.regs.pc = pc.wrapping_add(1);
+        execute(
+            instr,
+            Instruction::decode(instr),
+            &mut self.regs,
+            &mut self.mem,
+        )
+    }
+
+    /// Executes `instr` as if it were stored at [`PC`]; panics unless execution continues
+    fn execute(&mut self, instr: u16) {
+        assert_eq!(self.execute_at(PC, instr), Ok(Flow::Continue));
+    }
+
+    fn reg(&self, index: u16) -> u16 {
+        self.regs.read(index)
+    }
+
+    fn set_reg(&mut self, index: u16, value: u16) {
+        self.regs.write(index, value);
+    }
+
+    fn output(&self) -> Vec<u8> {
+        self.console.output()
+    }
+}
+
+#[test]
+fn add_register() {
+    let mut m = Machine::new();
+    m.set_reg(1, 3);
+    m.set_reg(2, 4);
+    m.execute(0x1042); // ADD R0, R1, R2
+    assert_eq!(m.reg(0), 7);
+    assert_eq!(m.regs.cond, CondFlag::Pos);
+}
+
+#[test]
+fn add_immediate_sign_extension() {
+    let mut m = Machine::new();
+    m.set_reg(1, 5);
+    m.execute(0x1270); // ADD R1, R1, #-16
+    assert_eq!(m.reg(1), 0xFFF5);
+    assert_eq!(m.regs.cond, CondFlag::Neg);
+
+    m.execute(0x126F); // ADD R1, R1, #15
+    assert_eq!(m.reg(1), 4);
+    assert_eq!(m.regs.cond, CondFlag::Pos);
+}
+
+#[test]
+fn add_wraps_around() {
+    let mut m = Machine::new();
+    m.set_reg(0, 0xFFFF);
+    m.execute(0x1021); // ADD R0, R0, #1
+    assert_eq!(m.reg(0), 0);
+    assert_eq!(m.regs.cond, CondFlag::Zero);
+
+    m.set_reg(0, 0x7FFF);
+    m.execute(0x1021); // ADD R0, R0, #1
+    assert_eq!(m.reg(0), 0x8000);
+    assert_eq!(m.regs.cond, CondFlag::Neg);
+}
+
+#[test]
+fn and_register_and_immediate() {
+    let mut m = Machine::new();
+    m.set_reg(1, 0b1100);
+    m.set_reg(2, 0b1010);
+    m.execute(0x5042); // AND R0, R1, R2
+    assert_eq!(m.reg(0), 0b1000);
+    assert_eq!(m.regs.cond, CondFlag::Pos);
+
+    m.set_reg(3, 0xABCD);
+    m.execute(0x56FF); // AND R3, R3, #-1
+    assert_eq!(m.reg(3), 0xABCD);
+    assert_eq!(m.regs.cond, CondFlag::Neg);
+
+    m.execute(0x56E0); // AND R3, R3, #0
+    assert_eq!(m.reg(3), 0);
+    assert_eq!(m.regs.cond, CondFlag::Zero);
+}
+
+#[test]
+fn not_complements() {
+    let mut m = Machine::new();
+    m.set_reg(1, 0x00FF);
+    m.execute(0x907F); // NOT R0, R1
+    assert_eq!(m.reg(0), 0xFF00);
+    assert_eq!(m.regs.cond, CondFlag::Neg);
+
+    m.set_reg(1, 0xFFFF);
+    m.execute(0x907F); // NOT R0, R1
+    assert_eq!(m.reg(0), 0);
+    assert_eq!(m.regs.cond, CondFlag::Zero);
+}
+
+#[test]
+fn br_conditions() {
+    // BRn, BRz, BRp, BRnzp and BR with no flags, each by 4
+    let cases = [
+        (0x0804, [true, false, false]),
+        (0x0404, [false, true, false]),
+        (0x0204, [false, false, true]),
+        (0x0E04, [true, true, true]),
+        (0x0004, [false, false, false]),
+    ];
+    for &(instr, taken) in &cases {
+        for (&cond, &taken) in [CondFlag::Neg, CondFlag::Zero, CondFlag::Pos]
+            .iter()
+            .zip(&taken)
+        {
+            let mut m = Machine::new();
+            m.regs.cond = cond;
+            m.execute(instr);
+            let expected = if taken { PC + 5 } else { PC + 1 };
+            assert_eq!(m.regs.pc, expected, "{:#06x} with {:?}", instr, cond);
+            assert_eq!(m.regs.cond, cond);
+        }
+    }
+}
+
+#[test]
+fn br_offset_sign_extension() {
+    let mut m = Machine::new();
+    m.regs.cond = CondFlag::Zero;
+    m.execute(0x0500); // BRz #-256
+    assert_eq!(m.regs.pc, PC + 1 - 256);
+
+    m.execute(0x04FF); // BRz #255
+    assert_eq!(m.regs.pc, PC + 1 + 255);
+}
+
+#[test]
+fn br_wraps_around() {
+    let mut m = Machine::new();
+    m.regs.cond = CondFlag::Zero;
+    assert_eq!(m.execute_at(0xFFFE, 0x0E02), Ok(Flow::Continue)); // BRnzp #2
+    assert_eq!(m.regs.pc, 0x0001);
+
+    assert_eq!(m.execute_at(0x0000, 0x0FFD), Ok(Flow::Continue)); // BRnzp #-3
+    assert_eq!(m.regs.pc, 0xFFFE);
+}
+
+#[test]
+fn ld_and_st() {
+    let mut m = Machine::new();
+    m.mem.poke(PC + 1 - 256, 0x8000);
+    m.execute(0x2500); // LD R2, #-256
+    assert_eq!(m.reg(2), 0x8000);
+    assert_eq!(m.regs.cond, CondFlag::Neg);
+
+    m.execute(0x34FF); // ST R2, #255
+    assert_eq!(m.mem.peek(PC + 1 + 255), 0x8000);
+    assert_eq!(m.regs.cond, CondFlag::Neg);
+}
+
+#[test]
+fn ld_sets_zero_flag() {
+    let mut m = Machine::new();
+    m.regs.cond = CondFlag::Pos;
+    m.execute(0x2005); // LD R0, #5
+    assert_eq!(m.reg(0), 0);
+    assert_eq!(m.regs.cond, CondFlag::Zero);
+}
+
+#[test]
+fn ld_and_st_wrap_around() {
+    let mut m = Machine::new();
+    m.mem.poke(0x0002, 0x1234);
+    assert_eq!(m.execute_at(0xFFFF, 0x2002), Ok(Flow::Continue)); // LD R0, #2
+    assert_eq!(m.reg(0), 0x1234);
+
+    assert_eq!(m.execute_at(0x0000, 0x31FE), Ok(Flow::Continue)); // ST R0, #-2
+    assert_eq!(m.mem.peek(0xFFFF), 0x1234);
+}
+
+#[test]
+fn ldr_and_str_offsets() {
+    let mut m = Machine::new();
+    m.set_reg(1, 0x4000);
+    m.mem.poke(0x4000 - 32, 0x0042);
+    m.execute(0x6060); // LDR R0, R1, #-32
+    assert_eq!(m.reg(0), 0x0042);
+    assert_eq!(m.regs.cond, CondFlag::Pos);
+
+    m.execute(0x705F); // STR R0, R1, #31
+    assert_eq!(m.mem.peek(0x4000 + 31), 0x0042);
+}
+
+#[test]
+fn ldr_and_str_wrap_around() {
+    let mut m = Machine::new();
+    m.set_reg(1, 0xFFFE);
+    m.set_reg(2, 0xBEEF);
+    m.execute(0x7443); // STR R2, R1, #3
+    assert_eq!(m.mem.peek(0x0001), 0xBEEF);
+
+    m.set_reg(1, 0x0001);
+    m.execute(0x607E); // LDR R0, R1, #-2
+    assert_eq!(m.mem.peek(0xFFFF), m.reg(0));
+}
+
+#[test]
+fn ldi_and_sti() {
+    let mut m = Machine::new();
+    m.mem.poke(PC + 1 + 3, 0x5000);
+    m.mem.poke(0x5000, 0xFFFF);
+    m.execute(0xA603); // LDI R3, #3
+    assert_eq!(m.reg(3), 0xFFFF);
+    assert_eq!(m.regs.cond, CondFlag::Neg);
+
+    m.set_reg(4, 0x0007);
+    m.execute(0xB803); // STI R4, #3
+    assert_eq!(m.mem.peek(0x5000), 0x0007);
+}
+
+#[test]
+fn lea_sets_flags() {
+    let mut m = Machine::new();
+    m.execute(0xE1FF); // LEA R0, #-1
+    assert_eq!(m.reg(0), PC);
+    assert_eq!(m.regs.cond, CondFlag::Pos);
+
+    assert_eq!(m.execute_at(0xFFFF, 0xE1FF), Ok(Flow::Continue)); // LEA R0, #-1
+    assert_eq!(m.reg(0), 0xFFFF);
+    assert_eq!(m.regs.cond, CondFlag::Neg);
+
+    assert_eq!(m.execute_at(0xFFFF, 0xE000), Ok(Flow::Continue)); // LEA R0, #0
+    assert_eq!(m.reg(0), 0);
+    assert_eq!(m.regs.cond, CondFlag::Zero);
+}
+
+#[test]
+fn jmp_and_ret() {
+    let mut m = Machine::new();
+    m.set_reg(2, 0x4567);
+    m.regs.cond = CondFlag::Neg;
+    m.execute(0xC080); // JMP R2
+    assert_eq!(m.regs.pc, 0x4567);
+    assert_eq!(m.regs.cond, CondFlag::Neg);
+
+    m.set_reg(7, 0x3456);
+    m.execute(0xC1C0); // RET
+    assert_eq!(m.regs.pc, 0x3456);
+}
+
+#[test]
+fn jsr_offset_sign_extension() {
+    let mut m = Machine::new();
+    m.execute(0x4C00); // JSR #-1024
+    assert_eq!(m.regs.pc, PC + 1 - 1024);
+    assert_eq!(m.reg(7), PC + 1);
+
+    m.execute(0x4BFF); // JSR #1023
+    assert_eq!(m.regs.pc, PC + 1 + 1023);
+    assert_eq!(m.reg(7), PC + 1);
+}
+
+#[test]
+fn jsrr() {
+    let mut m = Machine::new();
+    m.set_reg(3, 0x6000);
+    m.regs.cond = CondFlag::Zero;
+    m.execute(0x40C0); // JSRR R3
+    assert_eq!(m.regs.pc, 0x6000);
+    assert_eq!(m.reg(7), PC + 1);
+    assert_eq!(m.regs.cond, CondFlag::Zero);
+}
+
+#[test]
+fn jsrr_with_r7_as_base() {
+    let mut m = Machine::new();
+    m.set_reg(7, 0x6000);
+    m.execute(0x41C0); // JSRR R7
+    assert_eq!(m.regs.pc, 0x6000);
+    assert_eq!(m.reg(7), PC + 1);
+}
+
+#[test]
+fn jsr_wraps_around() {
+    let mut m = Machine::new();
+    assert_eq!(m.execute_at(0xFFFF, 0x4802), Ok(Flow::Continue)); // JSR #2
+    assert_eq!(m.regs.pc, 0x0002);
+    assert_eq!(m.reg(7), 0x0000);
+}
+
+#[test]
+fn rti_pops_pc_and_psr() {
+    let mut m = Machine::new();
+    m.regs.set_psr(0x0400); // supervisor, priority 4
+    m.set_reg(6, 0x2FFE);
+    m.mem.write(0x2FFE, 0x4000);
+    m.mem.write(0x2FFF, 0x0301); // supervisor, priority 3, P
+    m.execute(0x8000); // RTI
+    assert_eq!(m.regs.pc, 0x4000);
+    assert_eq!(m.reg(6), 0x3000);
+    assert_eq!(m.regs.psr(), 0x0301);
+    assert_eq!(m.regs.cond, CondFlag::Pos);
+}
+
+#[test]
+fn rti_to_user_mode_switches_stacks() {
+    let mut m = Machine::new();
+    m.regs.set_psr(0x0100);
+    m.regs.saved_usp = 0xF000;
+    m.set_reg(6, 0x2FFE);
+    m.mem.write(0x2FFE, 0x3005);
+    m.mem.write(0x2FFF, 0x8004); // user, priority 0, N
+    m.execute(0x8000); // RTI
+    assert_eq!(m.regs.pc, 0x3005);
+    assert!(m.regs.user_mode());
+    assert_eq!(m.reg(6), 0xF000);
+    assert_eq!(m.regs.saved_ssp, 0x3000);
+    assert_eq!(m.regs.psr(), 0x8004);
+}
+
+#[test]
+fn rti_in_user_mode_is_illegal() {
+    let mut m = Machine::new();
+    m.set_reg(6, 0x2FFE);
+    assert_eq!(m.execute_at(PC, 0x8000), Err(Fault::IllegalOpcode(0x8000)));
+    assert_eq!(m.reg(6), 0x2FFE);
+}
+
+#[test]
+fn illegal_opcodes() {
+    let mut m = Machine::new();
+    assert_eq!(m.execute_at(PC, 0xD123), Err(Fault::IllegalOpcode(0xD123))); // RES
+}
+
+#[test]
+fn unsupported_trap() {
+    let mut m = Machine::new();
+    assert_eq!(m.execute_at(PC, 0xF026), Err(Fault::UnsupportedTrap(0x26)));
+}
+
+#[test]
+fn getc_waits_for_input() {
+    let mut m = Machine::new();
+    m.regs.cond = CondFlag::Neg;
+    assert_eq!(m.execute_at(PC, 0xF020), Ok(Flow::WaitForInput));
+
+    m.console.push_input(b"x");
+    m.execute(0xF020); // GETC
+    assert_eq!(m.reg(0), u16::from(b'x'));
+    assert_eq!(m.regs.cond, CondFlag::Neg);
+    assert!(m.output().is_empty());
+}
+
+#[test]
+fn in_prompts_for_input() {
+    let mut m = Machine::new();
+    assert_eq!(m.execute_at(PC, 0xF023), Ok(Flow::WaitForInput));
+    assert!(m.output().is_empty());
+
+    m.console.push_input(b"y");
+    m.execute(0xF023); // IN
+    assert_eq!(m.reg(0), u16::from(b'y'));
+    assert_eq!(m.output(), b"Enter character: ");
+}
+
+#[test]
+fn out_writes_low_byte() {
+    let mut m = Machine::new();
+    m.set_reg(0, 0x1241);
+    m.execute(0xF021); // OUT
+    assert_eq!(m.output(), b"A");
+}
+
+#[test]
+fn puts_writes_until_zero() {
+    let mut m = Machine::new();
+    for (offset, &chr) in b"Hi!\0rest".iter().enumerate() {
+        m.mem.poke(0x4000 + offset as u16, chr.into());
+    }
+    m.set_reg(0, 0x4000);
+    m.execute(0xF022); // PUTS
+    assert_eq!(m.output(), b"Hi!");
+}
+
+#[test]
+fn puts_stops_at_end_of_memory() {
+    let mut m = Machine::new();
+    m.mem.poke(0xFFFF, u16::from(b'z'));
+    m.set_reg(0, 0xFFFF);
+    m.execute(0xF022); // PUTS
+    assert_eq!(m.output(), b"z");
+}
+
+#[test]
+fn putsp_even_length() {
+    let mut m = Machine::new();
+    m.mem.poke(0x4000, 0x6162); // "ba"
+    m.mem.poke(0x4001, 0x6463); // "cd"
+    m.set_reg(0, 0x4000);
+    m.execute(0xF024); // PUTSP
+    assert_eq!(m.output(), b"bacd");
+}
+
+#[test]
+fn putsp_odd_length() {
+    let mut m = Machine::new();
+    m.mem.poke(0x4000, 0x6261); // "ab"
+    m.mem.poke(0x4001, 0x0063); // "c"
+    m.mem.poke(0x4002, 0x6564); // not part of the string
+    m.set_reg(0, 0x4000);
+    m.execute(0xF024); // PUTSP
+    assert_eq!(m.output(), b"abc");
+}
+
+#[test]
+fn halt() {
+    let mut m = Machine::new();
+    assert_eq!(m.execute_at(PC, 0xF025), Ok(Flow::Halt));
+    assert_eq!(m.output(), b"HALT");
+}
+
+#[test]
+fn sign_extension_of_every_width() {
+    use crate::vm::utils::bit_ops::sign_extend;
+
+    for &bits in &[5, 6, 9, 11] {
+        let max = (1 << (bits - 1)) - 1;
+        let min = 1 << (bits - 1);
+        assert_eq!(sign_extend(max, bits), max);
+        assert_eq!(sign_extend(min, bits) as i16, -(min as i16));
+        assert_eq!(sign_extend((1 << bits) - 1, bits), 0xFFFF);
+        assert_eq!(sign_extend(0, bits), 0);
+    }
+}
@@ -1,8 +1,8 @@
-use crate::vm::{memory, utils, Memory, Registers};
+use crate::vm::{Memory, Registers};
 
 use std::convert::TryFrom;
-use std::io::{self, Write};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TrapCode {
     Getc,
     Out,
@@ -32,44 +32,67 @@ impl TryFrom<u16> for TrapCode {
     }
 }
 
-pub fn getc(regs: &mut Registers) {
-    regs.write(0, utils::io::read_next_byte() as u16);
+/// Reads a single character into `R0`; returns `false` if no input is available
+pub fn getc(regs: &mut Registers, mem: &mut Memory) -> bool {
+    match mem.read_input() {
+        Some(chr) => {
+            regs.write(0, chr as u16);
+            true
+        }
+        None => false,
+    }
 }
 
-pub fn out(regs: &Registers) {
-    print!("{}", regs.read(0) as u8 as char);
-    io::stdout().flush().expect("Error while flushing stdout");
+pub fn out(regs: &Registers, mem: &mut Memory) {
+    mem.write_output(&[regs.read(0) as u8]);
 }
 
 pub fn puts(regs: &Registers, mem: &mut Memory) {
-    for mem_addr in regs.read(0)..(memory::MEMORY_SIZE as u16) {
+    let mut output = Vec::new();
+    for mem_addr in regs.read(0)..=u16::MAX {
         let chr = mem.read(mem_addr);
         if chr == 0x0000 {
             break;
         }
-        print!("{}", chr as u8 as char);
+        output.push(chr as u8);
     }
-    io::stdout().flush().expect("Error while flushing stdout");
+    mem.write_output(&output);
 }
 
+/// Writes the string with two characters per word (low byte first) that starts at the address in
+/// `R0`; a zero high byte ends a string of odd length
 pub fn putsp(regs: &Registers, mem: &mut Memory) {
-    for mem_addr in regs.read(0)..(memory::MEMORY_SIZE as u16) {
+    let mut output = Vec::new();
+    for mem_addr in regs.read(0)..=u16::MAX {
         let word = mem.read(mem_addr);
         if word == 0x0000 {
             break;
         }
         let [chr2, chr1] = word.to_be_bytes();
-        print!("{}{}", chr1 as char, chr2 as char);
+        output.push(chr1);
+        if chr2 == 0x00 {
+            break;
+        }
+        output.push(chr2);
     }
-    io::stdout().flush().expect("Error while flushing stdout");
+    mem.write_output(&output);
 }
 
-pub fn r#in(regs: &mut Registers) {
-    print!("Enter character: ");
-    regs.write(0, utils::io::read_next_byte() as u16);
+/// Prompts for and reads a single character into `R0`; returns `false` if no input is available
+pub fn r#in(regs: &mut Registers, mem: &mut Memory) -> bool {
+    if !mem.has_input() {
+        return false;
+    }
+    mem.write_output(b"Enter character: ");
+    match mem.read_input() {
+        Some(chr) => {
+            regs.write(0, chr as u16);
+            true
+        }
+        None => false,
+    }
 }
 
-pub fn halt() {
-    print!("HALT");
-    io::stdout().flush().expect("Error while flushing stdout");
+pub fn halt(mem: &mut Memory) {
+    mem.write_output(b"HALT");
 }
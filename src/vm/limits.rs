@@ -0,0 +1,125 @@
+//! Limits that stop runaway programs
+
+use std::fmt;
+use std::time::Duration;
+
+/// Limits of a run, which stop the vm with [`StopReason::LimitExceeded`](super::StopReason)
+///
+/// Limits are checked by [`Vm::run`](super::Vm::run) and [`Vm::resume`](super::Vm::resume)
+/// before each instruction, so every engine stops at the same instruction. The default has no
+/// limits.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// Maximum number of executed instructions, see [`Vm::instructions`](super::Vm::instructions)
+    pub max_instructions: Option<u64>,
+    /// Maximum number of cycles, see [`Vm::cycles`](super::Vm::cycles)
+    pub max_cycles: Option<u64>,
+    /// Maximum host time of a single call to `run` or `resume`
+    ///
+    /// The time is checked every few thousand instructions, so it may be exceeded slightly.
+    pub timeout: Option<Duration>,
+    /// Maximum number of bytes the program writes to the console, see
+    /// [`Memory::output_bytes`](super::Memory::output_bytes)
+    ///
+    /// The trap that writes past the limit still completes, and the vm stops after it.
+    pub max_output: Option<u64>,
+}
+
+impl Limits {
+    /// Returns whether any limit is set
+    pub fn is_limited(&self) -> bool {
+        *self != Self::default()
+    }
+}
+
+/// Limit that stopped the vm
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Instructions,
+    Cycles,
+    Timeout,
+    Output,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Limit::Instructions => "instruction limit",
+            Limit::Cycles => "cycle limit",
+            Limit::Timeout => "timeout",
+            Limit::Output => "output limit",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Addresses of the most recently executed instructions
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct RecentTrace {
+    /// Ring buffer of addresses; `next` is the index of the oldest one once it is full
+    pcs: [u16; RecentTrace::CAPACITY],
+    next: usize,
+    len: usize,
+}
+
+impl RecentTrace {
+    /// Maximum number of remembered addresses
+    pub const CAPACITY: usize = 16;
+
+    pub(crate) fn new() -> Self {
+        Self {
+            pcs: [0; Self::CAPACITY],
+            next: 0,
+            len: 0,
+        }
+    }
+
+    /// Remembers that the instruction at `pc` was executed
+    pub(crate) fn push(&mut self, pc: u16) {
+        self.pcs[self.next] = pc;
+        self.next = (self.next + 1) % Self::CAPACITY;
+        self.len = (self.len + 1).min(Self::CAPACITY);
+    }
+
+    /// Remembers `instructions` instructions of the block at `start`, whose passes are `len`
+    /// instructions long
+    pub(crate) fn push_block(&mut self, start: u16, len: u16, instructions: u64) {
+        let len = u64::from(len.max(1));
+        let first = instructions.saturating_sub(Self::CAPACITY as u64);
+        for index in first..instructions {
+            self.push(start.wrapping_add((index % len) as u16));
+        }
+    }
+
+    /// Returns the addresses, oldest first
+    pub fn pcs(&self) -> impl Iterator<Item = u16> + '_ {
+        let first = (self.next + Self::CAPACITY - self.len) % Self::CAPACITY;
+        (0..self.len).map(move |index| self.pcs[(first + index) % Self::CAPACITY])
+    }
+}
+
+impl Default for RecentTrace {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for RecentTrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list()
+            .entries(self.pcs().map(|pc| format!("x{:04X}", pc)))
+            .finish()
+    }
+}
+
+impl fmt::Display for RecentTrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (index, pc) in self.pcs().enumerate() {
+            if index > 0 {
+                f.write_str(" ")?;
+            }
+            write!(f, "x{:04X}", pc)?;
+        }
+        Ok(())
+    }
+}
@@ -1,18 +1,88 @@
-use super::utils;
+use super::console::{Console, TerminalConsole};
+use super::device::{Device, Interrupt, IoBus};
+use super::history::Journal;
+use super::input_log::Replay;
+use super::instructions::Instruction;
+use super::watchpoint::{WatchKind, Watchpoint, WatchpointHit};
 
-pub const MEMORY_SIZE: usize = u16::MAX as _;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+use std::ops::RangeInclusive;
+
+#[cfg(test)]
+mod tests;
+
+pub const MEMORY_SIZE: usize = 1 << 16;
 
 /// Address constants of the memory mapped registers
-mod mem_mapped_reg_addr {
+pub(crate) mod mem_mapped_reg_addr {
     /// Keyboard status register
     pub const KBSR: u16 = 0xFE00;
     /// Keyboard data register
     pub const KBDR: u16 = 0xFE02;
+    /// Timer control register
+    pub const TCR: u16 = 0xFE08;
+    /// Timer interval register
+    pub const TIR: u16 = 0xFE0A;
+    /// Timer status register
+    pub const TSR: u16 = 0xFE0C;
+    /// Timer count register
+    pub const TCNT: u16 = 0xFE0E;
+    /// Disk status register
+    pub const DSR: u16 = 0xFE10;
+    /// Disk control register
+    pub const DCR: u16 = 0xFE12;
+    /// Disk sector register
+    pub const DSEC: u16 = 0xFE14;
+    /// Disk buffer address register
+    pub const DBUF: u16 = 0xFE16;
+    /// First address of the device register page
+    pub const DEVICE_PAGE: u16 = 0xFE00;
+}
+
+/// Numbers of data accesses since the last call to [`Memory::take_access_counts`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AccessCounts {
+    /// Reads and writes of ordinary memory
+    pub memory: u64,
+    /// Reads and writes of device registers and bytes transferred by the I/O traps
+    pub device: u64,
 }
 
 /// Wrapper type that represents the vm's memory
 pub struct Memory {
     mem: [u16; MEMORY_SIZE],
+    /// Decoded instructions of the addresses that code was fetched from; an entry is cleared
+    /// whenever its address is written
+    decoded: Box<[Option<Instruction>]>,
+    watchpoints: Vec<Watchpoint>,
+    /// First watchpoint triggered since the last call to `take_watchpoint_hit`, as
+    /// `(address, kind, old_value, new_value)`
+    pending_hit: Option<(u16, WatchKind, u16, u16)>,
+    /// Console that backs the keyboard registers and the I/O traps
+    console: Box<dyn Console>,
+    /// Input that was consumed by undone instructions and is delivered again before any new
+    /// input from the console
+    replayed_input: VecDeque<u8>,
+    /// Accesses of the current instruction, if the execution history is recorded
+    journal: Option<Journal>,
+    access_counts: AccessCounts,
+    /// Recorded input that replaces the console until all of it was delivered
+    replay: Option<Replay>,
+    /// Number of translated basic blocks that contain each address
+    block_refs: Box<[u8]>,
+    /// Addresses inside basic blocks that were written since the last call to
+    /// `take_modified_code`
+    modified_code: Vec<u16>,
+    /// Number of bytes written to the console
+    output_bytes: u64,
+    /// Devices of the device register page
+    bus: IoBus,
+    /// Whether a device register was written since the devices were last advanced
+    device_written: bool,
 }
 
 impl Memory {
@@ -20,6 +90,19 @@ impl Memory {
     pub fn new() -> Self {
         Self {
             mem: [0; MEMORY_SIZE],
+            decoded: vec![None; MEMORY_SIZE].into_boxed_slice(),
+            watchpoints: Vec::new(),
+            pending_hit: None,
+            console: Box::new(TerminalConsole),
+            replayed_input: VecDeque::new(),
+            journal: None,
+            access_counts: AccessCounts::default(),
+            replay: None,
+            block_refs: vec![0; MEMORY_SIZE].into_boxed_slice(),
+            modified_code: Vec::new(),
+            output_bytes: 0,
+            bus: IoBus::new(),
+            device_written: false,
         }
     }
 
@@ -28,20 +111,382 @@ impl Memory {
     /// This requires a mutable reference to self, because reading a Memory Mapped Register may
     /// have side-effects.
     pub fn read(&mut self, address: u16) -> u16 {
-        if address == mem_mapped_reg_addr::KBSR {
-            let chr = utils::io::read_next_byte();
-            if chr != 0 {
-                self.mem[mem_mapped_reg_addr::KBSR as usize] = 1 << 15;
-                self.mem[mem_mapped_reg_addr::KBDR as usize] = chr as u16;
-            } else {
-                self.mem[mem_mapped_reg_addr::KBSR as usize] = 0;
-            }
+        self.count_access(address);
+        let value = self.fetch(address);
+        if let Some(journal) = &mut self.journal {
+            journal.reads.push(address);
+        }
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(address, WatchKind::Read, value, value);
+        }
+        value
+    }
+
+    /// Reads the value at the given memory `address` like [`read`](Self::read), but without
+    /// triggering watchpoints
+    ///
+    /// This is used for instruction fetches, which are not considered data accesses.
+    pub fn fetch(&mut self, address: u16) -> u16 {
+        if address >= mem_mapped_reg_addr::DEVICE_PAGE {
+            return self.with_bus(|bus, mem| bus.read(address, mem));
+        }
+        self.mem[address as usize]
+    }
+
+    /// Fetches the instruction at the given `address` like [`fetch`](Self::fetch) and returns it
+    /// together with its decoded form
+    ///
+    /// Instructions are decoded once and cached until their address is written. Device
+    /// registers are never cached, since reading them has side-effects.
+    pub(crate) fn fetch_decoded(&mut self, address: u16) -> (u16, Instruction) {
+        if address >= mem_mapped_reg_addr::DEVICE_PAGE {
+            let instr = self.fetch(address);
+            return (instr, Instruction::decode(instr));
+        }
+        let instr = self.mem[address as usize];
+        let decoded = &mut self.decoded[address as usize];
+        match decoded {
+            Some(instruction) => (instr, *instruction),
+            None => (instr, *decoded.insert(Instruction::decode(instr))),
         }
+    }
+
+    /// Returns the next input byte from the console or `None` if no input is available
+    ///
+    /// This counts as a device access.
+    pub fn read_input(&mut self) -> Option<u8> {
+        self.access_counts.device += 1;
+        self.next_input()
+    }
+
+    /// Writes the `bytes` to the console, which counts as a device access per byte
+    pub fn write_output(&mut self, bytes: &[u8]) {
+        self.access_counts.device += bytes.len() as u64;
+        self.output_bytes += bytes.len() as u64;
+        self.console.write(bytes);
+    }
+
+    /// Returns the number of bytes written to the console since the memory was created
+    pub fn output_bytes(&self) -> u64 {
+        self.output_bytes
+    }
+
+    pub(crate) fn next_input(&mut self) -> Option<u8> {
+        let chr = match (self.replayed_input.pop_front(), &mut self.replay) {
+            (Some(chr), _) => Some(chr),
+            (None, Some(replay)) if !replay.is_done() => replay.read_byte(),
+            (None, _) => self.console.read_byte(),
+        };
+        if let (Some(journal), Some(chr)) = (&mut self.journal, chr) {
+            journal.input.push(chr);
+        }
+        chr
+    }
+
+    /// Returns whether [`read_input`](Self::read_input) would return a byte
+    pub fn has_input(&mut self) -> bool {
+        if !self.replayed_input.is_empty() {
+            return true;
+        }
+        match &self.replay {
+            Some(replay) if !replay.is_done() => replay.has_input(),
+            _ => self.console.has_input(),
+        }
+    }
+
+    /// Returns the value at the given memory `address` without any side-effects
+    ///
+    /// Unlike [`read`](Self::read), this neither polls Memory Mapped Registers nor triggers
+    /// watchpoints, which makes it suitable for inspecting memory from a debugger.
+    pub fn peek(&self, address: u16) -> u16 {
         self.mem[address as usize]
     }
 
+    /// Writes the `value` to the given memory `address` without triggering watchpoints
+    ///
+    /// This is the counterpart of [`peek`](Self::peek) for modifying memory from a debugger.
+    pub fn poke(&mut self, address: u16, value: u16) {
+        self.mem[address as usize] = value;
+        self.decoded[address as usize] = None;
+        if self.block_refs[address as usize] != 0 {
+            self.modified_code.push(address);
+        }
+    }
+
     /// Writes the `value` to the given memory `address`
+    ///
+    /// Device registers are written by their [`Device`], which decides what is stored.
     pub fn write(&mut self, address: u16, value: u16) {
+        self.count_access(address);
+        let old_value = self.mem[address as usize];
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(address, WatchKind::Write, old_value, value);
+        }
+        if address >= mem_mapped_reg_addr::DEVICE_PAGE {
+            self.device_written = true;
+            self.with_bus(|bus, mem| bus.write(address, value, mem));
+            return;
+        }
+        if let Some(journal) = &mut self.journal {
+            journal.writes.push((address, old_value));
+        }
+        self.mem[address as usize] = value;
+        self.decoded[address as usize] = None;
+        if self.block_refs[address as usize] != 0 {
+            self.modified_code.push(address);
+        }
+    }
+
+    /// Adds the `device` for the `addresses`, which must be in the device register page
+    /// (xFE00–xFFFF)
+    ///
+    /// The keyboard (xFE00–xFE03) and the timer (xFE08–xFE0F) are always present.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the addresses are outside of the device register page or overlap the
+    /// addresses of another device.
+    pub fn add_device(&mut self, addresses: RangeInclusive<u16>, device: Box<dyn Device>) {
+        self.bus.add(addresses, device);
+    }
+
+    /// Calls `f` with the devices, which are taken out of the memory meanwhile, so they can
+    /// access it
+    fn with_bus<T>(&mut self, f: impl FnOnce(&mut IoBus, &mut Self) -> T) -> T {
+        let mut bus = std::mem::take(&mut self.bus);
+        let result = f(&mut bus, self);
+        self.bus = bus;
+        result
+    }
+
+    /// Advances the devices by the given numbers of executed instructions and cycles
+    pub(crate) fn tick_devices(&mut self, instructions: u64, cycles: u64) {
+        self.device_written = false;
+        self.with_bus(|bus, mem| bus.tick(instructions, cycles, mem));
+    }
+
+    /// Returns the pending interrupt with the highest priority, if any
+    pub(crate) fn pending_interrupt(&mut self) -> Option<Interrupt> {
+        self.with_bus(|bus, mem| bus.interrupt(mem))
+    }
+
+    /// Returns the number of instructions that can execute before a device may request an
+    /// interrupt, given the maximum number of cycles of an instruction
+    pub(crate) fn device_budget(&mut self, max_instruction_cycles: u64) -> u64 {
+        self.with_bus(|bus, mem| bus.budget(max_instruction_cycles, mem))
+    }
+
+    /// Returns and resets the numbers of data accesses since the last call
+    pub fn take_access_counts(&mut self) -> AccessCounts {
+        std::mem::take(&mut self.access_counts)
+    }
+
+    fn count_access(&mut self, address: u16) {
+        if address >= mem_mapped_reg_addr::DEVICE_PAGE {
+            self.access_counts.device += 1;
+        } else {
+            self.access_counts.memory += 1;
+        }
+    }
+
+    /// Starts recording the accesses of the next instruction
+    pub(crate) fn begin_journal(&mut self) {
+        self.journal = Some(Journal::default());
+    }
+
+    /// Stops recording and returns the accesses since [`begin_journal`](Self::begin_journal)
+    pub(crate) fn end_journal(&mut self) -> Journal {
+        self.journal.take().unwrap_or_default()
+    }
+
+    /// Writes all words, the input that is delivered again before new console input and the
+    /// state of the devices
+    pub(crate) fn save_state<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        for &word in self.mem.iter() {
+            writer.write_u16::<BigEndian>(word)?;
+        }
+        let input_len = u32::try_from(self.replayed_input.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Too much pending input"))?;
+        writer.write_u32::<BigEndian>(input_len)?;
+        let (front, back) = self.replayed_input.as_slices();
+        writer.write_all(front)?;
+        writer.write_all(back)?;
+        self.bus.save_state(writer)
+    }
+
+    /// Replaces all words, the pending input and the state of the devices with the state
+    /// written by [`save_state`](Self::save_state); nothing is changed if reading fails
+    pub(crate) fn load_state<R: Read>(&mut self, reader: &mut R) -> io::Result<()> {
+        let mut mem = [0; MEMORY_SIZE];
+        reader.read_u16_into::<BigEndian>(&mut mem)?;
+        let input_len = reader.read_u32::<BigEndian>()?;
+        let mut input = Vec::new();
+        reader.take(u64::from(input_len)).read_to_end(&mut input)?;
+        if input.len() != input_len as usize {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let device_states = self.bus.read_state(reader)?;
+        self.bus.load_state(device_states)?;
+        self.mem = mem;
+        self.decoded.iter_mut().for_each(|decoded| *decoded = None);
+        self.replayed_input = input.into();
+        self.pending_hit = None;
+        Ok(())
+    }
+
+    /// Returns a pointer to the first of the memory words
+    ///
+    /// Native code reads ordinary memory through it; it takes a raw pointer, so it can be used
+    /// while native code also holds a pointer to the whole `Memory`.
+    #[cfg(feature = "jit")]
+    pub(crate) fn words_ptr(this: *mut Self) -> *mut u16 {
+        // SAFETY: only the address of the field is computed; no reference is created
+        unsafe { std::ptr::addr_of_mut!((*this).mem).cast() }
+    }
+
+    /// Registers a basic block that covers `len` addresses from `start`, so writes to them are
+    /// reported by [`take_modified_code`](Self::take_modified_code)
+    pub(crate) fn add_block(&mut self, start: u16, len: u16) {
+        for address in start..start + len {
+            self.block_refs[address as usize] += 1;
+        }
+    }
+
+    /// Unregisters a basic block registered with [`add_block`](Self::add_block)
+    pub(crate) fn remove_block(&mut self, start: u16, len: u16) {
+        for address in start..start + len {
+            self.block_refs[address as usize] -= 1;
+        }
+    }
+
+    /// Unregisters all basic blocks
+    pub(crate) fn clear_blocks(&mut self) {
+        self.block_refs.iter_mut().for_each(|refs| *refs = 0);
+        self.modified_code.clear();
+    }
+
+    /// Returns whether an address inside a basic block was written since the last call to
+    /// [`take_modified_code`](Self::take_modified_code)
+    pub(crate) fn code_modified(&self) -> bool {
+        !self.modified_code.is_empty()
+    }
+
+    /// Returns whether the last store must end the executing basic block, because it modified
+    /// code or wrote a device register
+    pub(crate) fn store_ends_block(&self) -> bool {
+        self.code_modified() || self.device_written
+    }
+
+    /// Returns and clears the addresses inside basic blocks that were written
+    pub(crate) fn take_modified_code(&mut self) -> Vec<u16> {
+        std::mem::take(&mut self.modified_code)
+    }
+
+    /// Returns whether recorded input is still being fed to the program
+    pub(crate) fn is_replaying(&self) -> bool {
+        self.replay.as_ref().is_some_and(|replay| !replay.is_done())
+    }
+
+    /// Replaces the console input with the recorded input until all of it was delivered
+    pub(crate) fn start_replay(&mut self, replay: Replay) {
+        self.replay = Some(replay);
+    }
+
+    /// Sets the number of instructions executed before the current one, which determines the
+    /// recorded input that is available
+    pub(crate) fn set_replay_instruction(&mut self, instruction: u64) {
+        if let Some(replay) = &mut self.replay {
+            replay.set_instruction(instruction);
+        }
+    }
+
+    /// Queues input bytes of undone instructions, so they are read again before new input
+    pub(crate) fn unread_input(&mut self, input: &[u8]) {
+        for &chr in input.iter().rev() {
+            self.replayed_input.push_front(chr);
+        }
+    }
+
+    /// Stores the `value` at `address` for a device transfer (DMA), which is recorded in the
+    /// journal
+    pub(crate) fn transfer_word(&mut self, address: u16, value: u16) {
+        if let Some(journal) = &mut self.journal {
+            journal.writes.push((address, self.mem[address as usize]));
+        }
+        self.poke(address, value);
+    }
+
+    /// Stores the `value` of a device register without involving its device
+    pub(crate) fn write_device_register(&mut self, address: u16, value: u16) {
+        if let Some(journal) = &mut self.journal {
+            journal.writes.push((address, self.mem[address as usize]));
+        }
         self.mem[address as usize] = value;
     }
+
+    /// Returns the console used for keyboard input and display output
+    pub fn console_mut(&mut self) -> &mut dyn Console {
+        self.console.as_mut()
+    }
+
+    /// Replaces the console; returns the previous one
+    pub fn set_console(&mut self, console: Box<dyn Console>) -> Box<dyn Console> {
+        std::mem::replace(&mut self.console, console)
+    }
+
+    /// Returns the currently set watchpoints
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /// Adds a watchpoint that is checked on every subsequent read or write
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    /// Removes all watchpoints equal to the given one; returns whether any were removed
+    pub fn remove_watchpoint(&mut self, watchpoint: &Watchpoint) -> bool {
+        let len = self.watchpoints.len();
+        self.watchpoints.retain(|w| w != watchpoint);
+        self.watchpoints.len() != len
+    }
+
+    /// Returns and clears the first watchpoint triggered since the last call, attributing the
+    /// access to the instruction at `pc`
+    pub fn take_watchpoint_hit(&mut self, pc: u16) -> Option<WatchpointHit> {
+        self.pending_hit
+            .take()
+            .map(|(address, kind, old_value, new_value)| WatchpointHit {
+                pc,
+                address,
+                kind,
+                old_value,
+                new_value,
+            })
+    }
+
+    fn check_watchpoints(
+        &mut self,
+        address: u16,
+        access: WatchKind,
+        old_value: u16,
+        new_value: u16,
+    ) {
+        if self.pending_hit.is_some() {
+            return;
+        }
+        let triggered = self
+            .watchpoints
+            .iter()
+            .find(|w| w.triggers(address, access, old_value, new_value));
+        if let Some(watchpoint) = triggered {
+            self.pending_hit = Some((address, watchpoint.kind, old_value, new_value));
+        }
+    }
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
 }
@@ -0,0 +1,74 @@
+//! Invalidation of decoded instructions when their address is written
+
+use super::*;
+use crate::vm::BufferedConsole;
+
+const ADD: u16 = 0x1021; // ADD R0, R0, #1
+const AND: u16 = 0x5020; // AND R0, R0, #0
+
+fn memory() -> Memory {
+    let mut mem = Memory::new();
+    mem.set_console(Box::new(BufferedConsole::new()));
+    mem.poke(0x3000, ADD);
+    mem.poke(0x3001, ADD);
+    mem
+}
+
+/// Fetches the instruction at `address` and checks that its decoded form was cached
+fn fetch(mem: &mut Memory, address: u16) -> (u16, Instruction) {
+    let fetched = mem.fetch_decoded(address);
+    assert_eq!(fetched.1, Instruction::decode(fetched.0));
+    assert_eq!(mem.decoded[address as usize], Some(fetched.1));
+    fetched
+}
+
+#[test]
+fn decodes_instructions_once() {
+    let mut mem = memory();
+    assert_eq!(mem.decoded[0x3000], None);
+    assert_eq!(fetch(&mut mem, 0x3000).0, ADD);
+    // The cached instruction is used as long as the word is unchanged
+    mem.mem[0x3000] = AND;
+    assert_eq!(mem.fetch_decoded(0x3000).1, Instruction::decode(ADD));
+
+    // Device registers are read every time
+    mem.poke(0xFE0A, AND);
+    assert_eq!(mem.fetch_decoded(0xFE0A), (AND, Instruction::decode(AND)));
+    assert_eq!(mem.decoded[0xFE0A], None);
+}
+
+#[test]
+fn drops_decoded_instructions_on_writes() {
+    let mut mem = memory();
+    fetch(&mut mem, 0x3000);
+    fetch(&mut mem, 0x3001);
+
+    // Stores by the program
+    mem.write(0x3000, AND);
+    assert_eq!(mem.decoded[0x3000], None);
+    assert!(mem.decoded[0x3001].is_some());
+    assert_eq!(fetch(&mut mem, 0x3000).0, AND);
+
+    // Writes by a debugger
+    mem.poke(0x3000, ADD);
+    assert_eq!(fetch(&mut mem, 0x3000).0, ADD);
+
+    // Transfers by a device
+    mem.transfer_word(0x3001, AND);
+    assert_eq!(mem.decoded[0x3001], None);
+    assert_eq!(fetch(&mut mem, 0x3001).0, AND);
+}
+
+#[test]
+fn drops_all_decoded_instructions_when_loading_state() {
+    let mut snapshot = Vec::new();
+    let mut mem = memory();
+    mem.poke(0x3000, AND);
+    mem.save_state(&mut snapshot).unwrap();
+
+    let mut mem = memory();
+    fetch(&mut mem, 0x3000);
+    mem.load_state(&mut &snapshot[..]).unwrap();
+    assert!(mem.decoded.iter().all(Option::is_none));
+    assert_eq!(fetch(&mut mem, 0x3000).0, AND);
+}
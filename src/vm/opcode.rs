@@ -1,43 +1,58 @@
 use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+/// Opcode of an instruction; the discriminant is its encoding in the 4 upper bits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Opcode {
     /// Add
-    Add,
+    Add = 0b0001,
     /// Branch
-    Br,
+    Br = 0b0000,
     /// Load
-    Ld,
+    Ld = 0b0010,
     /// Store
-    St,
+    St = 0b0011,
     /// Jump to subroutine
-    Jsr,
+    Jsr = 0b0100,
     /// Bitwise AND
-    And,
+    And = 0b0101,
     /// Load base + offset
-    Ldr,
+    Ldr = 0b0110,
     /// Store base + offset
-    Str,
-    /// Return from interrupt (unused)
-    Rti,
+    Str = 0b0111,
+    /// Return from interrupt
+    Rti = 0b1000,
     /// Bitwise NOT
-    Not,
+    Not = 0b1001,
     /// Load indirect
-    Ldi,
+    Ldi = 0b1010,
     /// Store indirect
-    Sti,
+    Sti = 0b1011,
     /// Jump
-    Jmp,
+    Jmp = 0b1100,
     /// Reserved (unused)
-    Res,
+    Res = 0b1101,
     /// Load effective address
-    Lea,
+    Lea = 0b1110,
     /// System call
-    Trap,
+    Trap = 0b1111,
 }
 
+/// Error of converting a value that does not fit into the 4 opcode bits into an [`Opcode`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidOpcode(pub u16);
+
+impl fmt::Display for InvalidOpcode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid opcode: {:#x}", self.0)
+    }
+}
+
+impl Error for InvalidOpcode {}
+
 impl TryFrom<u16> for Opcode {
-    type Error = ();
+    type Error = InvalidOpcode;
 
     fn try_from(value: u16) -> Result<Self, Self::Error> {
         use Opcode::*;
@@ -59,9 +74,48 @@ impl TryFrom<u16> for Opcode {
             0b1101 => Res,
             0b1110 => Lea,
             0b1111 => Trap,
-            _ => return Err(()),
+            _ => return Err(InvalidOpcode(value)),
         };
 
         Ok(opcode)
     }
 }
+
+impl Opcode {
+    /// Returns the opcode of the raw instruction `instr`
+    pub fn from_instr(instr: u16) -> Self {
+        // The 4 upper bits always hold a valid opcode
+        Opcode::try_from(instr >> 12).unwrap()
+    }
+
+    /// Returns the assembly mnemonic of the opcode (e.g. `ADD`)
+    pub fn mnemonic(self) -> &'static str {
+        use Opcode::*;
+
+        match self {
+            Add => "ADD",
+            Br => "BR",
+            Ld => "LD",
+            St => "ST",
+            Jsr => "JSR",
+            And => "AND",
+            Ldr => "LDR",
+            Str => "STR",
+            Rti => "RTI",
+            Not => "NOT",
+            Ldi => "LDI",
+            Sti => "STI",
+            Jmp => "JMP",
+            Res => "RES",
+            Lea => "LEA",
+            Trap => "TRAP",
+        }
+    }
+
+    /// Returns the opcode with the given (case-insensitive) mnemonic
+    pub fn from_mnemonic(mnemonic: &str) -> Option<Self> {
+        (0..16)
+            .filter_map(|value| Opcode::try_from(value).ok())
+            .find(|opcode| opcode.mnemonic().eq_ignore_ascii_case(mnemonic))
+    }
+}
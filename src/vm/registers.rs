@@ -1,6 +1,9 @@
 // Program Counter start
 const PC_START: u16 = 0x3000;
+// Initial Supervisor Stack Pointer; the supervisor stack grows down from the end of system space
+const SSP_START: u16 = 0x3000;
 
+#[derive(Debug, Clone)]
 pub struct Registers {
     /// Base Registers (R0..R7)
     base_regs: [u16; 8],
@@ -8,9 +11,20 @@ pub struct Registers {
     pub pc: u16,
     /// Condition Flags (NZP: Negative, Zero, Positive)
     pub cond: CondFlag,
+    /// Priority level (0..8) of the running program, which masks interrupts of lower or equal
+    /// priority
+    priority: u16,
+    /// Whether the program runs in user mode (bit 15 of the `PSR`)
+    user_mode: bool,
+    /// Supervisor Stack Pointer, which replaces `R6` when an interrupt is taken in user mode
+    pub saved_ssp: u16,
+    /// User Stack Pointer, which replaces `R6` when `RTI` returns to user mode
+    pub saved_usp: u16,
+    /// Bit mask of the Base Registers written since the last call to `take_written`
+    written: u8,
 }
 
-#[derive(Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u16)]
 pub enum CondFlag {
     Pos = 0b001,
@@ -24,6 +38,11 @@ impl Registers {
             base_regs: [0; 8],
             pc: PC_START,
             cond: CondFlag::Zero,
+            priority: 0,
+            user_mode: true,
+            saved_ssp: SSP_START,
+            saved_usp: 0,
+            written: 0,
         }
     }
 
@@ -41,6 +60,48 @@ impl Registers {
     /// Base Registers (R0, R1, ..., R7).
     pub fn write(&mut self, base_register_index: u16, value: u16) {
         self.base_regs[base_register_index as usize] = value;
+        self.written |= 1 << base_register_index;
+    }
+
+    /// Returns and clears the bit mask of the Base Registers written since the last call (bit
+    /// `n` for `Rn`)
+    pub fn take_written(&mut self) -> u8 {
+        std::mem::take(&mut self.written)
+    }
+
+    /// Returns the value of the Processor Status Register (`PSR`)
+    ///
+    /// The privilege bit (bit 15), the priority level (bits 10..8) and the condition flags
+    /// (bits 2..0) are modelled. Programs start in user mode at priority level 0.
+    pub fn psr(&self) -> u16 {
+        (self.user_mode as u16) << 15 | self.priority << 8 | self.cond as u16
+    }
+
+    /// Sets the Processor Status Register (`PSR`)
+    ///
+    /// Only the privilege bit (bit 15), the priority level (bits 10..8) and the condition flags
+    /// (bits 2..0) are used. If more than one flag is set, the first one in NZP order takes
+    /// precedence; if none is set, the `Z` flag is used. The stack pointers are not switched.
+    pub fn set_psr(&mut self, psr: u16) {
+        self.user_mode = psr & 0x8000 != 0;
+        self.priority = (psr >> 8) & 0x7;
+        self.cond = if psr & CondFlag::Neg as u16 != 0 {
+            CondFlag::Neg
+        } else if psr & CondFlag::Pos as u16 != 0 && psr & CondFlag::Zero as u16 == 0 {
+            CondFlag::Pos
+        } else {
+            CondFlag::Zero
+        };
+    }
+
+    /// Returns the priority level of the running program (bits 10..8 of the `PSR`)
+    pub fn priority(&self) -> u16 {
+        self.priority
+    }
+
+    /// Returns whether the program runs in user mode (bit 15 of the `PSR`)
+    pub fn user_mode(&self) -> bool {
+        self.user_mode
     }
 
     /// Updates the `COND` register based on the given `last_value`
@@ -54,3 +115,9 @@ impl Registers {
         };
     }
 }
+
+impl Default for Registers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,101 @@
+//! Snapshots of the complete machine state
+//!
+//! A snapshot contains everything needed to continue a program later: the registers, the cycle
+//! counter, all 64K words of memory (including the device registers), the input that was
+//! consumed by undone instructions and not yet read again and the internal state of the
+//! devices. A snapshot can only be loaded into a vm with devices at the same addresses.
+//! Breakpoints, watchpoints, tracers, the timing model and the console are not part of the
+//! machine state and are kept when a snapshot is loaded.
+//!
+//! Snapshot files start with the magic `LC3S` and a format version. All numbers are
+//! big-endian:
+//!
+//! | Field    | Size         | Content                                    |
+//! |----------|--------------|--------------------------------------------|
+//! | magic    | 4            | `LC3S`                                     |
+//! | version  | 2            | format version (`4`)                       |
+//! | regs     | 8 × 2        | `R0`..`R7`                                 |
+//! | pc       | 2            | `PC`                                       |
+//! | psr      | 2            | `PSR`                                      |
+//! | ssp, usp | 2 × 2        | `Saved_SSP`, `Saved_USP`                   |
+//! | cycles   | 8            | cycle counter                              |
+//! | instrs   | 8            | number of executed instructions            |
+//! | memory   | 65536 × 2    | all memory words, starting at x0000        |
+//! | input    | 4 + n        | number of pending input bytes, the bytes   |
+//! | devices  | 2 + …        | number of devices, then for each device its first and last address (2 × 2), the length of its state (4) and the state |
+
+use super::Vm;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{self, Read, Write};
+
+/// Magic bytes at the start of a snapshot
+const SNAPSHOT_MAGIC: &[u8; 4] = b"LC3S";
+/// Version of the snapshot format
+const SNAPSHOT_VERSION: u16 = 4;
+
+impl Vm {
+    /// Writes a snapshot of the machine state
+    pub fn save_snapshot<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(SNAPSHOT_MAGIC)?;
+        writer.write_u16::<BigEndian>(SNAPSHOT_VERSION)?;
+        for index in 0..8 {
+            writer.write_u16::<BigEndian>(self.regs.read(index))?;
+        }
+        writer.write_u16::<BigEndian>(self.regs.pc)?;
+        writer.write_u16::<BigEndian>(self.regs.psr())?;
+        writer.write_u16::<BigEndian>(self.regs.saved_ssp)?;
+        writer.write_u16::<BigEndian>(self.regs.saved_usp)?;
+        writer.write_u64::<BigEndian>(self.cycles)?;
+        writer.write_u64::<BigEndian>(self.instructions)?;
+        self.mem.save_state(&mut writer)?;
+        writer.flush()
+    }
+
+    /// Replaces the machine state with a snapshot written by
+    /// [`save_snapshot`](Self::save_snapshot)
+    ///
+    /// The execution history is cleared, because it belongs to the replaced state. Returns an
+    /// error of kind [`InvalidData`](io::ErrorKind::InvalidData) if the snapshot has an unknown
+    /// format; the machine state is only changed if the whole snapshot could be read.
+    pub fn load_snapshot<R: Read>(&mut self, mut reader: R) -> io::Result<()> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != SNAPSHOT_MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Not an LC-3 snapshot",
+            ));
+        }
+        let version = reader.read_u16::<BigEndian>()?;
+        if version != SNAPSHOT_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unsupported snapshot version {}", version),
+            ));
+        }
+
+        let mut regs = self.regs.clone();
+        for index in 0..8 {
+            regs.write(index, reader.read_u16::<BigEndian>()?);
+        }
+        regs.pc = reader.read_u16::<BigEndian>()?;
+        regs.set_psr(reader.read_u16::<BigEndian>()?);
+        regs.saved_ssp = reader.read_u16::<BigEndian>()?;
+        regs.saved_usp = reader.read_u16::<BigEndian>()?;
+        regs.take_written();
+        let cycles = reader.read_u64::<BigEndian>()?;
+        let instructions = reader.read_u64::<BigEndian>()?;
+        self.mem.load_state(&mut reader)?;
+
+        self.regs = regs;
+        self.cycles = cycles;
+        self.instructions = instructions;
+        self.stopped_at_breakpoint = None;
+        self.blocks.clear(&mut self.mem);
+        if let Some(history) = &mut self.history {
+            history.clear();
+        }
+        Ok(())
+    }
+}
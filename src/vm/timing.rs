@@ -0,0 +1,198 @@
+//! Timing model of the vm
+//!
+//! Every executed instruction costs the cycles of its opcode, which include fetching and
+//! decoding it, plus a number of cycles per data access: `LD`, `LDR`, `ST` and `STR` access
+//! memory once, `LDI`, `STI` and `RTI` twice. Accesses of the device registers (xFE00 and above)
+//! and the bytes transferred by the I/O traps cost the device latency instead. Dividing the
+//! cycle count by the clock frequency gives the simulated wall clock.
+//!
+//! A timing model can be read from a text file with one `NAME CYCLES` pair per line, where
+//! `NAME` is an opcode mnemonic, `memory` or `device`, and the clock frequency is given as
+//! `clock HZ`. Costs are at most [`MAX_CYCLES`] cycles. Everything after a `#` is a comment:
+//!
+//! ```plain
+//! # Slow memory
+//! memory 10
+//! LDI    8
+//! clock  2000000
+//! ```
+
+use super::memory::AccessCounts;
+use super::opcode::Opcode;
+
+use std::time::Duration;
+
+/// Largest cost in cycles of an opcode or a data access
+pub const MAX_CYCLES: u64 = 0xFFFF;
+
+/// Cycle costs of instructions, memory and device accesses
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimingModel {
+    /// Cycles by opcode encoding
+    opcode_cycles: [u64; 16],
+    /// Cycles per data access of ordinary memory
+    memory_cycles: u64,
+    /// Cycles per device access
+    device_cycles: u64,
+    /// Clock frequency in Hz, never 0
+    clock_hz: u64,
+}
+
+impl TimingModel {
+    /// Creates the default timing model
+    ///
+    /// Instructions take 6 cycles (5 for fetching and 1 for decoding and executing), `TRAP` 11
+    /// cycles (for reading the trap vector), each memory access 5 cycles and each device access
+    /// 20 cycles at a 10 MHz clock.
+    pub fn new() -> Self {
+        let mut opcode_cycles = [6; 16];
+        opcode_cycles[Opcode::Trap as usize] = 11;
+        Self {
+            opcode_cycles,
+            memory_cycles: 5,
+            device_cycles: 20,
+            clock_hz: 10_000_000,
+        }
+    }
+
+    /// Parses a timing model in the format described in the [module documentation](self),
+    /// starting from the default model
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut model = Self::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut fields = line.split_whitespace();
+            let (name, value) = match (fields.next(), fields.next(), fields.next()) {
+                (None, _, _) => continue,
+                (Some(name), Some(value), None) => (name, value),
+                _ => return Err(format!("line {}: expected `NAME CYCLES`", index + 1)),
+            };
+            let value = value
+                .parse()
+                .map_err(|_| format!("line {}: invalid number `{}`", index + 1, value))?;
+            let cycles = || match value {
+                0..=MAX_CYCLES => Ok(value),
+                _ => Err(format!(
+                    "line {}: at most {} cycles are allowed",
+                    index + 1,
+                    MAX_CYCLES
+                )),
+            };
+            match name.to_ascii_lowercase().as_str() {
+                "memory" => model.set_memory_cycles(cycles()?),
+                "device" => model.set_device_cycles(cycles()?),
+                "clock" if value > 0 => model.set_clock_hz(value),
+                "clock" => return Err(format!("line {}: the clock must not be 0", index + 1)),
+                _ => match Opcode::from_mnemonic(name) {
+                    Some(opcode) => model.set_cycles(opcode, cycles()?),
+                    None => return Err(format!("line {}: unknown name `{}`", index + 1, name)),
+                },
+            }
+        }
+        Ok(model)
+    }
+
+    /// Returns the clock frequency in Hz
+    pub fn clock_hz(&self) -> u64 {
+        self.clock_hz
+    }
+
+    /// Sets the clock frequency in Hz
+    ///
+    /// # Panics
+    ///
+    /// Panics if `clock_hz` is 0.
+    pub fn set_clock_hz(&mut self, clock_hz: u64) {
+        assert!(clock_hz > 0, "The clock frequency must not be 0");
+        self.clock_hz = clock_hz;
+    }
+
+    /// Returns the cycles of the given opcode, excluding its data accesses
+    pub fn cycles(&self, opcode: Opcode) -> u64 {
+        self.opcode_cycles[opcode as usize]
+    }
+
+    /// Sets the cycles of the given opcode, excluding its data accesses
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cycles` is larger than [`MAX_CYCLES`].
+    pub fn set_cycles(&mut self, opcode: Opcode, cycles: u64) {
+        self.opcode_cycles[opcode as usize] = checked_cycles(cycles);
+    }
+
+    /// Returns the cycles per data access of ordinary memory
+    pub fn memory_cycles(&self) -> u64 {
+        self.memory_cycles
+    }
+
+    /// Sets the cycles per data access of ordinary memory
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cycles` is larger than [`MAX_CYCLES`].
+    pub fn set_memory_cycles(&mut self, cycles: u64) {
+        self.memory_cycles = checked_cycles(cycles);
+    }
+
+    /// Returns the cycles per device access
+    pub fn device_cycles(&self) -> u64 {
+        self.device_cycles
+    }
+
+    /// Sets the cycles per device access
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cycles` is larger than [`MAX_CYCLES`].
+    pub fn set_device_cycles(&mut self, cycles: u64) {
+        self.device_cycles = checked_cycles(cycles);
+    }
+
+    /// Returns the cycles of the instruction `instr` that made the given accesses, saturating
+    /// at `u64::MAX`
+    pub fn instruction_cycles(&self, instr: u16, accesses: AccessCounts) -> u64 {
+        self.opcode_cycles[(instr >> 12) as usize].saturating_add(self.access_cycles(accesses))
+    }
+
+    /// Returns the cycles of the given data accesses, saturating at `u64::MAX`
+    pub fn access_cycles(&self, accesses: AccessCounts) -> u64 {
+        let memory = accesses.memory.saturating_mul(self.memory_cycles);
+        memory.saturating_add(accesses.device.saturating_mul(self.device_cycles))
+    }
+
+    /// Returns an upper bound of the cycles of a single instruction other than a `TRAP`, which
+    /// makes at most two data accesses; never 0
+    pub(crate) fn max_instruction_cycles(&self) -> u64 {
+        let opcode_cycles = self.opcode_cycles.iter().copied().max().unwrap_or_default();
+        let access_cycles = self.memory_cycles.max(self.device_cycles);
+        opcode_cycles
+            .saturating_add(access_cycles.saturating_mul(2))
+            .max(1)
+    }
+
+    /// Returns the simulated time that the given number of cycles take
+    pub fn duration(&self, cycles: u64) -> Duration {
+        let nanos = u128::from(cycles) * 1_000_000_000 / u128::from(self.clock_hz);
+        Duration::new(
+            (nanos / 1_000_000_000) as u64,
+            (nanos % 1_000_000_000) as u32,
+        )
+    }
+}
+
+/// Returns `cycles` if it is at most [`MAX_CYCLES`]
+fn checked_cycles(cycles: u64) -> u64 {
+    assert!(
+        cycles <= MAX_CYCLES,
+        "At most {} cycles are allowed",
+        MAX_CYCLES
+    );
+    cycles
+}
+
+impl Default for TimingModel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,82 @@
+//! Per-instruction execution hooks
+//!
+//! A [`Tracer`] registered with [`Vm::add_tracer`](super::Vm::add_tracer) is called after every
+//! completely executed instruction with a [`TraceEvent`] that describes what the instruction
+//! did. Instructions that fault or wait for input are not reported.
+
+use super::instructions;
+use super::memory::Memory;
+use super::opcode::Opcode;
+use super::registers::{CondFlag, Registers};
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Observer of executed instructions
+pub trait Tracer {
+    /// Called after the instruction described by `event` was executed
+    fn trace(&mut self, event: &TraceEvent);
+}
+
+/// Tracers shared through an `Rc<RefCell<_>>` can still be inspected while the vm owns a clone
+impl<T: Tracer> Tracer for Rc<RefCell<T>> {
+    fn trace(&mut self, event: &TraceEvent) {
+        self.borrow_mut().trace(event);
+    }
+}
+
+/// Description of a single executed instruction
+pub struct TraceEvent<'a> {
+    /// Address of the instruction
+    pub pc: u16,
+    /// Raw instruction word
+    pub instr: u16,
+    /// Decoded opcode of the instruction
+    pub opcode: Opcode,
+    /// Bit mask of the Base Registers written by the instruction (bit `n` for `Rn`)
+    pub written_registers: u8,
+    /// Addresses of the data reads, in order of the reads
+    pub reads: &'a [u16],
+    /// Written addresses with the values they had before the write, in order of the writes
+    pub writes: &'a [(u16, u16)],
+    /// Cycles that the instruction took according to the vm's timing model
+    pub cycles: u64,
+    /// Number of instructions executed before the instruction
+    pub instruction: u64,
+    /// Input bytes consumed by the instruction
+    pub input: &'a [u8],
+    /// Registers after the instruction was executed
+    pub regs: &'a Registers,
+    /// Memory after the instruction was executed
+    pub mem: &'a Memory,
+}
+
+impl TraceEvent<'_> {
+    /// Returns the Base Registers written by the instruction with their new values
+    pub fn register_writes(&self) -> impl Iterator<Item = (u16, u16)> + '_ {
+        (0..8)
+            .filter(move |index| self.written_registers & (1 << index) != 0)
+            .map(move |index| (index, self.regs.read(index)))
+    }
+
+    /// Returns the written addresses with their new values
+    pub fn memory_writes(&self) -> impl Iterator<Item = (u16, u16)> + '_ {
+        self.writes
+            .iter()
+            .map(move |&(address, _)| (address, self.mem.peek(address)))
+    }
+
+    /// Returns whether the instruction branched if it is a `BR` instruction
+    pub fn branch_taken(&self) -> Option<bool> {
+        match self.opcode {
+            // `BR` doesn't change the condition flags, so they are the ones it tested
+            Opcode::Br => Some(instructions::br_taken(self.instr >> 9, self.regs.cond)),
+            _ => None,
+        }
+    }
+
+    /// Returns the condition flags after the instruction was executed
+    pub fn cond(&self) -> CondFlag {
+        self.regs.cond
+    }
+}
@@ -6,21 +6,23 @@ pub mod io {
         BRKINT, ECHO, ICANON, ICRNL, IGNBRK, IGNCR, INLCR, ISTRIP, IXON, PARMRK, TCSANOW,
     };
 
-    pub fn read_next_byte() -> u8 {
+    /// Reads the next byte from stdin; returns `None` at the end of input
+    pub fn read_next_byte() -> Option<u8> {
         let mut single_byte_buffer = [0];
-        io::stdin()
-            .read_exact(&mut single_byte_buffer)
-            .expect("Error while reading next byte from stdin");
-        single_byte_buffer[0]
+        match io::stdin().read_exact(&mut single_byte_buffer) {
+            Ok(()) => Some(single_byte_buffer[0]),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => None,
+            Err(e) => panic!("Error while reading next byte from stdin: {}", e),
+        }
     }
 
     pub fn disable_input_buffering() -> termios::Termios {
         let original_termios = Termios::from_fd(0).unwrap();
 
-        let mut new_termios = original_termios.clone();
+        let mut new_termios = original_termios;
         new_termios.c_iflag &= IGNBRK | BRKINT | PARMRK | ISTRIP | INLCR | IGNCR | ICRNL | IXON;
         new_termios.c_lflag &= !(ICANON | ECHO);
-        tcsetattr(0, TCSANOW, &mut new_termios).unwrap();
+        tcsetattr(0, TCSANOW, &new_termios).unwrap();
 
         original_termios
     }
@@ -41,3 +43,30 @@ pub mod bit_ops {
         }
     }
 }
+
+/// Number parsing utility functions
+pub mod number {
+    /// Parses a word in LC-3 hexadecimal (`x3000`), C-style hexadecimal (`0x3000`) or decimal
+    /// notation, optionally with a `#` prefix (`#-5`, `12`)
+    ///
+    /// Letters may be upper or lower case and surrounding whitespace is ignored. Negative
+    /// decimal numbers down to -32768 are stored in two's complement.
+    pub fn parse_word(text: &str) -> Option<u16> {
+        let lowercase = text.trim().to_ascii_lowercase();
+        if let Some(hex) = lowercase
+            .strip_prefix("0x")
+            .or_else(|| lowercase.strip_prefix('x'))
+        {
+            // `from_str_radix` would accept a sign after the prefix
+            if !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+                return None;
+            }
+            return u16::from_str_radix(hex, 16).ok();
+        }
+        let decimal = lowercase.strip_prefix('#').unwrap_or(&lowercase);
+        match decimal.parse::<i32>() {
+            Ok(number) if (-0x8000..=0xFFFF).contains(&number) => Some(number as u16),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,65 @@
+//! Memory watchpoints
+//!
+//! A [`Watchpoint`] observes an inclusive range of memory addresses and triggers on the kind of
+//! access given by its [`WatchKind`]. Triggered watchpoints are recorded by the
+//! [`Memory`](super::Memory) and reported by the vm as a [`WatchpointHit`].
+
+use std::ops::RangeInclusive;
+
+/// Kind of memory access that triggers a [`Watchpoint`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    /// Any read of a watched address
+    Read,
+    /// Any write to a watched address, even if it does not change the stored value
+    Write,
+    /// A write to a watched address that changes the stored value
+    Change,
+}
+
+/// Watches an inclusive range of memory addresses for a specific kind of access
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watchpoint {
+    /// Watched addresses
+    pub range: RangeInclusive<u16>,
+    /// Kind of access that triggers this watchpoint
+    pub kind: WatchKind,
+}
+
+/// Information about a triggered [`Watchpoint`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchpointHit {
+    /// Address of the instruction that performed the access
+    pub pc: u16,
+    /// Accessed memory address
+    pub address: u16,
+    /// Kind of the triggered watchpoint
+    pub kind: WatchKind,
+    /// Value stored at `address` before the access
+    pub old_value: u16,
+    /// Value stored at `address` after the access (equal to `old_value` for reads)
+    pub new_value: u16,
+}
+
+impl Watchpoint {
+    /// Creates a new `Watchpoint` for the given address `range` and access `kind`
+    pub fn new(range: RangeInclusive<u16>, kind: WatchKind) -> Self {
+        Self { range, kind }
+    }
+
+    /// Returns whether this watchpoint triggers on an access of the given `kind` at `address`
+    ///
+    /// `old_value` and `new_value` are the values stored at `address` before and after the
+    /// access; they are only used by [`WatchKind::Change`] watchpoints.
+    pub fn triggers(&self, address: u16, kind: WatchKind, old_value: u16, new_value: u16) -> bool {
+        if !self.range.contains(&address) {
+            return false;
+        }
+        match (self.kind, kind) {
+            (WatchKind::Read, WatchKind::Read) => true,
+            (WatchKind::Write, WatchKind::Write) => true,
+            (WatchKind::Change, WatchKind::Write) => old_value != new_value,
+            _ => false,
+        }
+    }
+}
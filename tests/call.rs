@@ -0,0 +1,183 @@
+//! Calls of single subroutines
+
+mod common;
+
+use common::{poke_words, vm_with_program};
+use lc3_vm::{
+    CallError, CallingConvention, Engine, Limit, Limits, MemoryChange, StopReason, Violation, Vm,
+};
+
+/// Routines at x3000 and up
+const ROUTINES: &[(u16, &str, &[u16])] = &[
+    // R0 = R0 + R0
+    (0x3000, "DOUBLE", &[0x1000, 0xC1C0]),
+    // Increments R4 without restoring it
+    (0x3002, "CLOBBER", &[0x1921, 0xC1C0]),
+    // Pushes R1 without popping it
+    (0x3004, "PUSH", &[0x1DBF, 0x7380, 0xC1C0]),
+    // Loops forever
+    (0x3007, "SPIN", &[0x0FFF]),
+    // R0 = R0 + (R0 - 1) + ... + 1, recursively, saving R7 and R1 on the stack
+    (
+        0x3010,
+        "SUM",
+        &[
+            0x1020, // ADD R0, R0, #0
+            0x0C0B, // BRnz BASE
+            0x1DBE, // ADD R6, R6, #-2
+            0x7F80, // STR R7, R6, #0
+            0x7381, // STR R1, R6, #1
+            0x1220, // ADD R1, R0, #0
+            0x103F, // ADD R0, R0, #-1
+            0x4FF8, // JSR SUM
+            0x1001, // ADD R0, R0, R1
+            0x6381, // LDR R1, R6, #1
+            0x6F80, // LDR R7, R6, #0
+            0x1DA2, // ADD R6, R6, #2
+            0xC1C0, // RET
+            0x5020, // BASE: AND R0, R0, #0
+            0xC1C0, // RET
+        ],
+    ),
+];
+
+fn vm() -> Vm {
+    let (mut vm, _) = vm_with_program(Engine::Interpreter, 0x3000, &[], b"");
+    for &(address, _, words) in ROUTINES {
+        poke_words(&mut vm, address, words);
+    }
+    vm.set_symbols(ROUTINES.iter().map(|&(address, name, _)| (address, name)));
+    vm
+}
+
+#[test]
+fn calls_by_address_and_symbol() {
+    let mut vm = vm();
+    let result = vm.call(0x3000, &[21]).unwrap();
+    assert!(result.is_ok(), "{:?}", result);
+    assert_eq!(result.registers[0], 42);
+    assert_eq!(result.instructions, 2);
+    assert!(result.memory_changes.is_empty());
+
+    let result = vm.call("DOUBLE", &[0x8000]).unwrap();
+    assert_eq!(result.registers[0], 0);
+    assert!(!vm.has_breakpoint(0xFFFF));
+}
+
+#[test]
+fn recursive_calls_use_the_stack() {
+    let mut vm = vm();
+    let result = vm.call("SUM", &[10]).unwrap();
+    assert!(result.is_ok(), "{:?}", result.violations);
+    assert_eq!(result.registers[0], 55);
+    assert_eq!(result.registers[6], 0xFE00);
+    // Ten frames of R7 and R1 below the stack top; the first one saves R1 = 0, which is unchanged
+    assert_eq!(result.memory_changes.len(), 19);
+    assert_eq!(
+        result.memory_changes[0],
+        MemoryChange {
+            address: 0xFDEC,
+            old_value: 0,
+            new_value: 0x3018,
+        }
+    );
+    assert_eq!(
+        result.memory_changes[18],
+        MemoryChange {
+            address: 0xFDFE,
+            old_value: 0,
+            new_value: 0xFFFF,
+        }
+    );
+}
+
+#[test]
+fn reports_clobbered_registers_and_stack_imbalance() {
+    let mut vm = vm();
+    let result = vm.call("CLOBBER", &[0, 0, 0, 0, 5]).unwrap();
+    assert!(result.returned());
+    assert_eq!(
+        result.violations,
+        [Violation::Clobbered {
+            register: 4,
+            before: 5,
+            after: 6,
+        }]
+    );
+    assert_eq!(
+        result.violations[0].to_string(),
+        "R4 was clobbered: x0005 before the call, x0006 after it"
+    );
+
+    let result = vm.call("PUSH", &[0, 7]).unwrap();
+    assert_eq!(
+        result.violations,
+        [Violation::StackImbalance {
+            before: 0xFE00,
+            after: 0xFDFF,
+        }]
+    );
+    assert_eq!(
+        result.memory_changes,
+        [MemoryChange {
+            address: 0xFDFF,
+            old_value: 0,
+            new_value: 7,
+        }]
+    );
+
+    // Without a stack pointer and callee-saved registers, nothing is checked
+    vm.set_calling_convention(CallingConvention {
+        callee_saved: Vec::new(),
+        stack_pointer: None,
+        ..CallingConvention::default()
+    });
+    assert!(vm.call("PUSH", &[0, 7]).unwrap().is_ok());
+}
+
+#[test]
+fn a_routine_that_does_not_return_stops_at_a_limit() {
+    let mut vm = vm();
+    vm.set_limits(Limits {
+        max_instructions: Some(1000),
+        ..Limits::default()
+    });
+    let result = vm.call("SPIN", &[]).unwrap();
+    assert!(!result.returned());
+    match result.stop {
+        Some(StopReason::LimitExceeded { pc, limit, .. }) => {
+            assert_eq!((pc, limit), (0x3007, Limit::Instructions))
+        }
+        other => panic!("Unexpected stop {:?}", other),
+    }
+    assert!(result.violations.is_empty());
+}
+
+#[test]
+fn rejects_invalid_calls() {
+    let mut vm = vm();
+    assert_eq!(
+        vm.call("MISSING", &[]),
+        Err(CallError::UnknownSymbol("MISSING".to_string()))
+    );
+    assert_eq!(
+        vm.call(0x3000, &[0; 8]),
+        Err(CallError::TooManyArguments(8))
+    );
+
+    vm.set_calling_convention(CallingConvention {
+        callee_saved: vec![1, 8],
+        ..CallingConvention::default()
+    });
+    assert_eq!(vm.call(0x3000, &[]), Err(CallError::InvalidRegister(8)));
+    vm.set_calling_convention(CallingConvention {
+        stack_pointer: Some(12),
+        ..CallingConvention::default()
+    });
+    let error = vm.call(0x3000, &[]).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Invalid register in the calling convention: R12"
+    );
+    assert_eq!(vm.instructions(), 0);
+}
@@ -0,0 +1,39 @@
+//! Helpers shared by the integration tests and the benchmarks
+
+// Every test crate includes this module but uses only some of the helpers
+#![allow(dead_code)]
+
+use lc3_vm::{BufferedConsole, Engine, Vm};
+
+/// Returns every execution engine, including the JIT engines with the `jit` feature
+pub fn engines() -> Vec<Engine> {
+    #[cfg_attr(not(feature = "jit"), allow(unused_mut))]
+    let mut engines = vec![Engine::Interpreter, Engine::BasicBlocks];
+    #[cfg(feature = "jit")]
+    engines.extend([Engine::Jit, Engine::JitChecked]);
+    engines
+}
+
+/// Creates a vm on the `engine` with the `words` loaded at `origin` and a buffered console with
+/// the pending `input`, which is returned to inspect the output
+pub fn vm_with_program(
+    engine: Engine,
+    origin: u16,
+    words: &[u16],
+    input: &[u8],
+) -> (Vm, BufferedConsole) {
+    let console = BufferedConsole::with_input(input);
+    let mut vm = Vm::new();
+    vm.set_engine(engine);
+    vm.set_console(Box::new(console.clone()));
+    poke_words(&mut vm, origin, words);
+    (vm, console)
+}
+
+/// Writes the `words` to the memory of the `vm`, starting at `origin`
+pub fn poke_words(vm: &mut Vm, origin: u16, words: &[u16]) {
+    for (offset, &word) in words.iter().enumerate() {
+        vm.memory_mut()
+            .poke(origin.wrapping_add(offset as u16), word);
+    }
+}
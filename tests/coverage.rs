@@ -0,0 +1,123 @@
+//! LCOV records and summaries of the coverage tracer
+
+use lc3_vm::{BufferedConsole, Coverage, DebugInfo, StopReason, Vm};
+
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+
+/// Listed program at x3000 with its source lines: counts `R1` down from 2, with one branch
+/// that never branches and one that never executes
+const PROGRAM: &[(u16, usize, &str)] = &[
+    (0x5260, 2, "MAIN   AND R1, R1, #0"),
+    (0x1262, 3, "       ADD R1, R1, #2"),
+    (0x127F, 4, "LOOP   ADD R1, R1, #-1"),
+    (0x03FE, 5, "       BRp LOOP"),
+    (0x0801, 6, "       BRn NEVER"),
+    (0xF025, 7, "       HALT"),
+    (0x05F9, 8, "NEVER  BRz MAIN"),
+    (0x0000, 9, "DATA   .FILL x0000"),
+];
+
+/// Returns the listing and symbols of the program
+fn debug_info() -> DebugInfo {
+    let mut listing = String::from("(0000) 3000  0011000000000000 (   1)        .ORIG x3000\n");
+    for (offset, &(word, line, source)) in PROGRAM.iter().enumerate() {
+        listing += &format!(
+            "({:04X}) {:04X}  {:016b} ({:>4}) {}\n",
+            0x3000 + offset,
+            word,
+            word,
+            line,
+            source
+        );
+    }
+    let mut debug = DebugInfo::new();
+    debug.parse_listing(&listing);
+    debug.parse_symbols("// MAIN 3000\n// LOOP 3002\n// NEVER 3006\n// DATA 3007\n");
+    debug
+}
+
+fn coverage() -> Coverage {
+    let mut vm = Vm::new();
+    vm.set_console(Box::new(BufferedConsole::new()));
+    for (offset, &(word, _, _)) in PROGRAM.iter().enumerate() {
+        vm.memory_mut().poke(0x3000 + offset as u16, word);
+    }
+    let coverage = Rc::new(RefCell::new(Coverage::new()));
+    vm.add_tracer(Box::new(Rc::clone(&coverage)));
+    assert_eq!(vm.resume(), StopReason::Halted);
+    vm.clear_tracers();
+    Rc::try_unwrap(coverage).unwrap().into_inner()
+}
+
+#[test]
+fn writes_lcov_records() {
+    let coverage = coverage();
+    assert_eq!(coverage.count(0x3002), 2);
+    assert_eq!(coverage.branch(0x3003).taken, 1);
+    assert_eq!(coverage.branch(0x3003).not_taken, 1);
+
+    let mut lcov = Vec::new();
+    coverage
+        .write_lcov(&mut lcov, &debug_info(), Path::new("count.asm"))
+        .unwrap();
+    let lcov = String::from_utf8(lcov).unwrap();
+    let expected = [
+        "TN:",
+        "SF:count.asm",
+        "FN:2,MAIN",
+        "FNDA:1,MAIN",
+        "FN:4,LOOP",
+        "FNDA:2,LOOP",
+        "FN:8,NEVER",
+        "FNDA:0,NEVER",
+        "FNF:3",
+        "FNH:2",
+        // Taken once and fallen through once
+        "BRDA:5,12291,0,1",
+        "BRDA:5,12291,1,1",
+        // Never taken
+        "BRDA:6,12292,0,0",
+        "BRDA:6,12292,1,1",
+        // Never executed
+        "BRDA:8,12294,0,-",
+        "BRDA:8,12294,1,-",
+        "BRF:6",
+        "BRH:3",
+        "DA:2,1",
+        "DA:3,1",
+        "DA:4,2",
+        "DA:5,2",
+        "DA:6,1",
+        "DA:7,1",
+        "DA:8,0",
+        "LF:7",
+        "LH:6",
+        "end_of_record",
+    ];
+    assert_eq!(lcov.lines().collect::<Vec<_>>(), expected);
+}
+
+#[test]
+fn summarizes_symbols() {
+    let mut summary = Vec::new();
+    coverage()
+        .write_summary(&mut summary, &debug_info())
+        .unwrap();
+    let summary = String::from_utf8(summary).unwrap();
+    let rows: Vec<Vec<&str>> = summary
+        .lines()
+        .skip(1)
+        .map(|line| line.split_whitespace().collect())
+        .collect();
+    assert_eq!(
+        rows,
+        [
+            ["2/2", "100.0%", "0/0", "100.0%", "MAIN"],
+            ["4/4", "100.0%", "3/4", "75.0%", "LOOP"],
+            ["0/1", "0.0%", "0/2", "0.0%", "NEVER"],
+            ["6/7", "85.7%", "3/6", "50.0%", "Total"],
+        ]
+    );
+}
@@ -0,0 +1,111 @@
+//! Devices on the I/O bus
+
+mod common;
+
+use common::{engines, poke_words, vm_with_program};
+use lc3_vm::{Device, DeviceContext, Engine, Interrupt, Limits, StopReason, Vm};
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+const RING: u16 = 0xFE20;
+const ACK: u16 = 0xFE22;
+
+/// Device that requests an interrupt after `RING` was written until `ACK` is written
+struct Doorbell {
+    instructions: Rc<Cell<u64>>,
+}
+
+impl Device for Doorbell {
+    fn write(&mut self, address: u16, _value: u16, io: &mut DeviceContext) {
+        match address {
+            RING => io.set_register(RING, 1),
+            ACK => io.set_register(RING, 0),
+            _ => {}
+        }
+    }
+
+    fn tick(&mut self, instructions: u64, _cycles: u64, _io: &mut DeviceContext) {
+        self.instructions
+            .set(self.instructions.get() + instructions);
+    }
+
+    fn interrupt(&self, io: &DeviceContext) -> Option<Interrupt> {
+        (io.register(RING) != 0).then_some(Interrupt {
+            vector: 0x82,
+            priority: 2,
+        })
+    }
+}
+
+/// Program at x3000 that rings the doorbell and counts in `R1`
+const MAIN: &[u16] = &[
+    0xB002, // STI R0, RING_ADDR
+    0x1261, // LOOP ADD R1, R1, #1
+    0x0FFE, // BRnzp LOOP
+    0xFE20, // RING_ADDR
+];
+
+/// Interrupt service routine at x4000 that acknowledges the doorbell and halts
+const HANDLER: &[u16] = &[
+    0xB002, // STI R0, ACK_ADDR
+    0xA602, // LDI R3, RING_ADDR
+    0xF025, // HALT
+    0xFE22, // ACK_ADDR
+    0xFE20, // RING_ADDR
+];
+
+fn vm(engine: Engine) -> Vm {
+    let (mut vm, _) = vm_with_program(engine, 0x3000, MAIN, b"");
+    poke_words(&mut vm, 0x4000, HANDLER);
+    vm.memory_mut().poke(0x0182, 0x4000);
+    vm.registers_mut().write(6, 0x3000);
+    vm
+}
+
+#[test]
+fn custom_devices_raise_interrupts() {
+    for engine in engines() {
+        let instructions = Rc::new(Cell::new(0));
+        let mut vm = vm(engine);
+        vm.memory_mut().add_device(
+            RING..=ACK + 1,
+            Box::new(Doorbell {
+                instructions: Rc::clone(&instructions),
+            }),
+        );
+        assert_eq!(vm.resume(), StopReason::Halted, "{:?}", engine);
+        // The interrupt is taken right after the doorbell rang
+        assert_eq!(vm.registers().read(1), 0, "{:?}", engine);
+        assert_eq!(vm.memory().peek(0x2FFE), 0x3001, "{:?}", engine);
+        assert_eq!(vm.registers().priority(), 2, "{:?}", engine);
+        assert_eq!(vm.registers().read(3), 0, "{:?}", engine);
+        assert_eq!(instructions.get(), 4, "{:?}", engine);
+    }
+}
+
+#[test]
+fn unmapped_device_registers_are_memory() {
+    let mut vm = vm(Engine::Interpreter);
+    vm.memory_mut().write(0xFE40, 0x1234);
+    assert_eq!(vm.memory_mut().read(0xFE40), 0x1234);
+    // Without a device, the doorbell never interrupts
+    vm.set_limits(Limits {
+        max_instructions: Some(100),
+        ..Default::default()
+    });
+    assert!(matches!(vm.resume(), StopReason::LimitExceeded { .. }));
+    assert_eq!(vm.registers().read(1), 50);
+}
+
+#[test]
+#[should_panic(expected = "overlaps the device at xFE08–xFE0F")]
+fn devices_cannot_overlap() {
+    let mut vm = Vm::new();
+    vm.memory_mut().add_device(
+        0xFE0E..=0xFE11,
+        Box::new(Doorbell {
+            instructions: Rc::default(),
+        }),
+    );
+}
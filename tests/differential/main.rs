@@ -0,0 +1,342 @@
+//! Differential testing of the vm against a reference interpreter
+//!
+//! Random instruction sequences are executed step by step on a [`Vm`] and on the independent
+//! [`reference::Machine`]. After each step the registers, condition codes, output, stop
+//! reason and the memory written by the step are compared, and the whole memory is compared
+//! once a case ends. A divergence is minimized into a small reproducer before the test fails.
+//!
+//! `LC3_DIFF_CASES` sets the number of cases and `LC3_DIFF_SEED` the seed of the first case,
+//! so a reported case can be rerun on its own with `LC3_DIFF_CASES=1`.
+
+#[path = "../common/mod.rs"]
+mod common;
+mod reference;
+
+use common::vm_with_program;
+use lc3_vm::{BufferedConsole, Engine, Fault, StopReason, Vm};
+use reference::{Machine, Outcome};
+
+use std::env;
+use std::fmt::Write;
+
+/// Address of the first instruction of a case
+const ORIGIN: u16 = 0x3000;
+/// Maximum number of executed instructions per case
+const MAX_STEPS: usize = 200;
+/// Number of words of a generated program
+const PROGRAM_LEN: usize = 48;
+
+/// Small xorshift generator, so the cases are reproducible without extra dependencies
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+
+    fn word(&mut self) -> u16 {
+        self.next() as u16
+    }
+}
+
+/// Initial state of the machine for one test case
+#[derive(Debug, Clone)]
+struct Case {
+    regs: [u16; 8],
+    psr: u16,
+    /// Words stored from [`ORIGIN`] on
+    program: Vec<u16>,
+    input: Vec<u8>,
+}
+
+/// First difference between the vm and the reference
+#[derive(Debug)]
+struct Divergence {
+    /// Number of instructions executed before the difference
+    step: usize,
+    /// Address of the instruction that caused the difference, if known
+    pc: Option<u16>,
+    message: String,
+}
+
+impl Case {
+    fn generate(rng: &mut Rng) -> Self {
+        let mut regs = [0; 8];
+        for reg in &mut regs {
+            *reg = interesting_value(rng);
+        }
+        // Keep one register pointing near the program, so indirect accesses and jumps hit it
+        regs[rng.below(8) as usize] = ORIGIN + rng.below(PROGRAM_LEN as u64) as u16;
+        let program = (0..PROGRAM_LEN).map(|_| instruction(rng)).collect();
+        let input = (0..rng.below(4))
+            .map(|_| b'a' + rng.below(26) as u8)
+            .collect();
+        Self {
+            regs,
+            psr: (rng.below(2) as u16) << 15 | 1 << rng.below(3),
+            program,
+            input,
+        }
+    }
+
+    /// Executes the case on both machines; returns the first difference
+    fn run(&self) -> Option<Divergence> {
+        let (mut vm, console) =
+            vm_with_program(Engine::Interpreter, ORIGIN, &self.program, &self.input);
+        let mut reference = Machine::new();
+        reference.input.extend(&self.input);
+
+        for (index, &value) in self.regs.iter().enumerate() {
+            vm.registers_mut().write(index as u16, value);
+            reference.regs[index] = value;
+        }
+        vm.registers_mut().set_psr(self.psr);
+        reference.nzp = self.psr & 0x7;
+        reference.user = self.psr & 0x8000 != 0;
+        for (offset, &word) in self.program.iter().enumerate() {
+            reference.mem[(ORIGIN + offset as u16) as usize] = word;
+        }
+
+        for step in 0..MAX_STEPS {
+            let pc = reference.pc;
+            let expected = reference.step();
+            let actual = vm.step();
+            let diverged = |message: String| {
+                Some(Divergence {
+                    step,
+                    pc: Some(pc),
+                    message,
+                })
+            };
+
+            if !same_outcome(expected, actual) {
+                return diverged(format!(
+                    "expected {:?}, vm stopped with {:?}",
+                    expected, actual
+                ));
+            }
+            if let Some(message) = compare_state(&reference, &vm, &console) {
+                return diverged(message);
+            }
+            for &address in &reference.written {
+                let (expected, actual) =
+                    (reference.mem[address as usize], vm.memory().peek(address));
+                if expected != actual {
+                    return diverged(format!(
+                        "memory x{:04X}: expected x{:04X}, vm has x{:04X}",
+                        address, expected, actual
+                    ));
+                }
+            }
+            if expected != Outcome::Continue {
+                break;
+            }
+        }
+
+        (0..=u16::MAX)
+            .find(|&address| reference.mem[address as usize] != vm.memory().peek(address))
+            .map(|address| Divergence {
+                step: MAX_STEPS,
+                pc: None,
+                message: format!(
+                    "memory x{:04X} differs at the end: expected x{:04X}, vm has x{:04X}",
+                    address,
+                    reference.mem[address as usize],
+                    vm.memory().peek(address)
+                ),
+            })
+    }
+
+    /// Simplifies the case as long as it still diverges: drops the words after the diverging
+    /// instruction, removes words or replaces them with `NOP`s, zeroes registers and drops
+    /// input
+    fn minimize(mut self) -> Self {
+        if let Some(Divergence { pc: Some(pc), .. }) = self.run() {
+            let last = pc.wrapping_sub(ORIGIN) as usize;
+            if last < self.program.len() {
+                let mut candidate = self.clone();
+                candidate.program.truncate(last + 1);
+                if candidate.run().is_some() {
+                    self = candidate;
+                }
+            }
+        }
+
+        loop {
+            let mut changed = false;
+            for index in (0..self.program.len()).rev() {
+                if self.try_change(|case| {
+                    case.program.remove(index);
+                }) {
+                    changed = true;
+                } else if self.program[index] != 0 {
+                    changed |= self.try_change(|case| case.program[index] = 0);
+                }
+            }
+            for index in 0..self.regs.len() {
+                if self.regs[index] != 0 {
+                    changed |= self.try_change(|case| case.regs[index] = 0);
+                }
+            }
+            for index in (0..self.input.len()).rev() {
+                changed |= self.try_change(|case| {
+                    case.input.remove(index);
+                });
+            }
+            if !changed {
+                return self;
+            }
+        }
+    }
+
+    /// Applies `change` if the changed case still diverges; returns whether it was applied
+    fn try_change(&mut self, change: impl FnOnce(&mut Self)) -> bool {
+        let mut candidate = self.clone();
+        change(&mut candidate);
+        let diverges = candidate.run().is_some();
+        if diverges {
+            *self = candidate;
+        }
+        diverges
+    }
+
+    /// Describes the case and its divergence in a form that can be turned into a unit test
+    fn reproducer(&self, divergence: &Divergence) -> String {
+        let mut text = String::new();
+        writeln!(text, "Registers:").unwrap();
+        for (index, value) in self.regs.iter().enumerate() {
+            writeln!(text, "  R{} = x{:04X}", index, value).unwrap();
+        }
+        writeln!(text, "  PSR = x{:04X}", self.psr).unwrap();
+        writeln!(text, "Program:").unwrap();
+        for (offset, word) in self.program.iter().enumerate() {
+            writeln!(text, "  x{:04X}: x{:04X}", ORIGIN as usize + offset, word).unwrap();
+        }
+        writeln!(text, "Input: {:?}", String::from_utf8_lossy(&self.input)).unwrap();
+        write!(
+            text,
+            "Divergence after {} steps: {}",
+            divergence.step, divergence.message
+        )
+        .unwrap();
+        if let Some(pc) = divergence.pc {
+            write!(text, " (instruction at x{:04X})", pc).unwrap();
+        }
+        text
+    }
+}
+
+/// Returns a random value that is often an edge case of the 16-bit arithmetic
+fn interesting_value(rng: &mut Rng) -> u16 {
+    match rng.below(6) {
+        0 => [0x0000, 0x0001, 0x7FFF, 0x8000, 0xFFFE, 0xFFFF][rng.below(6) as usize],
+        1 => rng.below(32) as u16,
+        2 => 0u16.wrapping_sub(rng.below(32) as u16),
+        _ => rng.word(),
+    }
+}
+
+/// Returns a random instruction; traps mostly use supported vectors and illegal opcodes are rare
+fn instruction(rng: &mut Rng) -> u16 {
+    let word = rng.word();
+    match word >> 12 {
+        0x8 | 0xD if rng.below(4) != 0 => word & 0x0FFF | 0x1000,
+        0xF if rng.below(8) != 0 => 0xF020 + rng.below(6) as u16,
+        0xF if rng.below(2) != 0 => 0xF025,
+        _ => word,
+    }
+}
+
+fn same_outcome(expected: Outcome, actual: Option<StopReason>) -> bool {
+    match (expected, actual) {
+        (Outcome::Continue, None) | (Outcome::Halt, Some(StopReason::Halted)) => true,
+        (Outcome::WaitForInput, Some(StopReason::WaitingForInput(_))) => true,
+        (
+            Outcome::IllegalOpcode(instr),
+            Some(StopReason::Fault {
+                fault: Fault::IllegalOpcode(actual),
+                ..
+            }),
+        ) => instr == actual,
+        (
+            Outcome::UnsupportedTrap(vector),
+            Some(StopReason::Fault {
+                fault: Fault::UnsupportedTrap(actual),
+                ..
+            }),
+        ) => vector == actual,
+        _ => false,
+    }
+}
+
+/// Compares everything but memory; returns a description of the first difference
+fn compare_state(reference: &Machine, vm: &Vm, console: &BufferedConsole) -> Option<String> {
+    let regs = vm.registers();
+    for (index, &expected) in reference.regs.iter().enumerate() {
+        let actual = regs.read(index as u16);
+        if expected != actual {
+            return Some(format!(
+                "R{}: expected x{:04X}, vm has x{:04X}",
+                index, expected, actual
+            ));
+        }
+    }
+    if reference.pc != regs.pc {
+        return Some(format!(
+            "PC: expected x{:04X}, vm has x{:04X}",
+            reference.pc, regs.pc
+        ));
+    }
+    if reference.psr() != regs.psr() {
+        return Some(format!(
+            "PSR: expected x{:04X}, vm has x{:04X}",
+            reference.psr(),
+            regs.psr()
+        ));
+    }
+    let output = console.output();
+    if reference.output != output {
+        return Some(format!(
+            "output: expected {:?}, vm wrote {:?}",
+            String::from_utf8_lossy(&reference.output),
+            String::from_utf8_lossy(&output)
+        ));
+    }
+    None
+}
+
+fn env_or(name: &str, default: u64) -> u64 {
+    env::var(name)
+        .ok()
+        .map(|value| value.parse().expect(name))
+        .unwrap_or(default)
+}
+
+#[test]
+fn random_programs() {
+    let cases = env_or("LC3_DIFF_CASES", 500);
+    let first_seed = env_or("LC3_DIFF_SEED", 1);
+    for seed in first_seed..first_seed + cases {
+        let case = Case::generate(&mut Rng::new(seed));
+        if case.run().is_some() {
+            let case = case.minimize();
+            let divergence = case.run().unwrap();
+            panic!(
+                "Seed {} diverges from the reference; minimized case:\n{}",
+                seed,
+                case.reproducer(&divergence)
+            );
+        }
+    }
+}
@@ -0,0 +1,291 @@
+//! Reference LC-3 interpreter
+//!
+//! This is a deliberately simple model written from the ISA definition (Patt & Patel,
+//! *Introduction to Computing Systems*, 2nd edition, appendix A) without sharing any code with
+//! `lc3_vm`. Every instruction extracts its own bit fields and memory is a plain array.
+//!
+//! The trap routines are not part of the ISA, so they are modelled after the vm's built-in
+//! service routines: they run without executing LC-3 code, leave `R7` and the condition codes
+//! unchanged, and a `GETC` or `IN` without pending input leaves `PC` at the `TRAP` instruction.
+//! The keyboard status register is polled whenever it is read, which moves the next input byte
+//! into the keyboard data register. Like the vm, the machine always runs in supervisor mode, and
+//! `RTI` resolves condition codes with more than one bit set in NZP order.
+
+use std::collections::VecDeque;
+
+const KBSR: u16 = 0xFE00;
+const KBDR: u16 = 0xFE02;
+
+const N: u16 = 0b100;
+const Z: u16 = 0b010;
+const P: u16 = 0b001;
+
+/// Result of executing one instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Continue,
+    Halt,
+    /// `PC` still points to the trap that needs input
+    WaitForInput,
+    /// The reserved opcode or `RTI` in user mode; `PC` still points to the instruction
+    IllegalOpcode(u16),
+    /// `TRAP` with a vector that has no service routine; `PC` still points to the instruction
+    UnsupportedTrap(u8),
+}
+
+#[derive(Clone)]
+pub struct Machine {
+    pub regs: [u16; 8],
+    pub pc: u16,
+    /// Condition codes as `NZP` bits
+    pub nzp: u16,
+    /// Priority level, bits 10..8 of the `PSR`
+    pub priority: u16,
+    /// Privilege bit, bit 15 of the `PSR`
+    pub user: bool,
+    pub saved_ssp: u16,
+    pub saved_usp: u16,
+    pub mem: Vec<u16>,
+    pub input: VecDeque<u8>,
+    pub output: Vec<u8>,
+    /// Addresses written by the last step
+    pub written: Vec<u16>,
+}
+
+impl Machine {
+    pub fn new() -> Self {
+        Self {
+            regs: [0; 8],
+            pc: 0x3000,
+            nzp: Z,
+            priority: 0,
+            user: true,
+            saved_ssp: 0x3000,
+            saved_usp: 0,
+            mem: vec![0; 1 << 16],
+            input: VecDeque::new(),
+            output: Vec::new(),
+            written: Vec::new(),
+        }
+    }
+
+    fn read(&mut self, address: u16) -> u16 {
+        if address == KBSR {
+            match self.input.pop_front() {
+                Some(chr) if chr != 0 => {
+                    self.mem[KBSR as usize] = 0x8000;
+                    self.mem[KBDR as usize] = chr.into();
+                }
+                _ => self.mem[KBSR as usize] = 0,
+            }
+        }
+        self.mem[address as usize]
+    }
+
+    fn write(&mut self, address: u16, value: u16) {
+        self.mem[address as usize] = value;
+        self.written.push(address);
+    }
+
+    fn set_cc(&mut self, value: u16) {
+        self.nzp = if value == 0 {
+            Z
+        } else if value & 0x8000 != 0 {
+            N
+        } else {
+            P
+        };
+    }
+
+    /// Returns the processor status register
+    pub fn psr(&self) -> u16 {
+        (self.user as u16) << 15 | self.priority << 8 | self.nzp
+    }
+
+    /// Executes the instruction at `PC`
+    pub fn step(&mut self) -> Outcome {
+        self.written.clear();
+        let pc = self.pc;
+        let ir = self.read(pc);
+        self.pc = pc.wrapping_add(1);
+
+        let dr = (ir >> 9) & 7;
+        let sr1 = (ir >> 6) & 7;
+        let pc_offset9 = self.pc.wrapping_add(sext(ir, 9));
+        let second_operand = |regs: &[u16; 8]| {
+            if ir & 0x20 != 0 {
+                sext(ir, 5)
+            } else {
+                regs[(ir & 7) as usize]
+            }
+        };
+
+        match ir >> 12 {
+            // BR
+            0x0 => {
+                if dr & self.nzp != 0 {
+                    self.pc = pc_offset9;
+                }
+            }
+            // ADD
+            0x1 => {
+                let value = self.regs[sr1 as usize].wrapping_add(second_operand(&self.regs));
+                self.regs[dr as usize] = value;
+                self.set_cc(value);
+            }
+            // LD
+            0x2 => {
+                let value = self.read(pc_offset9);
+                self.regs[dr as usize] = value;
+                self.set_cc(value);
+            }
+            // ST
+            0x3 => self.write(pc_offset9, self.regs[dr as usize]),
+            // JSR, JSRR
+            0x4 => {
+                let target = if ir & 0x800 != 0 {
+                    self.pc.wrapping_add(sext(ir, 11))
+                } else {
+                    self.regs[sr1 as usize]
+                };
+                self.regs[7] = self.pc;
+                self.pc = target;
+            }
+            // AND
+            0x5 => {
+                let value = self.regs[sr1 as usize] & second_operand(&self.regs);
+                self.regs[dr as usize] = value;
+                self.set_cc(value);
+            }
+            // LDR
+            0x6 => {
+                let address = self.regs[sr1 as usize].wrapping_add(sext(ir, 6));
+                let value = self.read(address);
+                self.regs[dr as usize] = value;
+                self.set_cc(value);
+            }
+            // STR
+            0x7 => {
+                let address = self.regs[sr1 as usize].wrapping_add(sext(ir, 6));
+                self.write(address, self.regs[dr as usize]);
+            }
+            // RTI
+            0x8 if self.user => {
+                self.pc = pc;
+                return Outcome::IllegalOpcode(ir);
+            }
+            0x8 => {
+                let sp = self.regs[6];
+                self.pc = self.read(sp);
+                let psr = self.read(sp.wrapping_add(1));
+                self.regs[6] = sp.wrapping_add(2);
+                self.priority = (psr >> 8) & 7;
+                self.user = psr & 0x8000 != 0;
+                if self.user {
+                    self.saved_ssp = self.regs[6];
+                    self.regs[6] = self.saved_usp;
+                }
+                self.nzp = if psr & N != 0 {
+                    N
+                } else if psr & P != 0 && psr & Z == 0 {
+                    P
+                } else {
+                    Z
+                };
+            }
+            // NOT
+            0x9 => {
+                let value = !self.regs[sr1 as usize];
+                self.regs[dr as usize] = value;
+                self.set_cc(value);
+            }
+            // LDI
+            0xA => {
+                let address = self.read(pc_offset9);
+                let value = self.read(address);
+                self.regs[dr as usize] = value;
+                self.set_cc(value);
+            }
+            // STI
+            0xB => {
+                let address = self.read(pc_offset9);
+                self.write(address, self.regs[dr as usize]);
+            }
+            // JMP, RET
+            0xC => self.pc = self.regs[sr1 as usize],
+            // LEA
+            0xE => {
+                self.regs[dr as usize] = pc_offset9;
+                self.set_cc(pc_offset9);
+            }
+            // TRAP
+            0xF => return self.trap(pc, (ir & 0xFF) as u8),
+            // reserved
+            _ => {
+                self.pc = pc;
+                return Outcome::IllegalOpcode(ir);
+            }
+        }
+        Outcome::Continue
+    }
+
+    fn trap(&mut self, pc: u16, vector: u8) -> Outcome {
+        match vector {
+            // GETC, IN
+            0x20 | 0x23 => match self.input.pop_front() {
+                Some(chr) => {
+                    if vector == 0x23 {
+                        self.output.extend_from_slice(b"Enter character: ");
+                    }
+                    self.regs[0] = chr.into();
+                }
+                None => {
+                    self.pc = pc;
+                    return Outcome::WaitForInput;
+                }
+            },
+            // OUT
+            0x21 => self.output.push(self.regs[0] as u8),
+            // PUTS
+            0x22 => {
+                for address in self.regs[0]..=0xFFFF {
+                    let chr = self.read(address);
+                    if chr == 0 {
+                        break;
+                    }
+                    self.output.push(chr as u8);
+                }
+            }
+            // PUTSP
+            0x24 => {
+                for address in self.regs[0]..=0xFFFF {
+                    let word = self.read(address);
+                    if word == 0 {
+                        break;
+                    }
+                    self.output.push(word as u8);
+                    if word >> 8 == 0 {
+                        break;
+                    }
+                    self.output.push((word >> 8) as u8);
+                }
+            }
+            // HALT
+            0x25 => {
+                self.output.extend_from_slice(b"HALT");
+                return Outcome::Halt;
+            }
+            _ => {
+                self.pc = pc;
+                return Outcome::UnsupportedTrap(vector);
+            }
+        }
+        Outcome::Continue
+    }
+}
+
+/// Sign-extends the lowest `bits` bits of `value`
+fn sext(value: u16, bits: u32) -> u16 {
+    let shift = 16 - bits;
+    (((value << shift) as i16) >> shift) as u16
+}
@@ -0,0 +1,134 @@
+//! Block storage device
+
+mod common;
+
+use common::vm_with_program;
+use lc3_vm::{Disk, Engine, StopReason, Vm, DISK_ADDRESSES, SECTOR_WORDS};
+
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+/// Program at x3000 that writes the value at x3003 to `DCR` and halts
+const COMMAND: &[u16] = &[
+    0x2002, // LD R0, CMD
+    0xB002, // STI R0, DCR
+    0xF025, // HALT
+    0x0000, // CMD
+    0xFE12, // DCR
+];
+
+/// Returns the path of an image file that contains `sectors`
+fn image(name: &str, sectors: &[&[u16]]) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("lc3-disk-{}-{}.img", name, process::id()));
+    let bytes: Vec<u8> = sectors
+        .iter()
+        .flat_map(|sector| sector.iter())
+        .flat_map(|word| word.to_be_bytes())
+        .collect();
+    fs::write(&path, bytes).unwrap();
+    path
+}
+
+/// Returns a vm with the disk at `path` that transfers `sector` to or from `buffer` with the
+/// `command`
+fn vm(path: &PathBuf, sector: u16, buffer: u16, command: u16) -> Vm {
+    let (mut vm, _) = vm_with_program(Engine::Interpreter, 0x3000, COMMAND, b"");
+    let mem = vm.memory_mut();
+    mem.add_device(DISK_ADDRESSES, Box::new(Disk::open(path).unwrap()));
+    mem.poke(0x3003, command);
+    mem.write(0xFE14, sector);
+    mem.write(0xFE16, buffer);
+    vm
+}
+
+#[test]
+fn reads_sectors_into_memory() {
+    let data: Vec<u16> = (0..SECTOR_WORDS as u16).map(|i| i * 3).collect();
+    let path = image("read", &[&[0; SECTOR_WORDS], &data]);
+    let mut vm = vm(&path, 1, 0x5000, 1);
+    vm.enable_history(1 << 20);
+    assert_eq!(vm.resume(), StopReason::Halted);
+    for (offset, &word) in data.iter().enumerate() {
+        assert_eq!(vm.memory().peek(0x5000 + offset as u16), word);
+    }
+    assert_eq!(vm.memory().peek(0xFE10), 0x8000);
+    assert_eq!(vm.memory().peek(0xFE12), 0x0000);
+
+    // The transfer is undone together with the instruction that started it
+    assert!(vm.step_back());
+    assert!(vm.step_back());
+    assert_eq!(vm.memory().peek(0x5003), 0);
+    assert_eq!(vm.memory().peek(0xFE10), 0);
+
+    // Sectors past the end of the image are empty
+    let mut vm = self::vm(&path, 7, 0x5000, 1);
+    vm.memory_mut().poke(0x5001, 0xFFFF);
+    assert_eq!(vm.resume(), StopReason::Halted);
+    assert_eq!(vm.memory().peek(0x5001), 0);
+    assert_eq!(vm.memory().peek(0xFE10), 0x8000);
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn writes_memory_to_sectors() {
+    let path = image("write", &[]);
+    let mut vm = vm(&path, 2, 0x6000, 2);
+    for offset in 0..SECTOR_WORDS as u16 {
+        vm.memory_mut().poke(0x6000 + offset, 0xA000 | offset);
+    }
+    assert_eq!(vm.resume(), StopReason::Halted);
+    assert_eq!(vm.memory().peek(0xFE10), 0x8000);
+
+    let bytes = fs::read(&path).unwrap();
+    assert_eq!(bytes.len(), 3 * 512);
+    assert!(bytes[..1024].iter().all(|&byte| byte == 0));
+    assert_eq!(&bytes[1024..1028], &[0xA0, 0x00, 0xA0, 0x01]);
+    assert_eq!(&bytes[1534..], &[0xA0, 0xFF]);
+
+    // Unknown commands fail
+    let mut vm = self::vm(&path, 0, 0x6000, 3);
+    assert_eq!(vm.resume(), StopReason::Halted);
+    assert_eq!(vm.memory().peek(0xFE10), 0x8001);
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn rejects_buffers_in_the_device_registers() {
+    let path = image("device-page", &[&[0x1234; SECTOR_WORDS]]);
+    // The last buffer that fits below the device registers
+    let mut vm = vm(&path, 0, 0xFD00, 1);
+    assert_eq!(vm.resume(), StopReason::Halted);
+    assert_eq!(vm.memory().peek(0xFDFF), 0x1234);
+    assert_eq!(vm.memory().peek(0xFE10), 0x8000);
+
+    // Buffers that reach into the device registers or wrap around fail without a transfer
+    for buffer in [0xFD01, 0xFF00] {
+        for command in [1, 2] {
+            let mut vm = self::vm(&path, 0, buffer, command);
+            assert_eq!(vm.resume(), StopReason::Halted);
+            assert_eq!(vm.memory().peek(0xFE10), 0x8001);
+            assert_eq!(vm.memory().peek(buffer), 0);
+            assert_eq!(vm.memory().peek(0x0000), 0);
+        }
+    }
+    assert_eq!(fs::read(&path).unwrap(), [0x12, 0x34].repeat(SECTOR_WORDS));
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn interrupts_on_completion() {
+    let path = image("interrupt", &[&[0x1234; SECTOR_WORDS]]);
+    // Read sector 0 with the interrupt enabled at priority 3
+    let mut vm = vm(&path, 0, 0x5000, 0x4301);
+    vm.memory_mut().poke(0x0183, 0x4000);
+    vm.memory_mut().poke(0x4000, 0xF025);
+    vm.registers_mut().write(6, 0x3000);
+    assert_eq!(vm.resume(), StopReason::Halted);
+    assert_eq!(vm.registers().pc, 0x4001);
+    assert_eq!(vm.registers().priority(), 3);
+    assert_eq!(vm.memory().peek(0x2FFE), 0x3002);
+    assert_eq!(vm.memory().peek(0x50FF), 0x1234);
+    assert_eq!(vm.memory().peek(0xFE12), 0x4300);
+    fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,111 @@
+//! Framebuffer display
+
+mod common;
+
+use common::vm_with_program;
+use lc3_vm::{Display, DisplayImage, Engine, ImageFormat, StopReason, Vm};
+
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, Write};
+use std::process;
+use std::rc::Rc;
+
+/// Program at x3000 that paints the top left pixel red and the bottom right one blue
+const PAINT: &[u16] = &[
+    0x2005, // LD R0, RED
+    0xB005, // STI R0, TOP_LEFT
+    0x2205, // LD R1, BLUE
+    0xB205, // STI R1, BOTTOM_RIGHT
+    0xF025, // HALT
+    0x0000, //
+    0x7C00, // RED
+    0xC000, // TOP_LEFT
+    0x001F, // BLUE
+    0xFDFF, // BOTTOM_RIGHT
+];
+
+fn vm() -> Vm {
+    let (vm, _) = vm_with_program(Engine::Interpreter, 0x3000, PAINT, b"");
+    vm
+}
+
+/// Writer whose bytes can be inspected while the display owns it
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn captures_and_encodes_the_framebuffer() {
+    let mut vm = vm();
+    assert_eq!(vm.resume(), StopReason::Halted);
+    let image = DisplayImage::capture(vm.memory());
+    assert_eq!(image.pixel(0, 0), 0x7C00);
+    assert_eq!(image.rgb(0, 0), [255, 0, 0]);
+    assert_eq!(image.rgb(1, 0), [0, 0, 0]);
+    assert_eq!(image.rgb(127, 123), [0, 0, 255]);
+
+    let mut ppm = Vec::new();
+    image.write_ppm(&mut ppm).unwrap();
+    assert!(ppm.starts_with(b"P6\n128 124\n255\n\xFF\x00\x00\x00\x00\x00"));
+    assert_eq!(ppm.len(), 15 + 128 * 124 * 3);
+    assert!(ppm.ends_with(b"\x00\x00\xFF"));
+
+    let mut png = Vec::new();
+    image.write_png(&mut png).unwrap();
+    assert!(
+        png.starts_with(b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0DIHDR\x00\x00\x00\x80\x00\x00\x00\x7C")
+    );
+    // Signature, IHDR, IDAT with 124 scanlines of 385 bytes in a stored zlib stream, and IEND
+    assert_eq!(png.len(), 8 + 25 + 12 + (2 + 5 + 124 * 385 + 4) + 12);
+    assert!(png.ends_with(b"\x00\x00\x00\x00IEND\xAE\x42\x60\x82"));
+}
+
+#[test]
+fn writes_frames_after_the_framebuffer_changed() {
+    let dir = std::env::temp_dir().join(format!("lc3-display-{}", process::id()));
+    let display = Rc::new(RefCell::new(Display::frames(dir.clone(), ImageFormat::Ppm)));
+    display.borrow_mut().set_interval(1000);
+    let mut vm = vm();
+    vm.add_tracer(Box::new(Rc::clone(&display)));
+    vm.resume();
+
+    // The second pixel was painted within the interval after the first frame
+    assert_eq!(display.borrow().frames_shown(), 1);
+    display.borrow_mut().finish(vm.memory()).unwrap();
+    assert_eq!(display.borrow().frames_shown(), 2);
+
+    let first = fs::read(dir.join("frame-000001.ppm")).unwrap();
+    let last = fs::read(dir.join("frame-000002.ppm")).unwrap();
+    assert!(first.ends_with(b"\x00\x00\x00"));
+    assert!(last.ends_with(b"\x00\x00\xFF"));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn draws_half_blocks_in_the_terminal() {
+    let out = SharedBuffer::default();
+    let display = Rc::new(RefCell::new(Display::terminal(Box::new(out.clone()))));
+    display.borrow_mut().set_interval(1);
+    let mut vm = vm();
+    vm.add_tracer(Box::new(Rc::clone(&display)));
+    vm.resume();
+    display.borrow_mut().finish(vm.memory()).unwrap();
+
+    let text = String::from_utf8(out.0.borrow().clone()).unwrap();
+    // The display takes 62 lines and the console output scrolls below
+    assert!(text.starts_with("\x1b[2J\x1b[63r\x1b[63;1H\x1b7\x1b[H"));
+    assert!(text.contains("\x1b[38;2;255;0;0m\x1b[48;2;0;0;0m▀"));
+    assert!(text.contains("\x1b[38;2;0;0;0m\x1b[48;2;0;0;255m▀\x1b[0m\r\n\x1b8"));
+    assert!(text.ends_with("\x1b[r"));
+    assert_eq!(display.borrow().frames_shown(), 2);
+}
@@ -0,0 +1,183 @@
+//! GDB remote serial protocol over a socket pair
+
+mod common;
+
+use common::vm_with_program;
+use lc3_vm::{Engine, GdbStub, Limits, Vm};
+
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::thread;
+
+/// Program at x3000 that increments `R0` twice, stores it at x3004 and halts
+const PROGRAM: &[u16] = &[
+    0x1021, // ADD R0, R0, #1
+    0x1021, // ADD R0, R0, #1
+    0x3001, // ST R0, RESULT
+    0xF025, // HALT
+    0x0000, // RESULT
+];
+
+/// `BRnzp #-1`
+const TIGHT_LOOP: u16 = 0x0FFF;
+
+/// RSP client end of the connection
+struct Client {
+    stream: UnixStream,
+    no_ack: bool,
+}
+
+impl Client {
+    fn send_raw(&mut self, bytes: &[u8]) {
+        self.stream.write_all(bytes).unwrap();
+    }
+
+    fn send(&mut self, data: &str) {
+        let packet = format!("${}#{:02x}", data, checksum(data.as_bytes()));
+        self.send_raw(packet.as_bytes());
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let mut byte = [0];
+        self.stream.read_exact(&mut byte).unwrap();
+        byte[0]
+    }
+
+    /// Reads a reply packet, checks its checksum and acknowledges it
+    fn read_packet(&mut self) -> String {
+        assert_eq!(self.read_byte(), b'$');
+        let mut data = Vec::new();
+        loop {
+            match self.read_byte() {
+                b'#' => break,
+                byte => data.push(byte),
+            }
+        }
+        let digits = [self.read_byte(), self.read_byte()];
+        let sum = u8::from_str_radix(std::str::from_utf8(&digits).unwrap(), 16).unwrap();
+        assert_eq!(sum, checksum(&data), "checksum of {:?}", data);
+        if !self.no_ack {
+            // The stub closes the connection right after its reply to `k` and `D`
+            self.stream.write_all(b"+").ok();
+        }
+        String::from_utf8(data).unwrap()
+    }
+
+    /// Sends a packet and returns the reply
+    fn request(&mut self, data: &str) -> String {
+        self.send(data);
+        if !self.no_ack {
+            assert_eq!(self.read_byte(), b'+', "ack of {}", data);
+        }
+        self.read_packet()
+    }
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, &byte| sum.wrapping_add(byte))
+}
+
+/// Serves `vm` to the `client` function in another thread; returns the vm after the session
+fn serve(vm: Vm, client: impl FnOnce(&mut Client) + Send + 'static) -> Vm {
+    let (server, stream) = UnixStream::pair().unwrap();
+    let session = thread::spawn(move || {
+        client(&mut Client {
+            stream,
+            no_ack: false,
+        })
+    });
+    let vm = GdbStub::from_stream(vm, server).serve().unwrap();
+    session.join().unwrap();
+    vm
+}
+
+fn vm(program: &[u16]) -> Vm {
+    let (mut vm, _) = vm_with_program(Engine::Interpreter, 0x3000, program, b"");
+    vm.enable_history(1 << 20);
+    vm
+}
+
+#[test]
+fn debugs_a_program() {
+    let vm = serve(vm(PROGRAM), |client| {
+        assert_eq!(client.request("?"), "S05");
+        assert!(client
+            .request("qSupported:swbreak+")
+            .contains("QStartNoAckMode+"));
+
+        // Packets with a wrong checksum are rejected and sent again
+        client.send_raw(b"$g#00");
+        assert_eq!(client.read_byte(), b'-');
+        let registers = format!("{}30008002", "0000".repeat(8));
+        assert_eq!(client.request("g"), registers);
+
+        let registers = format!("0000{}30008002", "1234".repeat(7));
+        assert_eq!(client.request(&format!("G{}", registers)), "OK");
+        assert_eq!(client.request("p7"), "1234");
+        assert_eq!(client.request("P0=0000"), "OK");
+        assert_eq!(client.request("Gxyz"), "E01");
+
+        assert_eq!(client.request("m3000,2"), "10211021");
+        assert_eq!(client.request("M3005,2:abcd0001"), "OK");
+        assert_eq!(client.request("m3005,2"), "abcd0001");
+        assert_eq!(client.request("M3005,2:abcd"), "E01");
+
+        // Breakpoints
+        assert_eq!(client.request("Z0,3002,1"), "OK");
+        assert_eq!(client.request("c"), "T05swbreak:;");
+        assert_eq!(client.request("p8"), "3002");
+        assert_eq!(client.request("p0"), "0002");
+        assert_eq!(client.request("z0,3002,1"), "OK");
+
+        // Write watchpoints stop after the store
+        assert_eq!(client.request("Z2,3004,1"), "OK");
+        assert_eq!(client.request("c"), "T05watch:3004;");
+        assert_eq!(client.request("p8"), "3003");
+        assert_eq!(client.request("m3004,1"), "0002");
+        assert_eq!(client.request("z2,3004,1"), "OK");
+
+        // Reverse execution
+        assert_eq!(client.request("bs"), "S05");
+        assert_eq!(client.request("p8"), "3002");
+        assert_eq!(client.request("m3004,1"), "0000");
+        assert_eq!(client.request("bc"), "T05replaylog:begin;");
+        assert_eq!(client.request("p8"), "3000");
+        assert_eq!(client.request("p0"), "0000");
+
+        assert_eq!(client.request("s"), "S05");
+        assert_eq!(client.request("p8"), "3001");
+
+        // Without acknowledgements, the program runs to its end
+        assert_eq!(client.request("QStartNoAckMode"), "OK");
+        client.no_ack = true;
+        assert_eq!(client.request("c"), "W00");
+        assert_eq!(client.request("s"), "E01");
+        assert_eq!(client.request("k"), "");
+    });
+    assert_eq!(vm.registers().read(0), 2);
+    assert_eq!(vm.registers().read(1), 0x1234);
+    assert_eq!(vm.memory().peek(0x3004), 2);
+    assert_eq!(vm.memory().peek(0x3005), 0xABCD);
+}
+
+#[test]
+fn stops_at_interrupts_and_limits() {
+    serve(vm(&[TIGHT_LOOP]), |client| {
+        client.send("c");
+        assert_eq!(client.read_byte(), b'+');
+        client.send_raw(&[0x03]);
+        assert_eq!(client.read_packet(), "S02");
+        assert_eq!(client.request("D"), "OK");
+    });
+
+    let mut limited = vm(&[TIGHT_LOOP]);
+    limited.set_limits(Limits {
+        max_instructions: Some(10_000),
+        ..Limits::default()
+    });
+    let vm = serve(limited, |client| {
+        assert_eq!(client.request("c"), "S18");
+        assert_eq!(client.request("k"), "");
+    });
+    assert_eq!(vm.instructions(), 10_000);
+}
@@ -0,0 +1,82 @@
+//! Golden end-to-end runs of the bundled games with scripted input
+//!
+//! Each run feeds a fixed key sequence through a [`BufferedConsole`] until the program halts or
+//! waits for more input, and compares a checksum of the complete output with the recorded one.
+//! The runs are repeated with every execution engine, which must all produce the same output.
+
+mod common;
+
+use common::engines;
+use lc3_vm::{BufferedConsole, StopReason, Vm};
+
+use std::fs::File;
+
+/// Returns the 64-bit FNV-1a hash of `bytes`
+fn checksum(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xCBF2_9CE4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01B3)
+    })
+}
+
+/// Runs the program at `path` with the given `input` on every engine; returns the stop reason
+/// and the output checksum, after checking that the engines agree and all input was consumed
+fn run(path: &str, input: &[u8]) -> (StopReason, u64) {
+    let mut results = engines().into_iter().map(|engine| {
+        let console = BufferedConsole::with_input(input);
+        let mut vm = Vm::new();
+        vm.set_console(Box::new(console.clone()));
+        vm.set_engine(engine);
+        vm.load_program(File::open(path).unwrap()).unwrap();
+        let reason = vm.resume();
+        assert_eq!(console.pending_input(), 0, "{:?} left input unread", engine);
+        (engine, reason, checksum(&console.output()))
+    });
+    let (_, reason, checksum) = results.next().unwrap();
+    for (engine, other_reason, other_checksum) in results {
+        assert_eq!(
+            (other_reason, other_checksum),
+            (reason, checksum),
+            "{:?}",
+            engine
+        );
+    }
+    (reason, checksum)
+}
+
+/// Returns `count` moves that cycle through all directions
+fn moves(count: usize) -> Vec<u8> {
+    b"wasd".iter().cycle().take(count).copied().collect()
+}
+
+#[test]
+fn game_2048_until_lost() {
+    // The game is lost after the 148th move; `n` declines another game
+    let mut input = b"n".to_vec();
+    input.extend(moves(148));
+    input.push(b'n');
+    assert_eq!(
+        run("assets/2048.obj", &input),
+        (StopReason::Halted, 0x63AD_DEAB_E968_F33D)
+    );
+}
+
+#[test]
+fn game_2048_ansi() {
+    let mut input = b"y".to_vec();
+    input.extend(moves(40));
+    assert_eq!(
+        run("assets/2048.obj", &input),
+        (StopReason::WaitingForInput(0x30B4), 0x2614_590B_37AF_C5F8)
+    );
+}
+
+#[test]
+fn rogue() {
+    // Any key starts the game
+    let mut input = b"x".to_vec();
+    input.extend(b"ddddddssssssdddddddsssss".iter().cycle().take(200));
+    assert_eq!(
+        run("assets/rogue.obj", &input),
+        (StopReason::WaitingForInput(0x309B), 0xFC3A_ED12_0248_92BE)
+    );
+}
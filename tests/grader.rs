@@ -0,0 +1,130 @@
+//! Test files of the autograder
+
+use lc3_vm::{Engine, StopReason, TestSuite};
+
+/// Program image at x3000 that stores `R1 + R2` in `R0` and at x4100, then halts
+const SUM: &[u8] = &[
+    0x30, 0x00, // origin
+    0x10, 0x42, // ADD R0, R1, R2
+    0xB0, 0x02, // STI R0, #2
+    0xF0, 0x25, // HALT
+    0x00, 0x00, //
+    0x41, 0x00, // x4100
+];
+
+const SUITE: &str = r##"
+name = "sum"
+max_instructions = 1000
+
+[[case]]
+name = "positive"
+registers = { R1 = 3, R2 = "x0004" }
+[case.expect]
+output = "HALT"
+registers = { R0 = 7, PC = "x3003" }
+memory = { x4100 = 7 }
+
+[[case]]
+name = "negative"
+registers = { R1 = -1, R2 = "#-2" }
+[case.expect]
+output = "Sum\nHALT"
+registers = { R0 = 7 }
+memory = { x4100 = [7, 1] }
+
+[[case]]
+name = "endless"
+memory = { x3002 = "x0FFF" }
+max_instructions = 50
+[case.expect]
+stop = "limit"
+"##;
+
+#[test]
+fn runs_toml_cases() {
+    let suite = TestSuite::from_toml(SUITE).unwrap();
+    let report = suite.run(SUM, Engine::BasicBlocks);
+    assert_eq!((report.passed(), report.failed()), (2, 1));
+
+    let results = &report.results;
+    assert!(results[0].passed(), "{:?}", results[0].failures);
+    assert_eq!(results[1].stop, Some(StopReason::Halted));
+    assert_eq!(
+        results[1].failures,
+        [
+            "Output differs at line 1, column 1 (expected 8 bytes, got 4):\n  \
+             expected: \"Sum\"\n  actual:   \"HALT\"",
+            "R0: expected x0007 (7), got xFFFD (-3)",
+            "Memory x4100: expected x0007 (7), got xFFFD (-3)",
+            "Memory x4101: expected x0001 (1), got x0000 (0)",
+        ]
+    );
+    assert_eq!(results[2].instructions, 50);
+    assert!(results[2].passed(), "{:?}", results[2].failures);
+}
+
+#[test]
+fn runs_yaml_cases() {
+    let suite = TestSuite::from_yaml(
+        "cases:\n  - name: sum\n    registers: {R1: 1, R2: 2}\n    \
+         expect:\n      registers: {R0: 3}\n      stop: halted\n",
+    )
+    .unwrap();
+    let report = suite.run(SUM, Engine::Interpreter);
+    assert_eq!(report.failed(), 0, "{:?}", report.results[0].failures);
+}
+
+#[test]
+fn a_runaway_program_fails_with_the_default_limit() {
+    let suite = TestSuite::from_toml("[[case]]\nmemory = { x3002 = \"x0FFF\" }").unwrap();
+    let report = suite.run(SUM, Engine::BasicBlocks);
+    let failure = &report.results[0].failures[0];
+    assert!(
+        failure.starts_with(
+            "Expected the program to stop with HALT, but it reached the instruction limit at x3002"
+        ),
+        "{}",
+        failure
+    );
+}
+
+#[test]
+fn rejects_invalid_files() {
+    let error = |text| TestSuite::from_toml(text).unwrap_err();
+    assert_eq!(error("name = \"x\""), "The test file has no cases");
+    assert_eq!(
+        error("[[case]]\nregister = { R1 = 1 }"),
+        "case 1: unknown key `register`"
+    );
+    assert_eq!(
+        error("[[case]]\nname = \"a\"\nregisters = { R8 = 1 }"),
+        "case `a`: unknown register `R8`"
+    );
+    assert_eq!(
+        error("[[case]]\nname = \"a\"\nexpect = { stop = \"done\" }"),
+        "case `a` expect: invalid stop `done`; expected halted, waiting-for-input, fault, \
+         limit or any"
+    );
+    assert_eq!(
+        error("[[case]]\nname = \"a\"\nmemory = { x3000 = 70000 }"),
+        "case `a` memory x3000: invalid word 70000"
+    );
+}
+
+#[test]
+fn writes_reports() {
+    let suite = TestSuite::from_toml(SUITE).unwrap();
+    let report = suite.run(SUM, Engine::BasicBlocks);
+
+    let mut junit = Vec::new();
+    report.write_junit(&mut junit).unwrap();
+    let junit = String::from_utf8(junit).unwrap();
+    assert!(junit.contains(r#"<testsuite name="sum" tests="3" failures="1""#));
+    assert!(junit.contains("expected: &quot;Sum&quot;"));
+
+    let mut json = Vec::new();
+    report.write_json(&mut json).unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+    assert_eq!(json["failed"], 1);
+    assert_eq!(json["cases"][2]["stop"], "limit");
+}
@@ -0,0 +1,127 @@
+//! Reverse execution with the execution history
+
+mod common;
+
+use common::vm_with_program;
+use lc3_vm::{Engine, StopReason, Vm, WatchKind, Watchpoint, WatchpointHit};
+
+/// Address where the program saves the key
+const SAVED: u16 = 0x3008;
+
+/// Timer interval register, which the program writes the key to
+const TIR: u16 = 0xFE0A;
+
+/// Program at x3000 that polls the keyboard and stores the key in memory and in `TIR`
+const PROGRAM: &[u16] = &[
+    0xA005, // POLL LDI R0, KBSR
+    0x07FE, // BRzp POLL
+    0xA204, // LDI R1, KBDR
+    0x3204, // ST R1, SAVED
+    0xB204, // STI R1, TIR
+    0xF025, // HALT
+    0xFE00, // KBSR
+    0xFE02, // KBDR
+    0x0000, // SAVED
+    0xFE0A, // TIR
+];
+
+fn vm(input: &[u8]) -> Vm {
+    let (mut vm, _) = vm_with_program(Engine::Interpreter, 0x3000, PROGRAM, input);
+    vm.enable_history(1 << 20);
+    vm
+}
+
+#[test]
+fn undoes_memory_and_device_writes() {
+    let mut vm = vm(b"a");
+    assert_eq!(vm.resume(), StopReason::Halted);
+    assert_eq!(vm.memory().peek(SAVED), 0x61);
+    assert_eq!(vm.memory().peek(TIR), 0x61);
+    assert_eq!(vm.history_len() as u64, vm.instructions());
+
+    // HALT
+    assert!(vm.step_back());
+    assert_eq!(vm.registers().pc, 0x3005);
+    // STI R1, TIR
+    assert!(vm.step_back());
+    assert_eq!(vm.registers().pc, 0x3004);
+    assert_eq!(vm.memory().peek(TIR), 0);
+    assert_eq!(vm.memory().peek(SAVED), 0x61);
+    // ST R1, SAVED
+    assert!(vm.step_back());
+    assert_eq!(vm.memory().peek(SAVED), 0);
+    assert_eq!(vm.registers().read(1), 0x61);
+    assert_eq!(vm.instructions(), 3);
+}
+
+#[test]
+fn delivers_undone_input_again() {
+    let mut vm = vm(b"a");
+    assert_eq!(vm.resume(), StopReason::Halted);
+    let instructions = vm.instructions();
+
+    // Without breakpoints, the whole history is undone
+    assert_eq!(vm.reverse_continue(), None);
+    assert!(!vm.step_back());
+    assert_eq!(vm.registers().pc, 0x3000);
+    assert_eq!(vm.instructions(), 0);
+    assert_eq!(vm.memory().peek(0xFE00), 0);
+    assert_eq!(vm.memory().peek(0xFE02), 0);
+
+    // The key that the program read is read again
+    assert_eq!(vm.resume(), StopReason::Halted);
+    assert_eq!(vm.instructions(), instructions);
+    assert_eq!(vm.registers().read(1), 0x61);
+    assert_eq!(vm.memory().peek(SAVED), 0x61);
+}
+
+#[test]
+fn reverse_continue_stops_at_breakpoints_and_watchpoints() {
+    let mut vm = vm(b"a");
+    assert_eq!(vm.resume(), StopReason::Halted);
+    assert!(vm.add_breakpoint(0x3002));
+    assert_eq!(vm.reverse_continue(), Some(StopReason::Breakpoint(0x3002)));
+    assert_eq!(vm.registers().pc, 0x3002);
+    assert_eq!(vm.registers().read(1), 0);
+
+    // Executing forward from the breakpoint doesn't stop at it again
+    assert!(vm.remove_breakpoint(0x3002));
+    assert_eq!(vm.resume(), StopReason::Halted);
+
+    // The vm stops right before the store that triggers the watchpoint
+    vm.add_watchpoint(Watchpoint::new(SAVED..=SAVED, WatchKind::Write));
+    let hit = WatchpointHit {
+        pc: 0x3003,
+        address: SAVED,
+        kind: WatchKind::Write,
+        old_value: 0,
+        new_value: 0x61,
+    };
+    assert_eq!(vm.reverse_continue(), Some(StopReason::Watchpoint(hit)));
+    assert_eq!(vm.registers().pc, 0x3003);
+    assert_eq!(vm.memory().peek(SAVED), 0);
+    assert_eq!(vm.reverse_continue(), None);
+}
+
+#[test]
+fn forgets_the_oldest_instructions() {
+    let mut vm = vm(b"");
+    vm.enable_history(4096);
+    // The program polls the keyboard until the input arrives
+    let step = |vm: &mut Vm| {
+        for _ in 0..10_000 {
+            assert_eq!(vm.step(), None);
+        }
+    };
+    step(&mut vm);
+    let recorded = vm.history_len();
+    assert!(recorded > 0 && recorded < 10_000);
+    step(&mut vm);
+    assert_eq!(vm.history_len(), recorded);
+
+    for _ in 0..recorded {
+        assert!(vm.step_back());
+    }
+    assert!(!vm.step_back());
+    assert_eq!(vm.instructions(), 20_000 - recorded as u64);
+}
@@ -0,0 +1,109 @@
+//! Recording input logs and replaying them
+
+mod common;
+
+use common::vm_with_program;
+use lc3_vm::{read_input_log, Engine, InputEvent, InputRecorder, StopReason};
+
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+/// Program at x3000 that counts the polls of `KBSR` in `R2` until a key arrives, reads it into
+/// `R1` and reads another key with `GETC`
+const PROGRAM: &[u16] = &[
+    0x14A1, // POLL ADD R2, R2, #1
+    0xA004, // LDI R0, KBSR
+    0x07FD, // BRzp POLL
+    0xA203, // LDI R1, KBDR
+    0xF020, // GETC
+    0xF025, // HALT
+    0xFE00, // KBSR
+    0xFE02, // KBDR
+];
+
+/// Output stream whose contents stay readable after the recorder was dropped
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn parses_input_logs() {
+    let log = "# lc3-vm input log 1 \n12 97\n\n  40   10\n";
+    assert_eq!(
+        read_input_log(log.as_bytes()).unwrap(),
+        [
+            InputEvent {
+                instruction: 12,
+                byte: 97
+            },
+            InputEvent {
+                instruction: 40,
+                byte: 10
+            },
+        ]
+    );
+    assert!(read_input_log("# lc3-vm input log 1\n".as_bytes())
+        .unwrap()
+        .is_empty());
+
+    let error = |log: &str| read_input_log(log.as_bytes()).unwrap_err().to_string();
+    assert_eq!(error(""), "Not an input log");
+    assert_eq!(error("12 97\n"), "Not an input log");
+    assert_eq!(
+        error("# lc3-vm input log 1\n12 97\n40 256\n"),
+        "Invalid input log entry in line 3"
+    );
+    assert_eq!(
+        error("# lc3-vm input log 1\n12\n"),
+        "Invalid input log entry in line 2"
+    );
+    assert_eq!(
+        error("# lc3-vm input log 1\n12 97 3\n"),
+        "Invalid input log entry in line 2"
+    );
+}
+
+#[test]
+fn replays_recorded_runs() {
+    // The keys arrive while the program polls
+    let (mut vm, console) = vm_with_program(Engine::Interpreter, 0x3000, PROGRAM, b"");
+    let log = SharedBuffer::default();
+    let recorder = Rc::new(RefCell::new(InputRecorder::new(Box::new(log.clone()))));
+    vm.add_tracer(Box::new(Rc::clone(&recorder)));
+    for _ in 0..100 {
+        assert_eq!(vm.step(), None);
+    }
+    console.push_input(b"ab");
+    assert_eq!(vm.resume(), StopReason::Halted);
+    recorder.borrow_mut().flush().unwrap();
+    let polls = vm.registers().read(2);
+    assert!(polls > 30);
+
+    let log = log.0.borrow().clone();
+    let events = read_input_log(&log[..]).unwrap();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].byte, b'a');
+    assert_eq!(events[1].byte, b'b');
+    assert_eq!(events[1].instruction, vm.instructions() - 2);
+
+    // The replayed keys reach the same instructions, whatever the console has
+    let (mut replay, console) = vm_with_program(Engine::Interpreter, 0x3000, PROGRAM, b"xy");
+    replay.replay_input(events);
+    assert_eq!(replay.resume(), StopReason::Halted);
+    assert_eq!(replay.registers().read(2), polls);
+    assert_eq!(replay.registers().read(1), u16::from(b'a'));
+    assert_eq!(replay.registers().read(0), u16::from(b'b'));
+    assert_eq!(replay.instructions(), vm.instructions());
+    assert_eq!(console.pending_input(), 2);
+}
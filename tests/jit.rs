@@ -0,0 +1,114 @@
+//! Native code checked against the interpreter on hot loops, self-modifying code and device
+//! registers
+#![cfg(feature = "jit")]
+
+mod common;
+
+use common::vm_with_program;
+use lc3_vm::{Engine, StopReason};
+
+/// Program at x3000 that sums up 1000 to 1 in a loop and stores the sum at x3009
+const HOT_LOOP: &[u16] = &[
+    0x5020, // AND R0, R0, #0
+    0x2206, // LD R1, N
+    0x1001, // LOOP ADD R0, R0, R1
+    0x127F, // ADD R1, R1, #-1
+    0x03FD, // BRp LOOP
+    0x3003, // ST R0, RESULT
+    0xF025, // HALT
+    0x0000, 0x03E8, // N
+    0x0000, // RESULT
+];
+
+/// Program at x3000 that increments the immediate of an `ADD` in its loop on every iteration
+const SELF_MODIFYING: &[u16] = &[
+    0x2409, // LD R2, N
+    0x2602, // LOOP LD R3, INC
+    0x16E1, // ADD R3, R3, #1
+    0x3600, // ST R3, INC
+    0x1260, // INC ADD R1, R1, #0
+    0x14BF, // ADD R2, R2, #-1
+    0x03FA, // BRp LOOP
+    0xF025, // HALT
+    0x0000, 0x0000, 0x0064, // N
+];
+
+/// Program at x3000 that starts the timer and polls `TSR` in a loop until it expires
+const DEVICE_POLLING: &[u16] = &[
+    0x2008, // LD R0, INTERVAL
+    0xB008, // STI R0, TIR
+    0x2008, // LD R0, CONTROL
+    0xB008, // STI R0, TCR
+    0x1261, // WAIT ADD R1, R1, #1
+    0xA407, // LDI R2, TSR
+    0x07FD, // BRzp WAIT
+    0xB405, // STI R2, TSR
+    0xF025, // HALT
+    0x01F4, // INTERVAL
+    0xFE0A, // TIR
+    0x8000, // CONTROL
+    0xFE08, // TCR
+    0xFE0C, // TSR
+];
+
+/// Observable state of a vm after a run
+#[derive(Debug, PartialEq)]
+struct Run {
+    stop: StopReason,
+    registers: Vec<u16>,
+    instructions: u64,
+    cycles: u64,
+    memory: Vec<u16>,
+    output: Vec<u8>,
+}
+
+fn run(program: &[u16], engine: Engine) -> Run {
+    let (mut vm, console) = vm_with_program(engine, 0x3000, program, b"");
+    let stop = vm.resume();
+    let regs = vm.registers();
+    let mut registers: Vec<u16> = (0..8).map(|index| regs.read(index)).collect();
+    registers.extend([regs.pc, regs.psr()]);
+    let memory = (0x3000..0x3010)
+        .chain(0xFE00..0xFE10)
+        .map(|address| vm.memory().peek(address))
+        .collect();
+    Run {
+        stop,
+        registers,
+        instructions: vm.instructions(),
+        cycles: vm.cycles(),
+        memory,
+        output: console.output(),
+    }
+}
+
+/// Runs the program with checked native code and returns the result, which must agree with the
+/// interpreter
+fn run_checked(program: &[u16]) -> Run {
+    let checked = run(program, Engine::JitChecked);
+    assert_eq!(checked, run(program, Engine::Interpreter));
+    assert_eq!(checked.stop, StopReason::Halted);
+    checked
+}
+
+#[test]
+fn agrees_on_hot_loops() {
+    let run = run_checked(HOT_LOOP);
+    assert_eq!(run.memory[9], (500_500 % 65_536) as u16);
+    assert_eq!(run.instructions, 2 + 3 * 1000 + 2);
+}
+
+#[test]
+fn agrees_on_self_modifying_stores() {
+    // Every iteration executes the instruction that the previous one stored
+    let run = run_checked(SELF_MODIFYING);
+    assert_eq!(run.memory[4], 0x1260 + 100);
+    assert_eq!(run.instructions, 1 + 6 * 100 + 1);
+}
+
+#[test]
+fn agrees_on_device_accesses() {
+    let run = run_checked(DEVICE_POLLING);
+    assert!(run.registers[1] > 100);
+    assert_eq!(run.memory[0x10 + 0x0C], 0);
+}
@@ -0,0 +1,154 @@
+//! Execution limits of runaway programs on every engine
+
+mod common;
+
+use common::{engines, vm_with_program};
+use lc3_vm::{BufferedConsole, Engine, Limit, Limits, StopReason, Vm};
+
+use std::time::{Duration, Instant};
+
+/// `ADD R0, R0, #1; BRnzp #-2`
+const COUNTING_LOOP: &[u16] = &[0x1021, 0x0FFE];
+/// `BRnzp #-1`
+const TIGHT_LOOP: &[u16] = &[0x0FFF];
+/// `OUT; BRnzp #-2`
+const OUTPUT_LOOP: &[u16] = &[0xF021, 0x0FFE];
+
+/// Returns a vm on `engine` with `program` at x3000 and the given `limits`
+fn vm(engine: Engine, program: &[u16], limits: Limits) -> (Vm, BufferedConsole) {
+    let (mut vm, console) = vm_with_program(engine, 0x3000, program, b"");
+    vm.set_limits(limits);
+    (vm, console)
+}
+
+fn limit_of(reason: StopReason) -> Limit {
+    match reason {
+        StopReason::LimitExceeded { limit, .. } => limit,
+        _ => panic!("Expected a limit, got {:?}", reason),
+    }
+}
+
+#[test]
+fn instruction_limit_is_exact() {
+    for engine in engines() {
+        let limits = Limits {
+            max_instructions: Some(1001),
+            ..Limits::default()
+        };
+        let (mut vm, _) = vm(engine, COUNTING_LOOP, limits);
+        let reason = vm.resume();
+        match reason {
+            StopReason::LimitExceeded { pc, limit, recent } => {
+                assert_eq!((pc, limit), (0x3001, Limit::Instructions), "{:?}", engine);
+                let expected: Vec<u16> =
+                    [0x3001, 0x3000].iter().copied().cycle().take(16).collect();
+                assert_eq!(recent.pcs().collect::<Vec<_>>(), expected, "{:?}", engine);
+            }
+            _ => panic!("{:?} stopped with {:?}", engine, reason),
+        }
+        assert_eq!(vm.instructions(), 1001, "{:?}", engine);
+        assert_eq!(vm.registers().read(0), 501, "{:?}", engine);
+
+        // Raising the limit continues the same run
+        vm.set_limits(Limits {
+            max_instructions: Some(2000),
+            ..Limits::default()
+        });
+        assert_eq!(limit_of(vm.resume()), Limit::Instructions, "{:?}", engine);
+        assert_eq!(vm.instructions(), 2000, "{:?}", engine);
+        assert_eq!(vm.registers().read(0), 1000, "{:?}", engine);
+        assert_eq!(vm.registers().pc, 0x3000, "{:?}", engine);
+    }
+}
+
+#[test]
+fn cycle_limit_stops_before_the_next_instruction() {
+    for engine in engines() {
+        let limits = Limits {
+            max_cycles: Some(6001),
+            ..Limits::default()
+        };
+        let (mut vm, _) = vm(engine, COUNTING_LOOP, limits);
+        assert_eq!(limit_of(vm.resume()), Limit::Cycles, "{:?}", engine);
+        assert_eq!(
+            (vm.instructions(), vm.cycles()),
+            (1001, 6006),
+            "{:?}",
+            engine
+        );
+    }
+}
+
+#[test]
+fn output_limit() {
+    for engine in engines() {
+        let limits = Limits {
+            max_output: Some(100),
+            ..Limits::default()
+        };
+        let (mut vm, console) = vm(engine, OUTPUT_LOOP, limits);
+        vm.registers_mut().write(0, u16::from(b'x'));
+        assert_eq!(limit_of(vm.resume()), Limit::Output, "{:?}", engine);
+        assert_eq!(console.output(), vec![b'x'; 101], "{:?}", engine);
+        assert_eq!(vm.registers().pc, 0x3001, "{:?}", engine);
+    }
+}
+
+#[test]
+fn timeout_stops_a_tight_loop() {
+    for engine in engines() {
+        let limits = Limits {
+            timeout: Some(Duration::from_millis(50)),
+            ..Limits::default()
+        };
+        let (mut vm, _) = vm(engine, TIGHT_LOOP, limits);
+        let started = Instant::now();
+        let reason = vm.resume();
+        assert_eq!(limit_of(reason), Limit::Timeout, "{:?}", engine);
+        assert!(started.elapsed() < Duration::from_secs(5), "{:?}", engine);
+        assert!(vm.instructions() > 0, "{:?}", engine);
+    }
+}
+
+#[test]
+fn limits_do_not_stop_a_halting_program() {
+    for engine in engines() {
+        let limits = Limits {
+            max_instructions: Some(3),
+            ..Limits::default()
+        };
+        // `ADD R0, R0, #1; ADD R0, R0, #1; HALT`
+        let (mut vm, _) = vm(engine, &[0x1021, 0x1021, 0xF025], limits);
+        assert_eq!(vm.resume(), StopReason::Halted, "{:?}", engine);
+    }
+}
+
+#[test]
+fn single_steps_stop_at_limits() {
+    let limits = Limits {
+        max_instructions: Some(10),
+        ..Limits::default()
+    };
+    let (mut vm, _) = vm(Engine::Interpreter, COUNTING_LOOP, limits);
+    for _ in 0..10 {
+        assert_eq!(vm.step(), None);
+    }
+    assert_eq!(limit_of(vm.step().unwrap()), Limit::Instructions);
+    assert_eq!(vm.instructions(), 10);
+
+    // Frontends that step through a continue start the timeout themselves
+    let limits = Limits {
+        timeout: Some(Duration::from_millis(50)),
+        ..Limits::default()
+    };
+    let (mut vm, _) = self::vm(Engine::Interpreter, TIGHT_LOOP, limits);
+    vm.start_timeout();
+    let started = Instant::now();
+    let reason = loop {
+        if let Some(reason) = vm.step() {
+            break reason;
+        }
+    };
+    assert_eq!(limit_of(reason), Limit::Timeout);
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
@@ -0,0 +1,25 @@
+//! Numbers in the notations that the debuggers, the grader and the command line accept
+
+use lc3_vm::parse_word;
+
+#[test]
+fn parses_hexadecimal_and_decimal_words() {
+    for text in [
+        "x3000", "X3000", "0x3000", "0X3000", "12288", "#12288", " x3000\n",
+    ] {
+        assert_eq!(parse_word(text), Some(0x3000), "{:?}", text);
+    }
+    assert_eq!(parse_word("xFFFF"), Some(0xFFFF));
+    assert_eq!(parse_word("65535"), Some(0xFFFF));
+    assert_eq!(parse_word("#-5"), Some(0xFFFB));
+    assert_eq!(parse_word("-32768"), Some(0x8000));
+}
+
+#[test]
+fn rejects_invalid_words() {
+    for text in [
+        "", "x", "#", "x+10", "0x+10", "x10000", "65536", "-32769", "#x10", "3000h", "1.5", "R1",
+    ] {
+        assert_eq!(parse_word(text), None, "{:?}", text);
+    }
+}
@@ -0,0 +1,146 @@
+//! Call-graph attribution of the profiler
+
+mod common;
+
+use common::vm_with_program;
+use lc3_vm::{DebugInfo, Engine, Profiler, StopReason};
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Program at x3000 that calls `FOO` with `JSR` and `BAR` with `JSRR`, which calls `FOO` again
+const PROGRAM: &[u16] = &[
+    0x4803, // MAIN JSR FOO
+    0xE405, // LEA R2, BAR
+    0x4080, // JSRR R2
+    0xF025, // HALT
+    0x1021, // FOO ADD R0, R0, #1
+    0x1021, // ADD R0, R0, #1
+    0xC1C0, // RET
+    0x17E0, // BAR ADD R3, R7, #0
+    0x4FFB, // JSR FOO
+    0x1EE0, // ADD R7, R3, #0
+    0xC1C0, // RET
+];
+
+/// Program at x3000 that calls the recursive `REC` with 300 in `R1`, which calls itself until
+/// `R1` is 0, and counts in `R2` after the outermost call returned
+const RECURSION: &[u16] = &[
+    0x220B, // MAIN LD R1, N
+    0x4802, // JSR REC
+    0x14A1, // ADD R2, R2, #1
+    0xF025, // HALT
+    0x1DBF, // REC ADD R6, R6, #-1
+    0x7F80, // STR R7, R6, #0
+    0x127F, // ADD R1, R1, #-1
+    0x0401, // BRz BASE
+    0x4FFB, // JSR REC
+    0x6F80, // BASE LDR R7, R6, #0
+    0x1DA1, // ADD R6, R6, #1
+    0xC1C0, // RET
+    0x012C, // N
+];
+
+fn profile(program: &[u16]) -> Profiler {
+    let (mut vm, _) = vm_with_program(Engine::Interpreter, 0x3000, program, b"");
+    vm.registers_mut().write(6, 0x5000);
+    let profiler = Rc::new(RefCell::new(Profiler::new()));
+    vm.add_tracer(Box::new(Rc::clone(&profiler)));
+    assert_eq!(vm.resume(), StopReason::Halted);
+    vm.clear_tracers();
+    Rc::try_unwrap(profiler).unwrap().into_inner()
+}
+
+fn symbols() -> DebugInfo {
+    let mut debug = DebugInfo::new();
+    debug.add_symbol("MAIN", 0x3000);
+    debug.add_symbol("FOO", 0x3004);
+    debug.add_symbol("BAR", 0x3007);
+    debug
+}
+
+#[test]
+fn attributes_instructions_to_call_stacks() {
+    let profiler = profile(PROGRAM);
+    assert_eq!(profiler.total(), 14);
+    assert_eq!(profiler.count(0x3004), 2);
+    assert_eq!(profiler.count(0x3008), 1);
+
+    // `RET` counts for the subroutine that returns
+    let mut folded = Vec::new();
+    profiler.write_folded(&mut folded, &symbols()).unwrap();
+    assert_eq!(
+        String::from_utf8(folded).unwrap(),
+        "MAIN 4\nMAIN;FOO 3\nMAIN;BAR 4\nMAIN;BAR;FOO 3\n"
+    );
+
+    let mut folded = Vec::new();
+    profiler
+        .write_folded(&mut folded, &DebugInfo::new())
+        .unwrap();
+    assert!(String::from_utf8(folded)
+        .unwrap()
+        .ends_with("x3000;x3007;x3004 3\n"));
+}
+
+#[test]
+fn reports_functions_and_calls() {
+    let mut report = Vec::new();
+    profile(PROGRAM)
+        .write_report(&mut report, &symbols())
+        .unwrap();
+    let report = String::from_utf8(report).unwrap();
+    let section = |title: &str| {
+        let start = report.find(title).unwrap();
+        let lines: Vec<&str> = report[start..]
+            .lines()
+            .skip(2)
+            .take_while(|line| !line.is_empty())
+            .collect();
+        lines.join("\n")
+    };
+
+    assert!(report.starts_with("Executed instructions: 14\n"));
+    assert_eq!(
+        section("Functions:"),
+        "           4  28.57%           14 100.00%  MAIN\n\
+         \x20          4  28.57%            7  50.00%  BAR\n\
+         \x20          6  42.86%            6  42.86%  FOO"
+    );
+    assert_eq!(
+        section("Call graph:"),
+        "           1  MAIN -> FOO\n\
+         \x20          1  MAIN -> BAR\n\
+         \x20          1  BAR -> FOO"
+    );
+    assert!(section("Hot spots:").starts_with("           2  14.29%  x3004    FOO\n"));
+}
+
+#[test]
+fn attributes_calls_beyond_the_maximum_depth_to_the_deepest_frame() {
+    let profiler = profile(RECURSION);
+    assert_eq!(profiler.total(), 4 + 299 * 8 + 7);
+
+    let mut debug = DebugInfo::new();
+    debug.add_symbol("MAIN", 0x3000);
+    debug.add_symbol("REC", 0x3004);
+    let mut folded = Vec::new();
+    profiler.write_folded(&mut folded, &debug).unwrap();
+    let folded = String::from_utf8(folded).unwrap();
+    let stacks: Vec<(usize, &str)> = folded
+        .lines()
+        .map(|line| {
+            let (stack, count) = line.rsplit_once(' ').unwrap();
+            (stack.matches("REC").count(), count)
+        })
+        .collect();
+
+    // The 256 frames of the call tree, where the deepest one runs the 45 deeper calls, and
+    // the instructions after the outermost call returned are attributed to `MAIN` again
+    assert_eq!(stacks.len(), 257);
+    assert_eq!(stacks[0], (0, "4"));
+    for (depth, &stack) in stacks[1..256].iter().enumerate() {
+        assert_eq!(stack, (depth + 1, "8"));
+    }
+    assert_eq!(stacks[256], (256, "359"));
+}
@@ -0,0 +1,119 @@
+//! Saving and loading snapshots of the machine state
+
+mod common;
+
+use common::vm_with_program;
+use lc3_vm::{BufferedConsole, Disk, Engine, StopReason, Vm, DISK_ADDRESSES};
+
+use std::fs;
+use std::io::ErrorKind;
+use std::process;
+
+/// Program at x3000 that reads characters and echoes them until it reads a `q`
+const ECHO: &[u16] = &[
+    0xF020, // LOOP GETC
+    0xF021, // OUT
+    0x2203, // LD R1, QUIT
+    0x1201, // ADD R1, R0, R1
+    0x0BFB, // BRnp LOOP
+    0xF025, // HALT
+    0xFF8F, // QUIT -'q'
+];
+
+fn vm(input: &[u8]) -> (Vm, BufferedConsole) {
+    vm_with_program(Engine::Interpreter, 0x3000, ECHO, input)
+}
+
+#[test]
+fn resumes_from_a_snapshot() {
+    let (mut vm, _) = vm(b"ab");
+    assert_eq!(vm.resume(), StopReason::WaitingForInput(0x3000));
+    let mut snapshot = Vec::new();
+    vm.save_snapshot(&mut snapshot).unwrap();
+
+    let (mut resumed, console) = self::vm(b"cq");
+    resumed.memory_mut().poke(0x3001, 0);
+    resumed.load_snapshot(&snapshot[..]).unwrap();
+    for index in 0..8 {
+        assert_eq!(resumed.registers().read(index), vm.registers().read(index));
+    }
+    assert_eq!(resumed.registers().pc, 0x3000);
+    assert_eq!(resumed.registers().psr(), vm.registers().psr());
+    assert_eq!(resumed.instructions(), vm.instructions());
+    assert_eq!(resumed.cycles(), vm.cycles());
+    assert_eq!(resumed.resume(), StopReason::Halted);
+    assert_eq!(console.output(), b"cqHALT".to_vec());
+    assert_eq!(resumed.instructions(), vm.instructions() + 11);
+}
+
+#[test]
+fn rejects_other_formats() {
+    let (mut vm, _) = vm(b"");
+    let mut snapshot = Vec::new();
+    vm.save_snapshot(&mut snapshot).unwrap();
+
+    let mut older = snapshot.clone();
+    older[5] -= 1;
+    let error = vm.load_snapshot(&older[..]).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+    assert!(error
+        .to_string()
+        .starts_with("Unsupported snapshot version"));
+
+    snapshot[0] = b'X';
+    let error = vm.load_snapshot(&snapshot[..]).unwrap_err();
+    assert_eq!(error.to_string(), "Not an LC-3 snapshot");
+
+    let error = vm.load_snapshot(&b"LC3S"[..]).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn restores_the_state_of_devices() {
+    let (mut vm, _) = vm(b"");
+    // `ADD R1, R1, #1; BRnzp #-2`
+    vm.memory_mut().poke(0x3000, 0x1261);
+    vm.memory_mut().poke(0x3001, 0x0FFE);
+    // Restarting the timer between instructions leaves the next instruction uncounted
+    vm.memory_mut().write(0xFE0A, 10);
+    vm.memory_mut().write(0xFE08, 0x8000);
+    let mut snapshot = Vec::new();
+    vm.save_snapshot(&mut snapshot).unwrap();
+
+    let (mut resumed, _) = self::vm(b"");
+    resumed.load_snapshot(&snapshot[..]).unwrap();
+    for _ in 0..3 {
+        assert_eq!(vm.step(), None);
+        assert_eq!(resumed.step(), None);
+    }
+    assert_eq!(vm.memory().peek(0xFE0E), 8);
+    assert_eq!(resumed.memory().peek(0xFE0E), 8);
+}
+
+#[test]
+fn requires_the_same_devices() {
+    let path = std::env::temp_dir().join(format!("lc3-snapshot-{}.img", process::id()));
+    let with_disk = || {
+        let (mut vm, _) = vm(b"");
+        let disk = Disk::open(&path).unwrap();
+        vm.memory_mut().add_device(DISK_ADDRESSES, Box::new(disk));
+        vm
+    };
+
+    let mut snapshot = Vec::new();
+    with_disk().save_snapshot(&mut snapshot).unwrap();
+    with_disk().load_snapshot(&snapshot[..]).unwrap();
+    let (mut without_disk, _) = vm(b"");
+    let error = without_disk.load_snapshot(&snapshot[..]).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+    assert_eq!(
+        error.to_string(),
+        "The snapshot was taken with other devices"
+    );
+
+    snapshot.clear();
+    without_disk.save_snapshot(&mut snapshot).unwrap();
+    let error = with_disk().load_snapshot(&snapshot[..]).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+    fs::remove_file(path).unwrap();
+}
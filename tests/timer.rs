@@ -0,0 +1,157 @@
+//! Programmable timer and interrupts on every engine
+
+mod common;
+
+use common::{engines, poke_words, vm_with_program};
+use lc3_vm::{Engine, Fault, Limits, StopReason, Vm};
+
+/// Program at x3000 that starts the timer with the given control value and counts in `R1`
+const MAIN: &[u16] = &[
+    0x2005, // LD R0, INTERVAL
+    0xB005, // STI R0, TIR
+    0x2005, // LD R0, CONTROL
+    0xB005, // STI R0, TCR
+    0x1261, // LOOP ADD R1, R1, #1
+    0x0FFE, // BRnzp LOOP
+    0x0000, // INTERVAL
+    0xFE0A, // TIR
+    0x0000, // CONTROL
+    0xFE08, // TCR
+];
+
+/// Interrupt service routine at x4000 that counts interrupts in `R2` and halts at the third
+const HANDLER: &[u16] = &[
+    0x14A1, // ADD R2, R2, #1
+    0xB404, // STI R2, TSR
+    0x16BD, // ADD R3, R2, #-3
+    0x0401, // BRz DONE
+    0x8000, // RTI
+    0xF025, // DONE HALT
+    0xFE0C, // TSR
+];
+
+/// Returns a vm on `engine` that runs the timer with `interval` and `control`
+fn vm(engine: Engine, interval: u16, control: u16) -> Vm {
+    let (mut vm, _) = vm_with_program(engine, 0x3000, MAIN, b"");
+    poke_words(&mut vm, 0x4000, HANDLER);
+    let mem = vm.memory_mut();
+    mem.poke(0x3006, interval);
+    mem.poke(0x3008, control);
+    mem.poke(0x0181, 0x4000);
+    vm.registers_mut().write(6, 0x5000);
+    vm
+}
+
+#[test]
+fn interrupts_the_program_periodically() {
+    for engine in engines() {
+        let mut vm = vm(engine, 100, 0xC100);
+        assert_eq!(vm.resume(), StopReason::Halted, "{:?}", engine);
+        let regs = vm.registers();
+        assert_eq!(regs.read(2), 3, "{:?}", engine);
+        // The interrupts come every 100 instructions, of which the handler takes 5
+        assert_eq!(vm.instructions(), 4 + 3 * 100 + 5, "{:?}", engine);
+        assert_eq!(regs.read(1), (100 + 2 * 95) / 2, "{:?}", engine);
+        // The third interrupt is still being served
+        assert_eq!(regs.priority(), 1, "{:?}", engine);
+        assert_eq!(regs.read(6), 0x2FFE, "{:?}", engine);
+        assert_eq!(vm.memory().peek(0x2FFF), 0x8001, "{:?}", engine);
+        assert_eq!(vm.memory().peek(0x2FFE), 0x3004, "{:?}", engine);
+        // The service routine cleared the expired flag and the count restarted
+        assert_eq!(vm.memory().peek(0xFE0C), 0, "{:?}", engine);
+        assert_eq!(vm.memory().peek(0xFE0E), 95, "{:?}", engine);
+    }
+}
+
+#[test]
+fn counts_cycles() {
+    let mut reference = vm(Engine::Interpreter, 1000, 0xE100);
+    assert_eq!(reference.resume(), StopReason::Halted);
+    assert_eq!(reference.registers().read(2), 3);
+    assert!(reference.cycles() >= 3000);
+    for engine in engines() {
+        let mut vm = vm(engine, 1000, 0xE100);
+        assert_eq!(vm.resume(), StopReason::Halted, "{:?}", engine);
+        assert_eq!(vm.cycles(), reference.cycles(), "{:?}", engine);
+        assert_eq!(vm.registers().read(1), reference.registers().read(1));
+    }
+}
+
+#[test]
+fn masks_interrupts_of_lower_or_equal_priority() {
+    for engine in engines() {
+        let mut vm = vm(engine, 100, 0xC100);
+        vm.registers_mut().set_psr(0x0102);
+        vm.set_limits(Limits {
+            max_instructions: Some(1000),
+            ..Limits::default()
+        });
+        assert!(matches!(vm.resume(), StopReason::LimitExceeded { .. }));
+        assert_eq!(vm.registers().read(2), 0, "{:?}", engine);
+        assert_eq!(vm.memory().peek(0xFE0C), 0x8000, "{:?}", engine);
+    }
+}
+
+#[test]
+fn switches_to_the_supervisor_stack() {
+    for engine in engines() {
+        let mut vm = vm(engine, 100, 0xC100);
+        assert_eq!(vm.resume(), StopReason::Halted, "{:?}", engine);
+        // The service routine runs in supervisor mode on the supervisor stack
+        let regs = vm.registers();
+        assert!(!regs.user_mode(), "{:?}", engine);
+        assert_eq!(regs.read(6), 0x2FFE, "{:?}", engine);
+        assert_eq!(regs.saved_usp, 0x5000, "{:?}", engine);
+        assert_eq!(vm.memory().peek(0x4FFF), 0, "{:?}", engine);
+
+        // RTI returns to user mode and restores the user stack
+        vm.registers_mut().pc = 0x4004;
+        assert_eq!(vm.step(), None, "{:?}", engine);
+        let regs = vm.registers();
+        assert!(regs.user_mode(), "{:?}", engine);
+        assert_eq!(regs.pc, 0x3004, "{:?}", engine);
+        assert_eq!(regs.read(6), 0x5000, "{:?}", engine);
+        assert_eq!(regs.saved_ssp, 0x3000, "{:?}", engine);
+        assert_eq!(regs.psr(), 0x8001, "{:?}", engine);
+    }
+}
+
+#[test]
+fn rti_in_user_mode_is_illegal() {
+    let mut vm = vm(Engine::Interpreter, 100, 0xC100);
+    vm.memory_mut().poke(0x3000, 0x8000);
+    assert_eq!(
+        vm.resume(),
+        StopReason::Fault {
+            pc: 0x3000,
+            fault: Fault::IllegalOpcode(0x8000),
+        }
+    );
+    assert_eq!(vm.registers().read(6), 0x5000);
+}
+
+#[test]
+fn stops_at_breakpoints_in_service_routines() {
+    let mut vm = vm(Engine::Interpreter, 100, 0xC100);
+    vm.enable_history(1 << 20);
+    vm.add_breakpoint(0x4000);
+    assert_eq!(vm.resume(), StopReason::Breakpoint(0x4000));
+    // The interrupt was taken, but the first instruction of the routine was not executed
+    assert_eq!(vm.instructions(), 4 + 100);
+    assert_eq!(vm.registers().read(2), 0);
+    assert!(!vm.registers().user_mode());
+    assert_eq!(vm.memory().peek(0x2FFE), 0x3004);
+
+    // Stepping back undoes the interrupt
+    assert!(vm.step_back());
+    assert_eq!(vm.instructions(), 4 + 100);
+    assert!(vm.registers().user_mode());
+    assert_eq!(vm.registers().pc, 0x3004);
+    assert_eq!(vm.registers().read(6), 0x5000);
+    assert_eq!(vm.memory().peek(0x2FFE), 0);
+
+    assert_eq!(vm.resume(), StopReason::Breakpoint(0x4000));
+    assert_eq!(vm.resume(), StopReason::Breakpoint(0x4000));
+    assert_eq!(vm.registers().read(2), 1);
+    assert_eq!(vm.instructions(), 4 + 2 * 100);
+}
@@ -0,0 +1,101 @@
+//! Timing model files and cycle costs
+
+mod common;
+
+use common::engines;
+use lc3_vm::{AccessCounts, BufferedConsole, Limits, Opcode, TimingModel, Vm, MAX_CYCLES};
+
+#[test]
+fn parses_timing_models() {
+    let model = TimingModel::parse(
+        "# Slow memory\n\
+         memory 10\n\
+         \n\
+         ldi    8   # indirect loads\n\
+         DEVICE 0\n\
+         clock  2000000\n",
+    )
+    .unwrap();
+    assert_eq!(model.memory_cycles(), 10);
+    assert_eq!(model.device_cycles(), 0);
+    assert_eq!(model.cycles(Opcode::Ldi), 8);
+    assert_eq!(model.cycles(Opcode::Add), 6);
+    assert_eq!(model.clock_hz(), 2_000_000);
+
+    // `LDI` makes two memory accesses
+    let accesses = AccessCounts {
+        memory: 2,
+        device: 0,
+    };
+    assert_eq!(model.instruction_cycles(0xA000, accesses), 28);
+    assert_eq!(TimingModel::parse("").unwrap(), TimingModel::default());
+}
+
+#[test]
+fn reports_the_line_of_errors() {
+    fn error(text: &str) -> String {
+        TimingModel::parse(text).unwrap_err()
+    }
+    assert_eq!(error("memory 1\nLDI"), "line 2: expected `NAME CYCLES`");
+    assert_eq!(error("memory 1 2"), "line 1: expected `NAME CYCLES`");
+    assert_eq!(error("memory x5"), "line 1: invalid number `x5`");
+    assert_eq!(error("memory -1"), "line 1: invalid number `-1`");
+    assert_eq!(error("\n\nclock 0"), "line 3: the clock must not be 0");
+    assert_eq!(error("flash 3"), "line 1: unknown name `flash`");
+    assert_eq!(
+        error(&format!("ADD {}", MAX_CYCLES + 1)),
+        "line 1: at most 65535 cycles are allowed"
+    );
+    assert_eq!(
+        error("device 18446744073709551615"),
+        "line 1: at most 65535 cycles are allowed"
+    );
+    assert!(TimingModel::parse(&format!("device {}", MAX_CYCLES)).is_ok());
+}
+
+#[test]
+fn saturates_cycle_costs() {
+    let mut model = TimingModel::new();
+    model.set_cycles(Opcode::Sti, MAX_CYCLES);
+    model.set_memory_cycles(MAX_CYCLES);
+    let accesses = AccessCounts {
+        memory: u64::MAX / 2,
+        device: 1,
+    };
+    assert_eq!(model.access_cycles(accesses), u64::MAX);
+    assert_eq!(model.instruction_cycles(0xB000, accesses), u64::MAX);
+    assert_eq!(
+        model.instruction_cycles(0xB000, AccessCounts::default()),
+        MAX_CYCLES
+    );
+}
+
+#[test]
+#[should_panic(expected = "At most 65535 cycles are allowed")]
+fn rejects_oversized_costs() {
+    TimingModel::new().set_device_cycles(MAX_CYCLES + 1);
+}
+
+#[test]
+fn saturates_the_cycle_counter() {
+    // Snapshots may contain any cycle count
+    let mut vm = Vm::new();
+    vm.set_console(Box::new(BufferedConsole::new()));
+    vm.memory_mut().poke(0x3000, 0x0FFF); // BRnzp #-1
+    let mut snapshot = Vec::new();
+    vm.save_snapshot(&mut snapshot).unwrap();
+    snapshot[30..38].copy_from_slice(&(u64::MAX - 1).to_be_bytes());
+
+    for engine in engines() {
+        vm.set_engine(engine);
+        vm.load_snapshot(&snapshot[..]).unwrap();
+        assert_eq!(vm.cycles(), u64::MAX - 1);
+        vm.set_limits(Limits {
+            max_instructions: Some(1000),
+            ..Limits::default()
+        });
+        vm.resume();
+        assert_eq!(vm.cycles(), u64::MAX, "{:?}", engine);
+        vm.set_limits(Limits::default());
+    }
+}
@@ -0,0 +1,138 @@
+//! Trace records in the text, JSON and binary formats
+
+mod common;
+
+use common::vm_with_program;
+use lc3_vm::{Engine, Opcode, TraceFilter, TraceFormat, TraceRecorder};
+
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+/// Program at x3000 that copies the word at x3005 to x3006
+const PROGRAM: &[u16] = &[
+    0xE004, // LEA R0, DATA
+    0x6200, // LDR R1, R0, #0
+    0x7201, // STR R1, R0, #1
+    0xF025, // HALT
+    0x0000, 0x0005, // DATA
+];
+
+/// Output stream whose contents stay readable after the recorder was dropped
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Runs the program with a recorder of the `format` and returns the trace
+fn record(format: TraceFormat, filter: TraceFilter) -> Vec<u8> {
+    let (mut vm, _) = vm_with_program(Engine::Interpreter, 0x3000, PROGRAM, b"");
+    let output = SharedBuffer::default();
+    let mut recorder = TraceRecorder::new(Box::new(output.clone()), format);
+    recorder.set_filter(filter);
+    let recorder = Rc::new(RefCell::new(recorder));
+    vm.add_tracer(Box::new(Rc::clone(&recorder)));
+    vm.resume();
+    recorder.borrow_mut().flush().unwrap();
+    let trace = output.0.borrow().clone();
+    trace
+}
+
+/// Filter that leaves out the `HALT` trap
+fn program_only() -> TraceFilter {
+    let mut filter = TraceFilter::new();
+    filter.add_range(0x3000..=0x3002);
+    filter
+}
+
+#[test]
+fn writes_text_lines() {
+    let trace = record(TraceFormat::Text, program_only());
+    assert_eq!(
+        String::from_utf8(trace).unwrap(),
+        "x3000  E004  LEA   R0=x3005 nzp=P\n\
+         x3001  6200  LDR   R1=x0005 @x3005 nzp=P\n\
+         x3002  7201  STR   [x3006]=x0005 nzp=P\n"
+    );
+
+    let mut filter = program_only();
+    filter.add_opcode(Opcode::Str);
+    filter.add_opcode(Opcode::Trap);
+    let trace = record(TraceFormat::Text, filter);
+    assert_eq!(
+        String::from_utf8(trace).unwrap(),
+        "x3002  7201  STR   [x3006]=x0005 nzp=P\n"
+    );
+}
+
+#[test]
+fn writes_json_lines() {
+    let trace = String::from_utf8(record(TraceFormat::Json, program_only())).unwrap();
+    let records: Vec<Value> = trace
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(
+        records,
+        [
+            json!({
+                "pc": 0x3000,
+                "instr": 0xE004,
+                "opcode": "LEA",
+                "regs": { "R0": 0x3005 },
+                "reads": [],
+                "writes": [],
+                "nzp": "P",
+            }),
+            json!({
+                "pc": 0x3001,
+                "instr": 0x6200,
+                "opcode": "LDR",
+                "regs": { "R1": 5 },
+                "reads": [0x3005],
+                "writes": [],
+                "nzp": "P",
+            }),
+            json!({
+                "pc": 0x3002,
+                "instr": 0x7201,
+                "opcode": "STR",
+                "regs": {},
+                "reads": [],
+                "writes": [[0x3006, 5]],
+                "nzp": "P",
+            }),
+        ]
+    );
+}
+
+#[test]
+fn writes_binary_records() {
+    let trace = record(TraceFormat::Binary, program_only());
+    #[rustfmt::skip]
+    let expected: &[u8] = &[
+        b'L', b'C', b'3', b'T', 2,
+        // LEA: pc, instr, nzp, regmask, R0, reads, writes
+        0x30, 0x00, 0xE0, 0x04, 1, 0b01, 0x30, 0x05, 0, 0, 0, 0, 0, 0, 0, 0,
+        // LDR: pc, instr, nzp, regmask, R1, one read, writes
+        0x30, 0x01, 0x62, 0x00, 1, 0b10, 0x00, 0x05, 0, 0, 0, 1, 0x30, 0x05, 0, 0, 0, 0,
+        // STR: pc, instr, nzp, regmask, reads, one write with its new value
+        0x30, 0x02, 0x72, 0x01, 1, 0b00, 0, 0, 0, 0, 0, 0, 0, 1, 0x30, 0x06, 0x00, 0x05,
+    ];
+    assert_eq!(trace, expected);
+
+    // An empty trace still has the header
+    let mut filter = TraceFilter::new();
+    filter.add_range(0x4000..=0x4000);
+    assert_eq!(record(TraceFormat::Binary, filter), b"LC3T\x02");
+}
@@ -0,0 +1,138 @@
+//! Rendering and key handling of the terminal UI
+
+mod common;
+
+use common::vm_with_program;
+use lc3_vm::{disassemble, parse_keys, DebugInfo, Engine, Frame, Key, Tui};
+
+/// Program at x3000 that prints "Hi" and halts
+const HELLO: &[u16] = &[0xE002, 0xF022, 0xF025, 0x0048, 0x0069, 0x000A, 0x0000];
+
+/// Program at x3000 that echoes a key and halts
+const ECHO: &[u16] = &[0xF020, 0xF021, 0xF025];
+
+fn tui(program: &[u16]) -> Tui {
+    let (vm, _) = vm_with_program(Engine::Interpreter, 0x3000, program, b"");
+    let mut debug_info = DebugInfo::new();
+    debug_info.add_symbol("MAIN", 0x3000);
+    Tui::new(vm, debug_info)
+}
+
+fn contains(frame: &Frame, text: &str) -> bool {
+    frame.lines().any(|line| line.contains(text))
+}
+
+#[test]
+fn disassembles_instructions() {
+    let cases: &[(u16, &str)] = &[
+        (0x1042, "ADD R0, R1, R2"),
+        (0x127F, "ADD R1, R1, #-1"),
+        (0x0BFD, "BRnp x2FFE"),
+        (0x0E02, "BR x3003"),
+        (0x0000, "NOP"),
+        (0x6F80, "LDR R7, R6, #0"),
+        (0x4FF8, "JSR x2FF9"),
+        (0x4080, "JSRR R2"),
+        (0xC1C0, "RET"),
+        (0x983F, "NOT R4, R0"),
+        (0xF025, "HALT"),
+        (0xF0FF, "TRAP xFF"),
+        (0x8000, "RTI"),
+        (0xD123, ".FILL xD123"),
+    ];
+    for &(instr, text) in cases {
+        assert_eq!(disassemble(0x3000, instr), text, "x{:04X}", instr);
+    }
+}
+
+#[test]
+fn renders_registers_disassembly_and_memory() {
+    let mut tui = tui(HELLO);
+    tui.handle_key(Key::Char('s'));
+    let frame = tui.render(100, 30);
+    assert_eq!((frame.width(), frame.height()), (100, 30));
+    assert!(contains(&frame, "R0  x3003   12291"));
+    assert!(contains(&frame, "PC  x3001"));
+    assert!(contains(&frame, "PSR x8001     --P"));
+    assert!(contains(&frame, "Executed 1"));
+    assert!(contains(
+        &frame,
+        "   x3000  E002  MAIN         LEA R0, x3003"
+    ));
+    assert!(contains(&frame, " ▶ x3001  F022               PUTS"));
+    assert!(contains(
+        &frame,
+        "x3000  E002 F022 F025 0048 0069 000A 0000 0000"
+    ));
+    assert!(frame.line(29).contains("Stepped to x3001"));
+
+    // The selected instruction is highlighted
+    let row = (0..30)
+        .find(|&y| frame.line(y).contains("▶ x3001"))
+        .unwrap();
+    assert!(frame.is_inverse(30, row));
+
+    let small = tui.render(40, 10);
+    assert_eq!(small.line(0).trim_end(), "The terminal is too small");
+}
+
+#[test]
+fn stops_at_breakpoints_and_shows_output() {
+    let mut tui = tui(HELLO);
+    tui.handle_key(Key::Char('j'));
+    tui.handle_key(Key::Char('j'));
+    tui.handle_key(Key::Char('b'));
+    assert!(tui.vm().has_breakpoint(0x3002));
+
+    tui.handle_key(Key::Char('c'));
+    assert!(tui.is_running());
+    tui.update();
+    assert!(!tui.is_running());
+    assert_eq!(tui.vm().registers().pc, 0x3002);
+    let frame = tui.render(80, 24);
+    assert!(contains(&frame, "│Hi "));
+    assert!(contains(&frame, "●▶ x3002"));
+    assert!(frame.line(23).contains("Breakpoint at x3002"));
+
+    tui.handle_key(Key::Char('c'));
+    tui.update();
+    assert!(tui.render(80, 24).line(23).contains("The program halted"));
+    tui.handle_key(Key::Char('q'));
+    assert!(tui.has_quit());
+}
+
+#[test]
+fn passes_keys_to_the_running_program() {
+    let mut echo = tui(ECHO);
+    echo.handle_key(Key::Char('c'));
+    echo.update();
+    assert!(echo.is_running());
+    assert!(echo.render(80, 24).line(23).contains("Waiting for input"));
+
+    echo.handle_key(Key::Char('s'));
+    echo.update();
+    assert!(!echo.is_running());
+    assert!(contains(&echo.render(80, 24), "│sHALT"));
+
+    let mut spinning = tui(&[0x0FFF]);
+    spinning.handle_key(Key::Char('c'));
+    spinning.update();
+    spinning.handle_key(Key::Interrupt);
+    assert!(!spinning.is_running());
+    assert!(!spinning.has_quit());
+}
+
+#[test]
+fn parses_terminal_keys() {
+    assert_eq!(
+        parse_keys(b"s\x1b[A\x1b[B\x1b[5~\x1b[6~\x1b[1;5C\x03"),
+        [
+            Key::Char('s'),
+            Key::Up,
+            Key::Down,
+            Key::PageUp,
+            Key::PageDown,
+            Key::Interrupt,
+        ]
+    );
+}
@@ -0,0 +1,68 @@
+//! Watchpoints on reads, writes and changes of memory
+
+mod common;
+
+use common::vm_with_program;
+use lc3_vm::{Engine, StopReason, Vm, WatchKind, Watchpoint, WatchpointHit};
+
+/// Address of the word that the program reads and writes
+const DATA: u16 = 0x3005;
+
+/// Program at x3000 that increments the word at `DATA` and stores it twice
+const PROGRAM: &[u16] = &[
+    0x2004, // LD R0, DATA
+    0x1021, // ADD R0, R0, #1
+    0x3002, // ST R0, DATA
+    0x3001, // ST R0, DATA
+    0xF025, // HALT
+    0x0041, // DATA
+];
+
+fn vm(kind: WatchKind) -> Vm {
+    let (mut vm, _) = vm_with_program(Engine::Interpreter, 0x3000, PROGRAM, b"");
+    vm.add_watchpoint(Watchpoint::new(DATA..=DATA, kind));
+    vm
+}
+
+fn hit(pc: u16, kind: WatchKind, old_value: u16, new_value: u16) -> StopReason {
+    StopReason::Watchpoint(WatchpointHit {
+        pc,
+        address: DATA,
+        kind,
+        old_value,
+        new_value,
+    })
+}
+
+#[test]
+fn read_watchpoints_report_the_loaded_value() {
+    let mut vm = vm(WatchKind::Read);
+    assert_eq!(vm.resume(), hit(0x3000, WatchKind::Read, 0x41, 0x41));
+    // The vm stops after the instruction that read the word
+    assert_eq!(vm.registers().pc, 0x3001);
+    assert_eq!(vm.registers().read(0), 0x41);
+    assert_eq!(vm.resume(), StopReason::Halted);
+}
+
+#[test]
+fn write_watchpoints_trigger_on_every_store() {
+    let mut vm = vm(WatchKind::Write);
+    assert_eq!(vm.resume(), hit(0x3002, WatchKind::Write, 0x41, 0x42));
+    assert_eq!(vm.registers().pc, 0x3003);
+    assert_eq!(vm.memory().peek(DATA), 0x42);
+    // Storing the same value again is a write as well
+    assert_eq!(vm.resume(), hit(0x3003, WatchKind::Write, 0x42, 0x42));
+    assert_eq!(vm.resume(), StopReason::Halted);
+}
+
+#[test]
+fn change_watchpoints_ignore_stores_of_the_same_value() {
+    let mut vm = vm(WatchKind::Change);
+    assert_eq!(vm.resume(), hit(0x3002, WatchKind::Change, 0x41, 0x42));
+    assert_eq!(vm.resume(), StopReason::Halted);
+
+    // Removed watchpoints don't trigger
+    let mut vm = self::vm(WatchKind::Change);
+    assert!(vm.remove_watchpoint(&Watchpoint::new(DATA..=DATA, WatchKind::Change)));
+    assert_eq!(vm.resume(), StopReason::Halted);
+}